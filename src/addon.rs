@@ -0,0 +1,22 @@
+//! Running as a Home Assistant add-on.
+//!
+//! The Supervisor writes the options of an add-on to `/data/options.json`
+//! before starting its container, and keeps `/data` across restarts and
+//! updates. When that file exists and no config file was given, it is read
+//! as the config file, with the same keys as the TOML one, and the cache and
+//! the files kept next to it go to `/data` as well. The add-on's image can
+//! then run the binary directly.
+
+use std::path::{Path, PathBuf};
+
+/// Where the Supervisor writes the add-on's options.
+pub const OPTIONS_PATH: &str = "/data/options.json";
+
+/// Where the cache goes when running as an add-on.
+pub const CACHE_PATH: &str = "/data/cache.json";
+
+/// The options file, if this runs as an add-on.
+pub fn options_path() -> Option<PathBuf> {
+    let path = Path::new(OPTIONS_PATH);
+    path.is_file().then(|| path.to_path_buf())
+}
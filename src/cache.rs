@@ -0,0 +1,381 @@
+//! Local cache of record state, used to skip Cloudflare API calls when the
+//! public IP hasn't changed.
+
+use crate::error::{Context, Error, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::collections::btree_map::Entry;
+use std::fs::{self, File, TryLockError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// Default cache location: `cloudflare-ddns/cache.json` in the platform cache
+/// directory (`$XDG_CACHE_HOME` or `~/.cache` on Linux, `~/Library/Caches` on
+/// macOS, `%LOCALAPPDATA%` on Windows), falling back to `./cache/cache.json`
+/// when no cache directory can be determined.
+pub fn default_cache_path() -> PathBuf {
+    dirs::cache_dir()
+        .map(|dir| dir.join("cloudflare-ddns").join("cache.json"))
+        .unwrap_or_else(|| PathBuf::from("./cache/cache.json"))
+}
+
+/// Version of the cache format written by this build. Changing the format
+/// means bumping this and teaching [`migrate`] the step from the previous
+/// version.
+pub const CACHE_VERSION: u64 = 2;
+
+/// Last known state of a single record.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DnsCache {
+    pub record_name: String,
+    pub record_type: String,
+    pub ip_address: String,
+    pub last_checked: DateTime<Utc>,
+    pub last_updated: DateTime<Utc>,
+    /// Runs in a row that trusted this entry instead of asking the provider.
+    #[serde(default)]
+    pub cache_hits: u32,
+    /// When the record was last written, for [`RecordConfig::min_update_interval`].
+    ///
+    /// [`RecordConfig::min_update_interval`]: crate::config::RecordConfig::min_update_interval
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_written: Option<DateTime<Utc>>,
+    /// The address the record had before `ip_address`, to notice it
+    /// flapping back.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_ip: Option<String>,
+}
+
+impl DnsCache {
+    pub fn new(record_name: String, record_type: String, ip_address: String) -> Self {
+        let now = Utc::now();
+        Self {
+            record_name,
+            record_type,
+            ip_address,
+            last_checked: now,
+            last_updated: now,
+            cache_hits: 0,
+            last_written: None,
+            previous_ip: None,
+        }
+    }
+
+    pub fn is_expired(&self, expiry_hours: i64) -> bool {
+        let expiry_duration = Duration::hours(expiry_hours);
+        Utc::now() - self.last_checked > expiry_duration
+    }
+
+    pub fn matches_config(&self, record_name: &str, record_type: &str) -> bool {
+        self.record_name == record_name && self.record_type == record_type
+    }
+
+    pub fn update_ip(&mut self, new_ip: String) {
+        if self.ip_address != new_ip {
+            self.previous_ip = Some(std::mem::replace(&mut self.ip_address, new_ip));
+        }
+        self.last_updated = Utc::now();
+        self.last_checked = Utc::now();
+        self.cache_hits = 0;
+    }
+
+    pub fn update_written(&mut self) {
+        self.last_written = Some(Utc::now());
+    }
+
+    pub fn update_checked(&mut self) {
+        self.last_checked = Utc::now();
+        self.cache_hits = 0;
+    }
+}
+
+/// On-disk cache holding one entry per managed record, keyed by
+/// [`record_key`], plus the IDs of zones that were configured by name and the
+/// failure streaks of records that are currently failing.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CacheFile {
+    /// Format version, see [`CACHE_VERSION`].
+    pub version: u64,
+    pub records: BTreeMap<String, DnsCache>,
+    #[serde(default)]
+    pub zones: BTreeMap<String, String>,
+    /// Runs in a row each failing record has failed, keyed by [`record_key`].
+    /// Kept here so that one-shot runs from cron count them too.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub failures: BTreeMap<String, u32>,
+    /// Failover health checks in a row that the primary address of each
+    /// record has failed, keyed by [`record_key`].
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub health: BTreeMap<String, u32>,
+    /// Addresses that flapping records are waiting on to stay the same,
+    /// keyed by [`record_key`].
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub flaps: BTreeMap<String, Flap>,
+}
+
+/// A record whose address went back to the one it had before within
+/// `FLAP_WINDOW`, and the address it is held off from until that stays
+/// detected for `FLAP_CONFIRMATION`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Flap {
+    pub candidate: String,
+    /// Since when `candidate` has been detected in every run.
+    pub since: DateTime<Utc>,
+}
+
+impl Default for CacheFile {
+    fn default() -> Self {
+        Self {
+            version: CACHE_VERSION,
+            records: BTreeMap::new(),
+            zones: BTreeMap::new(),
+            failures: BTreeMap::new(),
+            health: BTreeMap::new(),
+            flaps: BTreeMap::new(),
+        }
+    }
+}
+
+impl CacheFile {
+    pub fn find(&self, record_name: &str, record_type: &str) -> Option<&DnsCache> {
+        self.records.get(&record_key(record_name, record_type))
+    }
+
+    pub fn find_mut(&mut self, record_name: &str, record_type: &str) -> Option<&mut DnsCache> {
+        self.records.get_mut(&record_key(record_name, record_type))
+    }
+
+    /// Adds an entry, replacing any previous one for the same record.
+    pub fn insert(&mut self, entry: DnsCache) -> &mut DnsCache {
+        let key = record_key(&entry.record_name, &entry.record_type);
+        match self.records.entry(key) {
+            Entry::Occupied(mut occupied) => {
+                occupied.insert(entry);
+                occupied.into_mut()
+            }
+            Entry::Vacant(vacant) => vacant.insert(entry),
+        }
+    }
+
+    /// Drops the entry, failure streak and failover health of a record that
+    /// is no longer managed.
+    pub fn remove(&mut self, record_name: &str, record_type: &str) {
+        let key = record_key(record_name, record_type);
+        self.records.remove(&key);
+        self.failures.remove(&key);
+        self.health.remove(&key);
+        self.flaps.remove(&key);
+    }
+
+    /// Counts another failed run of a record and returns how many runs in a
+    /// row it has now failed.
+    pub fn record_failure(&mut self, record_name: &str, record_type: &str) -> u32 {
+        let failures = self
+            .failures
+            .entry(record_key(record_name, record_type))
+            .or_default();
+        *failures += 1;
+        *failures
+    }
+
+    /// Ends the failure streak of a record, returning how many runs in a row
+    /// it had failed, if any.
+    pub fn clear_failures(&mut self, record_name: &str, record_type: &str) -> Option<u32> {
+        self.failures.remove(&record_key(record_name, record_type))
+    }
+}
+
+/// Key of a record's entry in [`CacheFile::records`]: `home.example.com/A`.
+pub fn record_key(record_name: &str, record_type: &str) -> String {
+    format!("{}/{}", record_name, record_type)
+}
+
+/// Reads the cache file, migrating it from older formats, and starts from an
+/// empty cache if it is missing or unreadable. A cache that can't be used is
+/// kept as `<cache_path>.bak` rather than overwritten.
+pub fn load_cache(cache_path: &Path) -> CacheFile {
+    if !cache_path.exists() {
+        info!("📄 No cache file found, will create one after first run");
+        return CacheFile::default();
+    }
+
+    match fs::read_to_string(cache_path) {
+        Ok(content) => match parse_cache(&content) {
+            Ok(cache) => {
+                info!("📄 Loaded cache from {}", cache_path.display());
+                cache
+            }
+            Err(e) => {
+                let backup_path = sibling_path(cache_path, "bak");
+                match fs::rename(cache_path, &backup_path) {
+                    Ok(()) => warn!(
+                        "⚠️  Cache file unusable ({}), moved it to {} and will recreate",
+                        e.report(),
+                        backup_path.display()
+                    ),
+                    Err(_) => warn!("⚠️  Cache file unusable ({}), will recreate", e.report()),
+                }
+                CacheFile::default()
+            }
+        },
+        Err(e) => {
+            warn!("⚠️  Failed to read cache file ({}), will recreate", e);
+            CacheFile::default()
+        }
+    }
+}
+
+/// Reads the cache without the fallbacks and messages of [`load_cache`],
+/// for a look at what the last run left.
+pub fn read_cache(cache_path: &Path) -> Result<CacheFile> {
+    parse_cache(&fs::read_to_string(cache_path)?)
+}
+
+/// Parses a cache file of any version up to [`CACHE_VERSION`].
+fn parse_cache(content: &str) -> Result<CacheFile> {
+    let mut value: Value = serde_json::from_str(content)?;
+
+    // Files from before versioning have no version field
+    let version = value.get("version").and_then(Value::as_u64).unwrap_or(0);
+    if version > CACHE_VERSION {
+        return Err(Error::Cache(format!(
+            "written by a newer release with cache version {}, this one reads up to {}",
+            version, CACHE_VERSION
+        )));
+    }
+
+    for from in version..CACHE_VERSION {
+        value = migrate(value, from)?;
+        info!("🔧 Migrated cache from version {} to {}", from, from + 1);
+    }
+
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Converts a cache from version `from` to the next one.
+fn migrate(value: Value, from: u64) -> Result<Value> {
+    let Value::Object(mut cache) = value else {
+        return Err(Error::Cache("expected a JSON object".to_string()));
+    };
+
+    match from {
+        // Unversioned: either the single record the first releases cached,
+        // or the records and zones of the current format
+        0 => {
+            if cache.contains_key("record_name") {
+                let record = Value::Object(cache);
+                cache = Map::new();
+                cache.insert("records".to_string(), Value::Array(vec![record]));
+            }
+            cache
+                .entry("records")
+                .or_insert_with(|| Value::Array(Vec::new()));
+        }
+        // A list of entries becomes a map keyed by name and type
+        1 => {
+            let records = match cache.remove("records") {
+                Some(Value::Array(records)) => records,
+                _ => return Err(Error::Cache("expected a list of records".to_string())),
+            };
+            let mut keyed = Map::new();
+            for record in records {
+                let key = match (record.get("record_name"), record.get("record_type")) {
+                    (Some(Value::String(name)), Some(Value::String(record_type))) => {
+                        record_key(name, record_type)
+                    }
+                    _ => {
+                        return Err(Error::Cache(
+                            "cache entry without a record name and type".to_string(),
+                        ));
+                    }
+                };
+                keyed.insert(key, record);
+            }
+            cache.insert("records".to_string(), Value::Object(keyed));
+        }
+        _ => {
+            return Err(Error::Cache(format!(
+                "no migration from cache version {}",
+                from
+            )));
+        }
+    }
+
+    cache.insert("version".to_string(), Value::from(from + 1));
+    Ok(Value::Object(cache))
+}
+
+/// Writes the cache file atomically: the content goes to a temporary file next
+/// to it that is then renamed over the old one, so readers never see a
+/// partially written cache.
+pub fn save_cache(cache: &CacheFile, cache_path: &Path) -> Result<()> {
+    let content = serde_json::to_string_pretty(cache)?;
+    atomic_write(cache_path, content.as_bytes())?;
+    info!("💾 Cache saved to {}", cache_path.display());
+
+    Ok(())
+}
+
+/// Advisory lock on the cache, held until dropped.
+#[derive(Debug)]
+pub struct CacheLock {
+    _file: File,
+}
+
+/// Takes an exclusive lock on `<cache_path>.lock` so that two instances can't
+/// race on the same cache. Fails immediately if another process holds it.
+pub fn lock_cache(cache_path: &Path) -> Result<CacheLock> {
+    if let Some(parent) = cache_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let lock_path = sibling_path(cache_path, "lock");
+    let file = File::create(&lock_path)
+        .with_context(|| format!("Failed to open lock file {}", lock_path.display()))?;
+
+    match file.try_lock() {
+        Ok(()) => Ok(CacheLock { _file: file }),
+        Err(TryLockError::WouldBlock) => Err(Error::Cache(format!(
+            "Another instance is already using the cache at {}",
+            cache_path.display()
+        ))),
+        Err(TryLockError::Error(e)) => {
+            Err(Error::from(e).context(format!("Failed to lock {}", lock_path.display())))
+        }
+    }
+}
+
+/// Writes `contents` to `path` through a temporary file next to it, so that
+/// a crash leaves either the old file or the new one, and never a partly
+/// written one. Creates the directory if it doesn't exist.
+pub fn atomic_write(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp_path = sibling_path(path, "tmp");
+    let mut tmp_file = File::create(&tmp_path)?;
+    tmp_file.write_all(contents)?;
+    tmp_file.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
+
+/// `cache.json` -> `cache.json.<suffix>`
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Deletes the cache file. Returns `false` if there was nothing to delete.
+pub fn clear_cache(cache_path: &Path) -> Result<bool> {
+    if !cache_path.exists() {
+        return Ok(false);
+    }
+
+    fs::remove_file(cache_path)?;
+    Ok(true)
+}
@@ -0,0 +1,198 @@
+use crate::logging::{LogBackend, LogFormat};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
+
+/// Dynamic DNS updater for Cloudflare.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Path to a TOML config file, or a JSON one with a `.json` extension
+    #[arg(long, global = true, env = "CLOUDFLARE_DDNS_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Path to the cache file [default: platform cache directory]
+    #[arg(long, global = true, env = "CACHE_PATH")]
+    pub cache_path: Option<PathBuf>,
+
+    /// Only log warnings, errors and the run summary
+    #[arg(long, global = true, env = "QUIET")]
+    pub quiet: bool,
+
+    /// Log output format
+    #[arg(long, global = true, env = "LOG_FORMAT", value_enum, default_value_t = LogFormat::Pretty)]
+    pub log_format: LogFormat,
+
+    /// Start log lines with an RFC 3339 timestamp
+    #[arg(long, global = true, env = "LOG_TIMESTAMPS")]
+    pub log_timestamps: bool,
+
+    /// Where logs are written. `journald` and `syslog` send them to the
+    /// system log with priorities that match their level
+    #[arg(long, global = true, env = "LOG_BACKEND", value_enum, default_value_t = LogBackend::Console)]
+    pub log_backend: LogBackend,
+
+    /// Leave out the emoji at the start of log lines and command output.
+    /// They are left out by default when the locale isn't UTF-8
+    #[arg(long, global = true, env = "NO_EMOJI")]
+    pub no_emoji: bool,
+
+    /// Result output of `run` and `check`. `json` prints a report of each
+    /// update cycle to stdout and moves the logs to stderr
+    #[arg(long, global = true, env = "OUTPUT", value_enum, default_value_t = Output::Text)]
+    pub output: Output,
+
+    /// Run as a Windows service, as set up by `service install`
+    #[cfg(windows)]
+    #[arg(long, hide = true)]
+    pub service: bool,
+
+    /// Options for `run`, accepted without the subcommand as well
+    #[command(flatten)]
+    pub run: RunArgs,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+impl Cli {
+    /// Parses the command line like [`Parser::parse`], and notes which run
+    /// options were only set through the environment.
+    pub fn parse_args() -> Self {
+        let matches = Self::command().get_matches();
+        let mut cli = Self::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+        cli.run.note_sources(&matches);
+        if let (Some(Command::Run(args)), Some(("run", matches))) =
+            (&mut cli.command, matches.subcommand())
+        {
+            args.note_sources(matches);
+        }
+        cli
+    }
+}
+
+/// What `run` and `check` print to stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Output {
+    /// The logs.
+    Text,
+    /// One JSON report per update cycle, the logs go to stderr.
+    Json,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Detect the public IP and update DNS records (the default)
+    Run(RunArgs),
+    /// Ask for an API token, a zone and records, and write a config file to
+    /// the `--config` path (default: config.toml)
+    Init,
+    /// Compare the cache, the live records and the public IP, without changing anything
+    Status,
+    /// Dry run: look up records and report what would change, without updating
+    Check,
+    /// List all DNS records in the configured zones
+    ListRecords(ListRecordsArgs),
+    /// Check that the credentials are valid and can access the configured zones
+    Verify,
+    /// Check the configuration and the credentials, failing on any problem
+    Validate,
+    /// Delete the cache file
+    ClearCache,
+    /// Exit with 0 if the daemon's last update cycle succeeded within
+    /// HEALTH_MAX_AGE seconds and 1 otherwise, for Docker's HEALTHCHECK
+    Healthcheck,
+    /// Show the latest address changes from the HISTORY_PATH database
+    History(HistoryArgs),
+    /// Print a hardened systemd service unit for running the daemon
+    SystemdUnit(SystemdUnitArgs),
+    /// Save or remove the API token in the OS keyring
+    #[command(subcommand)]
+    Auth(AuthCommand),
+    /// Install or remove the Windows service
+    #[cfg(windows)]
+    #[command(subcommand)]
+    Service(ServiceCommand),
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum AuthCommand {
+    /// Prompt for an API token and save it in the OS keyring, where it is
+    /// used when CLOUDFLARE_API_TOKEN isn't set
+    Login,
+    /// Remove the saved API token from the OS keyring
+    Logout,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ListRecordsArgs {
+    /// Only list this zone, given by name or ID. It doesn't have to be used
+    /// by any configured record
+    #[arg(long)]
+    pub zone: Option<String>,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct HistoryArgs {
+    /// Only show changes of this record name
+    #[arg(long)]
+    pub record: Option<String>,
+
+    /// Number of changes to show, newest first
+    #[arg(long, default_value_t = 20)]
+    pub limit: usize,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct SystemdUnitArgs {
+    /// Path of the cloudflare-ddns binary [default: this binary]
+    #[arg(long)]
+    pub binary: Option<PathBuf>,
+
+    /// File with the environment variables (CLOUDFLARE_API_TOKEN, ...)
+    #[arg(long, default_value = "/etc/cloudflare-ddns/env")]
+    pub env_file: PathBuf,
+}
+
+#[cfg(windows)]
+#[derive(Debug, Clone, Subcommand)]
+pub enum ServiceCommand {
+    /// Register the daemon as a service that starts with Windows. The
+    /// `--config` and `--cache-path` given here are passed on to it.
+    Install,
+    /// Stop and remove the service
+    Uninstall,
+}
+
+#[derive(Debug, Clone, Default, Args)]
+pub struct RunArgs {
+    /// Keep running and re-check every SLEEP_INTERVAL seconds
+    #[arg(long)]
+    pub daemon: bool,
+
+    /// Look up records and print the changes that would be made, without applying them
+    #[arg(long, env = "DRY_RUN")]
+    pub dry_run: bool,
+
+    /// Update every record with the current IP, even if the cache or the
+    /// record says it is already set
+    #[arg(long, conflicts_with = "daemon")]
+    pub force: bool,
+
+    /// Stop a one-shot run that hasn't finished after this many seconds,
+    /// keeping what it updated so far
+    #[arg(long, env = "MAX_RUNTIME", value_name = "SECONDS")]
+    pub max_runtime: Option<u64>,
+
+    /// Whether `max_runtime` came from MAX_RUNTIME rather than the flag,
+    /// which daemons ignore so that cron jobs can share their environment
+    #[arg(skip)]
+    pub max_runtime_from_env: bool,
+}
+
+impl RunArgs {
+    fn note_sources(&mut self, matches: &ArgMatches) {
+        self.max_runtime_from_env =
+            matches.value_source("max_runtime") == Some(ValueSource::EnvVariable);
+    }
+}
@@ -0,0 +1,706 @@
+//! Minimal client for the Cloudflare DNS API.
+
+pub use crate::http::HttpConfig;
+use crate::provider::{DnsRecord, UpdateDnsRecord};
+
+use crate::error::{Context, Error, Result};
+use crate::provider::DnsProvider;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+use tracing::{info, warn};
+
+#[derive(Debug, Deserialize)]
+struct CloudflareResponse<T> {
+    success: bool,
+    errors: Vec<CloudflareError>,
+    messages: Vec<String>,
+    result: Option<T>,
+    result_info: Option<ResultInfo>,
+}
+
+/// Pagination details of list responses.
+#[derive(Debug, Deserialize)]
+struct ResultInfo {
+    page: u32,
+    total_pages: u32,
+}
+
+/// One entry of the `errors` list of a response.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CloudflareError {
+    pub code: u32,
+    pub message: String,
+}
+
+/// A zone the credentials can access.
+#[derive(Debug, Deserialize)]
+pub struct Zone {
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct User {
+    email: String,
+}
+
+/// An API token as described by the token verification endpoint.
+#[derive(Debug, Deserialize)]
+pub struct TokenStatus {
+    /// `active`, `disabled` or `expired`.
+    pub status: String,
+    #[serde(default)]
+    pub expires_on: Option<DateTime<Utc>>,
+}
+
+/// Request body for the batch endpoint. Only full replacements are used.
+#[derive(Debug, Serialize)]
+struct BatchRequest<'a> {
+    puts: Vec<BatchPut<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct BatchPut<'a> {
+    id: &'a str,
+    #[serde(flatten)]
+    record: &'a UpdateDnsRecord,
+}
+
+/// How requests to the API are authenticated.
+#[derive(Debug, Clone)]
+pub enum Auth {
+    /// A scoped API token, sent as a bearer token.
+    Token(String),
+    /// The legacy Global API Key of an account, with the account's email.
+    GlobalKey { key: String, email: String },
+}
+
+/// Cloudflare API client. A single token is shared across all zones, so
+/// every call takes the zone ID of the record it operates on.
+pub struct CloudflareClient {
+    client: Client,
+    auth: Auth,
+    base_url: String,
+    per_page: u32,
+}
+
+/// Base URL of version 4 of the Cloudflare API.
+pub const API_BASE_URL: &str = "https://api.cloudflare.com/client/v4";
+
+/// Records requested per page when listing DNS records. Large pages keep the
+/// number of requests low for big zones.
+const DEFAULT_PER_PAGE: u32 = 500;
+
+/// How often a rate-limited request is retried before giving up.
+const RATE_LIMIT_RETRIES: u32 = 3;
+/// Wait used when a 429 response has no `Retry-After` header.
+const DEFAULT_RATE_LIMIT_WAIT: Duration = Duration::from_secs(10);
+/// Longer waits than this are reported instead of slept through.
+const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(120);
+
+/// API tokens expiring within this many days are warned about.
+const EXPIRY_WARNING_DAYS: i64 = 14;
+
+impl CloudflareClient {
+    pub fn new(api_token: String) -> Self {
+        Self::with_auth(Auth::Token(api_token))
+    }
+
+    pub fn with_auth(auth: Auth) -> Self {
+        Self {
+            client: HttpConfig::default().client(),
+            auth,
+            base_url: API_BASE_URL.to_string(),
+            per_page: DEFAULT_PER_PAGE,
+        }
+    }
+
+    /// Sends requests to another server than [`API_BASE_URL`], such as a
+    /// proxy in front of the API or a mock server in tests.
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Sends requests through `client`, for custom timeouts or a proxy.
+    pub fn with_client(mut self, client: Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Sets how many records are requested per page when listing DNS records.
+    pub fn with_per_page(mut self, per_page: u32) -> Self {
+        self.per_page = per_page.max(1);
+        self
+    }
+
+    /// Adds the authentication headers to a request.
+    fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.auth {
+            Auth::Token(token) => request.bearer_auth(token),
+            Auth::GlobalKey { key, email } => request
+                .header("X-Auth-Key", key)
+                .header("X-Auth-Email", email),
+        }
+    }
+
+    /// Sends a request, waiting out HTTP 429 responses as long as Cloudflare
+    /// asks for a reasonable delay. Persistent rate limiting surfaces as a
+    /// [`RateLimitedError`], and 5xx responses as errors for the retry policy.
+    async fn send(&self, request: RequestBuilder) -> Result<Response> {
+        let mut attempt = 0;
+        loop {
+            let this_request = request
+                .try_clone()
+                .ok_or_else(|| Error::Provider("Request body can't be retried".to_string()))?;
+            let response = this_request
+                .send()
+                .await
+                .and_then(error_for_server_status)?;
+
+            if response.status() != StatusCode::TOO_MANY_REQUESTS {
+                return Ok(response);
+            }
+
+            let delay = retry_after(&response);
+            attempt += 1;
+            let wait = delay.unwrap_or(DEFAULT_RATE_LIMIT_WAIT);
+            if attempt > RATE_LIMIT_RETRIES || wait > MAX_RATE_LIMIT_WAIT {
+                return Err(RateLimitedError { retry_after: delay }.into());
+            }
+
+            warn!(
+                "⚠️  Rate limited by Cloudflare, waiting {}s before retrying ({}/{})",
+                wait.as_secs(),
+                attempt,
+                RATE_LIMIT_RETRIES
+            );
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Checks the API token with Cloudflare's token verification endpoint.
+    pub async fn verify_token(&self) -> Result<TokenStatus> {
+        let url = format!("{}/user/tokens/verify", self.base_url);
+
+        let request = self
+            .authorize(self.client.get(&url))
+            .header("Content-Type", "application/json");
+        let response = self.send(request).await?;
+
+        let cf_response: CloudflareResponse<TokenStatus> = response.json().await?;
+
+        if !cf_response.success {
+            return Err(ApiError::new("API token was rejected", cf_response.errors).into());
+        }
+
+        cf_response.result.ok_or_else(|| {
+            Error::Provider("No token details in the verification response".to_string())
+        })
+    }
+
+    /// Returns the email address of the account the credentials belong to.
+    /// Unlike [`CloudflareClient::verify_token`], this works with a Global
+    /// API Key.
+    pub async fn get_user_email(&self) -> Result<String> {
+        let url = format!("{}/user", self.base_url);
+
+        let request = self
+            .authorize(self.client.get(&url))
+            .header("Content-Type", "application/json");
+        let response = self.send(request).await?;
+
+        let cf_response: CloudflareResponse<User> = response.json().await?;
+
+        if !cf_response.success {
+            return Err(ApiError::new("Credentials were rejected", cf_response.errors).into());
+        }
+
+        cf_response
+            .result
+            .map(|user| user.email)
+            .ok_or_else(|| Error::Provider("No user details in the response".to_string()))
+    }
+
+    /// Checks that the DNS records of a zone can be read, by fetching one.
+    pub async fn check_dns_access(&self, zone_id: &str) -> Result<()> {
+        self.get_dns_records_page(zone_id, None, 1, 1).await?;
+        Ok(())
+    }
+
+    /// Looks up the ID of the zone with the given name.
+    pub async fn get_zone_id(&self, zone_name: &str) -> Result<String> {
+        let url = format!("{}/zones?name={}", self.base_url, zone_name);
+
+        let request = self
+            .authorize(self.client.get(&url))
+            .header("Content-Type", "application/json");
+        let response = self.send(request).await?;
+
+        let cf_response: CloudflareResponse<Vec<Zone>> = response.json().await?;
+
+        if !cf_response.success {
+            return Err(ApiError::new("Failed to look up zone", cf_response.errors).into());
+        }
+
+        cf_response
+            .result
+            .and_then(|zones| zones.into_iter().next())
+            .map(|zone| zone.id)
+            .ok_or_else(|| Error::Provider(format!("No zone found with name '{}'", zone_name)))
+    }
+
+    /// Lists every zone the credentials can access, sorted by name.
+    pub async fn list_zones(&self) -> Result<Vec<Zone>> {
+        let url = format!("{}/zones", self.base_url);
+        let mut zones = Vec::new();
+        let mut page = 1;
+
+        loop {
+            let request = self
+                .authorize(self.client.get(&url))
+                .query(&[("page", page.to_string()), ("per_page", "50".to_string())])
+                .header("Content-Type", "application/json");
+            let response = self.send(request).await?;
+
+            let cf_response: CloudflareResponse<Vec<Zone>> = response.json().await?;
+
+            if !cf_response.success {
+                return Err(ApiError::new("Failed to list zones", cf_response.errors).into());
+            }
+
+            let mut page_zones = cf_response.result.unwrap_or_default();
+            let received = page_zones.len();
+            zones.append(&mut page_zones);
+
+            match cf_response.result_info {
+                Some(info) if info.page < info.total_pages && received > 0 => page = info.page + 1,
+                _ => break,
+            }
+        }
+
+        zones.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(zones)
+    }
+
+    /// Lists DNS records in a zone, optionally only those with the given name.
+    /// Follows pagination until every page has been read.
+    pub async fn get_dns_records(
+        &self,
+        zone_id: &str,
+        record_name: Option<&str>,
+    ) -> Result<Vec<DnsRecord>> {
+        let mut records = Vec::new();
+        let mut page = 1;
+
+        loop {
+            let (mut page_records, info) = self
+                .get_dns_records_page(zone_id, record_name, page, self.per_page)
+                .await?;
+            let received = page_records.len();
+            records.append(&mut page_records);
+
+            // Without pagination info (or on an empty page) there is nothing more to read
+            match info {
+                Some(info) if info.page < info.total_pages && received > 0 => page = info.page + 1,
+                _ => break,
+            }
+        }
+
+        Ok(records)
+    }
+
+    async fn get_dns_records_page(
+        &self,
+        zone_id: &str,
+        record_name: Option<&str>,
+        page: u32,
+        per_page: u32,
+    ) -> Result<(Vec<DnsRecord>, Option<ResultInfo>)> {
+        let url = format!("{}/zones/{}/dns_records", self.base_url, zone_id);
+        let mut query = vec![
+            ("page", page.to_string()),
+            ("per_page", per_page.to_string()),
+        ];
+        // Names are matched exactly, so wildcard records like `*.example.com` are found as-is
+        if let Some(name) = record_name {
+            query.push(("name", name.to_string()));
+        }
+
+        let request = self
+            .authorize(self.client.get(&url))
+            .query(&query)
+            .header("Content-Type", "application/json");
+        let response = self.send(request).await?;
+
+        let cf_response: CloudflareResponse<Vec<DnsRecord>> = response.json().await?;
+
+        if !cf_response.success {
+            return Err(ApiError::new("Cloudflare API error", cf_response.errors).into());
+        }
+
+        // Log any messages from Cloudflare
+        if !cf_response.messages.is_empty() {
+            info!("📝 Cloudflare messages: {:?}", cf_response.messages);
+        }
+
+        let records = cf_response
+            .result
+            .ok_or_else(|| Error::Provider("No result in response".to_string()))?;
+
+        Ok((records, cf_response.result_info))
+    }
+
+    /// Replaces the record with the given ID.
+    pub async fn update_dns_record(
+        &self,
+        zone_id: &str,
+        record_id: &str,
+        update_data: &UpdateDnsRecord,
+    ) -> Result<()> {
+        let url = format!(
+            "{}/zones/{}/dns_records/{}",
+            self.base_url, zone_id, record_id
+        );
+
+        let request = self
+            .authorize(self.client.put(&url))
+            .header("Content-Type", "application/json")
+            .json(update_data);
+        let response = self.send(request).await?;
+
+        let cf_response: CloudflareResponse<DnsRecord> = response.json().await?;
+
+        if !cf_response.success {
+            return Err(ApiError::new("Failed to update DNS record", cf_response.errors).into());
+        }
+
+        // Log any messages from Cloudflare
+        if !cf_response.messages.is_empty() {
+            info!("📝 Cloudflare messages: {:?}", cf_response.messages);
+        }
+
+        Ok(())
+    }
+
+    /// Replaces several records of a zone in a single request. Cloudflare
+    /// applies the batch atomically: either every record is updated or none is.
+    pub async fn batch_update_dns_records(
+        &self,
+        zone_id: &str,
+        updates: &[(&str, &UpdateDnsRecord)],
+    ) -> Result<()> {
+        let url = format!("{}/zones/{}/dns_records/batch", self.base_url, zone_id);
+        let body = BatchRequest {
+            puts: updates
+                .iter()
+                .map(|(id, record)| BatchPut { id, record })
+                .collect(),
+        };
+
+        let request = self
+            .authorize(self.client.post(&url))
+            .header("Content-Type", "application/json")
+            .json(&body);
+        let response = self.send(request).await?;
+
+        let cf_response: CloudflareResponse<serde_json::Value> = response.json().await?;
+
+        if !cf_response.success {
+            return Err(
+                ApiError::new("Failed to batch update DNS records", cf_response.errors).into(),
+            );
+        }
+
+        // Log any messages from Cloudflare
+        if !cf_response.messages.is_empty() {
+            info!("📝 Cloudflare messages: {:?}", cf_response.messages);
+        }
+
+        Ok(())
+    }
+
+    /// Creates a new record and returns it.
+    pub async fn create_dns_record(
+        &self,
+        zone_id: &str,
+        record_data: UpdateDnsRecord,
+    ) -> Result<DnsRecord> {
+        let url = format!("{}/zones/{}/dns_records", self.base_url, zone_id);
+
+        let request = self
+            .authorize(self.client.post(&url))
+            .header("Content-Type", "application/json")
+            .json(&record_data);
+        let response = self.send(request).await?;
+
+        let cf_response: CloudflareResponse<DnsRecord> = response.json().await?;
+
+        if !cf_response.success {
+            return Err(ApiError::new("Failed to create DNS record", cf_response.errors).into());
+        }
+
+        // Log any messages from Cloudflare
+        if !cf_response.messages.is_empty() {
+            info!("📝 Cloudflare messages: {:?}", cf_response.messages);
+        }
+
+        cf_response
+            .result
+            .ok_or_else(|| Error::Provider("No result in response".to_string()))
+    }
+
+    /// Deletes the record with the given ID.
+    pub async fn delete_dns_record(&self, zone_id: &str, record_id: &str) -> Result<()> {
+        let url = format!(
+            "{}/zones/{}/dns_records/{}",
+            self.base_url, zone_id, record_id
+        );
+
+        let request = self.authorize(self.client.delete(&url));
+        let response = self.send(request).await?;
+
+        let cf_response: CloudflareResponse<serde_json::Value> = response.json().await?;
+
+        if !cf_response.success {
+            return Err(ApiError::new("Failed to delete DNS record", cf_response.errors).into());
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DnsProvider for CloudflareClient {
+    async fn check_credentials(&self) -> Result<()> {
+        match &self.auth {
+            Auth::Token(_) => {
+                let token = self
+                    .verify_token()
+                    .await
+                    .context("Cloudflare doesn't accept the API token")?;
+                if token.status != "active" {
+                    return Err(Error::Provider(format!(
+                        "The API token is {}, create a new one at https://dash.cloudflare.com/profile/api-tokens",
+                        token.status
+                    )));
+                }
+                if let Some(expires_on) = token.expires_on
+                    && expires_on - Utc::now() < chrono::Duration::days(EXPIRY_WARNING_DAYS)
+                {
+                    warn!(
+                        "⚠️  The API token expires on {}",
+                        expires_on.format("%Y-%m-%d %H:%M UTC")
+                    );
+                }
+                info!("🔐 API token is active");
+            }
+            Auth::GlobalKey { .. } => {
+                let email = self
+                    .get_user_email()
+                    .await
+                    .context("Cloudflare doesn't accept the Global API Key and email")?;
+                info!("🔐 Global API Key accepted for {}", email);
+            }
+        }
+        Ok(())
+    }
+
+    async fn get_zone_id(&self, zone_name: &str) -> Result<String> {
+        CloudflareClient::get_zone_id(self, zone_name).await
+    }
+
+    async fn get_records(
+        &self,
+        zone_id: &str,
+        record_name: Option<&str>,
+    ) -> Result<Vec<DnsRecord>> {
+        self.get_dns_records(zone_id, record_name).await
+    }
+
+    async fn check_zone_access(&self, zone_id: &str) -> Result<()> {
+        self.check_dns_access(zone_id).await
+    }
+
+    async fn update_record(
+        &self,
+        zone_id: &str,
+        record_id: &str,
+        record: &UpdateDnsRecord,
+    ) -> Result<()> {
+        self.update_dns_record(zone_id, record_id, record).await
+    }
+
+    async fn update_records(
+        &self,
+        zone_id: &str,
+        updates: &[(&str, &UpdateDnsRecord)],
+    ) -> Result<()> {
+        self.batch_update_dns_records(zone_id, updates).await
+    }
+
+    async fn create_record(&self, zone_id: &str, record: UpdateDnsRecord) -> Result<DnsRecord> {
+        self.create_dns_record(zone_id, record).await
+    }
+
+    async fn delete_record(&self, zone_id: &str, record_id: &str) -> Result<()> {
+        self.delete_dns_record(zone_id, record_id).await
+    }
+}
+
+/// The kinds of API errors that have a known cause and remedy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiErrorKind {
+    /// Code 9109 (1000 from token verification, 9103 for a Global API Key):
+    /// the credentials are wrong.
+    InvalidCredentials,
+    /// Code 10000: the credentials work but lack a permission.
+    AuthenticationError,
+    /// Code 7003: the zone ID doesn't name a zone.
+    ZoneNotFound,
+    /// Code 81044: the record was deleted.
+    RecordNotFound,
+    /// Code 9021: the zone's plan doesn't allow the TTL.
+    InvalidTtl,
+    /// Code 9041: the record's content can't be proxied.
+    NotProxiable,
+    Other,
+}
+
+impl ApiErrorKind {
+    fn from_code(code: u32) -> Self {
+        match code {
+            1000 | 9103 | 9109 => Self::InvalidCredentials,
+            10000 => Self::AuthenticationError,
+            7003 => Self::ZoneNotFound,
+            81044 => Self::RecordNotFound,
+            9021 => Self::InvalidTtl,
+            9041 => Self::NotProxiable,
+            _ => Self::Other,
+        }
+    }
+
+    /// What to do about the error.
+    fn hint(self) -> Option<&'static str> {
+        match self {
+            Self::InvalidCredentials => Some(
+                "Check CLOUDFLARE_API_TOKEN (or CLOUDFLARE_API_KEY and CLOUDFLARE_EMAIL), or create a new token at https://dash.cloudflare.com/profile/api-tokens",
+            ),
+            Self::AuthenticationError => Some(
+                "The token needs the Zone:Zone:Read and Zone:DNS:Edit permissions for this zone, check its zone resources",
+            ),
+            Self::ZoneNotFound => Some(
+                "Check the zone ID, it is shown on the zone's Overview page and isn't the account ID, or give the zone name instead",
+            ),
+            Self::RecordNotFound => Some(
+                "The record was deleted in Cloudflare while it was being updated, it is looked up again on the next run",
+            ),
+            Self::InvalidTtl => Some(
+                "The zone's plan doesn't allow this TTL, use at least 60 seconds (30 on Enterprise plans) or \"auto\"",
+            ),
+            Self::NotProxiable => Some(
+                "Cloudflare can't proxy this record, set `proxied` to false or leave it unset to keep the record's setting",
+            ),
+            Self::Other => None,
+        }
+    }
+}
+
+/// A request that Cloudflare answered with `success: false`.
+#[derive(Debug)]
+pub struct ApiError {
+    /// What was being done, such as "Failed to update DNS record".
+    action: &'static str,
+    pub errors: Vec<CloudflareError>,
+}
+
+impl ApiError {
+    fn new(action: &'static str, errors: Vec<CloudflareError>) -> Self {
+        Self { action, errors }
+    }
+
+    /// The first known kind among the error codes.
+    pub fn kind(&self) -> ApiErrorKind {
+        self.errors
+            .iter()
+            .map(|e| ApiErrorKind::from_code(e.code))
+            .find(|kind| *kind != ApiErrorKind::Other)
+            .unwrap_or(ApiErrorKind::Other)
+    }
+
+    /// The code of the first error Cloudflare returned.
+    pub fn code(&self) -> Option<u32> {
+        self.errors.first().map(|e| e.code)
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let details = self
+            .errors
+            .iter()
+            .map(|e| format!("Code {}: {}", e.code, e.message.trim_end_matches('.')))
+            .collect::<Vec<_>>()
+            .join(", ");
+        write!(f, "{}: {}", self.action, details)?;
+        if let Some(hint) = self.kind().hint() {
+            write!(f, ". {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// Returned when Cloudflare keeps answering with HTTP 429 after waiting for
+/// the rate limit to reset.
+#[derive(Debug)]
+pub struct RateLimitedError {
+    /// Delay Cloudflare asked for in its last `Retry-After` header.
+    pub retry_after: Option<Duration>,
+}
+
+impl fmt::Display for RateLimitedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.retry_after {
+            Some(delay) => write!(
+                f,
+                "Cloudflare API rate limit exceeded, retry after {}s",
+                delay.as_secs()
+            ),
+            None => write!(f, "Cloudflare API rate limit exceeded"),
+        }
+    }
+}
+
+impl std::error::Error for RateLimitedError {}
+
+/// Parses a `Retry-After` header given in seconds. (Cloudflare doesn't send
+/// the HTTP-date form.)
+fn retry_after(response: &Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+/// Turns 5xx responses into errors so they can be retried. Other error
+/// statuses are left alone because Cloudflare explains them in the body.
+fn error_for_server_status(response: Response) -> reqwest::Result<Response> {
+    if response.status().is_server_error() {
+        response.error_for_status()
+    } else {
+        Ok(response)
+    }
+}
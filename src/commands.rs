@@ -0,0 +1,364 @@
+//! Implementations of the informational subcommands.
+
+use crate::cli::{AuthCommand, HistoryArgs, ListRecordsArgs, SystemdUnitArgs};
+use crate::logging::say;
+use anyhow::{Result, anyhow};
+use cloudflare_ddns::cache::{self, CacheFile};
+use cloudflare_ddns::config::{Config, ZoneRef};
+use cloudflare_ddns::credentials;
+use cloudflare_ddns::history::History;
+use cloudflare_ddns::ip::{IpDetector, IpSource, IpVersion, get_public_ip};
+use cloudflare_ddns::preflight;
+use cloudflare_ddns::provider::DnsProvider;
+use cloudflare_ddns::server::HealthFile;
+use cloudflare_ddns::systemd;
+use cloudflare_ddns::updater::{managed_records, resolve_zone_id};
+use std::collections::HashMap;
+use std::io::{self, BufRead, IsTerminal};
+use std::net::IpAddr;
+use std::path::Path;
+use tracing::info;
+
+/// Prints the cached state of every record next to its live Cloudflare record
+/// and the detected public IP, pointing out where they disagree.
+pub async fn show_status(
+    provider: &dyn DnsProvider,
+    cache: &mut CacheFile,
+    config: &Config,
+) -> Result<()> {
+    let records = managed_records(provider, cache, config).await?;
+
+    // Records sharing a source and version share the detected address
+    let mut public_ips: HashMap<(IpSource, IpVersion), Option<IpAddr>> = HashMap::new();
+    let mut out_of_sync = 0;
+
+    for record in &records {
+        say!("📌 {} ({})", record.name, record.record_type);
+
+        let version = IpVersion::for_record_type(&record.record_type);
+        let key = (record.ip_source.clone(), version);
+        if !public_ips.contains_key(&key) {
+            let detector = IpDetector {
+                source: record.ip_source.clone(),
+                ..config.ip.clone()
+            };
+            let public_ip = match get_public_ip(&detector, version).await {
+                Ok(ip) => Some(ip),
+                Err(e) => {
+                    println!("   Public {}: failed to detect ({})", version.label(), e);
+                    None
+                }
+            };
+            public_ips.insert(key.clone(), public_ip);
+        }
+        let public_ip = public_ips[&key].map(|detected| {
+            let address = record.address_for(detected);
+            if address == detected {
+                println!("   Public {}: {}", version.label(), address);
+            } else {
+                println!(
+                    "   Public {}: {} (prefix of {})",
+                    version.label(),
+                    address,
+                    detected
+                );
+            }
+            address.to_string()
+        });
+        let public_ip = public_ip.as_deref();
+
+        let cached_data = cache.find(&record.name, &record.record_type);
+        match cached_data {
+            Some(cached_data) => {
+                println!("   Cached IP: {}", cached_data.ip_address);
+                println!(
+                    "   Last checked: {}",
+                    cached_data.last_checked.format("%Y-%m-%d %H:%M:%S UTC")
+                );
+                println!(
+                    "   Last updated: {}",
+                    cached_data.last_updated.format("%Y-%m-%d %H:%M:%S UTC")
+                );
+                if cached_data.is_expired(record.cache_expiry_hours) {
+                    println!("   Cache entry is expired");
+                }
+            }
+            None => println!("   Not cached"),
+        }
+        let cached_ip = cached_data.map(|c| c.ip_address.clone());
+
+        // Update-only providers can't be asked for the record
+        let Some(zone) = &record.zone else {
+            println!("   {}: can't be read back, only updated", record.provider);
+            if let (Some(public_ip), Some(cached_ip)) = (public_ip, &cached_ip)
+                && public_ip != cached_ip
+            {
+                say!(
+                    "   ⚠️  Last sent {} but the public IP is {}",
+                    cached_ip,
+                    public_ip
+                );
+                out_of_sync += 1;
+            }
+            continue;
+        };
+        let live_record = match resolve_zone_id(provider, cache, config, zone).await {
+            Ok(zone_id) => provider
+                .get_records(&zone_id, Some(&record.name))
+                .await
+                .map(|records| {
+                    records
+                        .into_iter()
+                        .find(|r| r.record_type == record.record_type)
+                }),
+            Err(e) => Err(e),
+        };
+
+        let live = match live_record {
+            Ok(Some(live)) => {
+                println!(
+                    "   Cloudflare: {} (TTL: {}, proxied: {})",
+                    live.content, live.ttl, live.proxied
+                );
+                live
+            }
+            Ok(None) => {
+                println!("   Cloudflare: record not found");
+                continue;
+            }
+            Err(e) => {
+                println!("   Cloudflare: failed to fetch record ({})", e);
+                continue;
+            }
+        };
+
+        let mut in_sync = true;
+        if let Some(public_ip) = public_ip
+            && live.content != public_ip
+        {
+            say!(
+                "   ⚠️  Cloudflare has {} but the public IP is {}",
+                live.content,
+                public_ip
+            );
+            in_sync = false;
+        }
+        if let Some(cached_ip) = &cached_ip
+            && live.content != *cached_ip
+        {
+            say!(
+                "   ⚠️  Cloudflare has {} but the cache has {}",
+                live.content,
+                cached_ip
+            );
+            in_sync = false;
+        }
+        if !in_sync {
+            out_of_sync += 1;
+        }
+    }
+
+    if out_of_sync == 0 {
+        say!("✅ Every record that could be checked matches");
+    } else {
+        say!(
+            "⚠️  {} record(s) out of sync, `run --force` updates them regardless of the cache",
+            out_of_sync
+        );
+    }
+
+    Ok(())
+}
+
+/// Prints every DNS record in the zones used by the configured records.
+pub async fn list_records(
+    args: &ListRecordsArgs,
+    provider: &dyn DnsProvider,
+    cache: &mut CacheFile,
+    config: &Config,
+) -> Result<()> {
+    let zones = match &args.zone {
+        Some(zone) => vec![ZoneRef::from_name_or_id(zone)],
+        None => config.zones().into_iter().cloned().collect(),
+    };
+
+    for zone in &zones {
+        let zone_id = resolve_zone_id(provider, cache, config, zone).await?;
+        let records = provider.get_records(&zone_id, None).await?;
+
+        match zone {
+            ZoneRef::Name(name) => {
+                say!("🗂️  Zone {} ({}, {} records)", name, zone_id, records.len())
+            }
+            ZoneRef::Id(_) => say!("🗂️  Zone {} ({} records)", zone_id, records.len()),
+        }
+        for record in records {
+            println!(
+                "   {}  {:<6} {:<40} {} (TTL: {}, proxied: {})",
+                record.id,
+                record.record_type,
+                record.name,
+                record.content,
+                record.ttl,
+                record.proxied
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs the startup checks on their own.
+pub async fn verify(
+    provider: &dyn DnsProvider,
+    cache: &mut CacheFile,
+    config: &Config,
+) -> Result<()> {
+    preflight::verify(provider, cache, config).await?;
+    info!("✅ The credentials work for every configured zone");
+    Ok(())
+}
+
+/// Prints the latest address changes, as a JSON array with `json`.
+pub fn history(args: &HistoryArgs, config: &Config, json: bool) -> Result<()> {
+    let path = config.history_path.as_ref().ok_or_else(|| {
+        anyhow!("No history is kept, set HISTORY_PATH (or `history_path` in the config file)")
+    })?;
+    if !path.exists() {
+        return Err(anyhow!("No history database at {}", path.display()));
+    }
+
+    let entries = History::open(path)?.query(args.record.as_deref(), args.limit)?;
+    if json {
+        println!("{}", serde_json::to_string(&entries)?);
+        return Ok(());
+    }
+
+    if entries.is_empty() {
+        say!("🕘 No changes recorded yet");
+    }
+    for entry in entries {
+        say!(
+            "🕘 {}  {} ({})  {} -> {}  [{}]",
+            entry.time.format("%Y-%m-%d %H:%M:%S UTC"),
+            entry.record_name,
+            entry.record_type,
+            entry.old_ip.as_deref().unwrap_or("?"),
+            entry.new_ip,
+            entry.trigger
+        );
+    }
+    Ok(())
+}
+
+/// Reports every problem with the configuration and the credentials, and
+/// fails if there was any. Settings that don't load at all have already
+/// failed by the time this runs.
+pub async fn validate(
+    provider: &dyn DnsProvider,
+    cache: &mut CacheFile,
+    config: &Config,
+) -> Result<()> {
+    let mut problems = config.problems();
+    if let Err(e) = preflight::verify(provider, cache, config).await {
+        problems.push(e.report().to_string());
+    }
+
+    if problems.is_empty() {
+        say!(
+            "✅ The configuration of {} record(s) is valid and the credentials work",
+            config.records.len()
+        );
+        return Ok(());
+    }
+    for problem in &problems {
+        say!("❌ {}", problem);
+    }
+    Err(anyhow!("Found {} problem(s)", problems.len()))
+}
+
+pub fn clear_cache(cache_path: &Path) -> Result<()> {
+    if cache::clear_cache(cache_path)? {
+        info!("🗑️  Removed cache file {}", cache_path.display());
+    } else {
+        info!(
+            "📄 No cache file at {}, nothing to clear",
+            cache_path.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Fails unless the daemon using the cache at `cache_path` finished a
+/// successful cycle recently enough.
+pub fn healthcheck(cache_path: &Path) -> Result<()> {
+    let path = HealthFile::path(cache_path);
+    let health = HealthFile::read(&path)?;
+    let finished = health.last_cycle.format("%Y-%m-%d %H:%M:%S UTC");
+    if !health.last_cycle_succeeded {
+        return Err(anyhow!("The last update cycle, at {}, failed", finished));
+    }
+    if !health.is_healthy() {
+        return Err(anyhow!(
+            "The last update cycle finished at {}, more than {} seconds ago",
+            finished,
+            health.max_age
+        ));
+    }
+    say!("💚 Healthy, the last update cycle finished at {}", finished);
+    Ok(())
+}
+
+/// Prints a systemd unit for the daemon. The config file, if one was given,
+/// is passed on to the service.
+pub fn systemd_unit(args: &SystemdUnitArgs, config_path: Option<&Path>) -> Result<()> {
+    let binary = match &args.binary {
+        Some(binary) => binary.clone(),
+        None => std::env::current_exe()?,
+    };
+    // The service doesn't run in the current directory
+    let config_path = config_path.map(|path| path.canonicalize().unwrap_or(path.to_path_buf()));
+    print!(
+        "{}",
+        systemd::unit_file(&binary, &args.env_file, config_path.as_deref())
+    );
+    Ok(())
+}
+
+/// Asks for an API token, without echo from a terminal or as a line from a
+/// pipe.
+pub fn read_token() -> Result<String> {
+    let token = if io::stdin().is_terminal() {
+        rpassword::prompt_password("Cloudflare API token: ")?
+    } else {
+        let mut line = String::new();
+        io::stdin().lock().read_line(&mut line)?;
+        line
+    };
+    let token = token.trim();
+    if token.is_empty() {
+        return Err(anyhow!("No API token entered"));
+    }
+    Ok(token.to_string())
+}
+
+/// Saves the API token in the OS keyring, or removes it.
+pub fn auth(command: &AuthCommand) -> Result<()> {
+    match command {
+        AuthCommand::Login => {
+            let token = read_token()?;
+            credentials::store_token(&token)?;
+            say!("🔑 Saved the API token in the OS keyring");
+        }
+        AuthCommand::Logout => {
+            if credentials::delete_token()? {
+                say!("🗑️  Removed the API token from the OS keyring");
+            } else {
+                say!("🔑 No API token saved in the OS keyring");
+            }
+        }
+    }
+
+    Ok(())
+}
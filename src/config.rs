@@ -0,0 +1,1816 @@
+use crate::apprise;
+use crate::cache;
+use crate::cloudflare::Auth;
+use crate::credentials;
+use crate::dyndns::Login;
+use crate::email::{EmailConfig, SmtpTls};
+use crate::error::{Error, Result};
+use crate::failover::Failover;
+use crate::hooks::Hooks;
+use crate::http::{self, HttpConfig};
+use crate::ip::{IpDetector, IpService, IpSource, Ipv6Suffix, ScrapePage};
+use crate::leader::LeaderLock;
+use crate::mqtt::{self, MqttConfig};
+use crate::ntfy::{self, NtfyConfig};
+use crate::propagation::PropagationCheck;
+use crate::provider::Provider;
+use crate::retry::RetryPolicy;
+use crate::rfc2136::{Rfc2136Config, TsigAlgorithm, TsigKey};
+use crate::telegram::{
+    DEFAULT_FAILURE_TEMPLATE, DEFAULT_MESSAGE_TEMPLATE, DEFAULT_RECOVERED_TEMPLATE, TelegramConfig,
+};
+use regex::Regex;
+use serde::Deserialize;
+use std::fmt;
+use std::fs;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Settings as they appear in the optional TOML configuration file.
+///
+/// Every field is optional so that a file can provide only part of the
+/// configuration and leave the rest to environment variables.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    provider: Option<Provider>,
+    api_token: Option<String>,
+    /// Global API Key, used with `email` instead of `api_token`.
+    api_key: Option<String>,
+    email: Option<String>,
+    cloudflare_api_url: Option<String>,
+    duckdns: Option<FileDuckDns>,
+    dynu: Option<FileLogin>,
+    noip: Option<FileLogin>,
+    rfc2136: Option<FileRfc2136>,
+    zone_id: Option<String>,
+    zone_name: Option<String>,
+    record_type: Option<RecordTypes>,
+    ttl: Option<TtlValue>,
+    proxied: Option<bool>,
+    cache_expiry_hours: Option<i64>,
+    min_update_interval: Option<u64>,
+    flap_window: Option<u64>,
+    flap_confirmation: Option<u64>,
+    reconcile_every: Option<u32>,
+    dual_stack: Option<bool>,
+    create_if_missing: Option<bool>,
+    duplicate_records: Option<DuplicatePolicy>,
+    record_comment: Option<String>,
+    #[serde(default)]
+    record_tags: Vec<String>,
+    verify_propagation: Option<bool>,
+    verify_server: Option<String>,
+    verify_timeout: Option<u64>,
+    run_mode: Option<RunMode>,
+    sleep_interval: Option<u64>,
+    max_sleep_interval: Option<u64>,
+    start_jitter: Option<u64>,
+    watch_network: Option<bool>,
+    watch_interface: Option<String>,
+    watch_config: Option<bool>,
+    paused: Option<bool>,
+    pause_file: Option<PathBuf>,
+    retry: Option<RetryPolicy>,
+    webhook_url: Option<String>,
+    discord_webhook_url: Option<String>,
+    slack_webhook_url: Option<String>,
+    telegram: Option<FileTelegram>,
+    smtp: Option<FileSmtp>,
+    ntfy: Option<FileNtfy>,
+    gotify: Option<FileGotify>,
+    mqtt: Option<FileMqtt>,
+    #[serde(default)]
+    notify_urls: Vec<String>,
+    apprise_api_url: Option<String>,
+    failure_threshold: Option<u32>,
+    hooks: Option<FileHooks>,
+    ip_source: Option<String>,
+    ipv6_prefix_length: Option<u8>,
+    ipv6_suffix: Option<Ipv6Addr>,
+    #[serde(default)]
+    ip_services: Vec<IpService>,
+    ip_consensus: Option<usize>,
+    confirm_ip_source: Option<String>,
+    ip_scrape: Option<FileScrape>,
+    allow_private_ip: Option<bool>,
+    http: Option<FileHttp>,
+    http_listen: Option<SocketAddr>,
+    dyndns_server: Option<FileLogin>,
+    trigger_token: Option<String>,
+    admin_token: Option<String>,
+    state_path: Option<PathBuf>,
+    history_path: Option<PathBuf>,
+    health_max_age: Option<u64>,
+    leader_lock: Option<String>,
+    leader_lease: Option<u64>,
+    discovery_marker: Option<String>,
+    prune_records: Option<bool>,
+    #[serde(default)]
+    records: Vec<FileRecord>,
+}
+
+/// A `[[records]]` entry in the configuration file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileRecord {
+    name: String,
+    #[serde(rename = "type")]
+    record_type: Option<RecordTypes>,
+    zone_id: Option<String>,
+    zone_name: Option<String>,
+    proxied: Option<bool>,
+    provider: Option<Provider>,
+    ttl: Option<TtlValue>,
+    ip_source: Option<String>,
+    /// Sources that each contribute one record of the name and type.
+    ip_sources: Option<Vec<String>>,
+    /// Fixed address for the record, the same as `ip_source = "static:..."`.
+    content: Option<IpAddr>,
+    /// Content of a TXT, CNAME or other record that isn't an address.
+    template: Option<String>,
+    failover: Option<FileFailover>,
+    cache_expiry_hours: Option<i64>,
+    min_update_interval: Option<u64>,
+    ipv6_suffix: Option<Ipv6Addr>,
+}
+
+/// The `failover` table of a record in the configuration file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileFailover {
+    check: String,
+    backup: IpAddr,
+    failures: Option<u32>,
+}
+
+/// The `[http]` table of the configuration file, with times in seconds.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileHttp {
+    connect_timeout: Option<u64>,
+    timeout: Option<u64>,
+    keep_alive: Option<u64>,
+    max_redirects: Option<usize>,
+    proxy_url: Option<String>,
+}
+
+/// The `[ip_scrape]` table of the configuration file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileScrape {
+    url: Option<String>,
+    json_pointer: Option<String>,
+    regex: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+/// The `[telegram]` table of the configuration file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileTelegram {
+    bot_token: Option<String>,
+    chat_id: Option<String>,
+    message_template: Option<String>,
+    failure_template: Option<String>,
+    recovered_template: Option<String>,
+    failure_threshold: Option<u32>,
+}
+
+/// The `[smtp]` table of the configuration file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileSmtp {
+    host: Option<String>,
+    port: Option<u16>,
+    tls: Option<SmtpTls>,
+    username: Option<String>,
+    password: Option<String>,
+    from: Option<String>,
+    #[serde(default)]
+    to: Vec<String>,
+}
+
+/// The `[ntfy]` table of the configuration file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileNtfy {
+    server: Option<String>,
+    topic: Option<String>,
+    token: Option<String>,
+}
+
+/// The `[mqtt]` table of the configuration file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileMqtt {
+    url: Option<String>,
+    topic: Option<String>,
+    discovery_prefix: Option<String>,
+}
+
+/// The `[gotify]` table of the configuration file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileGotify {
+    url: Option<String>,
+    token: Option<String>,
+}
+
+/// The `[hooks]` table of the configuration file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileHooks {
+    on_change: Option<String>,
+    on_failure: Option<String>,
+    on_success: Option<String>,
+}
+
+/// The `[duckdns]` table of the configuration file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileDuckDns {
+    token: Option<String>,
+}
+
+/// The `[dynu]`, `[noip]` and `[dyndns_server]` tables of the configuration file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileLogin {
+    username: Option<String>,
+    password: Option<String>,
+}
+
+/// The `[rfc2136]` table of the configuration file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileRfc2136 {
+    server: Option<String>,
+    zone: Option<String>,
+    ttl: Option<u32>,
+    key_name: Option<String>,
+    key_algorithm: Option<String>,
+    key_secret: Option<String>,
+}
+
+/// A record type, or a list of them to manage several records with the same name.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum RecordTypes {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl RecordTypes {
+    fn into_vec(self) -> Vec<String> {
+        match self {
+            Self::One(record_type) => vec![record_type],
+            Self::Many(record_types) => record_types,
+        }
+    }
+}
+
+/// A TTL in the configuration file: a number of seconds, `"auto"` or `"preserve"`.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum TtlValue {
+    Seconds(u32),
+    Named(String),
+}
+
+impl TtlValue {
+    fn into_ttl(self) -> Result<Ttl> {
+        match self {
+            Self::Seconds(1) => Ok(Ttl::Auto),
+            Self::Seconds(ttl) => Ok(Ttl::Seconds(ttl)),
+            Self::Named(name) => name.parse(),
+        }
+    }
+}
+
+impl FileConfig {
+    fn read(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path).map_err(|e| {
+            Error::Config(format!(
+                "Failed to read config file {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+
+        // Home Assistant add-on options are JSON
+        let parsed = if path
+            .extension()
+            .is_some_and(|extension| extension == "json")
+        {
+            serde_json::from_str(&content).map_err(|e| e.to_string())
+        } else {
+            toml::from_str(&content).map_err(|e| e.to_string())
+        };
+        parsed.map_err(|e| {
+            Error::Config(format!(
+                "Failed to parse config file {}: {}",
+                path.display(),
+                e
+            ))
+        })
+    }
+}
+
+/// The Cloudflare zone a record belongs to, either by ID or by name.
+/// Names are resolved to IDs through the API and cached.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ZoneRef {
+    Id(String),
+    Name(String),
+}
+
+impl ZoneRef {
+    fn from_parts(id: Option<String>, name: Option<String>) -> Option<Self> {
+        id.map(Self::Id).or(name.map(Self::Name))
+    }
+
+    /// A zone given on the command line: 32 hex digits are a zone ID,
+    /// anything else is a zone name.
+    pub fn from_name_or_id(value: &str) -> Self {
+        if value.len() == 32 && value.chars().all(|c| c.is_ascii_hexdigit()) {
+            Self::Id(value.to_string())
+        } else {
+            Self::Name(value.to_string())
+        }
+    }
+}
+
+impl fmt::Display for ZoneRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Id(id) => write!(f, "{}", id),
+            Self::Name(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// A DNS record managed by the updater.
+#[derive(Debug, Clone)]
+pub struct RecordConfig {
+    pub name: String,
+    pub record_type: String,
+    /// The service hosting the record.
+    pub provider: Provider,
+    /// The Cloudflare zone of the record, `None` on update-only providers.
+    pub zone: Option<ZoneRef>,
+    /// Whether Cloudflare should proxy the record. `None` keeps the
+    /// existing record's setting.
+    pub proxied: Option<bool>,
+    pub ttl: Ttl,
+    /// Where the record's address is detected.
+    pub ip_source: IpSource,
+    /// Sources whose addresses the name points at together, one record per
+    /// address. Empty for a record of a single address from `ip_source`.
+    pub ip_sources: Vec<IpSource>,
+    /// Content of a record that isn't an address, with `{ip}` and
+    /// `{timestamp}` placeholders. `None` puts the address itself.
+    pub template: Option<String>,
+    /// Backup address used while health checks of the detected one fail.
+    pub failover: Option<Failover>,
+    pub cache_expiry_hours: i64,
+    /// Seconds that have to pass after the record was written before a new
+    /// address is written, 0 for no limit. Keeps flapping address detection
+    /// from rewriting it over and over.
+    pub min_update_interval: u64,
+    /// Host suffix that AAAA records put behind the detected prefix.
+    pub ipv6_suffix: Option<Ipv6Suffix>,
+}
+
+impl RecordConfig {
+    /// The content the record should have when its address is `ip`: the
+    /// address itself, or the template with `{ip}` filled in. `{timestamp}`
+    /// is only filled in when the record is written.
+    pub fn content_for(&self, ip: &str) -> String {
+        match &self.template {
+            Some(template) => template.replace("{ip}", ip),
+            None => ip.to_string(),
+        }
+    }
+
+    /// The sources the record's addresses are detected from.
+    pub fn sources(&self) -> &[IpSource] {
+        if self.ip_sources.is_empty() {
+            std::slice::from_ref(&self.ip_source)
+        } else {
+            &self.ip_sources
+        }
+    }
+
+    /// The address the record should point at when `detected` is the
+    /// public address of its family.
+    pub fn address_for(&self, detected: IpAddr) -> IpAddr {
+        match (detected, self.ipv6_suffix) {
+            (IpAddr::V6(prefix), Some(suffix)) => IpAddr::V6(suffix.apply(prefix)),
+            _ => detected,
+        }
+    }
+}
+
+/// Picks up records in a zone that carry a marker in their Cloudflare comment
+/// or tags, in addition to the configured records.
+#[derive(Debug, Clone)]
+pub struct Discovery {
+    pub marker: String,
+    pub zone: ZoneRef,
+    /// Delete marked records that aren't configured instead of updating them.
+    pub prune: bool,
+}
+
+/// The TTL to give updated records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Ttl {
+    /// Carry over the TTL of the existing record.
+    #[default]
+    Preserve,
+    /// Let Cloudflare pick the TTL (sent as 1).
+    Auto,
+    Seconds(u32),
+}
+
+impl Ttl {
+    /// The TTL to send for a record that currently has `existing`, or `None`
+    /// when creating a record.
+    pub fn resolve(self, existing: Option<u32>) -> u32 {
+        match self {
+            Self::Preserve => existing.unwrap_or(1),
+            Self::Auto => 1,
+            Self::Seconds(ttl) => ttl,
+        }
+    }
+}
+
+impl FromStr for Ttl {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "preserve" => Ok(Self::Preserve),
+            "auto" | "1" => Ok(Self::Auto),
+            other => other.parse().map(Self::Seconds).map_err(|_| {
+                Error::Config(format!(
+                    "Invalid TTL '{}', expected a number of seconds, 'auto' or 'preserve'",
+                    other
+                ))
+            }),
+        }
+    }
+}
+
+/// Whether to run a single update and exit, or keep updating on an interval.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RunMode {
+    Once,
+    Daemon,
+}
+
+impl FromStr for RunMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "once" => Ok(Self::Once),
+            "daemon" => Ok(Self::Daemon),
+            other => Err(Error::Config(format!(
+                "Invalid RUN_MODE '{}', expected 'once' or 'daemon'",
+                other
+            ))),
+        }
+    }
+}
+
+/// What to do when a zone has several records of the same name and type,
+/// such as the A records of a round-robin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DuplicatePolicy {
+    /// Point every one of them at the address.
+    UpdateAll,
+    /// Only update the first one Cloudflare lists.
+    #[default]
+    UpdateFirst,
+    /// Fail the record, leaving all of them alone.
+    Error,
+}
+
+impl FromStr for DuplicatePolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "update-all" => Ok(Self::UpdateAll),
+            "update-first" => Ok(Self::UpdateFirst),
+            "error" => Ok(Self::Error),
+            other => Err(Error::Config(format!(
+                "Invalid DUPLICATE_RECORDS '{}', expected 'update-all', 'update-first' or 'error'",
+                other
+            ))),
+        }
+    }
+}
+
+/// Fully resolved runtime configuration.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Credentials for the Cloudflare API, only required when a record is
+    /// hosted there.
+    pub auth: Option<Auth>,
+    /// Server the Cloudflare API requests go to instead of the real API.
+    pub cloudflare_api_url: Option<String>,
+    /// DuckDNS account token.
+    pub duckdns_token: Option<String>,
+    pub dynu: Option<Login>,
+    pub noip: Option<Login>,
+    /// DNS server that records on `rfc2136` are sent to.
+    pub rfc2136: Option<Rfc2136Config>,
+    pub records: Vec<RecordConfig>,
+    /// Cloudflare zone of records that don't set their own.
+    pub zone: Option<ZoneRef>,
+    /// TTL of records that don't set their own, and of discovered records.
+    pub ttl: Ttl,
+    /// Cache expiry of records that don't set their own.
+    pub cache_expiry_hours: i64,
+    /// Minimum update interval of records that don't set their own.
+    pub min_update_interval: u64,
+    /// Seconds after an address change during which going back to the
+    /// previous address counts as flapping, 0 to not look for flaps.
+    pub flap_window: u64,
+    /// Seconds a flapping record's new address has to stay detected before
+    /// it is written.
+    pub flap_confirmation: u64,
+    /// Check a record against its provider at least every this many runs,
+    /// even if the cache hasn't expired.
+    pub reconcile_every: Option<u32>,
+    /// Create records that don't exist yet instead of failing.
+    pub create_if_missing: bool,
+    /// How records with more than one match of their name and type are
+    /// updated.
+    pub duplicate_records: DuplicatePolicy,
+    /// Comment written to Cloudflare records when they are created or
+    /// updated, with `{timestamp}` and `{ip}` filled in.
+    pub record_comment: Option<String>,
+    /// Tags added to Cloudflare records next to the ones they already have.
+    pub record_tags: Vec<String>,
+    /// Look up updated records until they resolve to the new address.
+    pub verify: Option<PropagationCheck>,
+    pub run_mode: RunMode,
+    /// Seconds to wait between update cycles in daemon mode.
+    pub sleep_interval: u64,
+    /// Longest wait between cycles when the interval adapts: it doubles after
+    /// every quiet cycle, from `sleep_interval` up to this, and goes back to
+    /// `sleep_interval` after a change or failure.
+    pub max_sleep_interval: Option<u64>,
+    /// Most seconds to wait at random before the first update cycle, so that
+    /// instances started at the same time spread out their requests.
+    pub start_jitter: u64,
+    /// Check right away when the network changes in daemon mode (Linux only).
+    pub watch_network: bool,
+    /// Only count changes of this interface as network changes.
+    pub watch_interface: Option<String>,
+    /// Reload the configuration in daemon mode when `config_file` changes.
+    pub watch_config: bool,
+    /// The file the configuration was loaded from, if any.
+    pub config_file: Option<PathBuf>,
+    /// Skip updates, as during network maintenance.
+    pub paused: bool,
+    /// Skip updates while this file exists.
+    pub pause_file: Option<PathBuf>,
+    /// Retry policy for Cloudflare API calls and IP detection.
+    pub retry: RetryPolicy,
+    /// Look up records and report changes without applying them.
+    pub dry_run: bool,
+    /// Update every record even if the cache or the record itself already
+    /// has the current IP.
+    pub force: bool,
+    /// Print a JSON report of every cycle to stdout.
+    pub json_output: bool,
+    /// URL that receives a JSON POST when a record changes or fails to update.
+    pub webhook_url: Option<String>,
+    /// Discord incoming webhook that is posted to on changes and failures.
+    pub discord_webhook_url: Option<String>,
+    /// Slack incoming webhook that is posted to on changes and failures.
+    pub slack_webhook_url: Option<String>,
+    /// Telegram bot that is messaged on changes and repeated failures.
+    pub telegram: Option<TelegramConfig>,
+    /// SMTP server used to email changes and failures.
+    pub email: Option<EmailConfig>,
+    /// ntfy topic that changes and failures are published to.
+    pub ntfy: Option<NtfyConfig>,
+    /// Gotify server URL and application token for push notifications.
+    pub gotify: Option<(String, String)>,
+    /// MQTT broker that record state is published to.
+    pub mqtt: Option<MqttConfig>,
+    /// Apprise-style notification URLs.
+    pub notify_urls: Vec<String>,
+    /// Apprise API server for notification URLs without native support.
+    pub apprise_api_url: Option<String>,
+    /// Runs in a row a record has to fail before the failure is notified.
+    pub failure_threshold: u32,
+    /// Commands run when records change, fail or succeed.
+    pub hooks: Hooks,
+    /// Where the record cache is stored.
+    pub cache_path: PathBuf,
+    /// SQLite database that every address change is logged to.
+    pub history_path: Option<PathBuf>,
+    /// Where and how the public IP address is detected. Records can replace
+    /// the source with their own.
+    pub ip: IpDetector,
+    /// Second source that has to detect the same address before a record
+    /// is changed to it.
+    pub confirm_ip_source: Option<IpSource>,
+    /// Timeouts and connection settings of every HTTP client.
+    pub http: HttpConfig,
+    /// Address the daemon's HTTP server (health check) listens on.
+    pub http_listen: Option<SocketAddr>,
+    /// Login that routers use to send DynDNS2 updates to `/nic/update` on
+    /// the HTTP server.
+    pub dyndns_server: Option<Login>,
+    /// Bearer token that `/trigger` on the HTTP server requires to start a
+    /// cycle right away.
+    pub trigger_token: Option<String>,
+    /// Bearer token that the `/api` endpoints of the HTTP server require to
+    /// change records and intervals at runtime.
+    pub admin_token: Option<String>,
+    /// Where changes made through `/api` are kept, by default `state.json`
+    /// next to the cache. See [`crate::state`].
+    pub state_path: Option<PathBuf>,
+    /// Seconds after which the last successful cycle no longer counts as healthy.
+    pub health_max_age: u64,
+    /// What replicas of the daemon take turns through, so that only one
+    /// updates the records. See [`crate::leader`].
+    pub leader_lock: Option<LeaderLock>,
+    /// Seconds a TXT leader lease lasts without being renewed.
+    pub leader_lease: u64,
+    /// Records discovered in the default zone on every cycle.
+    pub discovery: Option<Discovery>,
+}
+
+impl Config {
+    /// What pauses updates right now: `PAUSED`, or the pause file when it
+    /// exists. The file is looked at on every call.
+    pub fn paused_by(&self) -> Option<String> {
+        if self.paused {
+            return Some("PAUSED".to_string());
+        }
+        self.pause_file
+            .as_ref()
+            .filter(|path| path.exists())
+            .map(|path| path.display().to_string())
+    }
+
+    /// Settings that load fine but won't work as intended, described for the
+    /// `validate` command. Empty when there is nothing to point out.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        for record in &self.records {
+            let label = format!("{} ({})", record.name, record.record_type);
+            if let Err(e) = check_hostname(&record.name) {
+                problems.push(format!("{}: {}", label, e));
+            }
+            // A template fills the address into a TXT, CNAME or other record
+            if record.template.is_none() && !matches!(record.record_type.as_str(), "A" | "AAAA") {
+                problems.push(format!(
+                    "{}: only A and AAAA records can be pointed at the public IP",
+                    label
+                ));
+            }
+            // Cloudflare takes 1 (automatic) or 60 seconds to a day
+            if record.provider == Provider::Cloudflare
+                && let Ttl::Seconds(ttl) = record.ttl
+                && !(60..=86400).contains(&ttl)
+            {
+                problems.push(format!(
+                    "{}: TTL {} is outside what Cloudflare accepts, use 'auto' or 60 to 86400 seconds (Enterprise plans also take 30)",
+                    label, ttl
+                ));
+            }
+            if record.cache_expiry_hours <= 0 {
+                problems.push(format!(
+                    "{}: cache_expiry_hours {} makes every run skip the cache",
+                    label, record.cache_expiry_hours
+                ));
+            }
+        }
+
+        if self.run_mode == RunMode::Daemon && self.sleep_interval == 0 {
+            problems.push(
+                "SLEEP_INTERVAL is 0, so the daemon would update without pausing".to_string(),
+            );
+        }
+        let longest_sleep = self.max_sleep_interval.unwrap_or(self.sleep_interval);
+        if matches!(self.leader_lock, Some(LeaderLock::Txt(_)))
+            && self.leader_lease <= longest_sleep
+        {
+            problems.push(format!(
+                "LEADER_LEASE ({}s) isn't longer than the {}s between cycles, so the leader's lease runs out while it sleeps",
+                self.leader_lease, longest_sleep
+            ));
+        }
+        if self.health_max_age <= longest_sleep
+            && (self.http_listen.is_some() || self.run_mode == RunMode::Daemon)
+        {
+            problems.push(format!(
+                "HEALTH_MAX_AGE ({}s) isn't longer than the {}s between cycles, so /healthz and `healthcheck` report unhealthy while the daemon sleeps",
+                self.health_max_age, longest_sleep
+            ));
+        }
+
+        problems
+    }
+
+    /// A Cloudflare record added at runtime, with the defaults of the
+    /// configuration for what isn't given.
+    pub fn new_record(
+        &self,
+        name: &str,
+        record_type: &str,
+        zone: Option<ZoneRef>,
+        proxied: Option<bool>,
+        ttl: Option<Ttl>,
+    ) -> Result<RecordConfig> {
+        check_hostname(name)?;
+        let record_type = record_type.trim().to_ascii_uppercase();
+        if record_type != "A" && record_type != "AAAA" {
+            return Err(Error::Config(format!(
+                "Invalid record type '{}', expected A or AAAA",
+                record_type
+            )));
+        }
+        let zone = zone.or_else(|| self.zone.clone()).ok_or_else(|| {
+            Error::Config(format!(
+                "Record {} needs a zone_id or zone_name, there is no default zone",
+                name
+            ))
+        })?;
+        Ok(RecordConfig {
+            name: name.trim_end_matches('.').to_string(),
+            record_type,
+            provider: Provider::Cloudflare,
+            zone: Some(zone),
+            proxied,
+            ttl: ttl.unwrap_or(self.ttl),
+            ip_source: self.ip.source.clone(),
+            ip_sources: Vec::new(),
+            template: None,
+            failover: None,
+            cache_expiry_hours: self.cache_expiry_hours,
+            min_update_interval: self.min_update_interval,
+            ipv6_suffix: None,
+        })
+    }
+
+    /// The zones of the configured records and of record discovery, each
+    /// listed once.
+    pub fn zones(&self) -> Vec<&ZoneRef> {
+        let mut zones = Vec::new();
+        let discovery_zone = self.discovery.as_ref().map(|d| &d.zone);
+        let record_zones = self.records.iter().filter_map(|r| r.zone.as_ref());
+        for zone in record_zones.chain(discovery_zone) {
+            if !zones.contains(&zone) {
+                zones.push(zone);
+            }
+        }
+        zones
+    }
+
+    /// Loads the configuration file (if one was given) and applies
+    /// environment variable overrides on top of it. `daemon` comes from the
+    /// `--daemon` command line flag and wins over `RUN_MODE`.
+    pub fn load(config_path: Option<&Path>, daemon: bool) -> Result<Self> {
+        let file = match config_path {
+            Some(path) => {
+                info!("📄 Loading config from {}", path.display());
+                FileConfig::read(path)?
+            }
+            None => FileConfig::default(),
+        };
+        Self::from_file(config_path, file, daemon)
+    }
+
+    /// Loads the configuration file like [`Config::load`], but without
+    /// looking at the environment, so that tests only depend on the file.
+    pub fn load_isolated(config_path: &Path) -> Result<Self> {
+        env::isolated(|| Self::load(Some(config_path), false))
+    }
+
+    /// A configuration from TOML, without looking at the environment.
+    #[cfg(test)]
+    pub(crate) fn from_toml(toml: &str) -> Self {
+        let file = toml::from_str(toml).unwrap();
+        env::isolated(|| Self::from_file(None, file, false)).unwrap()
+    }
+
+    fn from_file(config_path: Option<&Path>, file: FileConfig, daemon: bool) -> Result<Self> {
+        // Provider of the records that don't name their own
+        let default_provider = match env::var("DNS_PROVIDER") {
+            Ok(value) => value.parse()?,
+            Err(_) => file.provider.unwrap_or(Provider::Cloudflare),
+        };
+
+        // An API token is preferred over a Global API Key when both are set
+        let api_token = env_secret("CLOUDFLARE_API_TOKEN")?.or(file.api_token);
+        let global_key = match (
+            env_secret("CLOUDFLARE_API_KEY")?.or(file.api_key),
+            env::var("CLOUDFLARE_EMAIL").ok().or(file.email),
+        ) {
+            (Some(key), Some(email)) => Some(Auth::GlobalKey { key, email }),
+            (None, None) => None,
+            _ => {
+                return Err(Error::Config("CLOUDFLARE_API_KEY and CLOUDFLARE_EMAIL (or `api_key` and `email` in the config file) must be set together".to_string()));
+            }
+        };
+        let auth = match (api_token, global_key) {
+            (Some(token), Some(_)) => {
+                warn!("⚠️  Both an API token and a Global API Key are set, using the API token");
+                Some(Auth::Token(token))
+            }
+            (Some(token), None) => Some(Auth::Token(token)),
+            (None, global_key) => global_key,
+        };
+
+        // Default zone for records that don't specify their own
+        let zone = ZoneRef::from_parts(
+            env::var("CLOUDFLARE_ZONE_ID").ok(),
+            env::var("CLOUDFLARE_ZONE_NAME").ok(),
+        )
+        .or(ZoneRef::from_parts(file.zone_id, file.zone_name));
+        let require_zone = |record_zone: Option<ZoneRef>| {
+            record_zone.or_else(|| zone.clone()).ok_or_else(|| {
+                Error::Config("CLOUDFLARE_ZONE_ID or CLOUDFLARE_ZONE_NAME environment variable (or `zone_id`/`zone_name` in the config file) is required".to_string())
+            })
+        };
+        // Update-only providers have no zones
+        let record_zone = |provider: Provider, record_zone: Option<ZoneRef>| {
+            if provider.is_update_only() {
+                Ok(None)
+            } else {
+                require_zone(record_zone).map(Some)
+            }
+        };
+
+        // DNS_RECORD_TYPE may list several types (e.g. "A,AAAA") for every name
+        let record_types: Vec<String> = match env::var("DNS_RECORD_TYPE") {
+            Ok(types) => types.split(',').map(str::to_string).collect(),
+            Err(_) => file
+                .record_type
+                .map(RecordTypes::into_vec)
+                .unwrap_or_else(|| vec!["A".to_string()]),
+        };
+        let record_types = normalize_types(record_types);
+
+        let proxied = env_switch("DNS_RECORD_PROXIED")?.or(file.proxied);
+
+        // Defaults for records that don't set their own
+        let ttl = match env::var("DNS_RECORD_TTL") {
+            Ok(value) => value.parse()?,
+            Err(_) => match file.ttl {
+                Some(ttl) => ttl.into_ttl()?,
+                None => Ttl::Preserve,
+            },
+        };
+
+        let cache_expiry_hours = env_parse("CACHE_EXPIRY_HOURS")
+            .or(file.cache_expiry_hours)
+            .unwrap_or(24);
+        let min_update_interval = env_parse("MIN_UPDATE_INTERVAL")
+            .or(file.min_update_interval)
+            .unwrap_or(0);
+        let flap_window = env_parse("FLAP_WINDOW").or(file.flap_window).unwrap_or(0);
+        let flap_confirmation = env_parse("FLAP_CONFIRMATION")
+            .or(file.flap_confirmation)
+            .unwrap_or(flap_window);
+
+        let ip_source: IpSource = match env::var("IP_SOURCE").ok().or(file.ip_source) {
+            Some(value) => value.parse()?,
+            None => IpSource::default(),
+        };
+        let confirm_ip_source = match env::var("CONFIRM_IP_SOURCE")
+            .ok()
+            .or(file.confirm_ip_source)
+        {
+            Some(value) => Some(value.parse::<IpSource>()?),
+            None => None,
+        };
+
+        let prefix_len = env_parse("IPV6_PREFIX_LENGTH")
+            .or(file.ipv6_prefix_length)
+            .unwrap_or(64);
+        if prefix_len > 128 {
+            return Err(Error::Config(format!(
+                "Invalid IPV6_PREFIX_LENGTH {}, expected 0 to 128",
+                prefix_len
+            )));
+        }
+        let ipv6_suffix =
+            |suffix: Option<Ipv6Addr>| suffix.map(|suffix| Ipv6Suffix { suffix, prefix_len });
+        let default_suffix =
+            match env::var("IPV6_SUFFIX") {
+                Ok(value) => Some(value.parse().map_err(|e| {
+                    Error::Config(format!("Invalid IPV6_SUFFIX '{}': {}", value, e))
+                })?),
+                Err(_) => file.ipv6_suffix,
+            };
+
+        // DNS_RECORD_NAME (comma-separated) replaces the records from the file
+        let mut records = Vec::new();
+        match env::var("DNS_RECORD_NAME") {
+            Ok(names) => {
+                for name in names.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+                    for record_type in &record_types {
+                        records.push(RecordConfig {
+                            name: name.to_string(),
+                            record_type: record_type.clone(),
+                            provider: default_provider,
+                            zone: record_zone(default_provider, None)?,
+                            proxied,
+                            ttl,
+                            ip_source: ip_source.clone(),
+                            ip_sources: Vec::new(),
+                            template: None,
+                            failover: None,
+                            cache_expiry_hours,
+                            min_update_interval,
+                            ipv6_suffix: ipv6_suffix(default_suffix),
+                        });
+                    }
+                }
+            }
+            Err(_) => {
+                for record in file.records {
+                    let provider = record.provider.unwrap_or(default_provider);
+                    let zone = record_zone(
+                        provider,
+                        ZoneRef::from_parts(record.zone_id, record.zone_name),
+                    )?;
+                    let types = match (record.record_type, record.content) {
+                        (Some(types), _) => normalize_types(types.into_vec()),
+                        // The type of a fixed address follows from the address
+                        (None, Some(IpAddr::V6(_))) => vec!["AAAA".to_string()],
+                        (None, Some(IpAddr::V4(_))) => vec!["A".to_string()],
+                        (None, None) => record_types.clone(),
+                    };
+                    let record_ttl = match record.ttl {
+                        Some(value) => value.into_ttl().map_err(|e| {
+                            Error::Config(format!("Invalid `ttl` of record {}: {}", record.name, e))
+                        })?,
+                        None => ttl,
+                    };
+                    let mut record_ip_sources: Vec<IpSource> = Vec::new();
+                    if let Some(values) = record.ip_sources {
+                        if record.ip_source.is_some() || record.content.is_some() {
+                            return Err(Error::Config(format!(
+                                "Record {} sets `ip_sources` together with `ip_source` or `content`",
+                                record.name
+                            )));
+                        }
+                        if provider != Provider::Cloudflare {
+                            return Err(Error::Config(format!(
+                                "Record {} sets `ip_sources`, which only Cloudflare records support",
+                                record.name
+                            )));
+                        }
+                        for value in values {
+                            record_ip_sources.push(value.parse().map_err(|e| {
+                                Error::Config(format!(
+                                    "Invalid `ip_sources` entry of record {}: {}",
+                                    record.name, e
+                                ))
+                            })?);
+                        }
+                        if record_ip_sources.is_empty() {
+                            return Err(Error::Config(format!(
+                                "Record {} has an empty `ip_sources` list",
+                                record.name
+                            )));
+                        }
+                    }
+                    if record.template.is_some() {
+                        if record.content.is_some() || !record_ip_sources.is_empty() {
+                            return Err(Error::Config(format!(
+                                "Record {} sets `template` together with `content` or `ip_sources`",
+                                record.name
+                            )));
+                        }
+                        if provider != Provider::Cloudflare {
+                            return Err(Error::Config(format!(
+                                "Record {} sets `template`, which only Cloudflare records support",
+                                record.name
+                            )));
+                        }
+                        if types.iter().any(|t| t == "A" || t == "AAAA") {
+                            return Err(Error::Config(format!(
+                                "Record {} has a `template`, which needs a `type` such as TXT or CNAME instead of A or AAAA",
+                                record.name
+                            )));
+                        }
+                    }
+                    let record_failover = match record.failover {
+                        Some(failover) => {
+                            if record.content.is_some()
+                                || record.template.is_some()
+                                || !record_ip_sources.is_empty()
+                            {
+                                return Err(Error::Config(format!(
+                                    "Record {} sets `failover` together with `content`, `template` or `ip_sources`",
+                                    record.name
+                                )));
+                            }
+                            let backup_type = if failover.backup.is_ipv6() {
+                                "AAAA"
+                            } else {
+                                "A"
+                            };
+                            if types.iter().any(|t| t != backup_type) {
+                                return Err(Error::Config(format!(
+                                    "The failover backup {} of record {} only fits {} records",
+                                    failover.backup, record.name, backup_type
+                                )));
+                            }
+                            Some(Failover {
+                                check: failover.check.parse().map_err(|e| {
+                                    Error::Config(format!(
+                                        "Invalid failover `check` of record {}: {}",
+                                        record.name, e
+                                    ))
+                                })?,
+                                backup: failover.backup,
+                                failures: failover.failures.unwrap_or(3).max(1),
+                            })
+                        }
+                        None => None,
+                    };
+                    let record_ip_source = match (record.ip_source, record.content) {
+                        _ if !record_ip_sources.is_empty() => record_ip_sources[0].clone(),
+                        (Some(_), Some(_)) => {
+                            return Err(Error::Config(format!(
+                                "Record {} sets both `ip_source` and `content`",
+                                record.name
+                            )));
+                        }
+                        (Some(value), None) => value.parse().map_err(|e| {
+                            Error::Config(format!(
+                                "Invalid `ip_source` of record {}: {}",
+                                record.name, e
+                            ))
+                        })?,
+                        (None, Some(content)) => IpSource::Static(vec![content]),
+                        (None, None) => ip_source.clone(),
+                    };
+                    for record_type in types {
+                        records.push(RecordConfig {
+                            name: record.name.clone(),
+                            record_type,
+                            provider,
+                            zone: zone.clone(),
+                            proxied: record.proxied.or(proxied),
+                            ttl: record_ttl,
+                            ip_source: record_ip_source.clone(),
+                            ip_sources: record_ip_sources.clone(),
+                            template: record.template.clone(),
+                            failover: record_failover.clone(),
+                            cache_expiry_hours: record
+                                .cache_expiry_hours
+                                .unwrap_or(cache_expiry_hours),
+                            min_update_interval: record
+                                .min_update_interval
+                                .unwrap_or(min_update_interval),
+                            ipv6_suffix: ipv6_suffix(record.ipv6_suffix.or(default_suffix)),
+                        });
+                    }
+                }
+            }
+        }
+
+        // The same name and type listed twice would be updated twice per cycle
+        let mut seen = Vec::new();
+        records.retain(|r| {
+            let key = (r.name.to_ascii_lowercase(), r.record_type.clone());
+            let first = !seen.contains(&key);
+            seen.push(key);
+            first
+        });
+
+        // In dual-stack mode every A record gets a matching AAAA record
+        let dual_stack = env_switch("DUAL_STACK")?
+            .or(file.dual_stack)
+            .unwrap_or(false);
+
+        if dual_stack {
+            let missing_aaaa: Vec<RecordConfig> = records
+                .iter()
+                .filter(|r| r.record_type.eq_ignore_ascii_case("A"))
+                .filter(|a| {
+                    !records
+                        .iter()
+                        .any(|r| r.name == a.name && r.record_type.eq_ignore_ascii_case("AAAA"))
+                })
+                .map(|a| {
+                    // An IPv4-only fixed address or a source list belongs to
+                    // the A record, so the twin detects its address like
+                    // records without either
+                    let ipv4_only = !a.ip_sources.is_empty()
+                        || matches!(&a.ip_source, IpSource::Static(addresses)
+                            if !addresses.iter().any(IpAddr::is_ipv6));
+                    RecordConfig {
+                        record_type: "AAAA".to_string(),
+                        ip_source: if ipv4_only {
+                            ip_source.clone()
+                        } else {
+                            a.ip_source.clone()
+                        },
+                        ip_sources: Vec::new(),
+                        // The backup is an IPv4 address
+                        failover: None,
+                        ..a.clone()
+                    }
+                })
+                .collect();
+            records.extend(missing_aaaa);
+        }
+
+        let prune = env_switch("PRUNE_RECORDS")?
+            .or(file.prune_records)
+            .unwrap_or(false);
+        let discovery = match env::var("DISCOVERY_MARKER").ok().or(file.discovery_marker) {
+            Some(marker) => Some(Discovery {
+                marker,
+                zone: require_zone(None)?,
+                prune,
+            }),
+            None if prune => {
+                return Err(Error::Config(
+                    "PRUNE_RECORDS needs DISCOVERY_MARKER to tell which records are managed"
+                        .to_string(),
+                ));
+            }
+            None => None,
+        };
+
+        // Pruning without records would delete every marked record
+        if records.is_empty() && discovery.as_ref().is_none_or(|d| d.prune) {
+            return Err(Error::Config("DNS_RECORD_NAME environment variable (or `[[records]]` in the config file) is required".to_string()));
+        }
+
+        let file_scrape = file.ip_scrape.unwrap_or_default();
+        let scrape = match env::var("IP_SCRAPE_URL").ok().or(file_scrape.url) {
+            Some(url) => {
+                let regex = match env::var("IP_SCRAPE_REGEX").ok().or(file_scrape.regex) {
+                    Some(pattern) => Some(Regex::new(&pattern).map_err(|e| {
+                        Error::Config(format!("Invalid IP_SCRAPE_REGEX '{}': {}", pattern, e))
+                    })?),
+                    None => None,
+                };
+                let password = env_secret("IP_SCRAPE_PASSWORD")?.or(file_scrape.password);
+                Some(ScrapePage {
+                    url,
+                    json_pointer: env::var("IP_SCRAPE_JSON_POINTER")
+                        .ok()
+                        .or(file_scrape.json_pointer),
+                    regex,
+                    credentials: env::var("IP_SCRAPE_USERNAME")
+                        .ok()
+                        .or(file_scrape.username)
+                        .map(|username| (username, password.unwrap_or_default())),
+                })
+            }
+            None => None,
+        };
+        let uses_scrape = ip_source == IpSource::Scrape
+            || records
+                .iter()
+                .any(|r| r.sources().contains(&IpSource::Scrape));
+        if uses_scrape && scrape.is_none() {
+            return Err(Error::Config("IP_SOURCE=scrape needs IP_SCRAPE_URL (or `url` in the `[ip_scrape]` config section)".to_string()));
+        }
+
+        // Cloudflare credentials are only needed for records hosted there
+        let needs_cloudflare =
+            discovery.is_some() || records.iter().any(|r| r.provider == Provider::Cloudflare);
+        let auth = match auth {
+            None if needs_cloudflare => Some(credentials::load_token().map(Auth::Token).ok_or_else(|| {
+                Error::Config("CLOUDFLARE_API_TOKEN environment variable (or `api_token` in the config file, or a token saved with `cloudflare-ddns auth login`) is required, or CLOUDFLARE_API_KEY and CLOUDFLARE_EMAIL for a Global API Key".to_string())
+            })?),
+            auth => auth,
+        };
+
+        let duckdns_token = env_secret("DUCKDNS_TOKEN")?.or(file.duckdns.unwrap_or_default().token);
+        let dynu = login("DYNU", "dynu", file.dynu.unwrap_or_default())?;
+        let noip = login("NOIP", "noip", file.noip.unwrap_or_default())?;
+
+        let file_rfc2136 = file.rfc2136.unwrap_or_default();
+        let tsig_key = match (
+            env::var("RFC2136_KEY_NAME").ok().or(file_rfc2136.key_name),
+            env_secret("RFC2136_KEY_SECRET")?.or(file_rfc2136.key_secret),
+        ) {
+            (Some(name), Some(secret)) => {
+                let algorithm = match env::var("RFC2136_KEY_ALGORITHM")
+                    .ok()
+                    .or(file_rfc2136.key_algorithm)
+                {
+                    Some(algorithm) => algorithm.parse()?,
+                    None => TsigAlgorithm::HmacSha256,
+                };
+                Some(TsigKey::new(name, algorithm, &secret)?)
+            }
+            (None, None) => None,
+            _ => {
+                return Err(Error::Config("RFC2136_KEY_NAME and RFC2136_KEY_SECRET (or `key_name` and `key_secret` in the `[rfc2136]` config section) must be set together".to_string()));
+            }
+        };
+        let rfc2136 = env::var("RFC2136_SERVER")
+            .ok()
+            .or(file_rfc2136.server)
+            .map(|server| Rfc2136Config {
+                server,
+                zone: env::var("RFC2136_ZONE").ok().or(file_rfc2136.zone),
+                ttl: env_parse("RFC2136_TTL").or(file_rfc2136.ttl).unwrap_or(300),
+                key: tsig_key,
+            });
+
+        for record in records.iter().filter(|r| r.provider.is_update_only()) {
+            if !matches!(record.record_type.as_str(), "A" | "AAAA") {
+                return Err(Error::Config(format!(
+                    "{} ({}) can't be on {}, which only updates A and AAAA records",
+                    record.name, record.record_type, record.provider
+                )));
+            }
+            let configured = match record.provider {
+                Provider::DuckDns => duckdns_token.is_some(),
+                Provider::Dynu => dynu.is_some(),
+                Provider::NoIp => noip.is_some(),
+                Provider::Rfc2136 => rfc2136.is_some(),
+                Provider::Cloudflare => true,
+            };
+            if !configured {
+                return Err(Error::Config(format!(
+                    "{} is on {}, which needs {}",
+                    record.name,
+                    record.provider,
+                    match record.provider {
+                        Provider::DuckDns =>
+                            "DUCKDNS_TOKEN (or `token` in the `[duckdns]` config section)",
+                        Provider::Dynu =>
+                            "DYNU_USERNAME and DYNU_PASSWORD (or the `[dynu]` config section)",
+                        Provider::NoIp =>
+                            "NOIP_USERNAME and NOIP_PASSWORD (or the `[noip]` config section)",
+                        _ => "RFC2136_SERVER (or `server` in the `[rfc2136]` config section)",
+                    }
+                )));
+            }
+        }
+
+        let reconcile_every = env_parse("RECONCILE_EVERY")
+            .or(file.reconcile_every)
+            .filter(|runs| *runs > 0);
+
+        let create_if_missing = env_switch("CREATE_IF_MISSING")?
+            .or(file.create_if_missing)
+            .unwrap_or(false);
+
+        let verify = env_switch("VERIFY_PROPAGATION")?
+            .or(file.verify_propagation)
+            .unwrap_or(false)
+            .then(|| PropagationCheck {
+                server: env::var("VERIFY_SERVER").ok().or(file.verify_server),
+                timeout: Duration::from_secs(
+                    env_parse("VERIFY_TIMEOUT")
+                        .or(file.verify_timeout)
+                        .unwrap_or(60),
+                ),
+            });
+
+        let duplicate_records = match env::var("DUPLICATE_RECORDS") {
+            Ok(value) => value.parse()?,
+            Err(_) => file.duplicate_records.unwrap_or_default(),
+        };
+        let record_comment = env::var("RECORD_COMMENT").ok().or(file.record_comment);
+        let record_tags = match env::var("RECORD_TAGS") {
+            Ok(value) => value
+                .split(',')
+                .map(str::trim)
+                .filter(|tag| !tag.is_empty())
+                .map(str::to_string)
+                .collect(),
+            Err(_) => file.record_tags,
+        };
+
+        let run_mode = if daemon {
+            RunMode::Daemon
+        } else {
+            match env::var("RUN_MODE") {
+                Ok(value) => value.parse()?,
+                Err(_) => file.run_mode.unwrap_or(RunMode::Once),
+            }
+        };
+
+        let sleep_interval = env_parse("SLEEP_INTERVAL")
+            .or(file.sleep_interval)
+            .unwrap_or(600);
+        let max_sleep_interval = env_parse("MAX_SLEEP_INTERVAL").or(file.max_sleep_interval);
+        if let Some(max) = max_sleep_interval
+            && max < sleep_interval
+        {
+            return Err(Error::Config(format!(
+                "MAX_SLEEP_INTERVAL ({}) is shorter than SLEEP_INTERVAL ({})",
+                max, sleep_interval
+            )));
+        }
+
+        let start_jitter = env_parse("START_JITTER").or(file.start_jitter).unwrap_or(0);
+
+        let watch_network = env_switch("WATCH_NETWORK")?
+            .or(file.watch_network)
+            .unwrap_or(false);
+        let watch_config = env_switch("WATCH_CONFIG")?
+            .or(file.watch_config)
+            .unwrap_or(false);
+        if watch_config && config_path.is_none() {
+            return Err(Error::Config(
+                "WATCH_CONFIG needs a config file to watch, given with --config".to_string(),
+            ));
+        }
+        let paused = env_switch("PAUSED")?.or(file.paused).unwrap_or(false);
+        let pause_file = env::var("PAUSE_FILE")
+            .ok()
+            .map(PathBuf::from)
+            .or(file.pause_file);
+
+        // The interface the address is read from is the one worth watching
+        let watch_interface = env::var("WATCH_INTERFACE")
+            .ok()
+            .or(file.watch_interface)
+            .or(match &ip_source {
+                IpSource::Interface(name) => Some(name.clone()),
+                _ => None,
+            });
+
+        let mut retry = file.retry.unwrap_or_default();
+        if let Some(max_attempts) = env_parse("RETRY_MAX_ATTEMPTS") {
+            retry.max_attempts = max_attempts;
+        }
+        if let Some(base_delay_ms) = env_parse("RETRY_BASE_DELAY_MS") {
+            retry.base_delay_ms = base_delay_ms;
+        }
+        if let Some(jitter_ms) = env_parse("RETRY_JITTER_MS") {
+            retry.jitter_ms = jitter_ms;
+        }
+        retry.max_attempts = retry.max_attempts.max(1);
+
+        let http_listen = match env::var("HTTP_LISTEN") {
+            Ok(value) => Some(value.parse().map_err(|e| {
+                Error::Config(format!("Invalid HTTP_LISTEN address '{}': {}", value, e))
+            })?),
+            Err(_) => file.http_listen,
+        };
+        let dyndns_server = login(
+            "DYNDNS_SERVER",
+            "dyndns_server",
+            file.dyndns_server.unwrap_or_default(),
+        )?;
+        if dyndns_server.is_some() && http_listen.is_none() {
+            return Err(Error::Config(
+                "DYNDNS_SERVER_USERNAME needs HTTP_LISTEN (or `http_listen` in the config file) for the server to listen on".to_string(),
+            ));
+        }
+        let trigger_token = env_secret("TRIGGER_TOKEN")?
+            .or(file.trigger_token)
+            .filter(|token| !token.is_empty());
+        if trigger_token.is_some() && http_listen.is_none() {
+            return Err(Error::Config(
+                "TRIGGER_TOKEN needs HTTP_LISTEN (or `http_listen` in the config file) for the server to listen on".to_string(),
+            ));
+        }
+
+        let admin_token = env_secret("ADMIN_TOKEN")?
+            .or(file.admin_token)
+            .filter(|token| !token.is_empty());
+        if admin_token.is_some() && http_listen.is_none() {
+            return Err(Error::Config(
+                "ADMIN_TOKEN needs HTTP_LISTEN (or `http_listen` in the config file) for the server to listen on".to_string(),
+            ));
+        }
+        let state_path = env::var_os("STATE_PATH")
+            .map(PathBuf::from)
+            .or(file.state_path);
+
+        let history_path = env::var_os("HISTORY_PATH")
+            .map(PathBuf::from)
+            .or(file.history_path);
+
+        // Allow for a slow cycle on top of the longest sleep before reporting
+        // unhealthy
+        let health_max_age = env_parse("HEALTH_MAX_AGE")
+            .or(file.health_max_age)
+            .unwrap_or(max_sleep_interval.unwrap_or(sleep_interval) * 2 + 60);
+
+        let leader_lock = match env::var("LEADER_LOCK").ok().or(file.leader_lock) {
+            Some(lock) => Some(lock.parse::<LeaderLock>()?),
+            None => None,
+        };
+        if let Some(LeaderLock::Txt(_)) = &leader_lock
+            && zone.is_none()
+        {
+            return Err(Error::Config(
+                "LEADER_LOCK=txt:<name> keeps the lease in the default zone, set CLOUDFLARE_ZONE_ID or CLOUDFLARE_ZONE_NAME (or `zone_id` or `zone_name` in the config file)".to_string(),
+            ));
+        }
+        // A leader that stopped finishing cycles is no longer healthy either
+        let leader_lease = env_parse("LEADER_LEASE")
+            .or(file.leader_lease)
+            .unwrap_or(health_max_age);
+
+        let file_http = file.http.unwrap_or_default();
+        let default_http = HttpConfig::default();
+        let seconds = |env_name: &str, file_value: Option<u64>, default: Duration| {
+            env_parse(env_name)
+                .or(file_value)
+                .map_or(default, Duration::from_secs)
+        };
+        let http = HttpConfig {
+            connect_timeout: seconds(
+                "HTTP_CONNECT_TIMEOUT",
+                file_http.connect_timeout,
+                default_http.connect_timeout,
+            ),
+            timeout: seconds("HTTP_TIMEOUT", file_http.timeout, default_http.timeout),
+            keep_alive: seconds(
+                "HTTP_KEEP_ALIVE",
+                file_http.keep_alive,
+                default_http.keep_alive,
+            ),
+            max_redirects: env_parse("HTTP_MAX_REDIRECTS")
+                .or(file_http.max_redirects)
+                .unwrap_or(default_http.max_redirects),
+            // The URL may carry the proxy's password
+            proxy: match env_secret("PROXY_URL")?.or(file_http.proxy_url) {
+                Some(url) => Some(http::parse_proxy(&url)?),
+                None => None,
+            },
+        };
+
+        let file_telegram = file.telegram.unwrap_or_default();
+        let telegram = match (
+            env_secret("TELEGRAM_BOT_TOKEN")?.or(file_telegram.bot_token),
+            env::var("TELEGRAM_CHAT_ID").ok().or(file_telegram.chat_id),
+        ) {
+            (Some(bot_token), Some(chat_id)) => Some(TelegramConfig {
+                bot_token,
+                chat_id,
+                message_template: env::var("TELEGRAM_MESSAGE_TEMPLATE")
+                    .ok()
+                    .or(file_telegram.message_template)
+                    .unwrap_or_else(|| DEFAULT_MESSAGE_TEMPLATE.to_string()),
+                failure_template: env::var("TELEGRAM_FAILURE_TEMPLATE")
+                    .ok()
+                    .or(file_telegram.failure_template)
+                    .unwrap_or_else(|| DEFAULT_FAILURE_TEMPLATE.to_string()),
+                recovered_template: env::var("TELEGRAM_RECOVERED_TEMPLATE")
+                    .ok()
+                    .or(file_telegram.recovered_template)
+                    .unwrap_or_else(|| DEFAULT_RECOVERED_TEMPLATE.to_string()),
+                failure_threshold: env_parse("TELEGRAM_FAILURE_THRESHOLD")
+                    .or(file_telegram.failure_threshold)
+                    .unwrap_or(3),
+            }),
+            (None, None) => None,
+            _ => {
+                return Err(Error::Config("TELEGRAM_BOT_TOKEN and TELEGRAM_CHAT_ID (or `bot_token` and `chat_id` in the `[telegram]` config section) must be set together".to_string()));
+            }
+        };
+
+        let file_smtp = file.smtp.unwrap_or_default();
+        let email = match env::var("SMTP_HOST").ok().or(file_smtp.host) {
+            Some(host) => {
+                let tls = match env::var("SMTP_TLS") {
+                    Ok(value) => value.parse()?,
+                    Err(_) => file_smtp.tls.unwrap_or_default(),
+                };
+                let from = env::var("SMTP_FROM")
+                    .ok()
+                    .or(file_smtp.from)
+                    .ok_or_else(|| Error::Config("SMTP_FROM (or `from` in the `[smtp]` config section) is required for email notifications".to_string()))?;
+                let to = match env::var("SMTP_TO") {
+                    Ok(value) => value
+                        .split(',')
+                        .map(str::trim)
+                        .map(str::to_string)
+                        .collect(),
+                    Err(_) => file_smtp.to,
+                };
+                if to.is_empty() {
+                    return Err(Error::Config("SMTP_TO (or `to` in the `[smtp]` config section) is required for email notifications".to_string()));
+                }
+                let password = env_secret("SMTP_PASSWORD")?.or(file_smtp.password);
+                let credentials = env::var("SMTP_USERNAME")
+                    .ok()
+                    .or(file_smtp.username)
+                    .map(|username| (username, password.unwrap_or_default()));
+
+                Some(EmailConfig {
+                    host,
+                    port: env_parse("SMTP_PORT")
+                        .or(file_smtp.port)
+                        .unwrap_or(tls.default_port()),
+                    tls,
+                    credentials,
+                    from: from.parse().map_err(|e| {
+                        Error::Config(format!("Invalid SMTP_FROM address '{}': {}", from, e))
+                    })?,
+                    to: to
+                        .iter()
+                        .filter(|address| !address.is_empty())
+                        .map(|address| {
+                            address.parse().map_err(|e| {
+                                Error::Config(format!(
+                                    "Invalid SMTP_TO address '{}': {}",
+                                    address, e
+                                ))
+                            })
+                        })
+                        .collect::<Result<_>>()?,
+                })
+            }
+            None => None,
+        };
+
+        let file_ntfy = file.ntfy.unwrap_or_default();
+        let ntfy_token = env_secret("NTFY_TOKEN")?.or(file_ntfy.token);
+        let ntfy = env::var("NTFY_TOPIC")
+            .ok()
+            .or(file_ntfy.topic)
+            .map(|topic| NtfyConfig {
+                server: env::var("NTFY_SERVER")
+                    .ok()
+                    .or(file_ntfy.server)
+                    .unwrap_or_else(|| ntfy::DEFAULT_SERVER.to_string()),
+                topic,
+                token: ntfy_token,
+            });
+
+        let file_gotify = file.gotify.unwrap_or_default();
+        let gotify = match (
+            env::var("GOTIFY_URL").ok().or(file_gotify.url),
+            env_secret("GOTIFY_TOKEN")?.or(file_gotify.token),
+        ) {
+            (Some(url), Some(token)) => Some((url, token)),
+            (None, None) => None,
+            _ => {
+                return Err(Error::Config("GOTIFY_URL and GOTIFY_TOKEN (or `url` and `token` in the `[gotify]` config section) must be set together".to_string()));
+            }
+        };
+
+        let file_mqtt = file.mqtt.unwrap_or_default();
+        let mqtt = match env_secret("MQTT_URL")?.or(file_mqtt.url) {
+            Some(url) => Some(MqttConfig::from_url(
+                &url,
+                env::var("MQTT_TOPIC")
+                    .ok()
+                    .or(file_mqtt.topic)
+                    .unwrap_or_else(|| mqtt::DEFAULT_TOPIC.to_string()),
+                env::var("MQTT_DISCOVERY_PREFIX")
+                    .ok()
+                    .or(file_mqtt.discovery_prefix)
+                    .unwrap_or_else(|| mqtt::DEFAULT_DISCOVERY_PREFIX.to_string()),
+            )?),
+            None => None,
+        };
+
+        let file_hooks = file.hooks.unwrap_or_default();
+        let hooks = Hooks {
+            on_change: env::var("HOOK_ON_CHANGE").ok().or(file_hooks.on_change),
+            on_failure: env::var("HOOK_ON_FAILURE").ok().or(file_hooks.on_failure),
+            on_success: env::var("HOOK_ON_SUCCESS").ok().or(file_hooks.on_success),
+        };
+
+        Ok(Self {
+            auth,
+            cloudflare_api_url: env::var("CLOUDFLARE_API_URL")
+                .ok()
+                .or(file.cloudflare_api_url),
+            duckdns_token,
+            dynu,
+            noip,
+            rfc2136,
+            records,
+            zone,
+            ttl,
+            cache_expiry_hours,
+            min_update_interval,
+            flap_window,
+            flap_confirmation,
+            reconcile_every,
+            create_if_missing,
+            duplicate_records,
+            record_comment,
+            record_tags,
+            verify,
+            run_mode,
+            sleep_interval,
+            max_sleep_interval,
+            start_jitter,
+            watch_network,
+            watch_interface,
+            watch_config,
+            paused,
+            pause_file,
+            config_file: config_path.map(Path::to_path_buf),
+            retry,
+            dry_run: false,
+            force: false,
+            json_output: false,
+            webhook_url: env_secret("WEBHOOK_URL")?.or(file.webhook_url),
+            discord_webhook_url: env_secret("DISCORD_WEBHOOK_URL")?.or(file.discord_webhook_url),
+            slack_webhook_url: env_secret("SLACK_WEBHOOK_URL")?.or(file.slack_webhook_url),
+            telegram,
+            email,
+            ntfy,
+            gotify,
+            mqtt,
+            notify_urls: match env_secret("NOTIFY_URLS")? {
+                Some(urls) => apprise::split_urls(&urls),
+                None => file.notify_urls,
+            },
+            apprise_api_url: env::var("APPRISE_API_URL").ok().or(file.apprise_api_url),
+            failure_threshold: env_parse("FAILURE_THRESHOLD")
+                .or(file.failure_threshold)
+                .unwrap_or(1)
+                .max(1),
+            hooks,
+            cache_path: cache::default_cache_path(),
+            ip: IpDetector {
+                source: ip_source,
+                services: file.ip_services,
+                consensus: env_parse("IP_CONSENSUS").or(file.ip_consensus).unwrap_or(1),
+                allow_private: env_switch("ALLOW_PRIVATE_IP")?
+                    .or(file.allow_private_ip)
+                    .unwrap_or(false),
+                scrape,
+                http: http.clone(),
+            },
+            confirm_ip_source,
+            http,
+            http_listen,
+            dyndns_server,
+            trigger_token,
+            admin_token,
+            state_path,
+            history_path,
+            health_max_age,
+            leader_lock,
+            leader_lease,
+            discovery,
+        })
+    }
+}
+
+/// Checks that a record name is a hostname, optionally with a leading `*.`
+/// label for wildcard records.
+fn check_hostname(name: &str) -> Result<()> {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    if name.is_empty() || name.len() > 253 {
+        return Err(Error::Config(format!("'{}' isn't a valid hostname", name)));
+    }
+    let hostname = name.strip_prefix("*.").unwrap_or(name);
+    for label in hostname.split('.') {
+        let valid = !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(Error::Config(format!(
+                "'{}' isn't a valid hostname, label '{}' is malformed",
+                name, label
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Uppercases record types and drops empty entries.
+fn normalize_types(types: Vec<String>) -> Vec<String> {
+    types
+        .iter()
+        .map(|t| t.trim().to_ascii_uppercase())
+        .filter(|t| !t.is_empty())
+        .collect()
+}
+
+/// Reads the username and password of a dyndns2 service from
+/// `<prefix>_USERNAME` and `<prefix>_PASSWORD`, falling back to its table in
+/// the configuration file.
+fn login(prefix: &str, table: &str, file: FileLogin) -> Result<Option<Login>> {
+    match (
+        env::var(format!("{}_USERNAME", prefix))
+            .ok()
+            .or(file.username),
+        env_secret(&format!("{}_PASSWORD", prefix))?.or(file.password),
+    ) {
+        (Some(username), Some(password)) => Ok(Some(Login { username, password })),
+        (None, None) => Ok(None),
+        _ => Err(Error::Config(format!(
+            "{prefix}_USERNAME and {prefix}_PASSWORD (or `username` and `password` in the `[{table}]` config section) must be set together"
+        ))),
+    }
+}
+
+/// Reads a secret from the environment variable `name`, or from the file
+/// named by `<name>_FILE`, as used for Docker and Kubernetes secrets. A
+/// trailing newline in the file is ignored.
+fn env_secret(name: &str) -> Result<Option<String>> {
+    let file_var = format!("{}_FILE", name);
+    match (env::var(name), env::var_os(&file_var)) {
+        (Ok(_), Some(_)) => Err(Error::Config(format!(
+            "Both {} and {} are set, use only one",
+            name, file_var
+        ))),
+        (Ok(value), None) => Ok(Some(value)),
+        (Err(_), Some(path)) => {
+            let path = Path::new(&path);
+            let secret = fs::read_to_string(path).map_err(|e| {
+                Error::Config(format!(
+                    "Failed to read {} from {}: {}",
+                    name,
+                    path.display(),
+                    e
+                ))
+            })?;
+            Ok(Some(secret.trim_end_matches(['\r', '\n']).to_string()))
+        }
+        (Err(_), None) => Ok(None),
+    }
+}
+
+/// Parses an environment variable, treating unset or invalid values as absent.
+fn env_parse<T: std::str::FromStr>(name: &str) -> Option<T> {
+    env::var(name).ok().and_then(|value| value.parse().ok())
+}
+
+/// Reads an on/off switch, which mustn't be ignored when it's misspelled:
+/// `true`, `1`, `yes` and `on` or `false`, `0`, `no` and `off`, and an error
+/// for anything else.
+fn env_switch(name: &str) -> Result<Option<bool>> {
+    let Ok(value) = env::var(name) else {
+        return Ok(None);
+    };
+    match value.trim().to_ascii_lowercase().as_str() {
+        "true" | "1" | "yes" | "on" => Ok(Some(true)),
+        "false" | "0" | "no" | "off" | "" => Ok(Some(false)),
+        _ => Err(Error::Config(format!(
+            "Invalid {} '{}', expected true or false",
+            name, value
+        ))),
+    }
+}
+
+/// The environment settings are read from, which [`Config::load_isolated`]
+/// leaves empty.
+mod env {
+    use std::cell::Cell;
+    use std::env::VarError;
+    use std::ffi::{OsStr, OsString};
+
+    thread_local! {
+        static ISOLATED: Cell<bool> = const { Cell::new(false) };
+    }
+
+    pub fn var(name: impl AsRef<OsStr>) -> Result<String, VarError> {
+        if ISOLATED.get() {
+            return Err(VarError::NotPresent);
+        }
+        std::env::var(name)
+    }
+
+    pub fn var_os(name: impl AsRef<OsStr>) -> Option<OsString> {
+        if ISOLATED.get() {
+            return None;
+        }
+        std::env::var_os(name)
+    }
+
+    /// Runs `f` with an empty environment on this thread.
+    pub fn isolated<T>(f: impl FnOnce() -> T) -> T {
+        struct Reset;
+        impl Drop for Reset {
+            fn drop(&mut self) {
+                ISOLATED.set(false);
+            }
+        }
+        ISOLATED.set(true);
+        let _reset = Reset;
+        f()
+    }
+}
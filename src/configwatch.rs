@@ -0,0 +1,73 @@
+//! Notices edits of the config file, so the daemon can reload it without
+//! waiting for SIGHUP.
+//!
+//! The file's directory is watched rather than the file, since editors and
+//! Kubernetes ConfigMaps replace the file instead of writing to it. A burst
+//! of events only counts once the content of the file differs from what was
+//! last seen.
+
+use crate::error::{Context, Error, Result};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// How long to wait for the rest of a save before reading the file.
+const SETTLE: Duration = Duration::from_millis(500);
+
+/// A watch on the config file.
+pub struct ConfigWatcher {
+    path: PathBuf,
+    content: Option<Vec<u8>>,
+    events: mpsc::UnboundedReceiver<()>,
+    /// Stops watching when dropped.
+    _watcher: RecommendedWatcher,
+}
+
+impl ConfigWatcher {
+    /// Starts watching the file at `path`, as it is now.
+    pub fn new(path: &Path) -> Result<Self> {
+        let (sender, events) = mpsc::unbounded_channel();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                if event.is_ok() {
+                    let _ = sender.send(());
+                }
+            })
+            .map_err(Error::other)
+            .context("Failed to start watching the config file")?;
+
+        let directory = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        watcher
+            .watch(directory, RecursiveMode::NonRecursive)
+            .map_err(Error::other)
+            .with_context(|| format!("Failed to watch {}", directory.display()))?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            content: std::fs::read(path).ok(),
+            events,
+            _watcher: watcher,
+        })
+    }
+
+    /// Resolves once the content of the file has changed.
+    pub async fn changed(&mut self) {
+        loop {
+            if self.events.recv().await.is_none() {
+                return std::future::pending().await;
+            }
+            tokio::time::sleep(SETTLE).await;
+            while self.events.try_recv().is_ok() {}
+
+            let content = tokio::fs::read(&self.path).await.ok();
+            if content != self.content {
+                self.content = content;
+                return;
+            }
+        }
+    }
+}
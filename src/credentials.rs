@@ -0,0 +1,52 @@
+//! Storage for the API token in the OS keyring: the Keychain on macOS, the
+//! Credential Manager on Windows and the Secret Service (GNOME Keyring,
+//! KWallet) on Linux.
+
+use crate::error::{Context, Result};
+use keyring::{Entry, Error};
+use tracing::warn;
+
+const SERVICE: &str = "cloudflare-ddns";
+const USER: &str = "api-token";
+
+fn entry() -> Result<Entry> {
+    Entry::new(SERVICE, USER).context("The OS keyring is not available")
+}
+
+/// Saves the API token, replacing any token saved before.
+pub fn store_token(token: &str) -> Result<()> {
+    entry()?
+        .set_password(token)
+        .context("Failed to save the API token in the OS keyring")
+}
+
+/// The saved API token, if there is one.
+///
+/// Servers and containers usually have no keyring at all, which is the same
+/// as having no saved token. Other keyring errors are logged and also treated
+/// as no token, so the caller can report the token as missing.
+pub fn load_token() -> Option<String> {
+    let Ok(entry) = Entry::new(SERVICE, USER) else {
+        return None;
+    };
+    match entry.get_password() {
+        Ok(token) => Some(token),
+        Err(Error::NoEntry) => None,
+        Err(e) => {
+            warn!(
+                "⚠️  Failed to read the API token from the OS keyring: {}",
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Deletes the saved API token. Returns `false` if there wasn't one.
+pub fn delete_token() -> Result<bool> {
+    match entry()?.delete_credential() {
+        Ok(()) => Ok(true),
+        Err(Error::NoEntry) => Ok(false),
+        Err(e) => Err(e).context("Failed to delete the API token from the OS keyring"),
+    }
+}
@@ -0,0 +1,125 @@
+use crate::error::Result;
+use crate::notify::{Event, EventKind, Notifier};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use reqwest::Client;
+use serde::Serialize;
+
+const COLOR_CHANGED: u32 = 0x2ECC71;
+const COLOR_FAILED: u32 = 0xE74C3C;
+const COLOR_RECOVERED: u32 = 0x3498DB;
+const COLOR_SHUTDOWN: u32 = 0x95A5A6;
+
+#[derive(Debug, Serialize)]
+struct WebhookMessage {
+    embeds: Vec<Embed>,
+}
+
+#[derive(Debug, Serialize)]
+struct Embed {
+    title: String,
+    color: u32,
+    fields: Vec<EmbedField>,
+    timestamp: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+struct EmbedField {
+    name: &'static str,
+    value: String,
+    inline: bool,
+}
+
+impl EmbedField {
+    fn new(name: &'static str, value: impl Into<String>, inline: bool) -> Self {
+        Self {
+            name,
+            value: value.into(),
+            inline,
+        }
+    }
+}
+
+/// Posts an embed to a Discord incoming webhook when a record changes,
+/// starts failing or recovers, and when the daemon stops.
+pub struct Discord {
+    client: Client,
+    url: String,
+}
+
+impl Discord {
+    pub fn new(client: Client, url: String) -> Self {
+        Self { client, url }
+    }
+}
+
+#[async_trait]
+impl Notifier for Discord {
+    fn name(&self) -> &'static str {
+        "Discord"
+    }
+
+    /// Only the first failure of a streak is posted, not every failing cycle.
+    fn wants(&self, event: &Event<'_>) -> bool {
+        !event.is_repeated_failure()
+    }
+
+    async fn notify(&self, event: &Event<'_>) -> Result<()> {
+        let record = EmbedField::new(
+            "Record",
+            format!("{} ({})", event.record_name, event.record_type),
+            false,
+        );
+        let embed = match event.kind {
+            EventKind::IpChanged {
+                old_ip,
+                new_ip,
+                duration,
+            } => Embed {
+                title: event.title(),
+                color: COLOR_CHANGED,
+                fields: vec![
+                    record,
+                    EmbedField::new("Old IP", old_ip.unwrap_or("-"), true),
+                    EmbedField::new("New IP", new_ip, true),
+                    EmbedField::new("Duration", format!("{} ms", duration.as_millis()), true),
+                ],
+                timestamp: event.timestamp,
+            },
+            EventKind::UpdateFailed { error, .. } => Embed {
+                title: event.title(),
+                color: COLOR_FAILED,
+                fields: vec![record, EmbedField::new("Error", error, false)],
+                timestamp: event.timestamp,
+            },
+            EventKind::Recovered { failures } => Embed {
+                title: event.title(),
+                color: COLOR_RECOVERED,
+                fields: vec![
+                    record,
+                    EmbedField::new("Failed cycles", failures.to_string(), true),
+                ],
+                timestamp: event.timestamp,
+            },
+            EventKind::ShuttingDown => Embed {
+                title: event.title(),
+                color: COLOR_SHUTDOWN,
+                fields: vec![EmbedField::new("Details", event.message(), false)],
+                timestamp: event.timestamp,
+            },
+        };
+
+        // The webhook URL contains its secret token, so keep it out of error messages
+        self.client
+            .post(&self.url)
+            .json(&WebhookMessage {
+                embeds: vec![embed],
+            })
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(reqwest::Error::without_url)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,347 @@
+//! Minimal DNS wire format (RFC 1035) support: building and parsing messages
+//! and exchanging them with a server over UDP.
+
+use crate::error::{Error, Result};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::time::timeout;
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_NS: u16 = 2;
+pub const TYPE_CNAME: u16 = 5;
+pub const TYPE_TXT: u16 = 16;
+pub const TYPE_AAAA: u16 = 28;
+
+pub const CLASS_IN: u16 = 1;
+pub const CLASS_CH: u16 = 3;
+
+/// Recursion desired flag, set on queries to recursive resolvers.
+pub const FLAG_RD: u16 = 0x0100;
+
+const HEADER_LEN: usize = 12;
+
+/// UDP is lossy, so queries are sent a few times before giving up.
+const ATTEMPTS: u32 = 3;
+const ATTEMPT_TIMEOUT: Duration = Duration::from_millis(1500);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Question {
+    pub name: String,
+    pub qtype: u16,
+    pub qclass: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResourceRecord {
+    pub name: String,
+    pub rtype: u16,
+    pub class: u16,
+    pub ttl: u32,
+    pub data: Vec<u8>,
+}
+
+impl ResourceRecord {
+    /// The address held by an A or AAAA record.
+    pub fn ip(&self) -> Option<IpAddr> {
+        match (self.rtype, self.data.len()) {
+            (TYPE_A, 4) => {
+                let octets: [u8; 4] = self.data[..].try_into().ok()?;
+                Some(IpAddr::V4(Ipv4Addr::from(octets)))
+            }
+            (TYPE_AAAA, 16) => {
+                let octets: [u8; 16] = self.data[..].try_into().ok()?;
+                Some(IpAddr::V6(Ipv6Addr::from(octets)))
+            }
+            _ => None,
+        }
+    }
+
+    /// The name an NS or CNAME record points to.
+    pub fn target(&self) -> Option<String> {
+        if self.rtype != TYPE_NS && self.rtype != TYPE_CNAME {
+            return None;
+        }
+        // Names in parsed records are stored uncompressed
+        read_name(&self.data, 0).ok().map(|(name, _)| name)
+    }
+
+    /// The character strings held by a TXT record.
+    pub fn txt_strings(&self) -> Vec<String> {
+        let mut strings = Vec::new();
+        if self.rtype != TYPE_TXT {
+            return strings;
+        }
+
+        let mut offset = 0;
+        while let Some(&len) = self.data.get(offset) {
+            let Some(bytes) = self.data.get(offset + 1..offset + 1 + len as usize) else {
+                break;
+            };
+            strings.push(String::from_utf8_lossy(bytes).into_owned());
+            offset += 1 + len as usize;
+        }
+
+        strings
+    }
+}
+
+/// A DNS message. The four sections keep their RFC 1035 names; UPDATE
+/// messages (RFC 2136) reuse them as zone, prerequisite, update and additional.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Message {
+    pub id: u16,
+    pub flags: u16,
+    pub questions: Vec<Question>,
+    pub answers: Vec<ResourceRecord>,
+    pub authorities: Vec<ResourceRecord>,
+    pub additionals: Vec<ResourceRecord>,
+}
+
+impl Message {
+    /// A recursive query for a single name with a random ID.
+    pub fn query(name: &str, qtype: u16, qclass: u16) -> Self {
+        Self {
+            id: fastrand::u16(..),
+            flags: FLAG_RD,
+            questions: vec![Question {
+                name: name.to_string(),
+                qtype,
+                qclass,
+            }],
+            ..Self::default()
+        }
+    }
+
+    /// Response code from the header (0 = NOERROR).
+    pub fn rcode(&self) -> u16 {
+        self.flags & 0x000F
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut buf = Vec::with_capacity(512);
+        buf.extend_from_slice(&self.id.to_be_bytes());
+        buf.extend_from_slice(&self.flags.to_be_bytes());
+        for count in [
+            self.questions.len(),
+            self.answers.len(),
+            self.authorities.len(),
+            self.additionals.len(),
+        ] {
+            let count =
+                u16::try_from(count).map_err(|_| Error::Dns("Too many DNS records".to_string()))?;
+            buf.extend_from_slice(&count.to_be_bytes());
+        }
+
+        for question in &self.questions {
+            write_name(&mut buf, &question.name)?;
+            buf.extend_from_slice(&question.qtype.to_be_bytes());
+            buf.extend_from_slice(&question.qclass.to_be_bytes());
+        }
+
+        for record in self
+            .answers
+            .iter()
+            .chain(&self.authorities)
+            .chain(&self.additionals)
+        {
+            write_record(&mut buf, record)?;
+        }
+
+        Ok(buf)
+    }
+
+    pub fn parse(buf: &[u8]) -> Result<Self> {
+        if buf.len() < HEADER_LEN {
+            return Err(Error::Dns("DNS message is too short".to_string()));
+        }
+
+        let read_u16 = |offset: usize| u16::from_be_bytes([buf[offset], buf[offset + 1]]);
+        let mut message = Self {
+            id: read_u16(0),
+            flags: read_u16(2),
+            ..Self::default()
+        };
+        let counts = [read_u16(4), read_u16(6), read_u16(8), read_u16(10)];
+
+        let mut offset = HEADER_LEN;
+        for _ in 0..counts[0] {
+            let (name, next) = read_name(buf, offset)?;
+            let fixed = buf
+                .get(next..next + 4)
+                .ok_or_else(|| Error::Dns("DNS question is truncated".to_string()))?;
+            message.questions.push(Question {
+                name,
+                qtype: u16::from_be_bytes([fixed[0], fixed[1]]),
+                qclass: u16::from_be_bytes([fixed[2], fixed[3]]),
+            });
+            offset = next + 4;
+        }
+
+        for (section, count) in [
+            (&mut message.answers, counts[1]),
+            (&mut message.authorities, counts[2]),
+            (&mut message.additionals, counts[3]),
+        ] {
+            for _ in 0..count {
+                let (record, next) = read_record(buf, offset)?;
+                section.push(record);
+                offset = next;
+            }
+        }
+
+        Ok(message)
+    }
+}
+
+/// Sends a message to a DNS server over UDP and waits for the matching response.
+pub async fn exchange_udp(server: SocketAddr, message: &Message) -> Result<Message> {
+    let bind_addr = match server {
+        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    };
+    let socket = UdpSocket::bind(bind_addr).await?;
+    socket.connect(server).await?;
+
+    let request = message.to_bytes()?;
+    let mut buf = [0u8; 4096];
+    for _ in 0..ATTEMPTS {
+        socket.send(&request).await?;
+
+        // Ignore stray datagrams that don't answer this query
+        let response = timeout(ATTEMPT_TIMEOUT, async {
+            loop {
+                let len = socket.recv(&mut buf).await?;
+                if let Ok(response) = Message::parse(&buf[..len])
+                    && response.id == message.id
+                {
+                    return Ok::<_, std::io::Error>(response);
+                }
+            }
+        })
+        .await;
+
+        match response {
+            Ok(result) => return Ok(result?),
+            Err(_) => continue,
+        }
+    }
+
+    Err(Error::Dns(format!("DNS server {} did not respond", server)))
+}
+
+/// A server given as `host` or `host:port` as `host:port`, with the DNS
+/// port when none is given. IPv6 addresses may be bare or in brackets.
+pub fn server_address(server: &str) -> String {
+    if server.parse::<SocketAddr>().is_ok() {
+        server.to_string()
+    } else if let Ok(ip) = server.parse::<IpAddr>() {
+        SocketAddr::new(ip, 53).to_string()
+    } else if server.contains(':') {
+        server.to_string()
+    } else {
+        format!("{}:53", server)
+    }
+}
+
+/// Writes a name in wire format, without compression.
+pub fn write_name(buf: &mut Vec<u8>, name: &str) -> Result<()> {
+    for label in name
+        .trim_end_matches('.')
+        .split('.')
+        .filter(|l| !l.is_empty())
+    {
+        if label.len() > 63 {
+            return Err(Error::Dns(format!("DNS label '{}' is too long", label)));
+        }
+        buf.push(label.len() as u8);
+        buf.extend_from_slice(label.as_bytes());
+    }
+    buf.push(0);
+    Ok(())
+}
+
+fn write_record(buf: &mut Vec<u8>, record: &ResourceRecord) -> Result<()> {
+    write_name(buf, &record.name)?;
+    buf.extend_from_slice(&record.rtype.to_be_bytes());
+    buf.extend_from_slice(&record.class.to_be_bytes());
+    buf.extend_from_slice(&record.ttl.to_be_bytes());
+    let len = u16::try_from(record.data.len())
+        .map_err(|_| Error::Dns("DNS record is too large".to_string()))?;
+    buf.extend_from_slice(&len.to_be_bytes());
+    buf.extend_from_slice(&record.data);
+    Ok(())
+}
+
+/// Reads a possibly compressed name, returning it and the offset just past it.
+fn read_name(buf: &[u8], mut offset: usize) -> Result<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    // Every pointer must go backwards, which rules out loops
+    let mut limit = offset;
+
+    loop {
+        let len = *buf
+            .get(offset)
+            .ok_or_else(|| Error::Dns("DNS name is truncated".to_string()))?
+            as usize;
+
+        if len & 0xC0 == 0xC0 {
+            let low = *buf
+                .get(offset + 1)
+                .ok_or_else(|| Error::Dns("DNS name is truncated".to_string()))?
+                as usize;
+            let pointer = ((len & 0x3F) << 8) | low;
+            if pointer >= limit {
+                return Err(Error::Dns(
+                    "Invalid DNS name compression pointer".to_string(),
+                ));
+            }
+            end.get_or_insert(offset + 2);
+            limit = pointer;
+            offset = pointer;
+        } else if len == 0 {
+            let end = end.unwrap_or(offset + 1);
+            return Ok((labels.join("."), end));
+        } else {
+            let label = buf
+                .get(offset + 1..offset + 1 + len)
+                .ok_or_else(|| Error::Dns("DNS name is truncated".to_string()))?;
+            labels.push(String::from_utf8_lossy(label).into_owned());
+            offset += 1 + len;
+        }
+    }
+}
+
+fn read_record(buf: &[u8], offset: usize) -> Result<(ResourceRecord, usize)> {
+    let (name, next) = read_name(buf, offset)?;
+    let fixed = buf
+        .get(next..next + 10)
+        .ok_or_else(|| Error::Dns("DNS record is truncated".to_string()))?;
+    let data_len = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+    let data = buf
+        .get(next + 10..next + 10 + data_len)
+        .ok_or_else(|| Error::Dns("DNS record data is truncated".to_string()))?;
+    let rtype = u16::from_be_bytes([fixed[0], fixed[1]]);
+
+    // The name in NS and CNAME data may point elsewhere in the message, so
+    // it is expanded while the whole message is at hand
+    let data = if rtype == TYPE_NS || rtype == TYPE_CNAME {
+        let (target, _) = read_name(buf, next + 10)?;
+        let mut data = Vec::new();
+        write_name(&mut data, &target)?;
+        data
+    } else {
+        data.to_vec()
+    };
+
+    let record = ResourceRecord {
+        name,
+        rtype,
+        class: u16::from_be_bytes([fixed[2], fixed[3]]),
+        ttl: u32::from_be_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]),
+        data,
+    };
+
+    Ok((record, next + 10 + data_len))
+}
@@ -0,0 +1,169 @@
+//! Dynamic DNS services with a plain HTTP update URL: DuckDNS, and Dynu and
+//! No-IP, which speak the dyndns2 protocol.
+//!
+//! These services can point a hostname at an address but can't list records,
+//! so the updater decides from the cache alone when to update them.
+
+use crate::error::{Error, Result};
+use crate::provider::UpdateOnlyProvider;
+use async_trait::async_trait;
+use reqwest::Client;
+use std::net::IpAddr;
+
+/// The User-Agent sent with updates. No-IP blocks clients without one.
+const USER_AGENT: &str = concat!("cloudflare-ddns/", env!("CARGO_PKG_VERSION"));
+
+/// Username and password for a dyndns2 service.
+#[derive(Debug, Clone)]
+pub struct Login {
+    pub username: String,
+    pub password: String,
+}
+
+/// Updates subdomains of duckdns.org.
+pub struct DuckDns {
+    client: Client,
+    token: String,
+}
+
+impl DuckDns {
+    pub fn new(client: Client, token: String) -> Self {
+        Self { client, token }
+    }
+}
+
+#[async_trait]
+impl UpdateOnlyProvider for DuckDns {
+    fn name(&self) -> &'static str {
+        "DuckDNS"
+    }
+
+    async fn set_address(&self, hostname: &str, ip: IpAddr) -> Result<()> {
+        // DuckDNS wants the subdomain on its own
+        let domain = hostname.strip_suffix(".duckdns.org").unwrap_or(hostname);
+        let ip_param = if ip.is_ipv4() { "ip" } else { "ipv6" };
+
+        let body = self
+            .client
+            .get("https://www.duckdns.org/update")
+            .query(&[
+                ("domains", domain),
+                ("token", &self.token),
+                (ip_param, &ip.to_string()),
+            ])
+            .header("User-Agent", USER_AGENT)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+
+        // The response is a bare "OK" or "KO"
+        if body.trim() != "OK" {
+            return Err(Error::Provider(format!(
+                "DuckDNS rejected the update of '{}', check the token and that the domain belongs to it",
+                hostname
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// A service speaking the dyndns2 protocol that DynDNS introduced: a GET of
+/// `/nic/update` with basic auth, answered with a status code in plain text.
+pub struct Dyndns2 {
+    client: Client,
+    name: &'static str,
+    base_url: &'static str,
+    login: Login,
+    /// Whether the address family that isn't being updated has to be left
+    /// alone explicitly. Dynu otherwise sets the IPv4 address of an IPv6-only
+    /// update to the address the request came from.
+    keep_other_family: bool,
+}
+
+impl Dyndns2 {
+    pub fn dynu(client: Client, login: Login) -> Self {
+        Self {
+            client,
+            name: "Dynu",
+            base_url: "https://api.dynu.com",
+            login,
+            keep_other_family: true,
+        }
+    }
+
+    pub fn noip(client: Client, login: Login) -> Self {
+        Self {
+            client,
+            name: "No-IP",
+            base_url: "https://dynupdate.no-ip.com",
+            login,
+            keep_other_family: false,
+        }
+    }
+}
+
+#[async_trait]
+impl UpdateOnlyProvider for Dyndns2 {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    async fn set_address(&self, hostname: &str, ip: IpAddr) -> Result<()> {
+        let (ip_param, other_param) = if ip.is_ipv4() {
+            ("myip", "myipv6")
+        } else {
+            ("myipv6", "myip")
+        };
+        let ip = ip.to_string();
+        let mut query = vec![("hostname", hostname), (ip_param, &ip)];
+        if self.keep_other_family {
+            query.push((other_param, "no"));
+        }
+
+        let response = self
+            .client
+            .get(format!("{}/nic/update", self.base_url))
+            .query(&query)
+            .basic_auth(&self.login.username, Some(&self.login.password))
+            .header("User-Agent", USER_AGENT)
+            .send()
+            .await?;
+        // 5xx responses are left to the retry policy, the rest explain
+        // themselves in the body
+        let response = if response.status().is_server_error() {
+            response.error_for_status()?
+        } else {
+            response
+        };
+        let body = response.text().await?;
+
+        let code = body.split_whitespace().next().unwrap_or_default();
+        match code {
+            "good" | "nochg" => Ok(()),
+            "badauth" => Err(Error::Provider(format!(
+                "{} rejected the username or password",
+                self.name
+            ))),
+            "nohost" => Err(Error::Provider(format!(
+                "{} has no hostname '{}' in this account",
+                self.name, hostname
+            ))),
+            "abuse" => Err(Error::Provider(format!(
+                "{} blocked updates of '{}' for sending too many",
+                self.name, hostname
+            ))),
+            "911" | "dnserr" => Err(Error::Provider(format!(
+                "{} had a server error, try again later",
+                self.name
+            ))),
+            _ => Err(Error::Provider(format!(
+                "{} rejected the update of '{}': {}",
+                self.name,
+                hostname,
+                body.trim()
+            ))),
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use crate::error::{Error, Result};
+use crate::notify::{Event, EventKind, Notifier};
+use async_trait::async_trait;
+use lettre::message::Mailbox;
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::Deserialize;
+use std::str::FromStr;
+use std::time::Duration;
+
+const SMTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How the connection to the SMTP server is secured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// Upgrade a plain connection with STARTTLS (usually port 587).
+    #[default]
+    StartTls,
+    /// Connect over TLS from the start (usually port 465).
+    Tls,
+    /// Unencrypted, for relays on a trusted network only.
+    None,
+}
+
+impl SmtpTls {
+    pub fn default_port(self) -> u16 {
+        match self {
+            Self::StartTls => 587,
+            Self::Tls => 465,
+            Self::None => 25,
+        }
+    }
+}
+
+impl FromStr for SmtpTls {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "starttls" => Ok(Self::StartTls),
+            "tls" => Ok(Self::Tls),
+            "none" => Ok(Self::None),
+            other => Err(Error::Config(format!(
+                "Invalid SMTP_TLS '{}', expected 'starttls', 'tls' or 'none'",
+                other
+            ))),
+        }
+    }
+}
+
+/// Settings for the email notifier.
+#[derive(Debug, Clone)]
+pub struct EmailConfig {
+    pub host: String,
+    pub port: u16,
+    pub tls: SmtpTls,
+    /// Username and password, if the server requires authentication.
+    pub credentials: Option<(String, String)>,
+    pub from: Mailbox,
+    pub to: Vec<Mailbox>,
+}
+
+/// Sends an email through an SMTP server when a record changes, starts
+/// failing or recovers, and when the daemon stops.
+pub struct Email {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+}
+
+impl Email {
+    pub fn new(config: EmailConfig) -> Result<Self> {
+        let builder = match config.tls {
+            SmtpTls::StartTls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)?
+            }
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)?,
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host),
+        };
+        let mut builder = builder.port(config.port).timeout(Some(SMTP_TIMEOUT));
+        if let Some((username, password)) = config.credentials {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+
+        Ok(Self {
+            transport: builder.build(),
+            from: config.from,
+            to: config.to,
+        })
+    }
+}
+
+#[async_trait]
+impl Notifier for Email {
+    fn name(&self) -> &'static str {
+        "Email"
+    }
+
+    /// Mails about the start of a failure streak, not every failed cycle.
+    fn wants(&self, event: &Event<'_>) -> bool {
+        !event.is_repeated_failure()
+    }
+
+    async fn notify(&self, event: &Event<'_>) -> Result<()> {
+        let record = format!("{} ({})", event.record_name, event.record_type);
+        let (subject, body) = match event.kind {
+            EventKind::IpChanged { old_ip, new_ip, .. } => (
+                format!("{} now points to {}", event.record_name, new_ip),
+                format!(
+                    "The DNS record {} was updated.\n\nOld IP: {}\nNew IP: {}\nTime: {}\n",
+                    record,
+                    old_ip.unwrap_or("none (record created)"),
+                    new_ip,
+                    event.timestamp.to_rfc3339()
+                ),
+            ),
+            EventKind::UpdateFailed { error, .. } => (
+                format!("{} failed to update", event.record_name),
+                format!(
+                    "The DNS record {} could not be updated.\n\nError: {}\nTime: {}\n\nYou will get another email once it updates again.\n",
+                    record,
+                    error,
+                    event.timestamp.to_rfc3339()
+                ),
+            ),
+            EventKind::Recovered { failures } => (
+                format!("{} is updating again", event.record_name),
+                format!(
+                    "The DNS record {} was updated successfully after failing {} time(s) in a row.\n\nTime: {}\n",
+                    record,
+                    failures,
+                    event.timestamp.to_rfc3339()
+                ),
+            ),
+            EventKind::ShuttingDown => (
+                "Daemon is shutting down".to_string(),
+                format!(
+                    "{}.\n\nTime: {}\n",
+                    event.message(),
+                    event.timestamp.to_rfc3339()
+                ),
+            ),
+        };
+
+        let mut message = Message::builder()
+            .from(self.from.clone())
+            .subject(format!("[cloudflare-ddns] {}", subject))
+            .header(ContentType::TEXT_PLAIN);
+        for to in &self.to {
+            message = message.to(to.clone());
+        }
+
+        self.transport.send(message.body(body)?).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,173 @@
+//! The error type of the library.
+//!
+//! Errors are grouped by where they come from, so that callers can tell a
+//! configuration mistake from a failed address detection or a request that
+//! Cloudflare rejected. Context added on the way up wraps the original error
+//! in [`Error::Context`]; [`Error::root`] gets back to it.
+
+use crate::cloudflare::{ApiError, RateLimitedError};
+use std::error::Error as StdError;
+use std::fmt::{self, Display};
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The configuration is missing a setting or has an invalid one.
+    #[error("{0}")]
+    Config(String),
+    /// The public address couldn't be detected.
+    #[error("{0}")]
+    IpDetection(String),
+    /// Cloudflare answered a request with an error.
+    #[error(transparent)]
+    CloudflareApi(#[from] ApiError),
+    /// Cloudflare kept rate limiting the requests.
+    #[error(transparent)]
+    RateLimited(#[from] RateLimitedError),
+    /// A DNS provider rejected a request or gave an unexpected answer.
+    #[error("{0}")]
+    Provider(String),
+    /// A DNS query or its answer was invalid.
+    #[error("{0}")]
+    Dns(String),
+    /// The cache couldn't be read, written or locked.
+    #[error("{0}")]
+    Cache(String),
+    /// The update history couldn't be read or written.
+    #[error("{0}")]
+    History(String),
+    /// The changes made through the admin API couldn't be read or written.
+    #[error("{0}")]
+    State(String),
+    /// A notification couldn't be delivered.
+    #[error("{0}")]
+    Notification(String),
+    /// A hook command failed or didn't finish in time.
+    #[error("{0}")]
+    Hook(String),
+    /// A failover health check found the primary address unreachable.
+    #[error("{0}")]
+    HealthCheck(String),
+    /// Records failed to update during a cycle; their errors have been
+    /// reported one by one.
+    #[error("{failed} of {checked} records failed to update")]
+    RecordsFailed { failed: usize, checked: usize },
+    /// An HTTP request failed without an answer, or with a server error.
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// An error of another library.
+    #[error(transparent)]
+    Other(Box<dyn StdError + Send + Sync>),
+    /// What was being done when `source` happened. Only `message` is shown.
+    #[error("{message}")]
+    Context {
+        message: String,
+        #[source]
+        source: Box<Error>,
+    },
+}
+
+impl Error {
+    /// Wraps an error of another library.
+    pub fn other(error: impl StdError + Send + Sync + 'static) -> Self {
+        Self::Other(Box::new(error))
+    }
+
+    /// Wraps the error in `message`, like [`Context::context`].
+    pub fn context(self, message: impl Display) -> Self {
+        Self::Context {
+            message: message.to_string(),
+            source: Box::new(self),
+        }
+    }
+
+    /// The error without the context added to it, to branch on its kind.
+    pub fn root(&self) -> &Error {
+        let mut error = self;
+        while let Self::Context { source, .. } = error {
+            error = source;
+        }
+        error
+    }
+
+    /// This error and the errors it wraps, outermost first.
+    pub fn chain(&self) -> impl Iterator<Item = &Error> {
+        std::iter::successors(Some(self), |error| match error {
+            Self::Context { source, .. } => Some(source),
+            _ => None,
+        })
+    }
+
+    /// The message of this error followed by those of its causes, as in
+    /// `Failed to read the cache: No such file or directory (os error 2)`.
+    pub fn report(&self) -> Report<'_> {
+        Report(self)
+    }
+}
+
+/// Shows an error with all of its causes, see [`Error::report`].
+pub struct Report<'a>(&'a Error);
+
+impl Display for Report<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)?;
+        let mut source = self.0.source();
+        while let Some(cause) = source {
+            write!(f, ": {}", cause)?;
+            source = cause.source();
+        }
+        Ok(())
+    }
+}
+
+macro_rules! from_other {
+    ($($error:ty),* $(,)?) => {
+        $(
+            impl From<$error> for Error {
+                fn from(error: $error) -> Self {
+                    Self::other(error)
+                }
+            }
+        )*
+    };
+}
+
+from_other!(
+    serde_json::Error,
+    toml::de::Error,
+    url::ParseError,
+    chrono::ParseError,
+    base64::DecodeError,
+    keyring::Error,
+    lettre::address::AddressError,
+    lettre::error::Error,
+    lettre::transport::smtp::Error,
+    std::net::AddrParseError,
+    std::num::ParseIntError,
+    regex::Error,
+    tokio::time::error::Elapsed,
+    tokio::task::JoinError,
+);
+
+#[cfg(feature = "history")]
+from_other!(rusqlite::Error);
+
+/// Adds context to errors, like `anyhow::Context`.
+pub trait Context<T> {
+    fn context(self, message: impl Display) -> Result<T>;
+
+    fn with_context<M: Display>(self, message: impl FnOnce() -> M) -> Result<T>;
+}
+
+impl<T, E: Into<Error>> Context<T> for Result<T, E> {
+    fn context(self, message: impl Display) -> Result<T> {
+        self.map_err(|e| e.into().context(message))
+    }
+
+    fn with_context<M: Display>(self, message: impl FnOnce() -> M) -> Result<T> {
+        self.map_err(|e| e.into().context(message()))
+    }
+}
@@ -0,0 +1,149 @@
+//! Health checks that move a record to a backup address while its primary
+//! address is unreachable, and back once it answers again.
+//!
+//! The primary address is the one the record would normally get. Each cycle
+//! it is checked with a TCP connection, an HTTP request or a ping; after the
+//! configured number of failed checks in a row the record points at the
+//! backup address instead, until a check succeeds.
+
+use crate::error::{Error, Result};
+use reqwest::Client;
+use std::fmt;
+use std::net::IpAddr;
+use std::process::Stdio;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::process::Command;
+use tokio::time::timeout;
+
+/// How long a single check may take before it counts as failed.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The failover settings of a record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failover {
+    pub check: HealthCheck,
+    /// Address the record points at while the primary is down.
+    pub backup: IpAddr,
+    /// Failed checks in a row before switching to the backup.
+    pub failures: u32,
+}
+
+/// How the primary address is checked. `{ip}` in the host or URL is
+/// replaced by the primary address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HealthCheck {
+    /// `tcp:host:port`: a TCP connection can be opened.
+    Tcp { host: String, port: u16 },
+    /// `http://...` or `https://...`: the URL answers with a 2xx status.
+    Http(String),
+    /// `ping:host`: the host answers a ping, sent with the system's `ping`.
+    Ping(String),
+}
+
+impl FromStr for HealthCheck {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if s.starts_with("http://") || s.starts_with("https://") {
+            return Ok(Self::Http(s.to_string()));
+        }
+        if let Some(host) = s.strip_prefix("ping:")
+            && !host.is_empty()
+        {
+            return Ok(Self::Ping(host.to_string()));
+        }
+        if let Some(target) = s.strip_prefix("tcp:")
+            && let Some((host, port)) = target.rsplit_once(':')
+            && let Ok(port) = port.parse()
+        {
+            let host = host.trim_start_matches('[').trim_end_matches(']');
+            return Ok(Self::Tcp {
+                host: host.to_string(),
+                port,
+            });
+        }
+        Err(Error::Config(format!(
+            "Invalid health check '{}', expected 'tcp:<host>:<port>', 'ping:<host>' or an http(s):// URL",
+            s
+        )))
+    }
+}
+
+impl fmt::Display for HealthCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp { host, port } => write!(f, "tcp:{}:{}", host, port),
+            Self::Http(url) => write!(f, "{}", url),
+            Self::Ping(host) => write!(f, "ping:{}", host),
+        }
+    }
+}
+
+impl HealthCheck {
+    /// Checks the target with `primary` filled in for `{ip}`.
+    pub async fn probe(&self, client: &Client, primary: IpAddr) -> Result<()> {
+        let ip = primary.to_string();
+        match self {
+            Self::Tcp { host, port } => {
+                let host = host.replace("{ip}", &ip);
+                let connect = TcpStream::connect((host.as_str(), *port));
+                match timeout(CHECK_TIMEOUT, connect).await {
+                    Ok(Ok(_)) => Ok(()),
+                    Ok(Err(e)) => Err(Error::HealthCheck(format!(
+                        "can't connect to {}:{}: {}",
+                        host, port, e
+                    ))),
+                    Err(_) => Err(Error::HealthCheck(format!(
+                        "connecting to {}:{} timed out",
+                        host, port
+                    ))),
+                }
+            }
+            Self::Http(url) => {
+                // IPv6 addresses go in brackets in URLs
+                let url = match primary {
+                    IpAddr::V6(_) => url.replace("{ip}", &format!("[{}]", ip)),
+                    IpAddr::V4(_) => url.replace("{ip}", &ip),
+                };
+                let response = client
+                    .get(&url)
+                    .timeout(CHECK_TIMEOUT)
+                    .send()
+                    .await
+                    .map_err(|e| Error::HealthCheck(format!("{} failed: {}", url, e)))?;
+                if !response.status().is_success() {
+                    return Err(Error::HealthCheck(format!(
+                        "{} answered with {}",
+                        url,
+                        response.status()
+                    )));
+                }
+                Ok(())
+            }
+            Self::Ping(host) => {
+                let host = host.replace("{ip}", &ip);
+                let mut command = Command::new("ping");
+                if cfg!(windows) {
+                    command.args(["-n", "1", "-w", "5000"]);
+                } else {
+                    command.args(["-c", "1", "-W", "5"]);
+                }
+                let status = command
+                    .arg(&host)
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .kill_on_drop(true)
+                    .status();
+                match timeout(CHECK_TIMEOUT + Duration::from_secs(1), status).await {
+                    Ok(Ok(status)) if status.success() => Ok(()),
+                    Ok(Ok(_)) => Err(Error::HealthCheck(format!("{} doesn't answer pings", host))),
+                    Ok(Err(e)) => Err(Error::HealthCheck(format!("can't run ping: {}", e))),
+                    Err(_) => Err(Error::HealthCheck(format!("pinging {} timed out", host))),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use crate::error::Result;
+use crate::notify::{Event, EventKind, Notifier};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+struct GotifyMessage {
+    title: String,
+    message: String,
+    priority: u8,
+}
+
+/// Pushes a message to a Gotify server when a record changes, starts
+/// failing or recovers, or the daemon shuts down.
+pub struct Gotify {
+    client: Client,
+    url: String,
+    /// Application token created in the Gotify web UI.
+    token: String,
+}
+
+impl Gotify {
+    pub fn new(client: Client, url: String, token: String) -> Self {
+        Self { client, url, token }
+    }
+}
+
+#[async_trait]
+impl Notifier for Gotify {
+    fn name(&self) -> &'static str {
+        "Gotify"
+    }
+
+    fn wants(&self, event: &Event<'_>) -> bool {
+        !event.is_repeated_failure()
+    }
+
+    async fn notify(&self, event: &Event<'_>) -> Result<()> {
+        // Gotify clients alert loudly from priority 8 up
+        let priority = match event.kind {
+            EventKind::UpdateFailed { .. } => 8,
+            EventKind::IpChanged { .. } | EventKind::Recovered { .. } => 5,
+            EventKind::ShuttingDown => 2,
+        };
+
+        self.client
+            .post(format!("{}/message", self.url.trim_end_matches('/')))
+            .header("X-Gotify-Key", &self.token)
+            .json(&GotifyMessage {
+                title: event.title(),
+                message: event.message(),
+                priority,
+            })
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
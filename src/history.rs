@@ -0,0 +1,173 @@
+//! History of address changes, kept in an SQLite database so that ISP
+//! reassignments and flapping records can be looked into later.
+//!
+//! SQLite support is compiled in with the `history` feature, which is on by
+//! default. Without it, [`History::open`] fails.
+
+use crate::error::{Error, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Why a record was changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Trigger {
+    /// The record didn't exist and was created.
+    Created,
+    /// The detected public IP changed.
+    IpChanged,
+    /// The record no longer had the address it was last set to, so it was
+    /// changed outside this tool.
+    Drift,
+    /// `--force` sent the address.
+    Forced,
+}
+
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Created => "created",
+            Self::IpChanged => "ip_changed",
+            Self::Drift => "drift",
+            Self::Forced => "forced",
+        })
+    }
+}
+
+impl FromStr for Trigger {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "created" => Ok(Self::Created),
+            "ip_changed" => Ok(Self::IpChanged),
+            "drift" => Ok(Self::Drift),
+            "forced" => Ok(Self::Forced),
+            other => Err(Error::History(format!(
+                "Invalid history trigger '{}'",
+                other
+            ))),
+        }
+    }
+}
+
+/// A single change of a record's address.
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryEntry {
+    pub time: DateTime<Utc>,
+    pub record_name: String,
+    pub record_type: String,
+    /// The address before the change, unknown for new records and for
+    /// records on update-only providers that weren't cached.
+    pub old_ip: Option<String>,
+    pub new_ip: String,
+    pub trigger: Trigger,
+}
+
+/// An open history database.
+pub struct History {
+    #[cfg(feature = "history")]
+    conn: rusqlite::Connection,
+    #[cfg(not(feature = "history"))]
+    never: std::convert::Infallible,
+}
+
+#[cfg(feature = "history")]
+impl History {
+    /// Opens the database at `path`, creating it (and its directory) if it
+    /// doesn't exist yet.
+    pub fn open(path: &Path) -> Result<Self> {
+        use crate::error::Context;
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let conn = rusqlite::Connection::open(path)
+            .with_context(|| format!("Failed to open history database {}", path.display()))?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS changes (
+                id INTEGER PRIMARY KEY,
+                time TEXT NOT NULL,
+                record_name TEXT NOT NULL,
+                record_type TEXT NOT NULL,
+                old_ip TEXT,
+                new_ip TEXT NOT NULL,
+                trigger TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS changes_record_name ON changes (record_name);",
+        )?;
+        Ok(Self { conn })
+    }
+
+    /// Appends a change.
+    pub fn add(&self, entry: &HistoryEntry) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO changes (time, record_name, record_type, old_ip, new_ip, trigger)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            (
+                entry.time.to_rfc3339(),
+                &entry.record_name,
+                &entry.record_type,
+                &entry.old_ip,
+                &entry.new_ip,
+                entry.trigger.to_string(),
+            ),
+        )?;
+        Ok(())
+    }
+
+    /// The latest `limit` changes, newest first, optionally only those of
+    /// one record name.
+    pub fn query(&self, record_name: Option<&str>, limit: usize) -> Result<Vec<HistoryEntry>> {
+        let mut statement = self.conn.prepare(
+            "SELECT time, record_name, record_type, old_ip, new_ip, trigger FROM changes
+             WHERE ?1 IS NULL OR record_name = ?1
+             ORDER BY id DESC LIMIT ?2",
+        )?;
+        let rows = statement.query_map((record_name, limit as i64), |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get(1)?,
+                row.get(2)?,
+                row.get(3)?,
+                row.get(4)?,
+                row.get::<_, String>(5)?,
+            ))
+        })?;
+
+        let mut entries = Vec::new();
+        for row in rows {
+            let (time, record_name, record_type, old_ip, new_ip, trigger) = row?;
+            entries.push(HistoryEntry {
+                time: DateTime::parse_from_rfc3339(&time)?.with_timezone(&Utc),
+                record_name,
+                record_type,
+                old_ip,
+                new_ip,
+                trigger: trigger.parse()?,
+            });
+        }
+        Ok(entries)
+    }
+}
+
+#[cfg(not(feature = "history"))]
+impl History {
+    /// Fails, since this build has no SQLite support.
+    pub fn open(_path: &Path) -> Result<Self> {
+        Err(Error::Config(
+            "HISTORY_PATH needs a build with the `history` feature".to_string(),
+        ))
+    }
+
+    pub fn add(&self, _entry: &HistoryEntry) -> Result<()> {
+        match self.never {}
+    }
+
+    pub fn query(&self, _record_name: Option<&str>, _limit: usize) -> Result<Vec<HistoryEntry>> {
+        match self.never {}
+    }
+}
@@ -0,0 +1,135 @@
+//! User commands run when a record changes, fails or is processed
+//! successfully, for restarting services or updating firewalls.
+//!
+//! Commands run through the shell (`sh -c`, or `cmd /C` on Windows) with
+//! `DDNS_*` environment variables describing the event.
+
+use crate::error::{Error, Result};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+use tracing::{info, warn};
+
+/// How long a hook may run before it is killed.
+const HOOK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The configured hook commands.
+#[derive(Debug, Clone, Default)]
+pub struct Hooks {
+    /// Run after a record is updated to a new address or created.
+    pub on_change: Option<String>,
+    /// Run after a record fails to update.
+    pub on_failure: Option<String>,
+    /// Run after a record is processed without error, changed or not.
+    pub on_success: Option<String>,
+}
+
+/// What a hook is run for.
+#[derive(Debug, Clone, Copy)]
+pub enum HookEvent<'a> {
+    Change {
+        old_ip: Option<&'a str>,
+        new_ip: &'a str,
+    },
+    Failure {
+        error: &'a str,
+    },
+    Success {
+        ip: &'a str,
+        changed: bool,
+    },
+}
+
+impl HookEvent<'_> {
+    /// The value of `DDNS_EVENT`.
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Change { .. } => "change",
+            Self::Failure { .. } => "failure",
+            Self::Success { .. } => "success",
+        }
+    }
+}
+
+impl Hooks {
+    /// Runs the hook for the event, if one is configured. Hooks that fail are
+    /// logged but never fail the run.
+    pub async fn run(&self, record_name: &str, record_type: &str, event: HookEvent<'_>) {
+        let command = match event {
+            HookEvent::Change { .. } => &self.on_change,
+            HookEvent::Failure { .. } => &self.on_failure,
+            HookEvent::Success { .. } => &self.on_success,
+        };
+        let Some(command) = command else {
+            return;
+        };
+
+        let mut env = vec![
+            ("DDNS_EVENT", event.name().to_string()),
+            ("DDNS_RECORD_NAME", record_name.to_string()),
+            ("DDNS_RECORD_TYPE", record_type.to_string()),
+        ];
+        match event {
+            HookEvent::Change { old_ip, new_ip } => {
+                env.push(("DDNS_OLD_IP", old_ip.unwrap_or_default().to_string()));
+                env.push(("DDNS_NEW_IP", new_ip.to_string()));
+            }
+            HookEvent::Failure { error } => env.push(("DDNS_ERROR", error.to_string())),
+            HookEvent::Success { ip, changed } => {
+                env.push(("DDNS_IP", ip.to_string()));
+                env.push(("DDNS_CHANGED", changed.to_string()));
+            }
+        }
+
+        match run_command(command, &env).await {
+            Ok(()) => info!(
+                "🪝 Ran on_{} hook for {} ({})",
+                event.name(),
+                record_name,
+                record_type
+            ),
+            Err(e) => warn!("⚠️  on_{} hook failed: {}", event.name(), e),
+        }
+    }
+}
+
+async fn run_command(command: &str, env: &[(&str, String)]) -> Result<()> {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    let run = shell
+        .arg(command)
+        .envs(env.iter().map(|(name, value)| (name, value)))
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+
+    let output = tokio::time::timeout(HOOK_TIMEOUT, run)
+        .await
+        .map_err(|_| {
+            Error::Hook(format!(
+                "'{}' didn't finish within {} seconds",
+                command,
+                HOOK_TIMEOUT.as_secs()
+            ))
+        })?
+        .map_err(|e| Error::Hook(format!("Failed to run '{}': {}", command, e)))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(match stderr.trim() {
+            "" => Error::Hook(format!("'{}' failed with {}", command, output.status)),
+            stderr => Error::Hook(format!(
+                "'{}' failed with {}: {}",
+                command, output.status, stderr
+            )),
+        });
+    }
+    Ok(())
+}
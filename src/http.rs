@@ -0,0 +1,87 @@
+//! Settings shared by every HTTP client: the Cloudflare API, the IP services,
+//! the other DDNS providers and the notifiers.
+
+use crate::error::{Context, Result};
+use reqwest::redirect::Policy;
+use reqwest::{Client, ClientBuilder, NoProxy, Proxy};
+use std::time::Duration;
+
+/// Timeouts and connection settings of the HTTP clients.
+#[derive(Debug, Clone)]
+pub struct HttpConfig {
+    /// Time allowed to establish a connection.
+    pub connect_timeout: Duration,
+    /// Time allowed for a whole request, from connecting until the response
+    /// has been read.
+    pub timeout: Duration,
+    /// How long idle connections are kept open for reuse. Zero closes them
+    /// after every request.
+    pub keep_alive: Duration,
+    /// Redirects followed before a request fails. Zero doesn't follow any.
+    pub max_redirects: usize,
+    /// Proxy for every request. Without one, the proxies in `HTTPS_PROXY`,
+    /// `HTTP_PROXY` and `ALL_PROXY` are used.
+    pub proxy: Option<Proxy>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(10),
+            timeout: Duration::from_secs(30),
+            keep_alive: Duration::from_secs(90),
+            max_redirects: 10,
+            proxy: None,
+        }
+    }
+}
+
+impl HttpConfig {
+    /// A client builder with these settings, for clients that need more.
+    pub fn builder(&self) -> ClientBuilder {
+        let builder = Client::builder()
+            .connect_timeout(self.connect_timeout)
+            .timeout(self.timeout)
+            .redirect(match self.max_redirects {
+                0 => Policy::none(),
+                max => Policy::limited(max),
+            });
+
+        let builder = match &self.proxy {
+            Some(proxy) => builder.proxy(proxy.clone()),
+            None => builder,
+        };
+
+        if self.keep_alive.is_zero() {
+            builder.pool_max_idle_per_host(0)
+        } else {
+            builder.pool_idle_timeout(self.keep_alive)
+        }
+    }
+
+    /// A client with these settings.
+    ///
+    /// Panics like [`Client::new`] if the TLS backend can't be initialized.
+    pub fn client(&self) -> Client {
+        self.builder()
+            .build()
+            .expect("failed to initialize the HTTP client")
+    }
+}
+
+/// Parses a proxy URL such as `http://proxy.example.com:3128` or, with the
+/// `socks` feature, `socks5://127.0.0.1:1080`. Hosts listed in `NO_PROXY`
+/// are still reached directly.
+pub fn parse_proxy(url: &str) -> Result<Proxy> {
+    let proxy = Proxy::all(url).with_context(|| {
+        if url.starts_with("socks") && !cfg!(feature = "socks") {
+            format!(
+                "Invalid PROXY_URL '{}': SOCKS proxies need a build with the `socks` feature",
+                url
+            )
+        } else {
+            format!("Invalid PROXY_URL '{}'", url)
+        }
+    })?;
+    Ok(proxy.no_proxy(NoProxy::from_env()))
+}
@@ -0,0 +1,227 @@
+//! The `init` wizard, which asks for an API token, the zone and the records
+//! to keep updated, and writes a configuration file for them.
+
+use crate::commands::read_token;
+use crate::logging::say;
+use anyhow::{Context, Result, anyhow};
+use cloudflare_ddns::cloudflare::CloudflareClient;
+use cloudflare_ddns::credentials;
+use cloudflare_ddns::provider::DnsProvider;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+/// File written when no `--config` path is given.
+const DEFAULT_PATH: &str = "config.toml";
+
+/// A record picked in the wizard, with every type it should have.
+struct Choice {
+    name: String,
+    types: Vec<String>,
+}
+
+/// Runs the wizard and writes the configuration to `config_path`.
+pub async fn run(config_path: Option<&Path>) -> Result<()> {
+    let path = config_path.unwrap_or(Path::new(DEFAULT_PATH));
+    if path.exists()
+        && !confirm(
+            &format!("{} already exists, overwrite it?", path.display()),
+            false,
+        )?
+    {
+        return Err(anyhow!("Left {} unchanged", path.display()));
+    }
+
+    say!("🔑 Create an API token with Zone:Zone:Read and Zone:DNS:Edit permissions at");
+    println!("   https://dash.cloudflare.com/profile/api-tokens");
+    let token = read_token()?;
+    let client = CloudflareClient::new(token.clone());
+    client.check_credentials().await?;
+
+    let zones = client.list_zones().await?;
+    if zones.is_empty() {
+        return Err(anyhow!(
+            "The API token can't see any zones, it needs the Zone:Zone:Read permission"
+        ));
+    }
+    say!("🗂️  Zones:");
+    for (number, zone) in zones.iter().enumerate() {
+        println!("   {}) {}", number + 1, zone.name);
+    }
+    let zone = match zones.len() {
+        1 => &zones[0],
+        count => &zones[pick("Zone", count)? - 1],
+    };
+    say!("🗂️  Using zone {}", zone.name);
+
+    let records: Vec<_> = client
+        .get_dns_records(&zone.id, None)
+        .await?
+        .into_iter()
+        .filter(|r| r.record_type == "A" || r.record_type == "AAAA")
+        .collect();
+    if records.is_empty() {
+        say!("📋 The zone has no A or AAAA records yet");
+    } else {
+        say!("📋 Address records:");
+        for (number, record) in records.iter().enumerate() {
+            println!(
+                "   {}) {:<4} {} -> {}",
+                number + 1,
+                record.record_type,
+                record.name,
+                record.content
+            );
+        }
+    }
+
+    let answer =
+        prompt("Records to update (numbers, or new names to create, separated by commas)")?;
+    let mut choices: Vec<Choice> = Vec::new();
+    let mut creates = false;
+    for item in answer.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+        let (name, record_type) = match item.parse::<usize>() {
+            Ok(number) if (1..=records.len()).contains(&number) => {
+                let record = &records[number - 1];
+                (record.name.clone(), record.record_type.clone())
+            }
+            Ok(number) => return Err(anyhow!("There is no record {}", number)),
+            Err(_) => {
+                creates = true;
+                let name = if item == zone.name || item.ends_with(&format!(".{}", zone.name)) {
+                    item.to_string()
+                } else {
+                    format!("{}.{}", item, zone.name)
+                };
+                (name, "A".to_string())
+            }
+        };
+        match choices.iter_mut().find(|c| c.name == name) {
+            Some(choice) if !choice.types.contains(&record_type) => choice.types.push(record_type),
+            Some(_) => {}
+            None => choices.push(Choice {
+                name,
+                types: vec![record_type],
+            }),
+        }
+    }
+    if choices.is_empty() {
+        return Err(anyhow!("No records chosen"));
+    }
+
+    let use_keyring = confirm(
+        "Save the API token in the OS keyring instead of the config file?",
+        false,
+    )?;
+    if use_keyring {
+        credentials::store_token(&token)?;
+        say!("🔑 Saved the API token in the OS keyring");
+    }
+
+    let content = config_file(
+        (!use_keyring).then_some(token.as_str()),
+        &zone.id,
+        &zone.name,
+        &choices,
+        creates,
+    );
+    write_private(path, &content).with_context(|| format!("Failed to write {}", path.display()))?;
+
+    say!("📝 Wrote {}", path.display());
+    println!(
+        "   Try it with: cloudflare-ddns --config {} check",
+        path.display()
+    );
+    Ok(())
+}
+
+/// The configuration file for the choices made.
+fn config_file(
+    token: Option<&str>,
+    zone_id: &str,
+    zone_name: &str,
+    choices: &[Choice],
+    creates: bool,
+) -> String {
+    let mut content = String::from(
+        "# Written by `cloudflare-ddns init`. See config.example.toml for every setting.\n\n",
+    );
+    if let Some(token) = token {
+        let _ = writeln!(content, "api_token = {}", quote(token));
+    }
+    let _ = writeln!(content, "zone_id = {} # {}", quote(zone_id), zone_name);
+    if creates {
+        content.push_str("create_if_missing = true\n");
+    }
+
+    for choice in choices {
+        let _ = write!(content, "\n[[records]]\nname = {}\n", quote(&choice.name));
+        match choice.types.as_slice() {
+            [a] if a == "A" => {}
+            [single] => {
+                let _ = writeln!(content, "type = {}", quote(single));
+            }
+            types => {
+                let types: Vec<String> = types.iter().map(|t| quote(t)).collect();
+                let _ = writeln!(content, "type = [{}]", types.join(", "));
+            }
+        }
+    }
+    content
+}
+
+/// A TOML string literal.
+fn quote(value: &str) -> String {
+    toml::Value::String(value.to_string()).to_string()
+}
+
+/// Writes the file so that only its owner can read it, since it may hold the
+/// API token.
+fn write_private(path: &Path, content: &str) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(content.as_bytes())
+}
+
+/// Asks a question and returns the trimmed answer.
+fn prompt(question: &str) -> Result<String> {
+    print!("{}: ", question);
+    io::stdout().flush()?;
+    let mut line = String::new();
+    if io::stdin().lock().read_line(&mut line)? == 0 {
+        return Err(anyhow!("No answer given"));
+    }
+    Ok(line.trim().to_string())
+}
+
+/// Asks for a number from 1 to `count`.
+fn pick(question: &str, count: usize) -> Result<usize> {
+    loop {
+        match prompt(&format!("{} [1-{}]", question, count))?.parse() {
+            Ok(number) if (1..=count).contains(&number) => return Ok(number),
+            _ => println!("   Enter a number from 1 to {}", count),
+        }
+    }
+}
+
+/// Asks a yes/no question, with `default` for an empty answer.
+fn confirm(question: &str, default: bool) -> Result<bool> {
+    let hint = if default { "Y/n" } else { "y/N" };
+    loop {
+        match prompt(&format!("{} [{}]", question, hint))?
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "" => return Ok(default),
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => println!("   Answer y or n"),
+        }
+    }
+}
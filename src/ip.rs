@@ -0,0 +1,797 @@
+//! Public IP address detection.
+
+use crate::dns;
+use crate::error::{Context, Error, Result};
+use crate::http::HttpConfig;
+use crate::{router, stun};
+use futures::StreamExt;
+use futures::stream::FuturesUnordered;
+use regex::Regex;
+use reqwest::Client;
+use serde::Deserialize;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::process::Stdio;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// IP address family, used to pick detection services and match record types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+pub enum IpVersion {
+    #[serde(rename = "ipv4")]
+    V4,
+    #[serde(rename = "ipv6")]
+    V6,
+}
+
+impl IpVersion {
+    /// AAAA records hold IPv6 addresses; everything else gets the IPv4 address.
+    pub fn for_record_type(record_type: &str) -> Self {
+        if record_type.eq_ignore_ascii_case("AAAA") {
+            Self::V6
+        } else {
+            Self::V4
+        }
+    }
+
+    /// Human-readable name of the family, e.g. "IPv4".
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::V4 => "IPv4",
+            Self::V6 => "IPv6",
+        }
+    }
+
+    /// Whether the address belongs to this family.
+    pub fn matches(self, ip: IpAddr) -> bool {
+        match self {
+            Self::V4 => ip.is_ipv4(),
+            Self::V6 => ip.is_ipv6(),
+        }
+    }
+
+    /// Built-in HTTP services that only answer over this family.
+    fn default_services(self) -> Vec<IpService> {
+        match self {
+            Self::V4 => vec![
+                IpService::json("https://api.ipify.org?format=json", "ip"),
+                IpService::json("https://httpbin.org/ip", "origin"),
+                IpService::json("https://api.myip.com", "ip"),
+                IpService::text("https://ipinfo.io/ip"),
+            ],
+            Self::V6 => vec![
+                IpService::json("https://api6.ipify.org?format=json", "ip"),
+                IpService::json("https://api6.my-ip.io/v2/ip.json", "ip"),
+                IpService::text("https://ipv6.icanhazip.com"),
+            ],
+        }
+    }
+
+    /// Local address to bind to so requests go out over this family only.
+    fn unspecified_addr(self) -> IpAddr {
+        match self {
+            Self::V4 => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            Self::V6 => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        }
+    }
+}
+
+/// An HTTP endpoint that reports the caller's public IP address.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IpService {
+    pub url: String,
+    /// Field holding the address in a JSON response, e.g. `ip` or `data.ip`.
+    /// Without one the whole response body is taken as the address.
+    pub json_field: Option<String>,
+    /// Only use the service for this address family. `None` uses it for both.
+    pub family: Option<IpVersion>,
+}
+
+impl IpService {
+    /// A service answering with JSON that has the address in `field`.
+    pub fn json(url: &str, field: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            json_field: Some(field.to_string()),
+            family: None,
+        }
+    }
+
+    /// A service answering with just the address.
+    pub fn text(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            json_field: None,
+            family: None,
+        }
+    }
+
+    async fn fetch(&self, client: &Client) -> Result<String> {
+        let response = client.get(&self.url).send().await?.error_for_status()?;
+
+        let ip = match &self.json_field {
+            Some(field) => {
+                let body: serde_json::Value = response.json().await?;
+                field
+                    .split('.')
+                    .try_fold(&body, |value, key| value.get(key))
+                    .and_then(|value| value.as_str())
+                    .ok_or_else(|| {
+                        Error::IpDetection(format!("Response has no string field '{}'", field))
+                    })?
+                    .to_string()
+            }
+            None => response.text().await?,
+        };
+
+        Ok(ip.trim().to_string())
+    }
+}
+
+/// Where the public IP address is read from.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub enum IpSource {
+    /// Ask external HTTP services (the default).
+    #[default]
+    Http,
+    /// Use the address assigned to a local network interface, for machines
+    /// whose public IP is configured directly on the host.
+    Interface(String),
+    /// Ask a STUN server (`host:port`) over UDP.
+    Stun(String),
+    /// Ask a public resolver that answers with the address the query came from.
+    Dns(DnsIpService),
+    /// Ask the router for its WAN address over UPnP IGD.
+    Upnp,
+    /// Ask the router for its WAN address over NAT-PMP, at the given address
+    /// or the default gateway.
+    NatPmp(Option<Ipv4Addr>),
+    /// Ask an AVM Fritz!Box (`host:port`) for its WAN address.
+    Fritzbox(String),
+    /// Read the address from the page in [`IpDetector::scrape`], such as a
+    /// router's status API.
+    Scrape,
+    /// Run a command (the program and its arguments, separated by spaces)
+    /// and read the address from its output.
+    Command(String),
+    /// Always use these addresses, one per family, so records are kept at a
+    /// fixed value.
+    Static(Vec<IpAddr>),
+}
+
+/// How long the `command` source waits for the command to finish.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Resolvers with a special name that resolves to the client's own address.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum DnsIpService {
+    /// `myip.opendns.com` A/AAAA at resolver1.opendns.com
+    #[default]
+    OpenDns,
+    /// `whoami.cloudflare` TXT (CHAOS class) at 1.1.1.1
+    Cloudflare,
+    /// `o-o.myaddr.l.google.com` TXT at ns1.google.com
+    Google,
+}
+
+impl DnsIpService {
+    fn server(self, version: IpVersion) -> SocketAddr {
+        let ip: IpAddr = match (self, version) {
+            (Self::OpenDns, IpVersion::V4) => Ipv4Addr::new(208, 67, 222, 222).into(),
+            (Self::OpenDns, IpVersion::V6) => {
+                Ipv6Addr::new(0x2620, 0x119, 0x35, 0, 0, 0, 0, 0x35).into()
+            }
+            (Self::Cloudflare, IpVersion::V4) => Ipv4Addr::new(1, 1, 1, 1).into(),
+            (Self::Cloudflare, IpVersion::V6) => {
+                Ipv6Addr::new(0x2606, 0x4700, 0x4700, 0, 0, 0, 0, 0x1111).into()
+            }
+            (Self::Google, IpVersion::V4) => Ipv4Addr::new(216, 239, 32, 10).into(),
+            (Self::Google, IpVersion::V6) => {
+                Ipv6Addr::new(0x2001, 0x4860, 0x4802, 0x32, 0, 0, 0, 0xa).into()
+            }
+        };
+        SocketAddr::new(ip, 53)
+    }
+
+    fn query(self, version: IpVersion) -> dns::Message {
+        match self {
+            Self::OpenDns => {
+                let qtype = match version {
+                    IpVersion::V4 => dns::TYPE_A,
+                    IpVersion::V6 => dns::TYPE_AAAA,
+                };
+                dns::Message::query("myip.opendns.com", qtype, dns::CLASS_IN)
+            }
+            Self::Cloudflare => {
+                dns::Message::query("whoami.cloudflare", dns::TYPE_TXT, dns::CLASS_CH)
+            }
+            Self::Google => {
+                dns::Message::query("o-o.myaddr.l.google.com", dns::TYPE_TXT, dns::CLASS_IN)
+            }
+        }
+    }
+}
+
+impl FromStr for DnsIpService {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "opendns" => Ok(Self::OpenDns),
+            "cloudflare" => Ok(Self::Cloudflare),
+            "google" => Ok(Self::Google),
+            other => Err(Error::Config(format!(
+                "Unknown DNS IP service '{}', expected 'opendns', 'cloudflare' or 'google'",
+                other
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for DnsIpService {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::OpenDns => "opendns",
+            Self::Cloudflare => "cloudflare",
+            Self::Google => "google",
+        })
+    }
+}
+
+impl fmt::Display for IpSource {
+    /// Formats the source the way [`IpSource::from_str`] reads it.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Http => f.write_str("http"),
+            Self::Interface(name) => write!(f, "interface:{}", name),
+            Self::Stun(server) => write!(f, "stun:{}", server),
+            Self::Dns(service) => write!(f, "dns:{}", service),
+            Self::Upnp => f.write_str("upnp"),
+            Self::NatPmp(None) => f.write_str("natpmp"),
+            Self::NatPmp(Some(gateway)) => write!(f, "natpmp:{}", gateway),
+            Self::Fritzbox(host) => write!(f, "fritzbox:{}", host),
+            Self::Scrape => f.write_str("scrape"),
+            Self::Command(command) => write!(f, "command:{}", command),
+            Self::Static(addresses) => {
+                let addresses: Vec<String> = addresses.iter().map(IpAddr::to_string).collect();
+                write!(f, "static:{}", addresses.join(","))
+            }
+        }
+    }
+}
+
+impl FromStr for IpSource {
+    type Err = Error;
+
+    /// Parses `http`, `interface:<name>`, `stun[:<host>[:<port>]]`,
+    /// `dns[:opendns|cloudflare|google]`, `upnp`, `natpmp[:<gateway>]`,
+    /// `fritzbox[:<host>[:<port>]]`, `scrape`, `command:<command>` or
+    /// `static:<address>[,<address>]`.
+    fn from_str(s: &str) -> Result<Self> {
+        let (kind, arg) = match s.split_once(':') {
+            Some((kind, arg)) => (kind, Some(arg)),
+            None => (s, None),
+        };
+
+        match (kind.to_ascii_lowercase().as_str(), arg) {
+            ("http", None) => Ok(Self::Http),
+            ("interface", Some(name)) if !name.is_empty() => Ok(Self::Interface(name.to_string())),
+            ("stun", None) => Ok(Self::Stun(stun::DEFAULT_SERVER.to_string())),
+            ("stun", Some(server)) if !server.is_empty() => {
+                Ok(Self::Stun(if server.contains(':') {
+                    server.to_string()
+                } else {
+                    format!("{}:{}", server, stun::DEFAULT_PORT)
+                }))
+            }
+            ("dns", None) => Ok(Self::Dns(DnsIpService::default())),
+            ("dns", Some(service)) => Ok(Self::Dns(service.parse()?)),
+            ("upnp", None) => Ok(Self::Upnp),
+            ("natpmp", None) => Ok(Self::NatPmp(None)),
+            ("natpmp", Some(gateway)) => Ok(Self::NatPmp(Some(gateway.parse().map_err(|_| {
+                Error::Config(format!(
+                    "Invalid NAT-PMP gateway '{}', expected an IPv4 address",
+                    gateway
+                ))
+            })?))),
+            ("fritzbox", None) => Ok(Self::Fritzbox(format!(
+                "{}:{}",
+                router::FRITZBOX_HOST,
+                router::FRITZBOX_PORT
+            ))),
+            ("fritzbox", Some(host)) if !host.is_empty() => {
+                Ok(Self::Fritzbox(if host.contains(':') {
+                    host.to_string()
+                } else {
+                    format!("{}:{}", host, router::FRITZBOX_PORT)
+                }))
+            }
+            ("scrape", None) => Ok(Self::Scrape),
+            ("command", Some(command)) if !command.trim().is_empty() => {
+                Ok(Self::Command(command.trim().to_string()))
+            }
+            ("static", Some(addresses)) if !addresses.is_empty() => addresses
+                .split(',')
+                .map(|address| {
+                    address.trim().parse().map_err(|_| {
+                        Error::Config(format!("Invalid static address '{}'", address.trim()))
+                    })
+                })
+                .collect::<Result<_>>()
+                .map(Self::Static),
+            _ => Err(Error::Config(format!(
+                "Invalid IP_SOURCE '{}', expected 'http', 'interface:<name>', 'stun[:<host>[:<port>]]', 'dns[:<service>]', 'upnp', 'natpmp[:<gateway>]', 'fritzbox[:<host>[:<port>]]', 'scrape', 'command:<command>' or 'static:<address>'",
+                s
+            ))),
+        }
+    }
+}
+
+/// The interface ID of a host whose delegated IPv6 prefix changes. The
+/// record's address is the detected prefix followed by this suffix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv6Suffix {
+    /// Only the bits after the prefix are used, so `::1234:5678:9abc:def0`
+    /// and a full address work the same.
+    pub suffix: Ipv6Addr,
+    /// Leading bits taken from the detected address.
+    pub prefix_len: u8,
+}
+
+impl Ipv6Suffix {
+    /// Combines the prefix of `detected` with the suffix.
+    pub fn apply(self, detected: Ipv6Addr) -> Ipv6Addr {
+        let mask = match self.prefix_len {
+            0 => 0,
+            len => u128::MAX << (128 - u32::from(len.min(128))),
+        };
+        Ipv6Addr::from((u128::from(detected) & mask) | (u128::from(self.suffix) & !mask))
+    }
+}
+
+/// A page that reports the WAN address, such as a router's status API.
+#[derive(Debug, Clone)]
+pub struct ScrapePage {
+    pub url: String,
+    /// JSON pointer (RFC 6901) to the string holding the address, e.g.
+    /// `/wan/ipv4`.
+    pub json_pointer: Option<String>,
+    /// Finds the address in the body, or in the string at `json_pointer`.
+    /// The first capture group is used if there is one, otherwise the whole
+    /// match. Without a pattern the whole text is the address.
+    pub regex: Option<Regex>,
+    /// Username and password for HTTP basic authentication.
+    pub credentials: Option<(String, String)>,
+}
+
+/// How the public IP address is detected.
+#[derive(Debug, Clone)]
+pub struct IpDetector {
+    pub source: IpSource,
+    /// HTTP services for the `http` source, queried concurrently. Families without
+    /// any service listed here use the built-in services.
+    pub services: Vec<IpService>,
+    /// Number of HTTP services that must report the same address before it is
+    /// trusted. 1 takes the first valid answer.
+    pub consensus: usize,
+    /// Accept private, loopback and link-local addresses.
+    pub allow_private: bool,
+    /// The page read by the `scrape` source.
+    pub scrape: Option<ScrapePage>,
+    /// Timeouts of the HTTP services.
+    pub http: HttpConfig,
+}
+
+impl Default for IpDetector {
+    fn default() -> Self {
+        Self {
+            source: IpSource::default(),
+            services: Vec::new(),
+            consensus: 1,
+            allow_private: false,
+            scrape: None,
+            http: HttpConfig::default(),
+        }
+    }
+}
+
+/// Detects the public address of the given family.
+pub async fn get_public_ip(detector: &IpDetector, version: IpVersion) -> Result<IpAddr> {
+    let ip = match &detector.source {
+        // Given explicitly, so private addresses are fine too
+        IpSource::Static(addresses) => {
+            return addresses
+                .iter()
+                .copied()
+                .find(|ip| version.matches(*ip))
+                .ok_or_else(|| {
+                    Error::IpDetection(format!(
+                        "{} has no {} address",
+                        detector.source,
+                        version.label()
+                    ))
+                });
+        }
+        IpSource::Http => {
+            get_ip_from_http(
+                &detector.http,
+                &detector.services,
+                detector.consensus,
+                version,
+            )
+            .await?
+        }
+        IpSource::Interface(name) => get_ip_from_interface(name, version)?,
+        IpSource::Stun(server) => stun::query(server, version).await?,
+        IpSource::Dns(service) => get_ip_from_dns(*service, version).await?,
+        IpSource::Upnp | IpSource::NatPmp(_) if version == IpVersion::V6 => {
+            return Err(Error::IpDetection(format!(
+                "{} only reports the router's IPv4 address, use another ip_source for IPv6",
+                detector.source
+            )));
+        }
+        IpSource::Upnp => IpAddr::V4(router::upnp_external_ip(&detector.http).await?),
+        IpSource::NatPmp(gateway) => IpAddr::V4(router::natpmp_external_ip(*gateway).await?),
+        IpSource::Fritzbox(host) => {
+            router::fritzbox_external_ip(&detector.http, host, version).await?
+        }
+        IpSource::Scrape => {
+            let page = detector.scrape.as_ref().ok_or_else(|| {
+                Error::Config("The scrape IP source needs IP_SCRAPE_URL".to_string())
+            })?;
+            get_ip_from_page(&detector.http, page, version).await?
+        }
+        IpSource::Command(command) => get_ip_from_command(command, version).await?,
+    };
+
+    validate_ip(ip, version, detector.allow_private)
+}
+
+/// Checks that a detected address has the expected family and, unless
+/// `allow_private` is set, that it is publicly routable.
+pub fn validate_ip(ip: IpAddr, version: IpVersion, allow_private: bool) -> Result<IpAddr> {
+    if !version.matches(ip) {
+        return Err(Error::IpDetection(format!(
+            "Detected address {} is not an {} address",
+            ip,
+            version.label()
+        )));
+    }
+
+    if !allow_private && !is_public(ip) {
+        return Err(Error::IpDetection(format!(
+            "Detected address {} is not publicly routable (set ALLOW_PRIVATE_IP=true to use it anyway)",
+            ip
+        )));
+    }
+
+    Ok(ip)
+}
+
+/// Rejects unspecified, loopback, private, shared (CGNAT), link-local,
+/// broadcast, multicast, reserved, benchmarking, documentation and unique
+/// local addresses, and IPv4-mapped IPv6 addresses of any of those.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            let shared = first == 100 && (second & 0xC0) == 64;
+            let benchmarking = first == 198 && (second & 0xFE) == 18;
+            // 240.0.0.0/4, which takes in the broadcast address
+            let reserved = first >= 240;
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || shared
+                || benchmarking
+                || reserved)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(mapped) = ip.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(mapped));
+            }
+            let documentation = ip.segments()[..2] == [0x2001, 0xdb8];
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_unique_local()
+                || ip.is_unicast_link_local()
+                || ip.is_multicast()
+                || documentation)
+        }
+    }
+}
+
+/// Returns an address of the family assigned to the named interface,
+/// preferring public addresses. Loopback and link-local addresses are skipped.
+fn get_ip_from_interface(name: &str, version: IpVersion) -> Result<IpAddr> {
+    let interfaces = if_addrs::get_if_addrs().context("Failed to list network interfaces")?;
+
+    if !interfaces.iter().any(|iface| iface.name == name) {
+        return Err(Error::IpDetection(format!(
+            "Network interface '{}' not found",
+            name
+        )));
+    }
+
+    interfaces
+        .iter()
+        .filter(|iface| iface.name == name && !iface.is_loopback() && !iface.is_link_local())
+        .map(|iface| iface.ip())
+        .filter(|ip| version.matches(*ip))
+        .min_by_key(|ip| !is_public(*ip))
+        .ok_or_else(|| {
+            Error::IpDetection(format!(
+                "Network interface '{}' has no {} address",
+                name,
+                version.label()
+            ))
+        })
+}
+
+/// Asks a resolver for the special name that resolves to the client's address.
+async fn get_ip_from_dns(service: DnsIpService, version: IpVersion) -> Result<IpAddr> {
+    let server = service.server(version);
+    let response = dns::exchange_udp(server, &service.query(version)).await?;
+
+    if response.rcode() != 0 {
+        return Err(Error::IpDetection(format!(
+            "DNS server {} answered with error code {}",
+            server,
+            response.rcode()
+        )));
+    }
+
+    // The address comes back as an A/AAAA record or as the text of a TXT record
+    response
+        .answers
+        .iter()
+        .flat_map(|record| {
+            record.ip().into_iter().chain(
+                record
+                    .txt_strings()
+                    .into_iter()
+                    .filter_map(|txt| txt.parse().ok()),
+            )
+        })
+        .find(|ip| version.matches(*ip))
+        .ok_or_else(|| {
+            Error::IpDetection(format!(
+                "DNS server {} returned no {} address",
+                server,
+                version.label()
+            ))
+        })
+}
+
+/// Runs the command with `IP_VERSION` set to `ipv4` or `ipv6` and reads the
+/// first address of the family from its output, one per line.
+async fn get_ip_from_command(command: &str, version: IpVersion) -> Result<IpAddr> {
+    let mut words = command.split_whitespace();
+    let program = words
+        .next()
+        .ok_or_else(|| Error::Config("The command IP source needs a command".to_string()))?;
+    let run = tokio::process::Command::new(program)
+        .args(words)
+        .env(
+            "IP_VERSION",
+            match version {
+                IpVersion::V4 => "ipv4",
+                IpVersion::V6 => "ipv6",
+            },
+        )
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let output = tokio::time::timeout(COMMAND_TIMEOUT, run)
+        .await
+        .map_err(|_| {
+            Error::IpDetection(format!(
+                "'{}' didn't finish within {} seconds",
+                command,
+                COMMAND_TIMEOUT.as_secs()
+            ))
+        })?
+        .map_err(|e| Error::IpDetection(format!("Failed to run '{}': {}", program, e)))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(Error::IpDetection(format!(
+            "'{}' failed with {}: {}",
+            command,
+            output.status,
+            stderr.trim()
+        )));
+    }
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.trim().parse().ok())
+        .find(|ip| version.matches(*ip))
+        .ok_or_else(|| {
+            Error::IpDetection(format!(
+                "'{}' printed no {} address",
+                command,
+                version.label()
+            ))
+        })
+}
+
+/// Reads an address of the family from a status page.
+async fn get_ip_from_page(
+    http: &HttpConfig,
+    page: &ScrapePage,
+    version: IpVersion,
+) -> Result<IpAddr> {
+    // Status pages are usually on the local network, out of a proxy's reach
+    let client = http.builder().no_proxy().build()?;
+    let mut request = client.get(&page.url);
+    if let Some((username, password)) = &page.credentials {
+        request = request.basic_auth(username, Some(password));
+    }
+    let body = request.send().await?.error_for_status()?.text().await?;
+
+    let text = match &page.json_pointer {
+        Some(pointer) => {
+            let json: serde_json::Value = serde_json::from_str(&body)
+                .with_context(|| format!("{} didn't return JSON", page.url))?;
+            json.pointer(pointer)
+                .and_then(|value| value.as_str())
+                .ok_or_else(|| {
+                    Error::IpDetection(format!("{} has no string at '{}'", page.url, pointer))
+                })?
+                .to_string()
+        }
+        None => body,
+    };
+
+    let found = match &page.regex {
+        Some(regex) => regex
+            .captures_iter(&text)
+            .filter_map(|captures| captures.get(1).or_else(|| captures.get(0)))
+            .filter_map(|found| found.as_str().trim().parse().ok())
+            .find(|ip| version.matches(*ip)),
+        None => text.trim().parse().ok(),
+    };
+    found.ok_or_else(|| {
+        Error::IpDetection(format!(
+            "Found no {} address at {}",
+            version.label(),
+            page.url
+        ))
+    })
+}
+
+/// Detects the public address of the given family through HTTP services.
+/// All services are queried at once and the first address reported by
+/// `consensus` of them wins.
+async fn get_ip_from_http(
+    http: &HttpConfig,
+    services: &[IpService],
+    consensus: usize,
+    version: IpVersion,
+) -> Result<IpAddr> {
+    // Binding to the unspecified address of the family forces the connection
+    // over IPv4 or IPv6, so dual-stack services report the right address
+    let client = http
+        .builder()
+        .local_address(version.unspecified_addr())
+        .build()?;
+
+    let mut services: Vec<IpService> = services
+        .iter()
+        .filter(|service| service.family.is_none_or(|family| family == version))
+        .cloned()
+        .collect();
+    if services.is_empty() {
+        services = version.default_services();
+    }
+
+    let consensus = consensus.max(1);
+    if consensus > services.len() {
+        return Err(Error::IpDetection(format!(
+            "IP consensus requires {} services to agree but only {} {} services are configured",
+            consensus,
+            services.len(),
+            version.label()
+        )));
+    }
+
+    let mut pending: FuturesUnordered<_> = services
+        .iter()
+        .map(|service| async {
+            let result = service
+                .fetch(&client)
+                .await
+                .and_then(|ip| match ip.parse::<IpAddr>() {
+                    Ok(addr) if version.matches(addr) => Ok(addr),
+                    _ => Err(Error::IpDetection(format!(
+                        "'{}' is not a valid {} address",
+                        ip,
+                        version.label()
+                    ))),
+                });
+            result.with_context(|| format!("{} failed", service.url))
+        })
+        .collect();
+
+    let mut votes: Vec<(IpAddr, usize)> = Vec::new();
+    let mut last_error = None;
+    while let Some(result) = pending.next().await {
+        match result {
+            Ok(ip) => {
+                let count = match votes.iter_mut().find(|(addr, _)| *addr == ip) {
+                    Some((_, count)) => {
+                        *count += 1;
+                        *count
+                    }
+                    None => {
+                        votes.push((ip, 1));
+                        1
+                    }
+                };
+                if count >= consensus {
+                    return Ok(ip);
+                }
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    match (votes.is_empty(), last_error) {
+        (true, Some(e)) => Err(e),
+        _ => {
+            let answers: Vec<String> = votes
+                .iter()
+                .map(|(ip, count)| format!("{} ({}x)", ip, count))
+                .collect();
+            Err(Error::IpDetection(format!(
+                "No {} IP services agreed on the {} address, got: {}",
+                consensus,
+                version.label(),
+                answers.join(", ")
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_only_publicly_routable_addresses() {
+        for ip in ["1.1.1.1", "8.8.8.8", "2606:4700:4700::1111"] {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in [
+            "0.0.0.0",
+            "127.0.0.1",
+            "10.1.2.3",
+            "192.168.1.20",
+            "100.64.0.1",
+            "169.254.1.1",
+            "224.0.0.251",
+            "240.0.0.1",
+            "255.255.255.255",
+            "198.18.0.1",
+            "198.19.255.254",
+            "192.0.2.1",
+            "198.51.100.10",
+            "203.0.113.7",
+            "::",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "ff02::1",
+            "2001:db8::7",
+            "::ffff:192.168.1.20",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        assert!(is_public("::ffff:1.1.1.1".parse().unwrap()));
+    }
+}
@@ -0,0 +1,322 @@
+//! Leader election between replicas of the daemon, so that only one of them
+//! updates the records while the others stand by.
+//!
+//! A [`LeaderLock`] is one of:
+//!
+//! - `file:<path>`: an exclusive lock on a file on a volume the replicas
+//!   share. The leader holds it until it exits, and the operating system
+//!   releases it when the leader dies.
+//! - `txt:<name>`: a lease in a TXT record of the default Cloudflare zone,
+//!   for replicas without a shared volume. Its content is
+//!   `holder=<replica> until=<time>`; the leader renews it every cycle, and a
+//!   standby takes over once it has expired. The replicas' clocks have to
+//!   roughly agree.
+
+use crate::cache::CacheFile;
+use crate::config::Config;
+use crate::error::{Context, Error, Result};
+use crate::provider::{DnsProvider, DnsRecord, UpdateDnsRecord};
+use crate::updater::resolve_zone_id;
+use chrono::{DateTime, Utc};
+use std::fmt;
+use std::fs::{self, File, TryLockError};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// How long a new lease holder waits before checking that no other replica
+/// took the lease at the same time.
+const SETTLE: Duration = Duration::from_secs(2);
+
+/// TTL of the lease record; resolvers never need it.
+const LEASE_TTL: u32 = 60;
+
+/// What the replicas take turns through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LeaderLock {
+    File(PathBuf),
+    Txt(String),
+}
+
+impl FromStr for LeaderLock {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if let Some(path) = s.strip_prefix("file:")
+            && !path.is_empty()
+        {
+            return Ok(Self::File(PathBuf::from(path)));
+        }
+        if let Some(name) = s.strip_prefix("txt:")
+            && !name.is_empty()
+        {
+            return Ok(Self::Txt(name.trim_end_matches('.').to_lowercase()));
+        }
+        Err(Error::Config(format!(
+            "Invalid leader lock '{}', expected 'file:<path>' or 'txt:<record name>'",
+            s
+        )))
+    }
+}
+
+impl fmt::Display for LeaderLock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::File(path) => write!(f, "file:{}", path.display()),
+            Self::Txt(name) => write!(f, "txt:{}", name),
+        }
+    }
+}
+
+/// Whether this replica may update the records.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Role {
+    Leader,
+    /// Another replica leads, described by its lease when known.
+    Standby(Option<String>),
+}
+
+/// This replica's side of the election.
+pub struct Leader {
+    lock: LeaderLock,
+    /// Names this replica in lease records.
+    id: String,
+    lease: chrono::Duration,
+    /// The locked file, while leading through a file lock.
+    file: Option<File>,
+    leading: bool,
+}
+
+/// A lease found in a TXT record.
+struct Lease {
+    holder: String,
+    until: DateTime<Utc>,
+}
+
+impl Lease {
+    fn parse(content: &str) -> Option<Self> {
+        let mut holder = None;
+        let mut until = None;
+        for field in content.trim_matches('"').split_whitespace() {
+            match field.split_once('=') {
+                Some(("holder", value)) => holder = Some(value.to_string()),
+                Some(("until", value)) => {
+                    until = DateTime::parse_from_rfc3339(value)
+                        .ok()
+                        .map(|time| time.with_timezone(&Utc));
+                }
+                _ => {}
+            }
+        }
+        Some(Self {
+            holder: holder?,
+            until: until?,
+        })
+    }
+
+    fn content(&self) -> String {
+        format!(
+            "holder={} until={}",
+            self.holder,
+            self.until
+                .to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+        )
+    }
+}
+
+impl Leader {
+    /// Takes part in the election through `lock`. TXT leases that haven't
+    /// been renewed for `lease` expire.
+    pub fn new(lock: LeaderLock, lease: Duration) -> Self {
+        // Pods and containers get their name as the hostname
+        let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "cloudflare-ddns".to_string());
+        Self {
+            lock,
+            id: format!("{}-{:08x}", host, fastrand::u32(..)),
+            lease: chrono::Duration::seconds(lease.as_secs() as i64),
+            file: None,
+            leading: false,
+        }
+    }
+
+    pub fn lock(&self) -> &LeaderLock {
+        &self.lock
+    }
+
+    /// Whether the last [`Leader::check`] made this replica the leader.
+    pub fn is_leading(&self) -> bool {
+        self.leading
+    }
+
+    /// Takes the lock if it's free, or renews it if this replica has it.
+    pub async fn check(
+        &mut self,
+        provider: &dyn DnsProvider,
+        cache: &mut CacheFile,
+        config: &Config,
+    ) -> Result<Role> {
+        let role = match self.lock.clone() {
+            LeaderLock::File(path) => self.check_file(&path)?,
+            LeaderLock::Txt(name) => {
+                // Without an answer, the lease may have run out meanwhile
+                self.leading = false;
+                self.check_txt(provider, cache, config, &name).await?
+            }
+        };
+        self.leading = role == Role::Leader;
+        Ok(role)
+    }
+
+    fn check_file(&mut self, path: &std::path::Path) -> Result<Role> {
+        if self.file.is_some() {
+            return Ok(Role::Leader);
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = File::create(path)
+            .with_context(|| format!("Failed to open leader lock {}", path.display()))?;
+        match file.try_lock() {
+            Ok(()) => {
+                self.file = Some(file);
+                Ok(Role::Leader)
+            }
+            Err(TryLockError::WouldBlock) => Ok(Role::Standby(None)),
+            Err(TryLockError::Error(e)) => {
+                Err(Error::from(e).context(format!("Failed to lock {}", path.display())))
+            }
+        }
+    }
+
+    async fn check_txt(
+        &self,
+        provider: &dyn DnsProvider,
+        cache: &mut CacheFile,
+        config: &Config,
+        name: &str,
+    ) -> Result<Role> {
+        let zone = config
+            .zone
+            .as_ref()
+            .ok_or_else(|| Error::Config("A TXT leader lock needs a default zone".to_string()))?;
+        let zone_id = resolve_zone_id(provider, cache, config, zone).await?;
+        let records = lease_records(provider, &zone_id, name).await?;
+
+        let now = Utc::now();
+        let mine = self.lease_record(&records);
+        let current = records
+            .iter()
+            .filter_map(|record| Lease::parse(&record.content))
+            .filter(|lease| lease.until > now && lease.holder != self.id)
+            .max_by_key(|lease| lease.until);
+        if let Some(lease) = current
+            && mine.is_none()
+        {
+            return Ok(Role::Standby(Some(lease.holder)));
+        }
+
+        let lease = Lease {
+            holder: self.id.clone(),
+            until: now + self.lease,
+        };
+        let update = UpdateDnsRecord {
+            record_type: "TXT".to_string(),
+            name: name.to_string(),
+            content: lease.content(),
+            ttl: LEASE_TTL,
+            proxied: None,
+            comment: Some("cloudflare-ddns leader lease".to_string()),
+            tags: Vec::new(),
+        };
+        let renewing = mine.is_some();
+        // An expired lease is taken over, other TXT records are left alone
+        let expired = records
+            .iter()
+            .find(|record| Lease::parse(&record.content).is_some());
+        match mine.or(expired) {
+            Some(record) => {
+                provider
+                    .update_record(&zone_id, &record.id, &update)
+                    .await?
+            }
+            None => {
+                provider.create_record(&zone_id, update).await?;
+            }
+        }
+        if renewing {
+            return Ok(Role::Leader);
+        }
+
+        // Another standby may have taken the expired lease at the same time.
+        // Of several records, the one with the lowest ID counts, and the
+        // others are removed by their writers
+        tokio::time::sleep(SETTLE).await;
+        let records = lease_records(provider, &zone_id, name).await?;
+        let winner = records
+            .iter()
+            .filter(|record| Lease::parse(&record.content).is_some())
+            .min_by(|a, b| a.id.cmp(&b.id));
+        match winner.and_then(|record| Lease::parse(&record.content)) {
+            Some(lease) if lease.holder == self.id => Ok(Role::Leader),
+            winner => {
+                if let Some(record) = self.lease_record(&records) {
+                    provider.delete_record(&zone_id, &record.id).await?;
+                }
+                Ok(Role::Standby(winner.map(|lease| lease.holder)))
+            }
+        }
+    }
+
+    fn lease_record<'a>(&self, records: &'a [DnsRecord]) -> Option<&'a DnsRecord> {
+        records.iter().find(|record| {
+            Lease::parse(&record.content).is_some_and(|lease| lease.holder == self.id)
+        })
+    }
+
+    /// Gives up the lock, so that a standby can take over without waiting
+    /// for the lease to run out.
+    pub async fn release(
+        &mut self,
+        provider: &dyn DnsProvider,
+        cache: &mut CacheFile,
+        config: &Config,
+    ) -> Result<()> {
+        if !std::mem::take(&mut self.leading) {
+            return Ok(());
+        }
+        match self.lock.clone() {
+            LeaderLock::File(_) => {
+                self.file = None;
+                Ok(())
+            }
+            LeaderLock::Txt(name) => {
+                let Some(zone) = &config.zone else {
+                    return Ok(());
+                };
+                let zone_id = resolve_zone_id(provider, cache, config, zone).await?;
+                let records = lease_records(provider, &zone_id, &name).await?;
+                if let Some(record) = self.lease_record(&records) {
+                    provider.delete_record(&zone_id, &record.id).await?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The TXT records at `name`.
+async fn lease_records(
+    provider: &dyn DnsProvider,
+    zone_id: &str,
+    name: &str,
+) -> Result<Vec<DnsRecord>> {
+    let records = provider
+        .get_records(zone_id, Some(name))
+        .await
+        .with_context(|| format!("Failed to read the leader lease at {}", name))?;
+    Ok(records
+        .into_iter()
+        .filter(|record| record.record_type == "TXT" && record.name.eq_ignore_ascii_case(name))
+        .collect())
+}
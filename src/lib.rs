@@ -0,0 +1,73 @@
+//! Dynamic DNS for Cloudflare.
+//!
+//! The crate detects the machine's public IP address and keeps Cloudflare DNS
+//! records pointed at it. The `cloudflare-ddns` binary is a thin CLI on top of
+//! these modules, which can also be embedded in other tools:
+//!
+//! - [`provider`]: the DNS provider interfaces the updater is written against
+//! - [`cloudflare`]: API client for reading, creating and updating records
+//! - [`dyndns`]: DuckDNS, Dynu and No-IP, for records hosted outside Cloudflare
+//! - [`rfc2136`]: RFC 2136 dynamic updates to self-hosted DNS servers
+//! - [`ip`]: public IPv4/IPv6 address detection
+//! - [`http`]: timeouts and connection settings of the HTTP clients
+//! - [`dns`]: DNS message encoding and decoding over UDP
+//! - [`stun`]: minimal STUN client used by the `stun` IP source
+//! - [`router`]: UPnP IGD, NAT-PMP and Fritz!Box queries of the router's external address
+//! - [`cache`]: on-disk record state used to skip unnecessary API calls
+//! - [`history`]: SQLite log of every address change
+//! - [`state`]: records and intervals changed through the admin API
+//! - [`addon`]: the paths used when running as a Home Assistant add-on
+//! - [`credentials`]: the API token saved in the OS keyring
+//! - [`updater`]: the update cycle and daemon loop tying everything together
+//! - [`preflight`]: startup checks of the credentials and zone access
+//! - [`propagation`]: lookups confirming that updated records resolve
+//! - [`leader`]: leader election between replicas of the daemon
+//! - [`failover`]: health checks switching records to a backup address
+//! - [`server`]: the daemon's HTTP endpoints
+//! - [`systemd`]: `Type=notify` readiness and watchdog support
+//! - [`netwatch`]: Linux netlink events that trigger an early check
+//! - [`configwatch`]: config file edits that trigger a reload
+//! - [`notify`]: change and failure notifications (webhook, Telegram, Discord,
+//!   Slack, email, ntfy, Gotify)
+//! - [`mqtt`]: record state for MQTT brokers and Home Assistant
+//! - [`apprise`]: Apprise-style notification URLs
+//! - [`hooks`]: user commands run on changes, failures and successes
+//! - [`error`]: the error type shared by all of the above
+
+pub mod addon;
+pub mod apprise;
+pub mod cache;
+pub mod cloudflare;
+pub mod config;
+pub mod configwatch;
+pub mod credentials;
+pub mod discord;
+pub mod dns;
+pub mod dyndns;
+pub mod email;
+pub mod error;
+pub mod failover;
+pub mod gotify;
+pub mod history;
+pub mod hooks;
+pub mod http;
+pub mod ip;
+pub mod leader;
+pub mod mqtt;
+pub mod netwatch;
+pub mod notify;
+pub mod ntfy;
+pub mod preflight;
+pub mod propagation;
+pub mod provider;
+pub mod retry;
+pub mod rfc2136;
+pub mod router;
+pub mod server;
+pub mod slack;
+pub mod state;
+pub mod stun;
+pub mod systemd;
+pub mod telegram;
+pub mod updater;
+pub mod webhook;
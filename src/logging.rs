@@ -0,0 +1,339 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use cloudflare_ddns::updater::SUMMARY_TARGET;
+use std::borrow::Cow;
+use std::fmt;
+#[cfg(windows)]
+use std::fs::File;
+#[cfg(windows)]
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::{FormatTime, SystemTime};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Whether messages keep the emoji they start with, see [`set_emoji`].
+static EMOJI: AtomicBool = AtomicBool::new(true);
+
+/// Prints a line of command output like `println!`, without its leading
+/// emoji when those are turned off.
+macro_rules! say {
+    ($($arg:tt)*) => {
+        $crate::logging::print_line(&format!($($arg)*))
+    };
+}
+pub(crate) use say;
+
+pub fn print_line(line: &str) {
+    if EMOJI.load(Ordering::Relaxed) {
+        println!("{}", line);
+    } else {
+        println!("{}", without_emoji(line));
+    }
+}
+
+/// Turns emoji in command output and pretty logs on or off. Call before
+/// [`init`].
+pub fn set_emoji(enabled: bool) {
+    EMOJI.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether the terminal can be expected to show emoji: on Unix the locale
+/// must not name a character set other than UTF-8, and on Windows only
+/// Windows Terminal and VS Code's terminal draw them in the console.
+pub fn emoji_supported() -> bool {
+    if cfg!(windows) {
+        use std::io::IsTerminal;
+
+        return !std::io::stdout().is_terminal()
+            || std::env::var_os("WT_SESSION").is_some()
+            || std::env::var_os("TERM_PROGRAM").is_some();
+    }
+
+    // An unset locale is common in containers whose logs are read elsewhere
+    let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.is_empty());
+    match locale {
+        Some(locale) => {
+            let locale = locale.to_ascii_lowercase();
+            locale.contains("utf-8") || locale.contains("utf8")
+        }
+        None => true,
+    }
+}
+
+/// The line without the emoji (and the spaces after it) that starts it,
+/// keeping any indentation.
+fn without_emoji(line: &str) -> Cow<'_, str> {
+    let text = line.trim_start_matches(' ');
+    let stripped = text.trim_start_matches(|c: char| !c.is_ascii());
+    if stripped.len() == text.len() {
+        return Cow::Borrowed(line);
+    }
+    let indent = &line[..line.len() - text.len()];
+    Cow::Owned(format!("{}{}", indent, stripped.trim_start_matches(' ')))
+}
+
+/// How log lines are rendered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Human-friendly messages, one per line.
+    Pretty,
+    /// The level and the message without its emoji, easy to grep.
+    Plain,
+    /// One JSON object per line, for log collectors.
+    Json,
+}
+
+/// Where logs go, as chosen on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogBackend {
+    /// Standard output, or standard error with `--output json`.
+    Console,
+    /// The systemd journal, through its native socket.
+    Journald,
+    /// The local syslog daemon, through `/dev/log`.
+    Syslog,
+}
+
+/// Where log lines are written.
+pub enum LogTarget {
+    Stdout,
+    /// Keeps stdout free for `--output json`.
+    Stderr,
+    /// The Windows service's log file.
+    #[cfg(windows)]
+    File(File),
+    /// journald or syslog, with each line's level as its priority.
+    System(LogBackend),
+}
+
+/// Installs the global tracing subscriber.
+///
+/// Logging is set up before the config file is read, so it is controlled by
+/// the command line and environment only: `quiet` limits output to warnings,
+/// errors and the run summary, otherwise `RUST_LOG` can set a custom filter. `timestamps`
+/// starts pretty and plain lines with an RFC 3339 timestamp; JSON lines
+/// always have one. The system log gets plain messages and keeps its own
+/// time, regardless of `format` and `timestamps`.
+pub fn init(quiet: bool, format: LogFormat, timestamps: bool, target: LogTarget) -> Result<()> {
+    let filter = if quiet {
+        EnvFilter::new(format!("warn,{}=info", SUMMARY_TARGET))
+    } else {
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"))
+    };
+
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    let builder = match target {
+        LogTarget::Stdout => builder.with_writer(BoxMakeWriter::new(std::io::stdout)),
+        LogTarget::Stderr => builder.with_writer(BoxMakeWriter::new(std::io::stderr)),
+        #[cfg(windows)]
+        LogTarget::File(file) => builder
+            .with_writer(BoxMakeWriter::new(Mutex::new(file)))
+            .with_ansi(false),
+        #[cfg(unix)]
+        LogTarget::System(backend) => {
+            builder
+                .with_writer(system::SystemLog::connect(backend)?)
+                .with_ansi(false)
+                .event_format(Plain {
+                    timestamps: false,
+                    level: false,
+                })
+                .init();
+            return Ok(());
+        }
+        #[cfg(not(unix))]
+        LogTarget::System(backend) => {
+            return Err(anyhow::anyhow!(
+                "Logging to {} is only available on Unix",
+                format!("{:?}", backend).to_lowercase()
+            ));
+        }
+    };
+
+    match format {
+        LogFormat::Pretty if !EMOJI.load(Ordering::Relaxed) => builder
+            .event_format(Plain {
+                timestamps,
+                level: false,
+            })
+            .init(),
+        LogFormat::Pretty if timestamps => builder.with_target(false).with_level(false).init(),
+        LogFormat::Pretty => builder
+            .without_time()
+            .with_target(false)
+            .with_level(false)
+            .init(),
+        LogFormat::Plain => builder
+            .event_format(Plain {
+                timestamps,
+                level: true,
+            })
+            .init(),
+        LogFormat::Json => builder.json().init(),
+    }
+    Ok(())
+}
+
+/// `[timestamp] [LEVEL] message` lines without colors or the emoji that
+/// messages start with.
+struct Plain {
+    timestamps: bool,
+    level: bool,
+}
+
+impl<S, N> FormatEvent<S, N> for Plain
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        if self.timestamps {
+            SystemTime.format_time(&mut writer)?;
+            writer.write_char(' ')?;
+        }
+        if self.level {
+            write!(writer, "{:<5} ", event.metadata().level())?;
+        }
+
+        let mut message = String::new();
+        ctx.format_fields(Writer::new(&mut message), event)?;
+        writeln!(writer, "{}", without_emoji(&message))
+    }
+}
+
+/// Writers for the system log. Every event is sent as one datagram when its
+/// writer is dropped, with a priority that matches its level.
+#[cfg(unix)]
+mod system {
+    use super::LogBackend;
+    use anyhow::{Context, Result};
+    use std::io::{self, Write};
+    use std::os::unix::net::UnixDatagram;
+    use tracing::{Level, Metadata};
+    use tracing_subscriber::fmt::MakeWriter;
+
+    const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+    const SYSLOG_SOCKET: &str = "/dev/log";
+    const IDENTIFIER: &str = "cloudflare-ddns";
+    /// The `daemon` syslog facility.
+    const FACILITY: u8 = 3;
+
+    pub struct SystemLog {
+        socket: UnixDatagram,
+        journald: bool,
+    }
+
+    impl SystemLog {
+        pub fn connect(backend: LogBackend) -> Result<Self> {
+            let (path, journald) = match backend {
+                LogBackend::Journald => (JOURNALD_SOCKET, true),
+                _ => (SYSLOG_SOCKET, false),
+            };
+            let socket = UnixDatagram::unbound()?;
+            socket
+                .connect(path)
+                .with_context(|| format!("Failed to connect to {}", path))?;
+            Ok(Self { socket, journald })
+        }
+
+        fn line(&self, level: Level) -> Line<'_> {
+            Line {
+                log: self,
+                level,
+                buf: Vec::new(),
+            }
+        }
+
+        fn send(&self, level: &Level, message: &[u8]) {
+            let message = message.strip_suffix(b"\n").unwrap_or(message);
+            // Severities from syslog(3): err, warning, info and debug
+            let priority = match *level {
+                Level::ERROR => 3,
+                Level::WARN => 4,
+                Level::INFO => 6,
+                _ => 7,
+            };
+
+            let mut datagram = Vec::with_capacity(message.len() + 64);
+            if self.journald {
+                datagram.extend(format!("PRIORITY={}\n", priority).bytes());
+                datagram.extend(format!("SYSLOG_IDENTIFIER={}\n", IDENTIFIER).bytes());
+                // Values with a newline are sent with their length instead
+                if message.contains(&b'\n') {
+                    datagram.extend(b"MESSAGE\n");
+                    datagram.extend((message.len() as u64).to_le_bytes());
+                    datagram.extend(message);
+                } else {
+                    datagram.extend(b"MESSAGE=");
+                    datagram.extend(message);
+                }
+                datagram.push(b'\n');
+            } else {
+                datagram.extend(
+                    format!(
+                        "<{}>{}[{}]: ",
+                        FACILITY * 8 + priority,
+                        IDENTIFIER,
+                        std::process::id()
+                    )
+                    .bytes(),
+                );
+                datagram.extend(message);
+            }
+
+            // There is nowhere left to report a lost log line
+            let _ = self.socket.send(&datagram);
+        }
+    }
+
+    /// Collects one event for [`SystemLog`].
+    pub struct Line<'a> {
+        log: &'a SystemLog,
+        level: Level,
+        buf: Vec<u8>,
+    }
+
+    impl Write for Line<'_> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.buf.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Drop for Line<'_> {
+        fn drop(&mut self) {
+            if !self.buf.is_empty() {
+                self.log.send(&self.level, &self.buf);
+            }
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for SystemLog {
+        type Writer = Line<'a>;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.line(Level::INFO)
+        }
+
+        fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+            self.line(*meta.level())
+        }
+    }
+}
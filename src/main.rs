@@ -1,405 +1,270 @@
-use anyhow::{Result, anyhow};
-use chrono::{DateTime, Duration, Utc};
-use reqwest::Client;
-use serde::{Deserialize, Serialize};
-use std::env;
-use std::fs;
-use std::path::Path;
-
-#[derive(Debug, Deserialize)]
-struct IpResponse {
-    ip: String,
-}
-
-#[derive(Debug, Deserialize)]
-struct CloudflareResponse<T> {
-    success: bool,
-    errors: Vec<CloudflareError>,
-    messages: Vec<String>,
-    result: Option<T>,
-}
-
-#[derive(Debug, Deserialize)]
-struct CloudflareError {
-    code: u32,
-    message: String,
-}
+mod cli;
+mod commands;
+mod init;
+mod logging;
+#[cfg(windows)]
+mod service;
 
-#[derive(Debug, Deserialize)]
-struct DnsRecord {
-    id: String,
-    name: String,
-    content: String,
-    #[serde(rename = "type")]
-    record_type: String,
-    ttl: u32,
-}
+use anyhow::{Result, anyhow};
+use cli::{Cli, Command, Output, RunArgs};
+use cloudflare_ddns::addon;
+use cloudflare_ddns::cache::{CacheFile, default_cache_path, load_cache, lock_cache, save_cache};
+use cloudflare_ddns::config::{Config, RunMode};
+use cloudflare_ddns::notify::Dispatcher;
+use cloudflare_ddns::provider::{self, DnsProvider};
+use cloudflare_ddns::state;
+use cloudflare_ddns::updater::{
+    CycleReport, fill_cycle, print_json, run_daemon, shutdown_signal, start_jitter,
+};
+use logging::{LogBackend, LogTarget};
+use std::process::ExitCode;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Exit code of a run that updated or created records.
+const EXIT_UPDATED: u8 = 2;
+
+/// Exit code of a run where only some of the records failed.
+const EXIT_PARTIAL_FAILURE: u8 = 3;
+
+/// Exit code of a run whose updated records didn't resolve to the new
+/// address within VERIFY_TIMEOUT.
+const EXIT_UNVERIFIED: u8 = 4;
+
+/// Exit code of a run stopped because it took longer than MAX_RUNTIME.
+const EXIT_TIMED_OUT: u8 = 5;
+
+fn main() -> Result<ExitCode> {
+    let cli = Cli::parse_args();
+
+    #[cfg(windows)]
+    if cli.service {
+        return service::run(cli).map(|()| ExitCode::SUCCESS);
+    }
 
-#[derive(Debug, Serialize)]
-struct UpdateDnsRecord {
-    #[serde(rename = "type")]
-    record_type: String,
-    name: String,
-    content: String,
-    ttl: u32,
+    let log_target = match (cli.log_backend, cli.output) {
+        (LogBackend::Console, Output::Text) => LogTarget::Stdout,
+        (LogBackend::Console, Output::Json) => LogTarget::Stderr,
+        (backend, _) => LogTarget::System(backend),
+    };
+    logging::set_emoji(!cli.no_emoji && logging::emoji_supported());
+    logging::init(cli.quiet, cli.log_format, cli.log_timestamps, log_target)?;
+    tokio::runtime::Runtime::new()?.block_on(run(cli, shutdown_signal()))
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct DnsCache {
-    record_name: String,
-    record_type: String,
-    ip_address: String,
-    last_checked: DateTime<Utc>,
-    last_updated: DateTime<Utc>,
-}
+/// Runs the selected command. `shutdown` stops the daemon.
+async fn run(cli: Cli, shutdown: impl Future<Output = ()>) -> Result<ExitCode> {
+    let command = cli.command.unwrap_or(Command::Run(RunArgs::default()));
 
-impl DnsCache {
-    fn new(record_name: String, record_type: String, ip_address: String) -> Self {
-        let now = Utc::now();
-        Self {
-            record_name,
-            record_type,
-            ip_address,
-            last_checked: now,
-            last_updated: now,
-        }
+    #[cfg(windows)]
+    if let Command::Service(command) = &command {
+        return service::manage(command, cli.config.as_deref(), cli.cache_path.as_deref())
+            .map(|()| ExitCode::SUCCESS);
     }
 
-    fn is_expired(&self, expiry_hours: i64) -> bool {
-        let expiry_duration = Duration::hours(expiry_hours);
-        Utc::now() - self.last_checked > expiry_duration
-    }
+    // Home Assistant add-ons keep their options and state under /data
+    let addon_options = match cli.config {
+        Some(_) => None,
+        None => addon::options_path(),
+    };
+    let cache_path = match (cli.cache_path, &addon_options) {
+        (Some(path), _) => path,
+        (None, Some(_)) => addon::CACHE_PATH.into(),
+        (None, None) => default_cache_path(),
+    };
+    let config_path = cli.config.clone().or(addon_options);
 
-    fn matches_config(&self, record_name: &str, record_type: &str) -> bool {
-        self.record_name == record_name && self.record_type == record_type
+    if let Command::ClearCache = command {
+        let _lock = lock_cache(&cache_path)?;
+        return commands::clear_cache(&cache_path).map(|()| ExitCode::SUCCESS);
     }
-
-    fn update_ip(&mut self, new_ip: String) {
-        self.ip_address = new_ip;
-        self.last_updated = Utc::now();
-        self.last_checked = Utc::now();
+    if let Command::Healthcheck = command {
+        return commands::healthcheck(&cache_path).map(|()| ExitCode::SUCCESS);
     }
-
-    fn update_checked(&mut self) {
-        self.last_checked = Utc::now();
+    if let Command::SystemdUnit(args) = &command {
+        return commands::systemd_unit(args, cli.config.as_deref()).map(|()| ExitCode::SUCCESS);
     }
-}
-
-struct CloudflareClient {
-    client: Client,
-    api_token: String,
-    zone_id: String,
-}
-
-impl CloudflareClient {
-    fn new(api_token: String, zone_id: String) -> Self {
-        let client = Client::new();
-        Self {
-            client,
-            api_token,
-            zone_id,
-        }
+    if let Command::Auth(command) = &command {
+        return commands::auth(command).map(|()| ExitCode::SUCCESS);
     }
-
-    async fn get_dns_records(&self, record_name: &str) -> Result<Vec<DnsRecord>> {
-        let url = format!(
-            "https://api.cloudflare.com/client/v4/zones/{}/dns_records?name={}",
-            self.zone_id, record_name
-        );
-
-        let response = self
-            .client
-            .get(&url)
-            .header("Authorization", format!("Bearer {}", self.api_token))
-            .header("Content-Type", "application/json")
-            .send()
-            .await?;
-
-        let cf_response: CloudflareResponse<Vec<DnsRecord>> = response.json().await?;
-
-        if !cf_response.success {
-            let error_details = cf_response
-                .errors
-                .iter()
-                .map(|e| format!("Code {}: {}", e.code, e.message))
-                .collect::<Vec<_>>()
-                .join(", ");
-            return Err(anyhow!("Cloudflare API error: {}", error_details));
-        }
-
-        // Log any messages from Cloudflare
-        if !cf_response.messages.is_empty() {
-            println!("📝 Cloudflare messages: {:?}", cf_response.messages);
-        }
-
-        cf_response
-            .result
-            .ok_or_else(|| anyhow!("No result in response"))
+    if let Command::Init = &command {
+        return init::run(cli.config.as_deref())
+            .await
+            .map(|()| ExitCode::SUCCESS);
     }
 
-    async fn update_dns_record(&self, record_id: &str, update_data: UpdateDnsRecord) -> Result<()> {
-        let url = format!(
-            "https://api.cloudflare.com/client/v4/zones/{}/dns_records/{}",
-            self.zone_id, record_id
-        );
-
-        let response = self
-            .client
-            .put(&url)
-            .header("Authorization", format!("Bearer {}", self.api_token))
-            .header("Content-Type", "application/json")
-            .json(&update_data)
-            .send()
-            .await?;
-
-        let cf_response: CloudflareResponse<DnsRecord> = response.json().await?;
-
-        if !cf_response.success {
-            let error_details = cf_response
-                .errors
-                .iter()
-                .map(|e| format!("Code {}: {}", e.code, e.message))
-                .collect::<Vec<_>>()
-                .join(", ");
-            return Err(anyhow!("Failed to update DNS record: {}", error_details));
-        }
-
-        // Log any messages from Cloudflare
-        if !cf_response.messages.is_empty() {
-            println!("📝 Cloudflare messages: {:?}", cf_response.messages);
+    // Run options may be given before or after the `run` subcommand
+    let (daemon, dry_run, force) = match &command {
+        Command::Run(args) => (
+            cli.run.daemon || args.daemon,
+            cli.run.dry_run || args.dry_run,
+            cli.run.force || args.force,
+        ),
+        Command::Check => (false, true, false),
+        _ => (false, false, false),
+    };
+    let (max_runtime, max_runtime_from_env) = match &command {
+        Command::Run(args) if args.max_runtime.is_some() => {
+            (args.max_runtime, args.max_runtime_from_env)
         }
-
-        Ok(())
+        Command::Run(_) | Command::Check => (cli.run.max_runtime, cli.run.max_runtime_from_env),
+        _ => (None, false),
+    };
+    let mut max_runtime = max_runtime.map(Duration::from_secs);
+
+    // The daemon loads the configuration the same way again on SIGHUP
+    let load_config = || -> cloudflare_ddns::error::Result<Config> {
+        let mut config = Config::load(config_path.as_deref(), daemon)?;
+        config.dry_run = dry_run;
+        config.force = force;
+        config.json_output = cli.output == Output::Json;
+        config.cache_path = cache_path.clone();
+        state::apply_saved(&mut config)?;
+        Ok(config)
+    };
+    let config = load_config()?;
+    if force && config.run_mode == RunMode::Daemon {
+        return Err(anyhow!("--force can't be used in daemon mode"));
     }
-}
-
-async fn get_public_ip() -> Result<String> {
-    let client = Client::new();
-
-    // Try multiple IP services for reliability
-    let ip_services = [
-        "https://api.ipify.org?format=json",
-        "https://httpbin.org/ip",
-        "https://api.myip.com",
-    ];
-
-    for service in &ip_services {
-        match client.get(*service).send().await {
-            Ok(response) => {
-                if let Ok(ip_response) = response.json::<IpResponse>().await {
-                    return Ok(ip_response.ip);
-                }
-            }
-            Err(_) => continue,
+    // `check` is always a single cycle
+    if max_runtime.is_some()
+        && matches!(command, Command::Run(_))
+        && config.run_mode == RunMode::Daemon
+    {
+        if !max_runtime_from_env {
+            return Err(anyhow!("--max-runtime can't be used in daemon mode"));
         }
+        warn!("⚠️  MAX_RUNTIME only limits one-shot runs, ignoring it in daemon mode");
+        max_runtime = None;
     }
 
-    // Fallback to a simple text-based service
-    let response = client.get("https://ipinfo.io/ip").send().await?;
-    let ip = response.text().await?.trim().to_string();
-
-    Ok(ip)
-}
-
-fn load_cache() -> Option<DnsCache> {
-    let cache_path = "./cache/cache.json";
+    // Runs write the cache, so they hold its lock until they exit. Replicas
+    // taking turns through a leader lock may share the cache, and only the
+    // leader writes it
+    let replica = config.run_mode == RunMode::Daemon && config.leader_lock.is_some();
+    let _lock = match command {
+        Command::Run(_) if !replica => Some(lock_cache(&cache_path)?),
+        _ => None,
+    };
 
-    if !Path::new(cache_path).exists() {
-        println!("📄 No cache file found, will create one after first run");
-        return None;
-    }
+    let mut cache = load_cache(&config.cache_path);
+    let provider = provider::from_config(&config);
+    // Only runs notify; the other commands shouldn't connect to MQTT brokers
+    let dispatcher = match command {
+        Command::Run(_) => Dispatcher::from_config(&config)?,
+        _ => Dispatcher::default(),
+    };
 
-    match fs::read_to_string(cache_path) {
-        Ok(content) => match serde_json::from_str::<DnsCache>(&content) {
-            Ok(cache) => {
-                println!("📄 Loaded cache from {}", cache_path);
-                Some(cache)
-            }
-            Err(e) => {
-                println!("⚠️  Cache file corrupted ({}), will recreate", e);
-                None
+    let result = match command {
+        Command::Run(_) => match config.run_mode {
+            RunMode::Once => {
+                if let Some(pause) = config.paused_by() {
+                    info!("⏸️  Updates are paused by {}, skipping this run", pause);
+                    let report = CycleReport::paused(&config, pause);
+                    report.log_summary();
+                    if config.json_output {
+                        print_json(&report);
+                    }
+                    return Ok(ExitCode::SUCCESS);
+                }
+                start_jitter(&config).await;
+                return run_once(&*provider, &dispatcher, &mut cache, &config, max_runtime).await;
             }
+            RunMode::Daemon => run_daemon(
+                &*provider,
+                &dispatcher,
+                &mut cache,
+                &config,
+                shutdown,
+                load_config,
+            )
+            .await
+            .map_err(Into::into),
         },
-        Err(e) => {
-            println!("⚠️  Failed to read cache file ({}), will recreate", e);
-            None
+        Command::Check => {
+            return run_once(
+                &*provider,
+                &Dispatcher::default(),
+                &mut cache,
+                &config,
+                max_runtime,
+            )
+            .await;
         }
-    }
-}
-
-fn save_cache(cache: &DnsCache) -> Result<()> {
-    let cache_path = "./cache/cache.json";
-    let content = serde_json::to_string_pretty(cache)?;
-
-    // Create the cache directory if it doesn't exist
-    if let Some(parent) = Path::new(cache_path).parent() {
-        fs::create_dir_all(parent)?;
-    }
-
-    fs::write(cache_path, content)?;
-    println!("💾 Cache saved to {}", cache_path);
-
-    Ok(())
+        Command::Status => commands::show_status(&*provider, &mut cache, &config).await,
+        Command::ListRecords(args) => {
+            commands::list_records(&args, &*provider, &mut cache, &config).await
+        }
+        Command::Verify => commands::verify(&*provider, &mut cache, &config).await,
+        Command::Validate => commands::validate(&*provider, &mut cache, &config).await,
+        Command::History(args) => commands::history(&args, &config, cli.output == Output::Json),
+        Command::ClearCache
+        | Command::Healthcheck
+        | Command::SystemdUnit(_)
+        | Command::Auth(_)
+        | Command::Init => unreachable!("handled before loading the config"),
+        #[cfg(windows)]
+        Command::Service(_) => unreachable!("handled before loading the config"),
+    };
+    result.map(|()| ExitCode::SUCCESS)
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    // Read environment variables
-    let api_token = env::var("CLOUDFLARE_API_TOKEN")
-        .map_err(|_| anyhow!("CLOUDFLARE_API_TOKEN environment variable is required"))?;
-
-    let zone_id = env::var("CLOUDFLARE_ZONE_ID")
-        .map_err(|_| anyhow!("CLOUDFLARE_ZONE_ID environment variable is required"))?;
-
-    let record_name = env::var("DNS_RECORD_NAME")
-        .map_err(|_| anyhow!("DNS_RECORD_NAME environment variable is required"))?;
-
-    let record_type = env::var("DNS_RECORD_TYPE").unwrap_or_else(|_| "A".to_string());
-    let ttl: u32 = env::var("DNS_RECORD_TTL")
-        .unwrap_or_else(|_| "1".to_string())
-        .parse()
-        .unwrap_or(1);
-
-    let cache_expiry_hours: i64 = env::var("CACHE_EXPIRY_HOURS")
-        .unwrap_or_else(|_| "24".to_string())
-        .parse()
-        .unwrap_or(24);
-
-    println!("🌐 Getting current public IP address...");
-    let current_ip = get_public_ip().await?;
-    println!("📍 Current IP: {}", current_ip);
-
-    // Load cache and check if we can skip Cloudflare API call
-    let mut cache = load_cache();
-
-    if let Some(ref cached_data) = cache {
-        if cached_data.matches_config(&record_name, &record_type) {
-            if !cached_data.is_expired(cache_expiry_hours) {
-                if cached_data.ip_address == current_ip {
-                    println!(
-                        "✅ Cache hit! IP unchanged ({}), skipping Cloudflare API call",
-                        current_ip
-                    );
-                    println!(
-                        "   Last checked: {}",
-                        cached_data.last_checked.format("%Y-%m-%d %H:%M:%S UTC")
-                    );
-                    return Ok(());
-                } else {
-                    println!(
-                        "🔄 Cache hit but IP changed: {} -> {}",
-                        cached_data.ip_address, current_ip
-                    );
+/// Runs a single update cycle, printing its report with `--output json`.
+/// A cycle that takes longer than `max_runtime` is stopped, and the cache is
+/// saved with the records it got to.
+///
+/// The exit code tells scripts what happened: 0 when nothing needed
+/// changing, 2 when records were updated, 3 when some records failed but
+/// others didn't, 4 when updated records didn't resolve in time, 5 when the
+/// run was stopped at `max_runtime`, and 1 for any other error. A stopped
+/// run still prints a report of the records it got to, with the limit in
+/// its `errors` and the records it didn't get to counted as failed.
+async fn run_once(
+    provider: &dyn DnsProvider,
+    dispatcher: &Dispatcher,
+    cache: &mut CacheFile,
+    config: &Config,
+    max_runtime: Option<Duration>,
+) -> Result<ExitCode> {
+    let mut report = CycleReport::default();
+    let cycle = fill_cycle(provider, dispatcher, cache, config, &mut report);
+    match max_runtime {
+        Some(limit) => {
+            if tokio::time::timeout(limit, cycle).await.is_err() {
+                // Records updated before the deadline aren't updated again
+                if !config.dry_run
+                    && let Err(e) = save_cache(cache, &config.cache_path)
+                {
+                    warn!("⚠️  Failed to save cache: {}", e);
                 }
-            } else {
-                println!(
-                    "⏰ Cache expired ({}h), checking Cloudflare",
-                    cache_expiry_hours
+                let error = format!(
+                    "The run didn't finish within {}s (MAX_RUNTIME), stopped it",
+                    limit.as_secs()
                 );
-            }
-        } else {
-            println!("⚠️  Cache config mismatch, checking Cloudflare");
-        }
-    }
-
-    // Need to check Cloudflare API
-    println!("🔍 Connecting to Cloudflare API...");
-    let cf_client = CloudflareClient::new(api_token, zone_id);
-
-    println!("📋 Fetching DNS records for '{}'...", record_name);
-    let records = cf_client.get_dns_records(&record_name).await?;
-
-    if records.is_empty() {
-        return Err(anyhow!("No DNS record found with name '{}'", record_name));
-    }
-
-    // Find the record with the matching type (default to A record)
-    let target_record = records
-        .iter()
-        .find(|r| r.record_type == record_type)
-        .ok_or_else(|| {
-            anyhow!(
-                "No {} record found with name '{}'",
-                record_type,
-                record_name
-            )
-        })?;
-
-    println!(
-        "🔍 Found DNS record: {} -> {} (TTL: {})",
-        target_record.name, target_record.content, target_record.ttl
-    );
-
-    // Update or create cache with current Cloudflare record
-    match cache.as_mut() {
-        Some(cached_data) if cached_data.matches_config(&record_name, &record_type) => {
-            cached_data.update_checked();
-        }
-        _ => {
-            cache = Some(DnsCache::new(
-                record_name.clone(),
-                record_type.clone(),
-                target_record.content.clone(),
-            ));
-        }
-    }
-
-    // Check if update is needed
-    if target_record.content == current_ip {
-        println!("✅ DNS record is already up to date!");
-
-        // Update cache with current IP if it was different
-        if let Some(ref mut cached_data) = cache {
-            if cached_data.ip_address != current_ip {
-                cached_data.update_ip(current_ip);
-            }
-        }
-
-        // Save cache
-        if let Some(ref cached_data) = cache {
-            if let Err(e) = save_cache(cached_data) {
-                println!("⚠️  Failed to save cache: {}", e);
+                report.stop(error.clone());
+                report.duration_ms = limit.as_millis() as u64;
+                report.log_summary();
+                if config.json_output {
+                    print_json(&report);
+                }
+                eprintln!("Error: {}", error);
+                return Ok(ExitCode::from(EXIT_TIMED_OUT));
             }
         }
-
-        return Ok(());
+        None => cycle.await,
     }
-
-    println!(
-        "🔄 Updating DNS record from '{}' to '{}'...",
-        target_record.content, current_ip
-    );
-
-    let update_data = UpdateDnsRecord {
-        record_type: record_type.clone(),
-        name: record_name.clone(),
-        content: current_ip.clone(),
-        ttl,
-    };
-
-    cf_client
-        .update_dns_record(&target_record.id, update_data)
-        .await?;
-
-    println!("✅ Successfully updated DNS record!");
-    println!("   Record: {}", record_name);
-    println!("   Type: {}", record_type);
-    println!("   New IP: {}", current_ip);
-    println!("   TTL: {}", ttl);
-
-    // Update cache with new IP
-    if let Some(ref mut cached_data) = cache {
-        cached_data.update_ip(current_ip);
-    } else {
-        cache = Some(DnsCache::new(record_name, record_type, current_ip));
+    if config.json_output {
+        print_json(&report);
     }
 
-    // Save cache
-    if let Some(ref cached_data) = cache {
-        if let Err(e) = save_cache(cached_data) {
-            println!("⚠️  Failed to save cache: {}", e);
+    match report.result().map_err(anyhow::Error::from) {
+        Ok(()) if report.unverified > 0 => Ok(ExitCode::from(EXIT_UNVERIFIED)),
+        Ok(()) if report.changed > 0 => Ok(ExitCode::from(EXIT_UPDATED)),
+        Ok(()) => Ok(ExitCode::SUCCESS),
+        Err(e) if report.failed < report.checked => {
+            eprintln!("Error: {:?}", e);
+            Ok(ExitCode::from(EXIT_PARTIAL_FAILURE))
         }
+        Err(e) => Err(e),
     }
-
-    Ok(())
 }
@@ -0,0 +1,501 @@
+//! Publishes the address of every record and whether the daemon is running
+//! to an MQTT broker, with Home Assistant discovery messages so that the
+//! records show up as sensors without any configuration there.
+//!
+//! All messages are retained. `<topic>/status` is `online` while connected
+//! and `offline` after a shutdown, or when the broker loses the connection
+//! (as the last will). `<topic>/<record>` holds a JSON document with the
+//! record's name, type, address and time of its last update, published on
+//! every change and, from the cache, every time the connection is made.
+
+use crate::apprise::decode;
+use crate::error::{Error, Result};
+use url::Url;
+
+/// Topic the messages go under when none is configured.
+pub const DEFAULT_TOPIC: &str = "cloudflare-ddns";
+
+/// Where Home Assistant looks for discovery messages by default.
+pub const DEFAULT_DISCOVERY_PREFIX: &str = "homeassistant";
+
+/// Settings for the MQTT notifier.
+#[derive(Debug, Clone)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    /// Connect over TLS, for `mqtts://` URLs.
+    pub tls: bool,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub topic: String,
+    /// Prefix of the Home Assistant discovery topics, `None` to not send
+    /// any.
+    pub discovery_prefix: Option<String>,
+}
+
+impl MqttConfig {
+    /// Settings for a broker given as `mqtt://[user:password@]host[:port]`,
+    /// or `mqtts://` for TLS. An empty `discovery_prefix` turns discovery
+    /// off.
+    pub fn from_url(url: &str, topic: String, discovery_prefix: String) -> Result<Self> {
+        let invalid = |reason: &str| Error::Config(format!("Invalid MQTT_URL: {}", reason));
+        let parsed = Url::parse(url).map_err(|e| invalid(&e.to_string()))?;
+        let tls = match parsed.scheme() {
+            "mqtt" | "tcp" => false,
+            "mqtts" | "ssl" => true,
+            other => {
+                return Err(invalid(&format!(
+                    "unsupported scheme '{}://', use mqtt:// or mqtts://",
+                    other
+                )));
+            }
+        };
+        let host = parsed
+            .host_str()
+            .filter(|host| !host.is_empty())
+            .ok_or_else(|| invalid("no broker host"))?
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
+        let topic = topic.trim_matches('/').to_string();
+        if topic.is_empty() || topic.contains(['+', '#']) {
+            return Err(Error::Config(format!(
+                "Invalid MQTT_TOPIC '{}', expected a topic without wildcards",
+                topic
+            )));
+        }
+        let discovery_prefix = discovery_prefix.trim_matches('/').to_string();
+        Ok(Self {
+            host,
+            port: parsed.port().unwrap_or(if tls { 8883 } else { 1883 }),
+            tls,
+            username: Some(decode(parsed.username())).filter(|name| !name.is_empty()),
+            password: parsed.password().map(decode),
+            topic,
+            discovery_prefix: Some(discovery_prefix).filter(|prefix| !prefix.is_empty()),
+        })
+    }
+}
+
+/// How long to wait before connecting again after the connection failed.
+#[cfg(feature = "mqtt")]
+const RECONNECT_DELAY: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// How long a notification waits for its messages to be sent.
+#[cfg(feature = "mqtt")]
+const SEND_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Keeps the broker up to date with the records. The connection is made,
+/// and remade after errors, by a background task.
+pub struct Mqtt {
+    #[cfg(feature = "mqtt")]
+    inner: std::sync::Arc<connection::Inner>,
+    #[cfg(not(feature = "mqtt"))]
+    never: std::convert::Infallible,
+}
+
+#[cfg(feature = "mqtt")]
+mod connection {
+    use super::*;
+    use chrono::{DateTime, Utc};
+    use rumqttc::{
+        AsyncClient, Event, LastWill, MqttOptions, Outgoing, Packet, QoS, TlsConfiguration,
+        Transport,
+    };
+    use serde_json::json;
+    use std::collections::BTreeSet;
+    use std::path::PathBuf;
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use tokio::sync::{Notify, watch};
+    use tracing::{info, warn};
+
+    /// Lowercase letters, digits and underscores only, for topics and IDs.
+    fn slug(value: &str) -> String {
+        value
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_lowercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect()
+    }
+
+    pub struct Inner {
+        client: AsyncClient,
+        config: MqttConfig,
+        /// Identifies this instance in Home Assistant.
+        node_id: String,
+        cache_path: PathBuf,
+        /// Whether the daemon runs, which has an online status.
+        daemon: bool,
+        /// Records whose discovery messages have been sent.
+        announced: Mutex<BTreeSet<(String, String)>>,
+        /// Messages handed to the client so far, and those it has sent.
+        queued: AtomicU64,
+        sent: watch::Receiver<u64>,
+        disconnected: Notify,
+    }
+
+    impl Inner {
+        fn status_topic(&self) -> String {
+            format!("{}/status", self.config.topic)
+        }
+
+        fn state_topic(&self, record_name: &str, record_type: &str) -> String {
+            format!(
+                "{}/{}",
+                self.config.topic,
+                slug(&format!("{}_{}", record_name, record_type))
+            )
+        }
+
+        /// Queues a retained message and returns the number that has to be
+        /// sent for it to be out.
+        async fn publish(&self, topic: String, payload: String) -> Result<u64> {
+            let target = self.queued.fetch_add(1, Ordering::SeqCst) + 1;
+            self.client
+                .publish(topic, QoS::AtLeastOnce, true, payload)
+                .await
+                .map_err(|e| Error::Notification(format!("MQTT publish failed: {}", e)))?;
+            Ok(target)
+        }
+
+        /// Waits until the messages up to `target` have been sent.
+        async fn sent(&self, target: u64) -> Result<()> {
+            let mut sent = self.sent.clone();
+            tokio::time::timeout(SEND_TIMEOUT, sent.wait_for(|sent| *sent >= target))
+                .await
+                .map_err(|_| {
+                    Error::Notification(format!(
+                        "The MQTT broker at {}:{} couldn't be reached",
+                        self.config.host, self.config.port
+                    ))
+                })?
+                .map_err(|_| Error::Notification("The MQTT connection was closed".to_string()))?;
+            Ok(())
+        }
+
+        async fn publish_state(
+            &self,
+            record_name: &str,
+            record_type: &str,
+            ip: &str,
+            last_update: DateTime<Utc>,
+        ) -> Result<u64> {
+            let state = json!({
+                "name": record_name,
+                "type": record_type,
+                "ip": ip,
+                "last_update": last_update.to_rfc3339(),
+            });
+            self.publish(
+                self.state_topic(record_name, record_type),
+                state.to_string(),
+            )
+            .await
+        }
+
+        /// Sends the discovery messages of a record, once per connection.
+        async fn announce(&self, record_name: &str, record_type: &str) -> Result<u64> {
+            let Some(prefix) = &self.config.discovery_prefix else {
+                return Ok(0);
+            };
+            let key = (record_name.to_string(), record_type.to_string());
+            if !self
+                .announced
+                .lock()
+                .expect("mqtt lock poisoned")
+                .insert(key)
+            {
+                return Ok(0);
+            }
+            let record = slug(&format!("{}_{}", record_name, record_type));
+            let state_topic = self.state_topic(record_name, record_type);
+            let address = json!({
+                "name": format!("{} {}", record_name, record_type),
+                "unique_id": format!("{}_{}_ip", self.node_id, record),
+                "state_topic": state_topic,
+                "value_template": "{{ value_json.ip }}",
+                "icon": "mdi:ip-network",
+                "device": self.device(),
+            });
+            let address = self.with_availability(address);
+            self.publish(
+                format!("{}/sensor/{}/{}_ip/config", prefix, self.node_id, record),
+                address.to_string(),
+            )
+            .await?;
+            let last_update = json!({
+                "name": format!("{} {} last update", record_name, record_type),
+                "unique_id": format!("{}_{}_last_update", self.node_id, record),
+                "state_topic": state_topic,
+                "value_template": "{{ value_json.last_update }}",
+                "device_class": "timestamp",
+                "entity_category": "diagnostic",
+                "device": self.device(),
+            });
+            let last_update = self.with_availability(last_update);
+            self.publish(
+                format!(
+                    "{}/sensor/{}/{}_last_update/config",
+                    prefix, self.node_id, record
+                ),
+                last_update.to_string(),
+            )
+            .await
+        }
+
+        /// Entities of the daemon are unavailable while it's offline.
+        fn with_availability(&self, mut entity: serde_json::Value) -> serde_json::Value {
+            if self.daemon {
+                entity["availability_topic"] = self.status_topic().into();
+            }
+            entity
+        }
+
+        fn device(&self) -> serde_json::Value {
+            json!({
+                "identifiers": [self.node_id],
+                "name": "Cloudflare DDNS",
+                "manufacturer": "cloudflare-ddns",
+                "sw_version": env!("CARGO_PKG_VERSION"),
+            })
+        }
+
+        /// Everything a fresh connection of the daemon needs: the status,
+        /// the discovery messages and the addresses the cache knows.
+        async fn connected(&self, records: &[(String, String)]) -> Result<()> {
+            self.announced.lock().expect("mqtt lock poisoned").clear();
+            // Single runs only publish changes
+            if !self.daemon {
+                return Ok(());
+            }
+            self.publish(self.status_topic(), "online".to_string())
+                .await?;
+            if let Some(prefix) = &self.config.discovery_prefix {
+                let status = json!({
+                    "name": "Status",
+                    "unique_id": format!("{}_status", self.node_id),
+                    "state_topic": self.status_topic(),
+                    "payload_on": "online",
+                    "payload_off": "offline",
+                    "device_class": "connectivity",
+                    "device": self.device(),
+                });
+                self.publish(
+                    format!("{}/binary_sensor/{}/status/config", prefix, self.node_id),
+                    status.to_string(),
+                )
+                .await?;
+            }
+            // Without a cache yet, the first change publishes the address
+            let cache = crate::cache::read_cache(&self.cache_path).ok();
+            for (name, record_type) in records {
+                self.announce(name, record_type).await?;
+                if let Some(entry) = cache.as_ref().and_then(|c| c.find(name, record_type)) {
+                    self.publish_state(name, record_type, &entry.ip_address, entry.last_updated)
+                        .await?;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl Mqtt {
+        /// Connects to the broker in the background. For the daemon,
+        /// `records` are announced to Home Assistant on connecting, with their
+        /// last known address read from the cache at `cache_path`.
+        pub fn connect(
+            config: MqttConfig,
+            records: Vec<(String, String)>,
+            cache_path: PathBuf,
+            daemon: bool,
+        ) -> Result<Self> {
+            let node_id = slug(&config.topic);
+            let client_id = format!("{}-{}", node_id, fastrand::u32(..));
+            let mut options = MqttOptions::new(client_id, &config.host, config.port);
+            options.set_keep_alive(std::time::Duration::from_secs(30));
+            if let Some(username) = &config.username {
+                options.set_credentials(username, config.password.clone().unwrap_or_default());
+            }
+            if config.tls {
+                options.set_transport(Transport::tls_with_config(TlsConfiguration::Native));
+            }
+            if daemon {
+                options.set_last_will(LastWill::new(
+                    format!("{}/status", config.topic),
+                    "offline",
+                    QoS::AtLeastOnce,
+                    true,
+                ));
+            }
+
+            let (client, mut eventloop) = AsyncClient::new(options, 64);
+            let (sent_tx, sent) = watch::channel(0);
+            let inner = std::sync::Arc::new(Inner {
+                client,
+                config,
+                node_id,
+                cache_path,
+                daemon,
+                announced: Mutex::new(BTreeSet::new()),
+                queued: AtomicU64::new(0),
+                sent,
+                disconnected: Notify::new(),
+            });
+
+            let task = inner.clone();
+            tokio::spawn(async move {
+                let mut failing = false;
+                loop {
+                    match eventloop.poll().await {
+                        Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                            failing = false;
+                            info!(
+                                "📡 Connected to the MQTT broker at {}:{}",
+                                task.config.host, task.config.port
+                            );
+                            // Publishing waits for this loop, so it can't
+                            // happen in it
+                            let inner = task.clone();
+                            let records = records.clone();
+                            tokio::spawn(async move {
+                                if let Err(e) = inner.connected(&records).await {
+                                    warn!("⚠️  Failed to publish to the MQTT broker: {}", e);
+                                }
+                            });
+                        }
+                        Ok(Event::Outgoing(Outgoing::Publish(_))) => {
+                            sent_tx.send_modify(|sent| *sent += 1);
+                        }
+                        Ok(Event::Outgoing(Outgoing::Disconnect)) => {
+                            task.disconnected.notify_one();
+                            break;
+                        }
+                        Ok(_) => {}
+                        Err(e) => {
+                            if !failing {
+                                warn!(
+                                    "⚠️  MQTT connection to {}:{} failed, retrying every {} seconds: {}",
+                                    task.config.host,
+                                    task.config.port,
+                                    RECONNECT_DELAY.as_secs(),
+                                    e
+                                );
+                            }
+                            failing = true;
+                            tokio::time::sleep(RECONNECT_DELAY).await;
+                        }
+                    }
+                }
+            });
+
+            Ok(Self { inner })
+        }
+
+        /// Publishes the new address of a record.
+        pub(super) async fn changed(
+            &self,
+            record_name: &str,
+            record_type: &str,
+            ip: &str,
+            time: DateTime<Utc>,
+        ) -> Result<()> {
+            // Records added at runtime weren't announced on connecting
+            self.inner.announce(record_name, record_type).await?;
+            let target = self
+                .inner
+                .publish_state(record_name, record_type, ip, time)
+                .await?;
+            self.inner.sent(target).await
+        }
+
+        /// Marks the daemon offline and disconnects.
+        pub(super) async fn stopping(&self) -> Result<()> {
+            let target = self
+                .inner
+                .publish(self.inner.status_topic(), "offline".to_string())
+                .await?;
+            self.inner.sent(target).await?;
+            self.inner
+                .client
+                .disconnect()
+                .await
+                .map_err(|e| Error::Notification(format!("MQTT disconnect failed: {}", e)))?;
+            let _ = tokio::time::timeout(SEND_TIMEOUT, self.inner.disconnected.notified()).await;
+            Ok(())
+        }
+    }
+
+    // Ends the connection of notifiers replaced by a reload
+    impl Drop for Mqtt {
+        fn drop(&mut self) {
+            let _ = self.inner.client.try_disconnect();
+        }
+    }
+}
+
+#[cfg(not(feature = "mqtt"))]
+impl Mqtt {
+    /// Fails, since this build has no MQTT support.
+    pub fn connect(
+        _config: MqttConfig,
+        _records: Vec<(String, String)>,
+        _cache_path: std::path::PathBuf,
+        _daemon: bool,
+    ) -> Result<Self> {
+        Err(Error::Config(
+            "MQTT_URL needs a build with the `mqtt` feature".to_string(),
+        ))
+    }
+
+    async fn changed(
+        &self,
+        _record_name: &str,
+        _record_type: &str,
+        _ip: &str,
+        _time: chrono::DateTime<chrono::Utc>,
+    ) -> Result<()> {
+        match self.never {}
+    }
+
+    async fn stopping(&self) -> Result<()> {
+        match self.never {}
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::notify::Notifier for Mqtt {
+    fn name(&self) -> &'static str {
+        "MQTT"
+    }
+
+    fn wants(&self, event: &crate::notify::Event<'_>) -> bool {
+        use crate::notify::EventKind;
+        matches!(
+            event.kind,
+            EventKind::IpChanged { .. } | EventKind::ShuttingDown
+        )
+    }
+
+    async fn notify(&self, event: &crate::notify::Event<'_>) -> Result<()> {
+        use crate::notify::EventKind;
+        match event.kind {
+            EventKind::IpChanged { new_ip, .. } => {
+                self.changed(
+                    event.record_name,
+                    event.record_type,
+                    new_ip,
+                    event.timestamp,
+                )
+                .await
+            }
+            EventKind::ShuttingDown => self.stopping().await,
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,235 @@
+//! Notices address, link and default route changes through a Linux netlink
+//! socket, so the daemon can check its records right away instead of at the
+//! next interval.
+//!
+//! Netlink only exists on Linux; elsewhere [`NetworkWatcher::new`] fails.
+
+use crate::error::Result;
+
+/// A subscription to the kernel's network change events.
+pub struct NetworkWatcher {
+    #[cfg(target_os = "linux")]
+    socket: tokio::io::unix::AsyncFd<std::os::fd::OwnedFd>,
+    /// Only changes of this interface count, if set.
+    #[cfg(target_os = "linux")]
+    interface: Option<std::ffi::CString>,
+    #[cfg(not(target_os = "linux"))]
+    never: std::convert::Infallible,
+}
+
+#[cfg(target_os = "linux")]
+impl NetworkWatcher {
+    /// Subscribes to changes of `interface`, or of every interface.
+    pub fn new(interface: Option<&str>) -> Result<Self> {
+        use crate::error::{Context, Error};
+        use std::ffi::CString;
+        use std::os::fd::{FromRawFd, OwnedFd};
+
+        let interface = interface
+            .map(|name| {
+                CString::new(name).map_err(|_| {
+                    Error::Config(format!("Invalid network interface name '{}'", name))
+                })
+            })
+            .transpose()?;
+
+        // SAFETY: plain socket(2) and bind(2) calls on a zeroed sockaddr_nl
+        let socket = unsafe {
+            let fd = libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            );
+            if fd < 0 {
+                return Err(std::io::Error::last_os_error())
+                    .context("Failed to open a netlink socket");
+            }
+            let socket = OwnedFd::from_raw_fd(fd);
+
+            let mut address: libc::sockaddr_nl = std::mem::zeroed();
+            address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+            address.nl_groups = (libc::RTMGRP_LINK
+                | libc::RTMGRP_IPV4_IFADDR
+                | libc::RTMGRP_IPV6_IFADDR
+                | libc::RTMGRP_IPV4_ROUTE
+                | libc::RTMGRP_IPV6_ROUTE) as u32;
+            let bound = libc::bind(
+                fd,
+                &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            );
+            if bound < 0 {
+                return Err(std::io::Error::last_os_error())
+                    .context("Failed to subscribe to netlink route events");
+            }
+            socket
+        };
+
+        Ok(Self {
+            socket: tokio::io::unix::AsyncFd::new(socket)?,
+            interface,
+        })
+    }
+
+    /// Resolves once a relevant change has been received.
+    pub async fn changed(&mut self) -> Result<()> {
+        use std::os::fd::AsRawFd;
+
+        let mut buf = vec![0u8; 32 * 1024];
+        loop {
+            let mut guard = self.socket.readable().await?;
+            let received = guard.try_io(|socket| {
+                // SAFETY: recv(2) into a buffer of the given length
+                let len = unsafe {
+                    libc::recv(
+                        socket.as_raw_fd(),
+                        buf.as_mut_ptr() as *mut libc::c_void,
+                        buf.len(),
+                        0,
+                    )
+                };
+                if len < 0 {
+                    Err(std::io::Error::last_os_error())
+                } else {
+                    Ok(len as usize)
+                }
+            });
+
+            match received {
+                Ok(Ok(len)) => {
+                    if self.any_relevant(&buf[..len]) {
+                        return Ok(());
+                    }
+                }
+                // The kernel dropped events that didn't fit, some of which
+                // may have mattered
+                Ok(Err(e)) if e.raw_os_error() == Some(libc::ENOBUFS) => return Ok(()),
+                Ok(Err(e)) => return Err(e.into()),
+                Err(_would_block) => {}
+            }
+        }
+    }
+
+    /// Throws away the events received so far, such as the rest of a change
+    /// that has already been noticed.
+    pub fn discard_pending(&mut self) {
+        use std::os::fd::AsRawFd;
+
+        let mut buf = [0u8; 4096];
+        loop {
+            // SAFETY: recv(2) into a buffer of the given length
+            let len = unsafe {
+                libc::recv(
+                    self.socket.get_ref().as_raw_fd(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                    libc::MSG_DONTWAIT,
+                )
+            };
+            if len <= 0 {
+                break;
+            }
+        }
+    }
+
+    /// Whether any of the netlink messages in `buf` is about the watched
+    /// interface or the default route.
+    fn any_relevant(&self, mut buf: &[u8]) -> bool {
+        const HEADER_LEN: usize = 16;
+
+        while buf.len() >= HEADER_LEN {
+            let len = u32_at(buf, 0) as usize;
+            if len < HEADER_LEN || len > buf.len() {
+                break;
+            }
+            let kind = u16::from_ne_bytes([buf[4], buf[5]]);
+            if self.is_relevant(kind, &buf[HEADER_LEN..len]) {
+                return true;
+            }
+            // Messages are aligned to 4 bytes
+            buf = &buf[((len + 3) & !3).min(buf.len())..];
+        }
+        false
+    }
+
+    fn is_relevant(&self, kind: u16, body: &[u8]) -> bool {
+        match kind {
+            // ifinfomsg and ifaddrmsg both have the interface index at offset 4
+            libc::RTM_NEWLINK | libc::RTM_DELLINK | libc::RTM_NEWADDR | libc::RTM_DELADDR => {
+                body.len() >= 8 && self.watches(u32_at(body, 4))
+            }
+            libc::RTM_NEWROUTE | libc::RTM_DELROUTE => {
+                const RTMSG_LEN: usize = 12;
+                // Only the default route, rtm_dst_len 0
+                if body.len() < RTMSG_LEN || body[1] != 0 {
+                    return false;
+                }
+                match route_output_interface(&body[RTMSG_LEN..]) {
+                    Some(index) => self.watches(index),
+                    None => true,
+                }
+            }
+            _ => false,
+        }
+    }
+
+    fn watches(&self, index: u32) -> bool {
+        match &self.interface {
+            None => true,
+            Some(name) => {
+                // Looked up every time because interfaces such as ppp0 get a
+                // new index when they come back
+                // SAFETY: `name` is a valid C string
+                let current = unsafe { libc::if_nametoindex(name.as_ptr()) };
+                current == 0 || current == index
+            }
+        }
+    }
+}
+
+/// The `RTA_OIF` attribute of a route message.
+#[cfg(target_os = "linux")]
+fn route_output_interface(mut attributes: &[u8]) -> Option<u32> {
+    const RTA_OIF: u16 = 4;
+
+    while attributes.len() >= 4 {
+        let len = u16::from_ne_bytes([attributes[0], attributes[1]]) as usize;
+        let kind = u16::from_ne_bytes([attributes[2], attributes[3]]);
+        if len < 4 || len > attributes.len() {
+            break;
+        }
+        if kind == RTA_OIF && len >= 8 {
+            return Some(u32_at(attributes, 4));
+        }
+        attributes = &attributes[((len + 3) & !3).min(attributes.len())..];
+    }
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    u32::from_ne_bytes([
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ])
+}
+
+#[cfg(not(target_os = "linux"))]
+impl NetworkWatcher {
+    /// Fails, since only Linux has netlink.
+    pub fn new(_interface: Option<&str>) -> Result<Self> {
+        Err(crate::error::Error::Config(
+            "WATCH_NETWORK is only supported on Linux".to_string(),
+        ))
+    }
+
+    pub async fn changed(&mut self) -> Result<()> {
+        match self.never {}
+    }
+
+    pub fn discard_pending(&mut self) {
+        match self.never {}
+    }
+}
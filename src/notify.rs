@@ -0,0 +1,325 @@
+//! Notifications about record changes and failures.
+//!
+//! Every channel implements [`Notifier`]. The [`Dispatcher`] turns the
+//! results of an update cycle into [`Event`]s and hands them to all configured
+//! notifiers, so the update loop doesn't need to know which channels exist.
+
+use crate::apprise::{self, AppriseApi};
+use crate::config::{Config, RunMode};
+use crate::discord::Discord;
+use crate::email::Email;
+use crate::error::{Error, Result};
+use crate::gotify::Gotify;
+use crate::mqtt::Mqtt;
+use crate::ntfy::Ntfy;
+use crate::slack::Slack;
+use crate::telegram::Telegram;
+use crate::webhook::Webhook;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::future::join_all;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Something that happened to a record, or to the daemon itself.
+#[derive(Debug, Clone)]
+pub struct Event<'a> {
+    /// The record the event is about, empty for [`EventKind::ShuttingDown`].
+    pub record_name: &'a str,
+    pub record_type: &'a str,
+    pub kind: EventKind<'a>,
+    pub timestamp: DateTime<Utc>,
+    /// Failures in a row after which a failing record is reported, see
+    /// [`Config::failure_threshold`].
+    pub failure_threshold: u32,
+}
+
+#[derive(Debug, Clone)]
+pub enum EventKind<'a> {
+    /// The record was updated to a new address. `old_ip` is `None` for newly
+    /// created records, and `duration` is how long processing the record took.
+    IpChanged {
+        old_ip: Option<&'a str>,
+        new_ip: &'a str,
+        duration: Duration,
+    },
+    /// The record could not be updated. `failures` counts the cycles in a row
+    /// it has failed, including this one.
+    UpdateFailed { error: &'a str, failures: u32 },
+    /// The record was processed successfully after failing `failures` cycles in a row.
+    Recovered { failures: u32 },
+    /// The daemon received a shutdown signal and is exiting.
+    ShuttingDown,
+}
+
+impl EventKind<'_> {
+    /// The event name used in logs and webhook payloads.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::IpChanged { .. } => "ip_changed",
+            Self::UpdateFailed { .. } => "update_failed",
+            Self::Recovered { .. } => "recovered",
+            Self::ShuttingDown => "shutting_down",
+        }
+    }
+}
+
+impl Event<'_> {
+    /// Whether this is a failure of a record whose failure streak has already
+    /// been reported. Channels meant for people usually skip these.
+    pub fn is_repeated_failure(&self) -> bool {
+        matches!(self.kind, EventKind::UpdateFailed { failures, .. } if failures > self.failure_threshold)
+    }
+
+    /// A one-line summary, for channels with a separate title.
+    pub fn title(&self) -> String {
+        match self.kind {
+            EventKind::IpChanged {
+                old_ip: Some(_), ..
+            } => format!("🔄 {} updated", self.record_name),
+            EventKind::IpChanged { old_ip: None, .. } => format!("➕ {} created", self.record_name),
+            EventKind::UpdateFailed { .. } => format!("❌ {} failed to update", self.record_name),
+            EventKind::Recovered { .. } => format!("✅ {} recovered", self.record_name),
+            EventKind::ShuttingDown => "🛑 cloudflare-ddns is shutting down".to_string(),
+        }
+    }
+
+    /// Plain-text details to go with [`Event::title`].
+    pub fn message(&self) -> String {
+        let record = format!("{} ({})", self.record_name, self.record_type);
+        match self.kind {
+            EventKind::IpChanged { old_ip, new_ip, .. } => {
+                format!("{}: {} -> {}", record, old_ip.unwrap_or("-"), new_ip)
+            }
+            EventKind::UpdateFailed { error, .. } => format!("{}: {}", record, error),
+            EventKind::Recovered { failures } => {
+                format!(
+                    "{} is updating again after {} failed cycle(s)",
+                    record, failures
+                )
+            }
+            EventKind::ShuttingDown => {
+                "The daemon was asked to stop, records won't be updated until it is started again"
+                    .to_string()
+            }
+        }
+    }
+}
+
+/// A notification channel.
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    /// Name of the channel for log messages.
+    fn name(&self) -> &'static str;
+
+    /// Whether the channel is interested in an event. Channels use this to
+    /// stay quiet about, for example, the second failure in a row.
+    fn wants(&self, _event: &Event<'_>) -> bool {
+        true
+    }
+
+    async fn notify(&self, event: &Event<'_>) -> Result<()>;
+}
+
+/// Sends events to every configured notifier.
+///
+/// Failures of a record are held back until it has failed
+/// `failure_threshold` runs in a row, and so is the recovery of a record
+/// whose failures never were reported.
+pub struct Dispatcher {
+    notifiers: Vec<Box<dyn Notifier>>,
+    failure_threshold: u32,
+}
+
+impl Default for Dispatcher {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
+impl Dispatcher {
+    pub fn new(notifiers: Vec<Box<dyn Notifier>>) -> Self {
+        Self {
+            notifiers,
+            failure_threshold: 1,
+        }
+    }
+
+    /// Only report failures once a record has failed this many runs in a
+    /// row.
+    pub fn with_failure_threshold(mut self, failure_threshold: u32) -> Self {
+        self.failure_threshold = failure_threshold.max(1);
+        self
+    }
+
+    /// Builds the notifiers enabled in the configuration.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let client = config.http.client();
+        let mut notifiers: Vec<Box<dyn Notifier>> = Vec::new();
+        if let Some(url) = &config.webhook_url {
+            notifiers.push(Box::new(Webhook::new(client.clone(), url.clone())));
+        }
+        if let Some(telegram) = &config.telegram {
+            notifiers.push(Box::new(Telegram::new(client.clone(), telegram.clone())));
+        }
+        if let Some(url) = &config.discord_webhook_url {
+            notifiers.push(Box::new(Discord::new(client.clone(), url.clone())));
+        }
+        if let Some(url) = &config.slack_webhook_url {
+            notifiers.push(Box::new(Slack::new(client.clone(), url.clone())));
+        }
+        if let Some(email) = &config.email {
+            notifiers.push(Box::new(Email::new(email.clone())?));
+        }
+        if let Some(ntfy) = &config.ntfy {
+            notifiers.push(Box::new(Ntfy::new(client.clone(), ntfy.clone())));
+        }
+        if let Some((url, token)) = &config.gotify {
+            notifiers.push(Box::new(Gotify::new(
+                client.clone(),
+                url.clone(),
+                token.clone(),
+            )));
+        }
+        if let Some(mqtt) = &config.mqtt {
+            let records = config
+                .records
+                .iter()
+                .map(|record| (record.name.clone(), record.record_type.clone()))
+                .collect();
+            notifiers.push(Box::new(Mqtt::connect(
+                mqtt.clone(),
+                records,
+                config.cache_path.clone(),
+                config.run_mode == RunMode::Daemon,
+            )?));
+        }
+
+        // URLs for services without a built-in notifier go through Apprise
+        let mut forwarded = Vec::new();
+        for url in &config.notify_urls {
+            match apprise::notifier_from_url(url, &client)? {
+                Some(notifier) => notifiers.push(notifier),
+                None => forwarded.push(url.clone()),
+            }
+        }
+        if !forwarded.is_empty() {
+            let api_url = config.apprise_api_url.clone().ok_or_else(|| {
+                Error::Config(format!(
+                    "Notification URL scheme '{}://' isn't supported natively, set APPRISE_API_URL (or `apprise_api_url` in the config file) to send it through an Apprise API server",
+                    apprise::scheme(&forwarded[0])
+                ))
+            })?;
+            notifiers.push(Box::new(AppriseApi::new(
+                client.clone(),
+                api_url,
+                forwarded,
+            )));
+        }
+
+        Ok(Self::new(notifiers).with_failure_threshold(config.failure_threshold))
+    }
+
+    /// Adds another notifier.
+    pub fn push(&mut self, notifier: impl Notifier + 'static) {
+        self.notifiers.push(Box::new(notifier));
+    }
+
+    /// Reports a successful change. `old_ip` is `None` for newly created
+    /// records, and `duration` is how long processing the record took.
+    pub async fn ip_changed(
+        &self,
+        record_name: &str,
+        record_type: &str,
+        old_ip: Option<&str>,
+        new_ip: &str,
+        duration: Duration,
+    ) {
+        self.dispatch(Event {
+            record_name,
+            record_type,
+            kind: EventKind::IpChanged {
+                old_ip,
+                new_ip,
+                duration,
+            },
+            timestamp: Utc::now(),
+            failure_threshold: self.failure_threshold,
+        })
+        .await;
+    }
+
+    /// Reports a failure of a record that has now failed `failures` runs in
+    /// a row.
+    pub async fn update_failed(
+        &self,
+        record_name: &str,
+        record_type: &str,
+        error: &str,
+        failures: u32,
+    ) {
+        if failures < self.failure_threshold {
+            if !self.notifiers.is_empty() {
+                info!(
+                    "🤫 Not reporting the failure yet ({} of {} in a row)",
+                    failures, self.failure_threshold
+                );
+            }
+            return;
+        }
+
+        self.dispatch(Event {
+            record_name,
+            record_type,
+            kind: EventKind::UpdateFailed { error, failures },
+            timestamp: Utc::now(),
+            failure_threshold: self.failure_threshold,
+        })
+        .await;
+    }
+
+    /// Reports that a record was processed successfully after failing
+    /// `failures` runs in a row.
+    pub async fn recovered(&self, record_name: &str, record_type: &str, failures: u32) {
+        if failures < self.failure_threshold {
+            return;
+        }
+
+        self.dispatch(Event {
+            record_name,
+            record_type,
+            kind: EventKind::Recovered { failures },
+            timestamp: Utc::now(),
+            failure_threshold: self.failure_threshold,
+        })
+        .await;
+    }
+
+    /// Reports that the daemon is exiting.
+    pub async fn shutting_down(&self) {
+        self.dispatch(Event {
+            record_name: "",
+            record_type: "",
+            kind: EventKind::ShuttingDown,
+            timestamp: Utc::now(),
+            failure_threshold: self.failure_threshold,
+        })
+        .await;
+    }
+
+    /// Delivery problems are logged but never fail the run.
+    async fn dispatch(&self, event: Event<'_>) {
+        let deliveries = self
+            .notifiers
+            .iter()
+            .filter(|notifier| notifier.wants(&event))
+            .map(|notifier| async {
+                match notifier.notify(&event).await {
+                    Ok(()) => info!("📣 {} notified ({})", notifier.name(), event.kind.name()),
+                    Err(e) => warn!("⚠️  Failed to send {} notification: {}", notifier.name(), e),
+                }
+            });
+
+        join_all(deliveries).await;
+    }
+}
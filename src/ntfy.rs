@@ -0,0 +1,76 @@
+use crate::error::Result;
+use crate::notify::{Event, EventKind, Notifier};
+use async_trait::async_trait;
+use reqwest::Client;
+
+/// Public server used when only a topic is configured.
+pub const DEFAULT_SERVER: &str = "https://ntfy.sh";
+
+/// Settings for the ntfy notifier.
+#[derive(Debug, Clone)]
+pub struct NtfyConfig {
+    pub server: String,
+    pub topic: String,
+    /// Access token for protected topics.
+    pub token: Option<String>,
+}
+
+/// Publishes a message to an ntfy topic when a record changes, starts
+/// failing or recovers, or the daemon shuts down.
+pub struct Ntfy {
+    client: Client,
+    config: NtfyConfig,
+}
+
+impl Ntfy {
+    pub fn new(client: Client, config: NtfyConfig) -> Self {
+        Self { client, config }
+    }
+}
+
+#[async_trait]
+impl Notifier for Ntfy {
+    fn name(&self) -> &'static str {
+        "ntfy"
+    }
+
+    fn wants(&self, event: &Event<'_>) -> bool {
+        !event.is_repeated_failure()
+    }
+
+    async fn notify(&self, event: &Event<'_>) -> Result<()> {
+        // ntfy priorities go from 1 (min) to 5 (max), 3 is the default
+        let (priority, tags) = match event.kind {
+            EventKind::IpChanged { .. } => ("3", "globe_with_meridians"),
+            EventKind::UpdateFailed { .. } => ("4", "warning"),
+            EventKind::Recovered { .. } => ("3", "white_check_mark"),
+            EventKind::ShuttingDown => ("2", "stop_sign"),
+        };
+        let url = format!(
+            "{}/{}",
+            self.config.server.trim_end_matches('/'),
+            self.config.topic
+        );
+
+        // The title goes in a header, which must be plain ASCII, so the emoji
+        // is left to the tag instead
+        let title = event.title();
+        let title = title
+            .trim_start_matches(|c: char| !c.is_ascii())
+            .trim_start();
+
+        let mut request = self
+            .client
+            .post(&url)
+            .header("Title", title)
+            .header("Priority", priority)
+            .header("Tags", tags)
+            .body(event.message());
+        if let Some(token) = &self.config.token {
+            request = request.bearer_auth(token);
+        }
+
+        request.send().await?.error_for_status()?;
+        Ok(())
+    }
+}
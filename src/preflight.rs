@@ -0,0 +1,56 @@
+//! Startup checks of the credentials, so that a wrong or under-privileged
+//! token is reported up front instead of as an API error in the middle of an
+//! update cycle.
+
+use crate::cache::CacheFile;
+use crate::config::Config;
+use crate::error::{Context, Error, Result};
+use crate::provider::DnsProvider;
+use crate::retry::is_transient;
+use crate::updater::resolve_zone_id;
+use tracing::info;
+
+/// Checks that the credentials are valid and can read the DNS records of
+/// every configured zone.
+///
+/// Write access can't be tested without changing a record, so a token that
+/// can read DNS records but not edit them still passes.
+pub async fn verify(
+    provider: &dyn DnsProvider,
+    cache: &mut CacheFile,
+    config: &Config,
+) -> Result<()> {
+    provider.check_credentials().await?;
+
+    for zone in config.zones() {
+        let zone_id = resolve_zone_id(provider, cache, config, zone)
+            .await
+            .with_context(|| {
+                format!(
+                    "Can't look up zone {}, the token needs the Zone:Zone:Read permission for it",
+                    zone
+                )
+            })?;
+        provider
+            .check_zone_access(&zone_id)
+            .await
+            .with_context(|| {
+                format!(
+                    "Can't read the DNS records of zone {}, the token needs the Zone:DNS:Edit permission for it",
+                    zone
+                )
+            })?;
+        info!("🔐 DNS records of zone {} are accessible", zone);
+    }
+
+    Ok(())
+}
+
+/// Whether a failed check says nothing about the credentials, because
+/// Cloudflare couldn't be reached or was rate limiting.
+pub fn is_inconclusive(error: &Error) -> bool {
+    is_transient(error)
+        || error
+            .chain()
+            .any(|cause| matches!(cause, Error::RateLimited(_)))
+}
@@ -0,0 +1,136 @@
+//! Checks after an update that DNS actually answers with the new address.
+
+use crate::dns::{self, CLASS_IN, Message, TYPE_A, TYPE_AAAA, TYPE_NS};
+use crate::error::{Context, Error, Result};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::time::{Instant, sleep};
+
+/// Resolver used to find a zone's nameservers.
+const RESOLVER: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)), 53);
+
+/// Time between two lookups of a record that doesn't have the new address yet.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Where and for how long updated records are looked up.
+#[derive(Debug, Clone)]
+pub struct PropagationCheck {
+    /// Resolver (`host` or `host:port`) to ask. When unset, every
+    /// authoritative nameserver of the record's zone is asked.
+    pub server: Option<String>,
+    /// How long the new address may take to show up.
+    pub timeout: Duration,
+}
+
+impl PropagationCheck {
+    /// Waits until DNS returns `ip` for the record, or fails with what it
+    /// returned instead once the timeout is up.
+    pub async fn wait_for(&self, name: &str, ip: IpAddr) -> Result<()> {
+        // Any name below a wildcard matches it
+        let lookup_name = match name.strip_prefix("*.") {
+            Some(parent) => format!("ddns-check.{}", parent),
+            None => name.to_string(),
+        };
+
+        let servers = match &self.server {
+            Some(server) => vec![resolve(server).await?],
+            None => nameservers(&lookup_name).await?,
+        };
+
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            let mut last_error = None;
+            for server in &servers {
+                if let Err(e) = check(*server, &lookup_name, ip, self.server.is_some()).await {
+                    last_error = Some(e);
+                    break;
+                }
+            }
+
+            match last_error {
+                None => return Ok(()),
+                Some(e) if Instant::now() + POLL_INTERVAL > deadline => return Err(e),
+                Some(_) => sleep(POLL_INTERVAL).await,
+            }
+        }
+    }
+}
+
+/// Asks a server for the record once.
+async fn check(server: SocketAddr, name: &str, ip: IpAddr, recursive: bool) -> Result<()> {
+    let qtype = if ip.is_ipv4() { TYPE_A } else { TYPE_AAAA };
+    let mut query = Message::query(name, qtype, CLASS_IN);
+    if !recursive {
+        query.flags &= !dns::FLAG_RD;
+    }
+
+    let response = dns::exchange_udp(server, &query).await?;
+    if response.rcode() != 0 {
+        return Err(Error::Dns(format!(
+            "{} answered with error code {}",
+            server,
+            response.rcode()
+        )));
+    }
+
+    let addresses: Vec<IpAddr> = response.answers.iter().filter_map(|r| r.ip()).collect();
+    if addresses.contains(&ip) {
+        Ok(())
+    } else if addresses.is_empty() {
+        Err(Error::Dns(format!(
+            "{} has no address for '{}'",
+            server, name
+        )))
+    } else {
+        let addresses: Vec<String> = addresses.iter().map(IpAddr::to_string).collect();
+        Err(Error::Dns(format!(
+            "{} still returns {} for '{}'",
+            server,
+            addresses.join(", "),
+            name
+        )))
+    }
+}
+
+/// The addresses of the nameservers of the zone holding `name`, found by
+/// asking for the NS records of ever shorter suffixes of the name.
+async fn nameservers(name: &str) -> Result<Vec<SocketAddr>> {
+    let mut zone = name.trim_end_matches('.');
+    loop {
+        let response =
+            dns::exchange_udp(RESOLVER, &Message::query(zone, TYPE_NS, CLASS_IN)).await?;
+        let hosts: Vec<String> = response
+            .answers
+            .iter()
+            .filter(|r| r.rtype == TYPE_NS && r.name.eq_ignore_ascii_case(zone))
+            .filter_map(|r| r.target())
+            .collect();
+
+        if !hosts.is_empty() {
+            let mut servers = Vec::new();
+            for host in hosts {
+                servers.push(resolve(&host).await?);
+            }
+            return Ok(servers);
+        }
+
+        zone = match zone.split_once('.') {
+            Some((_, parent)) if parent.contains('.') => parent,
+            _ => return Err(Error::Dns(format!("Found no nameservers for '{}'", name))),
+        };
+    }
+}
+
+/// Looks up a `host` or `host:port`, preferring an IPv4 address.
+async fn resolve(server: &str) -> Result<SocketAddr> {
+    let addresses: Vec<SocketAddr> = tokio::net::lookup_host(dns::server_address(server))
+        .await
+        .with_context(|| format!("Failed to resolve DNS server '{}'", server))?
+        .collect();
+    addresses
+        .iter()
+        .find(|a| a.is_ipv4())
+        .or(addresses.first())
+        .copied()
+        .ok_or_else(|| Error::Dns(format!("DNS server '{}' has no address", server)))
+}
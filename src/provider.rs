@@ -0,0 +1,262 @@
+//! The interfaces between the update cycle and the DNS services hosting the
+//! records.
+//!
+//! Services with a full API (Cloudflare) implement [`DnsProvider`], which
+//! lets the updater compare records against their live state. Simple dynamic
+//! DNS services that can only be told a hostname's new address (DuckDNS,
+//! Dynu, No-IP) and RFC 2136 servers implement [`UpdateOnlyProvider`]. Each record picks its
+//! service through a [`Provider`] variant.
+
+use crate::cloudflare::CloudflareClient;
+use crate::config::Config;
+use crate::dyndns::{DuckDns, Dyndns2};
+use crate::error::{Error, Result};
+use crate::rfc2136::Rfc2136;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// A DNS record as returned by the provider.
+#[derive(Debug, Deserialize)]
+pub struct DnsRecord {
+    pub id: String,
+    pub name: String,
+    pub content: String,
+    #[serde(rename = "type")]
+    pub record_type: String,
+    pub ttl: u32,
+    #[serde(default)]
+    pub proxied: bool,
+    #[serde(default)]
+    pub comment: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Whether Cloudflare can proxy the record with its current content.
+    #[serde(default = "proxiable_by_default")]
+    pub proxiable: bool,
+    /// Set on records that another Cloudflare product, such as Email
+    /// Routing, manages and that can't be edited.
+    #[serde(default)]
+    pub locked: bool,
+}
+
+fn proxiable_by_default() -> bool {
+    true
+}
+
+/// A record to create, or the new contents of an existing one.
+#[derive(Debug, Serialize)]
+pub struct UpdateDnsRecord {
+    #[serde(rename = "type")]
+    pub record_type: String,
+    pub name: String,
+    pub content: String,
+    pub ttl: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxied: Option<bool>,
+    /// Note shown next to the record in the Cloudflare dashboard.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+/// The DNS services records can be hosted on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    Cloudflare,
+    DuckDns,
+    Dynu,
+    NoIp,
+    Rfc2136,
+}
+
+impl Provider {
+    /// Whether the provider only implements [`UpdateOnlyProvider`].
+    pub fn is_update_only(self) -> bool {
+        self != Self::Cloudflare
+    }
+}
+
+impl FromStr for Provider {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "cloudflare" => Ok(Self::Cloudflare),
+            "duckdns" => Ok(Self::DuckDns),
+            "dynu" => Ok(Self::Dynu),
+            "noip" => Ok(Self::NoIp),
+            "rfc2136" => Ok(Self::Rfc2136),
+            other => Err(Error::Config(format!(
+                "Invalid DNS_PROVIDER '{}', expected 'cloudflare', 'duckdns', 'dynu', 'noip' or 'rfc2136'",
+                other
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Provider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Cloudflare => "cloudflare",
+            Self::DuckDns => "duckdns",
+            Self::Dynu => "dynu",
+            Self::NoIp => "noip",
+            Self::Rfc2136 => "rfc2136",
+        })
+    }
+}
+
+/// A DNS service the records are read from and written to.
+///
+/// Zones are addressed by the ID the provider gives them, which
+/// [`DnsProvider::get_zone_id`] looks up from the zone name.
+#[async_trait]
+pub trait DnsProvider: Send + Sync {
+    /// Checks that the provider accepts the credentials, logging anything
+    /// worth knowing about them (like an upcoming expiry).
+    async fn check_credentials(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Looks up the ID of the zone with the given name.
+    async fn get_zone_id(&self, zone_name: &str) -> Result<String>;
+
+    /// Lists the records in a zone, optionally only those with the given name.
+    async fn get_records(&self, zone_id: &str, record_name: Option<&str>)
+    -> Result<Vec<DnsRecord>>;
+
+    /// Checks that the records of a zone can be read. Providers that can do
+    /// this more cheaply than listing the whole zone should.
+    async fn check_zone_access(&self, zone_id: &str) -> Result<()> {
+        self.get_records(zone_id, None).await.map(|_| ())
+    }
+
+    /// Replaces the record with the given ID.
+    async fn update_record(
+        &self,
+        zone_id: &str,
+        record_id: &str,
+        record: &UpdateDnsRecord,
+    ) -> Result<()>;
+
+    /// Replaces several records of a zone. Providers with a batch API apply
+    /// the updates atomically, the default sends them one at a time and stops
+    /// at the first failure.
+    async fn update_records(
+        &self,
+        zone_id: &str,
+        updates: &[(&str, &UpdateDnsRecord)],
+    ) -> Result<()> {
+        for (record_id, record) in updates {
+            self.update_record(zone_id, record_id, record).await?;
+        }
+        Ok(())
+    }
+
+    /// Creates a new record and returns it.
+    async fn create_record(&self, zone_id: &str, record: UpdateDnsRecord) -> Result<DnsRecord>;
+
+    /// Deletes a record.
+    async fn delete_record(&self, zone_id: &str, record_id: &str) -> Result<()>;
+}
+
+/// A DNS service that can point a hostname at an address but can't list
+/// records, so there is nothing to compare against before updating.
+#[async_trait]
+pub trait UpdateOnlyProvider: Send + Sync {
+    /// Name of the service for log messages.
+    fn name(&self) -> &'static str;
+
+    /// Sets the IPv4 (A record) or IPv6 (AAAA record) address of a hostname.
+    async fn set_address(&self, hostname: &str, ip: IpAddr) -> Result<()>;
+}
+
+/// Builds the Cloudflare client for the records hosted on Cloudflare.
+pub fn from_config(config: &Config) -> Box<dyn DnsProvider> {
+    match &config.auth {
+        Some(auth) => {
+            let mut client =
+                CloudflareClient::with_auth(auth.clone()).with_client(config.http.client());
+            if let Some(url) = &config.cloudflare_api_url {
+                client = client.with_base_url(url);
+            }
+            Box::new(client)
+        }
+        None => Box::new(NoCredentials),
+    }
+}
+
+/// Builds the update-only providers that have credentials configured.
+pub fn update_only_from_config(config: &Config) -> HashMap<Provider, Box<dyn UpdateOnlyProvider>> {
+    let client = config.http.client();
+    let mut providers: HashMap<Provider, Box<dyn UpdateOnlyProvider>> = HashMap::new();
+    if let Some(token) = &config.duckdns_token {
+        providers.insert(
+            Provider::DuckDns,
+            Box::new(DuckDns::new(client.clone(), token.clone())),
+        );
+    }
+    if let Some(login) = &config.dynu {
+        providers.insert(
+            Provider::Dynu,
+            Box::new(Dyndns2::dynu(client.clone(), login.clone())),
+        );
+    }
+    if let Some(login) = &config.noip {
+        providers.insert(
+            Provider::NoIp,
+            Box::new(Dyndns2::noip(client.clone(), login.clone())),
+        );
+    }
+    if let Some(rfc2136) = &config.rfc2136 {
+        providers.insert(Provider::Rfc2136, Box::new(Rfc2136::new(rfc2136.clone())));
+    }
+    providers
+}
+
+/// Stands in for the Cloudflare client when every record is on an
+/// update-only provider. The configuration requires credentials as soon as
+/// one record needs Cloudflare, so none of these are reached.
+struct NoCredentials;
+
+#[async_trait]
+impl DnsProvider for NoCredentials {
+    async fn get_zone_id(&self, _zone_name: &str) -> Result<String> {
+        Err(no_credentials())
+    }
+
+    async fn get_records(
+        &self,
+        _zone_id: &str,
+        _record_name: Option<&str>,
+    ) -> Result<Vec<DnsRecord>> {
+        Err(no_credentials())
+    }
+
+    async fn update_record(
+        &self,
+        _zone_id: &str,
+        _record_id: &str,
+        _record: &UpdateDnsRecord,
+    ) -> Result<()> {
+        Err(no_credentials())
+    }
+
+    async fn create_record(&self, _zone_id: &str, _record: UpdateDnsRecord) -> Result<DnsRecord> {
+        Err(no_credentials())
+    }
+
+    async fn delete_record(&self, _zone_id: &str, _record_id: &str) -> Result<()> {
+        Err(no_credentials())
+    }
+}
+
+fn no_credentials() -> Error {
+    Error::Config("No Cloudflare credentials are configured".to_string())
+}
@@ -0,0 +1,89 @@
+use crate::error::{Error, Result};
+use serde::Deserialize;
+use std::future::Future;
+use std::time::Duration;
+use tracing::warn;
+
+/// How often and how patiently to retry transient network failures.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: u32,
+    /// Delay before the first retry; doubled on every further attempt.
+    pub base_delay_ms: u64,
+    /// Upper bound of the random delay added to each backoff.
+    pub jitter_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay_ms: 1000,
+            jitter_ms: 250,
+        }
+    }
+}
+
+impl RetryPolicy {
+    fn delay_for(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .base_delay_ms
+            .saturating_mul(1u64 << (attempt - 1).min(16));
+        let jitter = if self.jitter_ms > 0 {
+            fastrand::u64(0..=self.jitter_ms)
+        } else {
+            0
+        };
+
+        Duration::from_millis(backoff.saturating_add(jitter))
+    }
+}
+
+/// Runs `operation`, retrying with exponential backoff while it fails with a
+/// transient error (timeouts, connection failures and 5xx responses).
+pub async fn retry<T, F, Fut>(policy: &RetryPolicy, label: &str, mut operation: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut attempt = 1;
+
+    loop {
+        match operation().await {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt < policy.max_attempts && is_transient(&e) => {
+                let delay = policy.delay_for(attempt);
+                warn!(
+                    "⚠️  {} failed (attempt {}/{}): {}, retrying in {}ms",
+                    label,
+                    attempt,
+                    policy.max_attempts,
+                    e,
+                    delay.as_millis()
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Whether an error is worth retrying: a network problem or a server error,
+/// rather than a request the API rejected.
+pub fn is_transient(error: &Error) -> bool {
+    error
+        .chain()
+        .filter_map(|cause| match cause {
+            Error::Http(e) => Some(e),
+            _ => None,
+        })
+        .any(|e| {
+            e.is_timeout()
+                || e.is_connect()
+                || e.is_request()
+                || e.status().is_some_and(|status| status.is_server_error())
+        })
+}
@@ -0,0 +1,269 @@
+//! Dynamic DNS updates (RFC 2136, what `nsupdate` sends) to a self-hosted
+//! authoritative server such as BIND or Knot, optionally signed with a TSIG
+//! key (RFC 8945).
+
+use crate::dns::{self, CLASS_IN, Message, Question, ResourceRecord, TYPE_A, TYPE_AAAA};
+use crate::error::{Context, Error, Result};
+use crate::provider::UpdateOnlyProvider;
+use async_trait::async_trait;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use hmac::{Hmac, Mac};
+use sha2::{Sha256, Sha512};
+use std::net::IpAddr;
+use std::str::FromStr;
+
+const TYPE_SOA: u16 = 6;
+const TYPE_TSIG: u16 = 250;
+const CLASS_ANY: u16 = 255;
+
+/// The UPDATE opcode in the header flags.
+const OPCODE_UPDATE: u16 = 5 << 11;
+
+/// Seconds the server's clock may differ from ours before it rejects the
+/// signature.
+const TSIG_FUDGE: u16 = 300;
+
+/// Where and how updates are sent.
+#[derive(Debug, Clone)]
+pub struct Rfc2136Config {
+    /// `host` or `host:port` of the primary server for the zone.
+    pub server: String,
+    /// Zone the records are in. When unset, a record's zone is its name
+    /// without the first label.
+    pub zone: Option<String>,
+    pub ttl: u32,
+    pub key: Option<TsigKey>,
+}
+
+/// HMAC algorithms accepted for TSIG keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TsigAlgorithm {
+    HmacSha256,
+    HmacSha512,
+}
+
+impl TsigAlgorithm {
+    /// The algorithm's domain name in TSIG records.
+    fn name(self) -> &'static str {
+        match self {
+            Self::HmacSha256 => "hmac-sha256",
+            Self::HmacSha512 => "hmac-sha512",
+        }
+    }
+
+    fn sign(self, secret: &[u8], data: &[u8]) -> Vec<u8> {
+        // HMAC accepts keys of any length
+        match self {
+            Self::HmacSha256 => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("any key length");
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            }
+            Self::HmacSha512 => {
+                let mut mac = Hmac::<Sha512>::new_from_slice(secret).expect("any key length");
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            }
+        }
+    }
+}
+
+impl FromStr for TsigAlgorithm {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().trim_end_matches('.').to_ascii_lowercase().as_str() {
+            "hmac-sha256" => Ok(Self::HmacSha256),
+            "hmac-sha512" => Ok(Self::HmacSha512),
+            other => Err(Error::Config(format!(
+                "Unsupported TSIG algorithm '{}', expected 'hmac-sha256' or 'hmac-sha512'",
+                other
+            ))),
+        }
+    }
+}
+
+/// A TSIG key as found in a BIND `key` statement or made by `tsig-keygen`.
+#[derive(Debug, Clone)]
+pub struct TsigKey {
+    pub name: String,
+    pub algorithm: TsigAlgorithm,
+    pub secret: Vec<u8>,
+}
+
+impl TsigKey {
+    /// Builds a key from its base64 encoded secret.
+    pub fn new(name: String, algorithm: TsigAlgorithm, secret: &str) -> Result<Self> {
+        let secret = BASE64
+            .decode(secret.trim())
+            .context("The TSIG key secret isn't valid base64")?;
+        Ok(Self {
+            name,
+            algorithm,
+            secret,
+        })
+    }
+
+    /// Appends a TSIG record signing the message as it is now.
+    fn sign(&self, message: &mut Message) -> Result<()> {
+        let time_signed = chrono::Utc::now().timestamp() as u64;
+
+        // The MAC covers the unsigned message followed by the TSIG
+        // variables (RFC 8945, section 4.3.3)
+        let mut data = message.to_bytes()?;
+        dns::write_name(&mut data, &self.name.to_ascii_lowercase())?;
+        data.extend_from_slice(&CLASS_ANY.to_be_bytes());
+        data.extend_from_slice(&0u32.to_be_bytes());
+        dns::write_name(&mut data, self.algorithm.name())?;
+        data.extend_from_slice(&time_signed.to_be_bytes()[2..]);
+        data.extend_from_slice(&TSIG_FUDGE.to_be_bytes());
+        // Error and other data length
+        data.extend_from_slice(&[0, 0, 0, 0]);
+        let mac = self.algorithm.sign(&self.secret, &data);
+
+        let mut rdata = Vec::new();
+        dns::write_name(&mut rdata, self.algorithm.name())?;
+        rdata.extend_from_slice(&time_signed.to_be_bytes()[2..]);
+        rdata.extend_from_slice(&TSIG_FUDGE.to_be_bytes());
+        rdata.extend_from_slice(&(mac.len() as u16).to_be_bytes());
+        rdata.extend_from_slice(&mac);
+        rdata.extend_from_slice(&message.id.to_be_bytes());
+        rdata.extend_from_slice(&[0, 0, 0, 0]);
+
+        message.additionals.push(ResourceRecord {
+            name: self.name.clone(),
+            rtype: TYPE_TSIG,
+            class: CLASS_ANY,
+            ttl: 0,
+            data: rdata,
+        });
+        Ok(())
+    }
+}
+
+/// Sends RFC 2136 updates to an authoritative server.
+pub struct Rfc2136 {
+    config: Rfc2136Config,
+}
+
+impl Rfc2136 {
+    pub fn new(config: Rfc2136Config) -> Self {
+        Self { config }
+    }
+
+    /// The UPDATE message replacing every record of the hostname's type with
+    /// a single one holding `ip`.
+    fn update_message(&self, hostname: &str, ip: IpAddr) -> Message {
+        let zone = match &self.config.zone {
+            Some(zone) => zone.as_str(),
+            None => hostname.split_once('.').map_or(hostname, |(_, zone)| zone),
+        };
+        let (rtype, data) = match ip {
+            IpAddr::V4(ip) => (TYPE_A, ip.octets().to_vec()),
+            IpAddr::V6(ip) => (TYPE_AAAA, ip.octets().to_vec()),
+        };
+
+        Message {
+            id: fastrand::u16(..),
+            flags: OPCODE_UPDATE,
+            // The zone section
+            questions: vec![Question {
+                name: zone.to_string(),
+                qtype: TYPE_SOA,
+                qclass: CLASS_IN,
+            }],
+            // The update section: delete the RRset, then add the new record
+            authorities: vec![
+                ResourceRecord {
+                    name: hostname.to_string(),
+                    rtype,
+                    class: CLASS_ANY,
+                    ttl: 0,
+                    data: Vec::new(),
+                },
+                ResourceRecord {
+                    name: hostname.to_string(),
+                    rtype,
+                    class: CLASS_IN,
+                    ttl: self.config.ttl,
+                    data,
+                },
+            ],
+            ..Message::default()
+        }
+    }
+}
+
+#[async_trait]
+impl UpdateOnlyProvider for Rfc2136 {
+    fn name(&self) -> &'static str {
+        "RFC 2136"
+    }
+
+    async fn set_address(&self, hostname: &str, ip: IpAddr) -> Result<()> {
+        let mut message = self.update_message(hostname, ip);
+        if let Some(key) = &self.config.key {
+            key.sign(&mut message)?;
+        }
+
+        let server = tokio::net::lookup_host(dns::server_address(&self.config.server))
+            .await
+            .with_context(|| format!("Failed to resolve DNS server '{}'", self.config.server))?
+            .next()
+            .ok_or_else(|| {
+                Error::Provider(format!(
+                    "DNS server '{}' has no address",
+                    self.config.server
+                ))
+            })?;
+
+        // The response's own signature isn't checked: nothing in it is used
+        // besides the result of the update
+        let response = dns::exchange_udp(server, &message).await?;
+        match response.rcode() {
+            0 => Ok(()),
+            rcode => Err(Error::Provider(format!(
+                "{} rejected the update of '{}': {}",
+                server,
+                hostname,
+                describe_error(rcode, tsig_error(&response))
+            ))),
+        }
+    }
+}
+
+/// The error field of the response's TSIG record, which explains a NOTAUTH.
+fn tsig_error(response: &Message) -> Option<u16> {
+    let tsig = response
+        .additionals
+        .iter()
+        .find(|record| record.rtype == TYPE_TSIG)?;
+    // After the algorithm name come the time (6), fudge (2), MAC and the
+    // original ID (2), then the error
+    let mut offset = 0;
+    while let Some(&len) = tsig.data.get(offset) {
+        offset += 1 + len as usize;
+        if len == 0 {
+            break;
+        }
+    }
+    let mac_len = u16::from_be_bytes(tsig.data.get(offset + 8..offset + 10)?.try_into().ok()?);
+    let error_at = offset + 10 + mac_len as usize + 2;
+    Some(u16::from_be_bytes(
+        tsig.data.get(error_at..error_at + 2)?.try_into().ok()?,
+    ))
+}
+
+fn describe_error(rcode: u16, tsig_error: Option<u16>) -> &'static str {
+    match (rcode, tsig_error) {
+        (9, Some(16)) => "the TSIG signature doesn't match, check the key secret and algorithm",
+        (9, Some(17)) => "the server doesn't know the TSIG key name",
+        (9, Some(18)) => "the clocks of this machine and the server are too far apart",
+        (9, _) => "not authorized, the server needs a TSIG key or allows updates from elsewhere",
+        (5, _) => "refused, check the server's update-policy or allow-update",
+        (10, _) => "the record is not in the zone, set the zone explicitly",
+        (2, _) => "the server failed to apply it",
+        _ => "the server returned an error",
+    }
+}
@@ -0,0 +1,278 @@
+//! Asks the local router for its external (WAN) address, through NAT-PMP
+//! (RFC 6886), a UPnP Internet Gateway Device or the interface of AVM
+//! Fritz!Box routers.
+//!
+//! Nothing outside the local network is contacted, and a new address shows
+//! up as soon as the router has it.
+
+use crate::error::{Context, Error, Result};
+use crate::http::HttpConfig;
+use crate::ip::IpVersion;
+use reqwest::{Client, Url};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::time::{Instant, timeout, timeout_at};
+
+const NATPMP_PORT: u16 = 5351;
+/// NAT-PMP waits 250 ms for the first answer and doubles the wait on every
+/// retry.
+const NATPMP_ATTEMPTS: u32 = 4;
+const NATPMP_FIRST_TIMEOUT: Duration = Duration::from_millis(250);
+
+const SSDP_ADDRESS: SocketAddr = SocketAddr::new(
+    std::net::IpAddr::V4(Ipv4Addr::new(239, 255, 255, 250)),
+    1900,
+);
+/// How long to wait for a gateway to answer the SSDP search.
+const SSDP_TIMEOUT: Duration = Duration::from_secs(3);
+const IGD_SEARCH_TARGET: &str = "urn:schemas-upnp-org:device:InternetGatewayDevice:1";
+
+/// Name a Fritz!Box answers to on its own network.
+pub const FRITZBOX_HOST: &str = "fritz.box";
+/// Port of the Fritz!Box UPnP interface.
+pub const FRITZBOX_PORT: u16 = 49000;
+const FRITZBOX_CONTROL_PATH: &str = "/igdupnp/control/WANIPConn1";
+const FRITZBOX_SERVICE: &str = "urn:schemas-upnp-org:service:WANIPConnection:1";
+
+/// Asks the router with NAT-PMP. Without a `gateway`, the default gateway is
+/// used, which can only be found on Linux.
+pub async fn natpmp_external_ip(gateway: Option<Ipv4Addr>) -> Result<Ipv4Addr> {
+    let gateway = match gateway {
+        Some(gateway) => gateway,
+        None => default_gateway()?,
+    };
+
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    socket.connect((gateway, NATPMP_PORT)).await?;
+
+    // Version 0, opcode 0: external address request
+    let request = [0u8, 0];
+    let mut buf = [0u8; 16];
+    let failed = |e: std::io::Error| {
+        Error::IpDetection(format!("NAT-PMP request to {} failed: {}", gateway, e))
+    };
+    let mut wait = NATPMP_FIRST_TIMEOUT;
+    for _ in 0..NATPMP_ATTEMPTS {
+        socket.send(&request).await.map_err(failed)?;
+        if let Ok(received) = timeout(wait, socket.recv(&mut buf)).await {
+            return parse_natpmp_response(&buf[..received.map_err(failed)?]);
+        }
+        wait *= 2;
+    }
+
+    Err(Error::IpDetection(format!(
+        "Router {} didn't answer the NAT-PMP request, it may not support NAT-PMP",
+        gateway
+    )))
+}
+
+fn parse_natpmp_response(response: &[u8]) -> Result<Ipv4Addr> {
+    if response.len() < 12 || response[0] != 0 || response[1] != 128 {
+        return Err(Error::IpDetection("Invalid NAT-PMP response".to_string()));
+    }
+    let result = u16::from_be_bytes([response[2], response[3]]);
+    if result != 0 {
+        return Err(Error::IpDetection(format!(
+            "Router refused the NAT-PMP request with result code {}",
+            result
+        )));
+    }
+    Ok(Ipv4Addr::new(
+        response[8],
+        response[9],
+        response[10],
+        response[11],
+    ))
+}
+
+/// The default IPv4 gateway, from the kernel's routing table.
+#[cfg(target_os = "linux")]
+fn default_gateway() -> Result<Ipv4Addr> {
+    let routes =
+        std::fs::read_to_string("/proc/net/route").context("Failed to read /proc/net/route")?;
+
+    // Columns: Iface Destination Gateway Flags ..., addresses in hex as
+    // stored in memory
+    routes
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let columns: Vec<&str> = line.split_whitespace().collect();
+            match columns.as_slice() {
+                [_, "00000000", gateway, ..] => u32::from_str_radix(gateway, 16).ok(),
+                _ => None,
+            }
+        })
+        .find(|gateway| *gateway != 0)
+        .map(|gateway| Ipv4Addr::from(gateway.to_ne_bytes()))
+        .ok_or_else(|| {
+            Error::IpDetection("Found no default gateway, use natpmp:<router address>".to_string())
+        })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn default_gateway() -> Result<Ipv4Addr> {
+    Err(Error::IpDetection(
+        "The default gateway can't be found on this system, use natpmp:<router address>"
+            .to_string(),
+    ))
+}
+
+/// Asks the Internet Gateway Device found on the local network over UPnP.
+pub async fn upnp_external_ip(http: &HttpConfig) -> Result<Ipv4Addr> {
+    let location = discover_gateway().await?;
+    let client = local_client(http)?;
+
+    let description = client
+        .get(location.clone())
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    let (service_type, control_url) = wan_connection_service(&description).ok_or_else(|| {
+        Error::IpDetection(format!(
+            "The router at {} has no WANIPConnection or WANPPPConnection service",
+            location
+        ))
+    })?;
+    let base = match tag(&description, "URLBase") {
+        Some(base) => Url::parse(base.trim())?,
+        None => location,
+    };
+    let control_url = base.join(control_url.trim())?;
+
+    let address = soap_call(
+        &client,
+        control_url,
+        service_type,
+        "GetExternalIPAddress",
+        "NewExternalIPAddress",
+    )
+    .await?;
+    address
+        .parse()
+        .with_context(|| format!("The router reported an invalid address '{}'", address))
+}
+
+/// Asks an AVM Fritz!Box at `host` (`host:port`) over its UPnP interface on
+/// port 49000, which also reports the IPv6 address.
+pub async fn fritzbox_external_ip(
+    http: &HttpConfig,
+    host: &str,
+    version: IpVersion,
+) -> Result<IpAddr> {
+    let client = local_client(http)?;
+    let control_url = Url::parse(&format!("http://{}{}", host, FRITZBOX_CONTROL_PATH))
+        .with_context(|| format!("Invalid Fritz!Box address '{}'", host))?;
+
+    let (action, field) = match version {
+        IpVersion::V4 => ("GetExternalIPAddress", "NewExternalIPAddress"),
+        IpVersion::V6 => ("X_AVM_DE_GetExternalIPv6Address", "NewExternalIPv6Address"),
+    };
+    let address = soap_call(&client, control_url, FRITZBOX_SERVICE, action, field)
+        .await
+        .map_err(|e| {
+            Error::IpDetection(format!(
+                "Fritz!Box request failed ({}), check that \"Transmit status information over UPnP\" is enabled in its network settings",
+                e
+            ))
+        })?;
+    address
+        .parse()
+        .with_context(|| format!("The Fritz!Box reported an invalid address '{}'", address))
+}
+
+/// An HTTP client for devices on the local network, which a configured proxy
+/// couldn't reach.
+fn local_client(http: &HttpConfig) -> Result<Client> {
+    Ok(http.builder().no_proxy().build()?)
+}
+
+/// Calls a UPnP action that takes no arguments and returns the text of the
+/// `field` element of the response.
+async fn soap_call(
+    client: &Client,
+    control_url: Url,
+    service_type: &str,
+    action: &str,
+    field: &str,
+) -> Result<String> {
+    let body = format!(
+        r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">
+<s:Body><u:{action} xmlns:u="{service_type}"></u:{action}></s:Body>
+</s:Envelope>"#
+    );
+    let response = client
+        .post(control_url)
+        .header("Content-Type", r#"text/xml; charset="utf-8""#)
+        .header("SOAPAction", format!(r#""{}#{}""#, service_type, action))
+        .body(body)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+
+    tag(&response, field)
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+        .ok_or_else(|| {
+            Error::IpDetection(format!("The router's {} response has no {}", action, field))
+        })
+}
+
+/// Searches the local network for an Internet Gateway Device and returns the
+/// URL of its description.
+async fn discover_gateway() -> Result<Url> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    let search = format!(
+        "M-SEARCH * HTTP/1.1\r\nHOST: {}\r\nST: {}\r\nMAN: \"ssdp:discover\"\r\nMX: 2\r\n\r\n",
+        SSDP_ADDRESS, IGD_SEARCH_TARGET
+    );
+    socket.send_to(search.as_bytes(), SSDP_ADDRESS).await?;
+
+    let deadline = Instant::now() + SSDP_TIMEOUT;
+    let mut buf = [0u8; 2048];
+    loop {
+        let (len, _) = timeout_at(deadline, socket.recv_from(&mut buf))
+            .await
+            .map_err(|_| {
+                Error::IpDetection("No UPnP gateway answered on the local network".to_string())
+            })??;
+        let response = String::from_utf8_lossy(&buf[..len]);
+        let location = response.lines().find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.trim()
+                .eq_ignore_ascii_case("location")
+                .then(|| value.trim().to_string())
+        });
+        // Other devices may answer too; keep waiting for one with a location
+        if let Some(location) = location {
+            return Url::parse(&location)
+                .with_context(|| format!("Invalid UPnP description URL '{}'", location));
+        }
+    }
+}
+
+/// The type and control URL of the first WAN connection service in a device
+/// description.
+fn wan_connection_service(description: &str) -> Option<(&str, &str)> {
+    description.split("<service>").skip(1).find_map(|service| {
+        let service_type = tag(service, "serviceType")?.trim();
+        let is_wan_connection = service_type.contains(":WANIPConnection:")
+            || service_type.contains(":WANPPPConnection:");
+        is_wan_connection.then_some((service_type, tag(service, "controlURL")?))
+    })
+}
+
+/// The text inside the first `<name>` element.
+fn tag<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let open = format!("<{}>", name);
+    let close = format!("</{}>", name);
+    let start = xml.find(&open)? + open.len();
+    let end = start + xml[start..].find(&close)?;
+    Some(&xml[start..end])
+}
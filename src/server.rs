@@ -0,0 +1,528 @@
+//! HTTP server exposed in daemon mode.
+//!
+//! `/healthz` reports whether the most recent update cycle succeeded recently
+//! enough, for container health probes. The same outcome is written to a
+//! [`HealthFile`] after every cycle, which the `healthcheck` command reads
+//! where probes can run a command but not reach a port.
+//!
+//! With [`crate::config::Config::dyndns_server`] set, `/nic/update` takes
+//! DynDNS2 updates too, so routers with a built-in DDNS client can push
+//! their address. Each update is passed to the daemon as a [`PushRequest`].
+//!
+//! With [`crate::config::Config::trigger_token`] set, a `POST` to `/trigger`
+//! with that bearer token starts a cycle without waiting for the interval.
+//!
+//! With [`crate::config::Config::admin_token`] set, `/api/records` and
+//! `/api/settings` list and change the managed records and the intervals.
+//! The daemon answers each call as an [`AdminRequest`] and keeps the
+//! changes in its [`crate::state`] file.
+
+use crate::cache::atomic_write;
+use crate::dyndns::Login;
+use crate::error::{Context, Error, Result};
+use crate::state::{AddedRecord, RecordId};
+use axum::Router;
+use axum::extract::{ConnectInfo, Path, Query, State};
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::response::{IntoResponse, Json, Response};
+use axum::routing::{delete, get, post};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+use tokio::sync::{Notify, mpsc, oneshot};
+use tracing::{error, info, warn};
+
+/// Outcome of the most recent update cycle, shared with the HTTP server.
+#[derive(Debug)]
+pub struct Health {
+    last_cycle: Mutex<Option<CycleStatus>>,
+    max_age: Duration,
+    file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct CycleStatus {
+    finished: DateTime<Utc>,
+    success: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct HealthResponse {
+    healthy: bool,
+    last_cycle: Option<DateTime<Utc>>,
+    last_cycle_succeeded: Option<bool>,
+}
+
+impl Health {
+    /// A cycle older than `max_age_secs` counts as unhealthy even if it succeeded.
+    pub fn new(max_age_secs: u64) -> Self {
+        Self {
+            last_cycle: Mutex::new(None),
+            max_age: Duration::seconds(max_age_secs as i64),
+            file: None,
+        }
+    }
+
+    /// Also writes each outcome to `path`. A file left by an earlier run is
+    /// removed, so that it doesn't count until the first cycle finishes.
+    pub fn with_file(mut self, path: PathBuf) -> Self {
+        match std::fs::remove_file(&path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!("⚠️  Failed to remove {}: {}", path.display(), e),
+        }
+        self.file = Some(path);
+        self
+    }
+
+    pub fn record_cycle(&self, success: bool) {
+        let finished = Utc::now();
+        *self.last_cycle.lock().expect("health lock poisoned") =
+            Some(CycleStatus { finished, success });
+        if let Some(path) = &self.file {
+            let file = HealthFile {
+                last_cycle: finished,
+                last_cycle_succeeded: success,
+                max_age: self.max_age.num_seconds() as u64,
+            };
+            if let Err(e) = file.save(path) {
+                warn!("⚠️  {}", e);
+            }
+        }
+    }
+
+    fn response(&self) -> HealthResponse {
+        let last_cycle = *self.last_cycle.lock().expect("health lock poisoned");
+        HealthResponse {
+            healthy: last_cycle
+                .is_some_and(|c| c.success && Utc::now() - c.finished <= self.max_age),
+            last_cycle: last_cycle.map(|c| c.finished),
+            last_cycle_succeeded: last_cycle.map(|c| c.success),
+        }
+    }
+}
+
+/// The outcome of the daemon's last cycle as written to disk, by default to
+/// `health.json` next to the cache.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HealthFile {
+    pub last_cycle: DateTime<Utc>,
+    pub last_cycle_succeeded: bool,
+    /// Seconds after which the cycle is too old to be healthy, from
+    /// [`crate::config::Config::health_max_age`].
+    pub max_age: u64,
+}
+
+impl HealthFile {
+    /// Where the daemon writes the file for the cache at `cache_path`.
+    pub fn path(cache_path: &std::path::Path) -> PathBuf {
+        cache_path.with_file_name("health.json")
+    }
+
+    /// Reads the file at `path`, failing when the daemon hasn't written one.
+    pub fn read(path: &std::path::Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path).map_err(|e| {
+            Error::Cache(format!(
+                "No update cycle has finished, can't read {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    fn save(&self, path: &std::path::Path) -> Result<()> {
+        let write = || -> std::io::Result<()> {
+            let content = serde_json::to_string(self)?;
+            atomic_write(path, content.as_bytes())
+        };
+        write().map_err(|e| Error::Cache(format!("Failed to write {}: {}", path.display(), e)))
+    }
+
+    /// Whether the cycle succeeded and finished at most `max_age` ago.
+    pub fn is_healthy(&self) -> bool {
+        self.last_cycle_succeeded
+            && Utc::now() - self.last_cycle <= Duration::seconds(self.max_age as i64)
+    }
+}
+
+/// Addresses a router pushed for some of the configured hostnames.
+#[derive(Debug)]
+pub struct PushRequest {
+    pub hostnames: Vec<String>,
+    pub addresses: Vec<IpAddr>,
+    /// Takes one DynDNS2 answer per hostname, such as `good 203.0.113.7`
+    /// or `nohost`.
+    pub reply: oneshot::Sender<Vec<String>>,
+}
+
+/// Where `/nic/update` passes the updates it accepts.
+#[derive(Debug, Clone)]
+pub struct DynDnsServer {
+    pub login: Login,
+    pub requests: mpsc::Sender<PushRequest>,
+}
+
+/// What `/trigger` needs: its token, and how it wakes the daemon up.
+#[derive(Debug, Clone)]
+pub struct TriggerEndpoint {
+    pub token: String,
+    pub cycle: Arc<Notify>,
+}
+
+/// A call to the admin API.
+#[derive(Debug)]
+pub enum AdminCommand {
+    ListRecords,
+    AddRecord(AddedRecord),
+    RemoveRecord(RecordId),
+    Settings,
+    UpdateSettings(Settings),
+}
+
+/// The intervals of the daemon, as shown and changed by `/api/settings`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    pub sleep_interval: Option<u64>,
+    /// `0` turns the adaptive interval off.
+    pub max_sleep_interval: Option<u64>,
+}
+
+/// The daemon's answer to an [`AdminCommand`].
+#[derive(Debug)]
+pub enum AdminReply {
+    Ok(serde_json::Value),
+    Created(serde_json::Value),
+    Removed,
+    Invalid(String),
+    NotFound(String),
+    Conflict(String),
+    Failed(String),
+}
+
+impl IntoResponse for AdminReply {
+    fn into_response(self) -> Response {
+        let error = |status, message: String| {
+            (status, Json(serde_json::json!({ "error": message }))).into_response()
+        };
+        match self {
+            Self::Ok(body) => Json(body).into_response(),
+            Self::Created(body) => (StatusCode::CREATED, Json(body)).into_response(),
+            Self::Removed => StatusCode::NO_CONTENT.into_response(),
+            Self::Invalid(message) => error(StatusCode::BAD_REQUEST, message),
+            Self::NotFound(message) => error(StatusCode::NOT_FOUND, message),
+            Self::Conflict(message) => error(StatusCode::CONFLICT, message),
+            Self::Failed(message) => error(StatusCode::INTERNAL_SERVER_ERROR, message),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct AdminRequest {
+    pub command: AdminCommand,
+    pub reply: oneshot::Sender<AdminReply>,
+}
+
+/// Where the admin API passes the calls it accepts.
+#[derive(Debug, Clone)]
+pub struct AdminApi {
+    pub token: String,
+    pub requests: mpsc::Sender<AdminRequest>,
+}
+
+#[derive(Clone)]
+struct AppState {
+    health: Arc<Health>,
+    dyndns: Option<DynDnsServer>,
+    trigger: Option<TriggerEndpoint>,
+    admin: Option<AdminApi>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UpdateQuery {
+    hostname: Option<String>,
+    myip: Option<String>,
+}
+
+async fn healthz(State(state): State<AppState>) -> impl IntoResponse {
+    let response = state.health.response();
+    let status = if response.healthy {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(response))
+}
+
+/// Answers in the plain text format of the DynDNS2 protocol.
+fn answer(status: StatusCode, body: impl Into<String>) -> Response {
+    (
+        status,
+        [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+        body.into(),
+    )
+        .into_response()
+}
+
+/// Whether the request's basic auth matches `login`.
+fn authorized(headers: &HeaderMap, login: &Login) -> bool {
+    let Some(credentials) = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Basic "))
+        .and_then(|value| STANDARD.decode(value.trim()).ok())
+    else {
+        return false;
+    };
+    let expected = format!("{}:{}", login.username, login.password);
+    constant_time_eq(&credentials, expected.as_bytes())
+}
+
+/// Compares without returning early, so the time taken doesn't tell how
+/// much of the password was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// The hostnames of a DynDNS2 update, lowercase and without trailing dots.
+fn hostnames(hostname: Option<&str>) -> Vec<String> {
+    hostname
+        .unwrap_or_default()
+        .split(',')
+        .map(|name| name.trim().trim_end_matches('.').to_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+/// The addresses of a DynDNS2 update, or `None` when `myip` isn't a list of
+/// addresses. Without `myip`, the update is for the address the request came
+/// from.
+fn addresses(myip: Option<&str>, peer: IpAddr) -> Option<Vec<IpAddr>> {
+    match myip.map(str::trim) {
+        Some(myip) if !myip.is_empty() => {
+            myip.split(',').map(|ip| ip.trim().parse().ok()).collect()
+        }
+        _ => Some(vec![peer.to_canonical()]),
+    }
+}
+
+async fn nic_update(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Query(query): Query<UpdateQuery>,
+) -> Response {
+    let Some(dyndns) = state.dyndns else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if !authorized(&headers, &dyndns.login) {
+        warn!(
+            "⚠️  Rejected a DynDNS2 update from {} with a wrong login",
+            peer.ip()
+        );
+        return (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Basic realm=\"cloudflare-ddns\"")],
+            "badauth",
+        )
+            .into_response();
+    }
+
+    let hostnames = hostnames(query.hostname.as_deref());
+    if hostnames.is_empty() {
+        return answer(StatusCode::BAD_REQUEST, "notfqdn");
+    }
+    let Some(addresses) = addresses(query.myip.as_deref(), peer.ip()) else {
+        return answer(StatusCode::BAD_REQUEST, "911");
+    };
+
+    let (reply, answers) = oneshot::channel();
+    let request = PushRequest {
+        hostnames,
+        addresses,
+        reply,
+    };
+    if dyndns.requests.send(request).await.is_err() {
+        return answer(StatusCode::SERVICE_UNAVAILABLE, "911");
+    }
+    match answers.await {
+        Ok(lines) => answer(StatusCode::OK, lines.join("\n")),
+        Err(_) => answer(StatusCode::SERVICE_UNAVAILABLE, "911"),
+    }
+}
+
+/// Whether the request carries `token` as its bearer token.
+fn has_bearer(headers: &HeaderMap, token: &str) -> bool {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|given| constant_time_eq(given.as_bytes(), token.as_bytes()))
+}
+
+fn unauthorized() -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        [(header::WWW_AUTHENTICATE, "Bearer")],
+    )
+        .into_response()
+}
+
+async fn trigger_cycle(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let Some(trigger) = state.trigger else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if !has_bearer(&headers, &trigger.token) {
+        warn!("⚠️  Rejected a /trigger request with a wrong token");
+        return unauthorized();
+    }
+    // Requests arriving during a cycle start a single one after it
+    info!("👉 Got a /trigger request, checking right away");
+    trigger.cycle.notify_one();
+    StatusCode::ACCEPTED.into_response()
+}
+
+/// Passes an authorized admin call to the daemon and answers with its reply.
+async fn admin(state: AppState, headers: &HeaderMap, command: AdminCommand) -> Response {
+    let Some(admin) = state.admin else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if !has_bearer(headers, &admin.token) {
+        warn!("⚠️  Rejected an admin API request with a wrong token");
+        return unauthorized();
+    }
+    let (reply, answer) = oneshot::channel();
+    let request = AdminRequest { command, reply };
+    if admin.requests.send(request).await.is_err() {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    }
+    match answer.await {
+        Ok(reply) => reply.into_response(),
+        Err(_) => StatusCode::SERVICE_UNAVAILABLE.into_response(),
+    }
+}
+
+async fn list_records(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    admin(state, &headers, AdminCommand::ListRecords).await
+}
+
+async fn add_record(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(record): Json<AddedRecord>,
+) -> Response {
+    admin(state, &headers, AdminCommand::AddRecord(record)).await
+}
+
+async fn remove_record(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path((name, record_type)): Path<(String, String)>,
+) -> Response {
+    let id = RecordId {
+        name: name.trim_end_matches('.').to_ascii_lowercase(),
+        record_type: record_type.to_ascii_uppercase(),
+    };
+    admin(state, &headers, AdminCommand::RemoveRecord(id)).await
+}
+
+async fn settings(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    admin(state, &headers, AdminCommand::Settings).await
+}
+
+async fn update_settings(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(settings): Json<Settings>,
+) -> Response {
+    admin(state, &headers, AdminCommand::UpdateSettings(settings)).await
+}
+
+/// Binds the listener and serves requests in the background.
+pub async fn spawn(
+    addr: SocketAddr,
+    health: Arc<Health>,
+    dyndns: Option<DynDnsServer>,
+    trigger: Option<TriggerEndpoint>,
+    admin: Option<AdminApi>,
+) -> Result<()> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to listen on {}", addr))?;
+    info!("🩺 Serving /healthz on http://{}", addr);
+    if dyndns.is_some() {
+        info!("📮 Taking DynDNS2 updates on http://{}/nic/update", addr);
+    }
+    if trigger.is_some() {
+        info!("👉 Starting cycles on requests to http://{}/trigger", addr);
+    }
+    if admin.is_some() {
+        info!("🛠️  Serving the admin API on http://{}/api", addr);
+    }
+
+    let app = Router::new()
+        .route("/healthz", get(healthz))
+        .route("/nic/update", get(nic_update))
+        .route("/trigger", post(trigger_cycle))
+        .route("/api/records", get(list_records).post(add_record))
+        .route("/api/records/{name}/{type}", delete(remove_record))
+        .route("/api/settings", get(settings).patch(update_settings))
+        .with_state(AppState {
+            health,
+            dyndns,
+            trigger,
+            admin,
+        });
+
+    tokio::spawn(async move {
+        let app = app.into_make_service_with_connect_info::<SocketAddr>();
+        if let Err(e) = axum::serve(listener, app).await {
+            error!("❌ HTTP server failed: {}", e);
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_hostnames_of_an_update() {
+        assert_eq!(
+            hostnames(Some("Home.Example.com., ,vpn.example.com")),
+            ["home.example.com", "vpn.example.com"]
+        );
+        assert!(hostnames(Some(" , ")).is_empty());
+        assert!(hostnames(None).is_empty());
+    }
+
+    #[test]
+    fn reads_the_addresses_of_an_update() {
+        let peer: IpAddr = "::ffff:1.1.1.1".parse().unwrap();
+
+        assert_eq!(
+            addresses(Some("8.8.8.8, 2606:4700::1"), peer).unwrap(),
+            [
+                "8.8.8.8".parse::<IpAddr>().unwrap(),
+                "2606:4700::1".parse().unwrap()
+            ]
+        );
+        // Routers that leave out myip, or send it empty, mean their own address
+        for myip in [None, Some(" ")] {
+            assert_eq!(
+                addresses(myip, peer).unwrap(),
+                ["1.1.1.1".parse::<IpAddr>().unwrap()]
+            );
+        }
+        assert_eq!(addresses(Some("8.8.8.8,home"), peer), None);
+    }
+}
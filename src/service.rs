@@ -0,0 +1,223 @@
+//! Running as a native Windows service.
+//!
+//! `service install` registers this binary with the service control manager,
+//! which starts it with `--service run --daemon`. In that mode the daemon
+//! reports its state to the service manager, stops when the service is
+//! stopped, and logs to a file since a service has no console.
+
+use crate::cli::{Cli, ServiceCommand};
+use crate::logging::{self, LogTarget, say};
+use anyhow::{Context, Result, anyhow};
+use cloudflare_ddns::cache::default_cache_path;
+use std::ffi::OsString;
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tracing::error;
+use windows_service::service::{
+    ServiceAccess, ServiceAction, ServiceActionType, ServiceControl, ServiceControlAccept,
+    ServiceErrorControl, ServiceExitCode, ServiceFailureActions, ServiceFailureResetPeriod,
+    ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+use windows_service::{define_windows_service, service_dispatcher};
+
+const SERVICE_NAME: &str = "cloudflare-ddns";
+const DISPLAY_NAME: &str = "Cloudflare DDNS";
+
+/// The command line, handed from `main` to the thread the service manager
+/// runs the service on.
+static CLI: Mutex<Option<Cli>> = Mutex::new(None);
+
+define_windows_service!(ffi_service_main, service_main);
+
+/// Sets up logging and hands control to the service manager, which runs the
+/// daemon until the service is stopped.
+pub fn run(cli: Cli) -> Result<()> {
+    let cache_path = cli.cache_path.clone().unwrap_or_else(default_cache_path);
+    let log_path = log_path(&cache_path);
+    if let Some(dir) = log_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let log_file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)
+        .with_context(|| format!("Failed to open log file {}", log_path.display()))?;
+    logging::set_emoji(!cli.no_emoji);
+    logging::init(
+        cli.quiet,
+        cli.log_format,
+        cli.log_timestamps,
+        LogTarget::File(log_file),
+    )?;
+
+    *CLI.lock().unwrap() = Some(cli);
+    service_dispatcher::start(SERVICE_NAME, ffi_service_main).context(
+        "Failed to connect to the service manager, --service is only for running as an installed service",
+    )
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(e) = run_service() {
+        error!("❌ Service failed: {}", e);
+    }
+}
+
+fn run_service() -> Result<()> {
+    let cli = CLI
+        .lock()
+        .unwrap()
+        .take()
+        .ok_or_else(|| anyhow!("The service was started twice"))?;
+
+    let stop = Arc::new(Notify::new());
+    let stop_requested = stop.clone();
+    let status = service_control_handler::register(SERVICE_NAME, move |control| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            stop_requested.notify_one();
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    })?;
+    let set_state = |current_state, controls_accepted, exit_code| {
+        status.set_service_status(ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state,
+            controls_accepted,
+            exit_code: ServiceExitCode::ServiceSpecific(exit_code),
+            checkpoint: 0,
+            wait_hint: Duration::default(),
+            process_id: None,
+        })
+    };
+
+    set_state(
+        ServiceState::Running,
+        ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+        0,
+    )?;
+    let result = tokio::runtime::Runtime::new()
+        .map_err(anyhow::Error::from)
+        .and_then(|runtime| runtime.block_on(crate::run(cli, async move { stop.notified().await })))
+        .map(drop);
+
+    // A non-zero exit code makes the service manager apply the failure
+    // actions set up by `install`
+    let exit_code = if result.is_ok() { 0 } else { 1 };
+    set_state(
+        ServiceState::Stopped,
+        ServiceControlAccept::empty(),
+        exit_code,
+    )?;
+    result
+}
+
+/// Where the service logs to: next to the cache file.
+fn log_path(cache_path: &Path) -> PathBuf {
+    cache_path.with_file_name("cloudflare-ddns.log")
+}
+
+/// Installs or removes the service.
+pub fn manage(
+    command: &ServiceCommand,
+    config: Option<&Path>,
+    cache_path: Option<&Path>,
+) -> Result<()> {
+    match command {
+        ServiceCommand::Install => install(config, cache_path),
+        ServiceCommand::Uninstall => uninstall(),
+    }
+}
+
+fn install(config: Option<&Path>, cache_path: Option<&Path>) -> Result<()> {
+    let manager = ServiceManager::local_computer(
+        None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )
+    .context("Failed to connect to the service manager, run this from an administrator prompt")?;
+
+    // The service runs as LocalSystem from C:\Windows\System32, so it gets
+    // absolute paths and keeps its cache in ProgramData rather than in the
+    // profile of whoever installed it
+    let cache_path = match cache_path {
+        Some(path) => std::path::absolute(path)?,
+        None => {
+            let program_data = std::env::var_os("ProgramData").unwrap_or("C:\\ProgramData".into());
+            PathBuf::from(program_data)
+                .join("cloudflare-ddns")
+                .join("cache.json")
+        }
+    };
+    let mut launch_arguments: Vec<OsString> =
+        vec!["--service".into(), "run".into(), "--daemon".into()];
+    if let Some(config) = config {
+        launch_arguments.push("--config".into());
+        launch_arguments.push(std::path::absolute(config)?.into());
+    }
+    launch_arguments.push("--cache-path".into());
+    launch_arguments.push(cache_path.clone().into());
+
+    let service = manager
+        .create_service(
+            &ServiceInfo {
+                name: SERVICE_NAME.into(),
+                display_name: DISPLAY_NAME.into(),
+                service_type: ServiceType::OWN_PROCESS,
+                start_type: ServiceStartType::AutoStart,
+                error_control: ServiceErrorControl::Normal,
+                executable_path: std::env::current_exe()?,
+                launch_arguments,
+                dependencies: vec![],
+                account_name: None,
+                account_password: None,
+            },
+            ServiceAccess::CHANGE_CONFIG | ServiceAccess::START,
+        )
+        .context("Failed to create the service")?;
+    service.set_description("Keeps Cloudflare DNS records pointed at this machine's public IP")?;
+
+    // Restart 30 seconds after a failure, including a failed exit
+    service.update_failure_actions(ServiceFailureActions {
+        reset_period: ServiceFailureResetPeriod::After(Duration::from_secs(24 * 60 * 60)),
+        reboot_msg: None,
+        command: None,
+        actions: Some(vec![ServiceAction {
+            action_type: ServiceActionType::Restart,
+            delay: Duration::from_secs(30),
+        }]),
+    })?;
+    service.set_failure_actions_on_non_crash_failures(true)?;
+
+    service
+        .start::<&str>(&[])
+        .context("Installed the service but failed to start it")?;
+    say!("✅ Installed and started the {} service", SERVICE_NAME);
+    println!("   Logs: {}", log_path(&cache_path).display());
+    Ok(())
+}
+
+fn uninstall() -> Result<()> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+        .context(
+            "Failed to connect to the service manager, run this from an administrator prompt",
+        )?;
+    let service = manager
+        .open_service(
+            SERVICE_NAME,
+            ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+        )
+        .context("Failed to open the service, is it installed?")?;
+
+    // The service is only removed once it has stopped
+    service.delete()?;
+    if service.query_status()?.current_state != ServiceState::Stopped {
+        service.stop()?;
+    }
+    say!("🗑️  Removed the {} service", SERVICE_NAME);
+    Ok(())
+}
@@ -0,0 +1,117 @@
+use crate::error::Result;
+use crate::notify::{Event, EventKind, Notifier};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+struct WebhookMessage {
+    /// Shown in notifications and by clients that can't render blocks.
+    text: String,
+    blocks: Vec<Block>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Block {
+    Header { text: Text },
+    Section { fields: Vec<Text> },
+    Context { elements: Vec<Text> },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type")]
+enum Text {
+    #[serde(rename = "plain_text")]
+    Plain { text: String },
+    #[serde(rename = "mrkdwn")]
+    Markdown { text: String },
+}
+
+/// A bold label above a value, as used in section fields.
+fn field(label: &str, value: &str) -> Text {
+    Text::Markdown {
+        text: format!("*{}*\n{}", label, value),
+    }
+}
+
+/// Posts a Block Kit message to a Slack incoming webhook when a record
+/// changes, starts failing or recovers, and when the daemon stops.
+pub struct Slack {
+    client: Client,
+    url: String,
+}
+
+impl Slack {
+    pub fn new(client: Client, url: String) -> Self {
+        Self { client, url }
+    }
+}
+
+#[async_trait]
+impl Notifier for Slack {
+    fn name(&self) -> &'static str {
+        "Slack"
+    }
+
+    /// A failing record is posted once, when its streak of failures starts.
+    fn wants(&self, event: &Event<'_>) -> bool {
+        !event.is_repeated_failure()
+    }
+
+    async fn notify(&self, event: &Event<'_>) -> Result<()> {
+        let record = field(
+            "Record",
+            &format!("{} ({})", event.record_name, event.record_type),
+        );
+        let mut blocks = vec![Block::Header {
+            text: Text::Plain {
+                text: event.title(),
+            },
+        }];
+        match event.kind {
+            EventKind::IpChanged {
+                old_ip,
+                new_ip,
+                duration,
+            } => blocks.push(Block::Section {
+                fields: vec![
+                    record,
+                    field("Duration", &format!("{} ms", duration.as_millis())),
+                    field("Old IP", old_ip.unwrap_or("-")),
+                    field("New IP", new_ip),
+                ],
+            }),
+            EventKind::UpdateFailed { error, .. } => {
+                blocks.push(Block::Section {
+                    fields: vec![record],
+                });
+                blocks.push(Block::Context {
+                    elements: vec![Text::Markdown {
+                        text: error.to_string(),
+                    }],
+                });
+            }
+            EventKind::Recovered { failures } => blocks.push(Block::Section {
+                fields: vec![record, field("Failed cycles", &failures.to_string())],
+            }),
+            EventKind::ShuttingDown => blocks.push(Block::Context {
+                elements: vec![Text::Markdown {
+                    text: event.message(),
+                }],
+            }),
+        }
+        let text = format!("{}: {}", event.title(), event.message());
+
+        // Anyone with the webhook URL can post to the channel, don't log it
+        self.client
+            .post(&self.url)
+            .json(&WebhookMessage { text, blocks })
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(reqwest::Error::without_url)?;
+
+        Ok(())
+    }
+}
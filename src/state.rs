@@ -0,0 +1,185 @@
+//! Records and intervals changed through the daemon's admin API, kept in a
+//! JSON file so that they outlive restarts.
+//!
+//! The configuration file is left as written. The state lists the records
+//! added to and removed from it and the intervals to use instead of its
+//! own, and is applied on top of it whenever the configuration is loaded.
+
+use crate::cache::atomic_write;
+use crate::config::{Config, RecordConfig, Ttl, ZoneRef};
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// Changes made through the admin API.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct State {
+    /// Records added at runtime. They replace configured records of the
+    /// same name and type.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub added: Vec<AddedRecord>,
+    /// Configured records that are no longer managed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removed: Vec<RecordId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sleep_interval: Option<u64>,
+    /// `0` turns the adaptive interval of the configuration off.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_sleep_interval: Option<u64>,
+}
+
+/// The name and type that tell records apart.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordId {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub record_type: String,
+}
+
+impl RecordId {
+    pub fn of(record: &RecordConfig) -> Self {
+        Self {
+            name: record.name.to_ascii_lowercase(),
+            record_type: record.record_type.to_ascii_uppercase(),
+        }
+    }
+
+    fn matches(&self, record: &RecordConfig) -> bool {
+        *self == Self::of(record)
+    }
+
+    /// Whether `added` is the record of this name and type.
+    pub fn matches_added(&self, added: &AddedRecord) -> bool {
+        self.name == added.name.trim_end_matches('.').to_ascii_lowercase()
+            && self.record_type == added.record_type.trim().to_ascii_uppercase()
+    }
+}
+
+/// A Cloudflare record added at runtime, as given to `POST /api/records`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AddedRecord {
+    pub name: String,
+    #[serde(rename = "type", default = "default_type")]
+    pub record_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zone_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zone_name: Option<String>,
+    /// `None` keeps the existing record's setting.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxied: Option<bool>,
+    /// Seconds, or 1 for automatic. `None` uses the configured TTL.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u32>,
+}
+
+fn default_type() -> String {
+    "A".to_string()
+}
+
+impl AddedRecord {
+    /// The record with the defaults of `config` filled in.
+    pub fn to_record(&self, config: &Config) -> Result<RecordConfig> {
+        let zone = match (&self.zone_id, &self.zone_name) {
+            (Some(id), _) => Some(ZoneRef::Id(id.clone())),
+            (None, Some(name)) => Some(ZoneRef::Name(name.clone())),
+            (None, None) => None,
+        };
+        let ttl = self.ttl.map(|ttl| match ttl {
+            1 => Ttl::Auto,
+            seconds => Ttl::Seconds(seconds),
+        });
+        config.new_record(&self.name, &self.record_type, zone, self.proxied, ttl)
+    }
+}
+
+/// Where the state of `config` is kept.
+pub fn state_path(config: &Config) -> PathBuf {
+    config
+        .state_path
+        .clone()
+        .unwrap_or_else(|| config.cache_path.with_file_name("state.json"))
+}
+
+impl State {
+    /// Reads the state, which is empty until the API first changes something.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(path)
+            .map_err(|e| Error::State(format!("Failed to read {}: {}", path.display(), e)))?;
+        serde_json::from_str(&content)
+            .map_err(|e| Error::State(format!("Invalid state in {}: {}", path.display(), e)))
+    }
+
+    /// Writes the state through a temporary file, so that a crash can't
+    /// leave half of it behind.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let write = || -> std::io::Result<()> {
+            let content = serde_json::to_string_pretty(self)?;
+            atomic_write(path, content.as_bytes())
+        };
+        write().map_err(|e| Error::State(format!("Failed to write {}: {}", path.display(), e)))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.sleep_interval.is_none()
+            && self.max_sleep_interval.is_none()
+    }
+
+    /// Changes `config` the way the API did.
+    pub fn apply(&self, config: &mut Config) -> Result<()> {
+        config
+            .records
+            .retain(|record| !self.removed.iter().any(|id| id.matches(record)));
+        for added in &self.added {
+            let record = added.to_record(config)?;
+            let id = RecordId::of(&record);
+            match config.records.iter_mut().find(|r| id.matches(r)) {
+                Some(existing) => *existing = record,
+                None => config.records.push(record),
+            }
+        }
+        let configured = (config.sleep_interval, config.max_sleep_interval);
+        if let Some(interval) = self.sleep_interval {
+            config.sleep_interval = interval;
+        }
+        match self.max_sleep_interval {
+            Some(0) => config.max_sleep_interval = None,
+            Some(max) => config.max_sleep_interval = Some(max),
+            None => {}
+        }
+        // The configuration may have changed since the API set the intervals
+        if let Some(max) = config.max_sleep_interval
+            && max < config.sleep_interval
+        {
+            warn!(
+                "⚠️  The intervals set through the admin API don't fit the configuration, MAX_SLEEP_INTERVAL ({}) would be shorter than SLEEP_INTERVAL ({}); using the configured ones",
+                max, config.sleep_interval
+            );
+            (config.sleep_interval, config.max_sleep_interval) = configured;
+        }
+        Ok(())
+    }
+}
+
+/// Applies the state next to `config`'s cache, if the API changed anything.
+pub fn apply_saved(config: &mut Config) -> Result<()> {
+    let path = state_path(config);
+    let state = State::load(&path)?;
+    if !state.is_empty() {
+        info!(
+            "📝 Applying the changes made through the admin API, from {}",
+            path.display()
+        );
+        state.apply(config)?;
+    }
+    Ok(())
+}
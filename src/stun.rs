@@ -0,0 +1,165 @@
+//! Minimal STUN client (RFC 5389) for discovering the public IP address.
+//!
+//! Only the Binding request is implemented: the server echoes back the address
+//! it saw the request come from in an (XOR-)MAPPED-ADDRESS attribute.
+
+use crate::error::{Error, Result};
+use crate::ip::IpVersion;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::{UdpSocket, lookup_host};
+use tokio::time::timeout;
+
+/// Server used by the `stun` IP source when none is given.
+pub const DEFAULT_SERVER: &str = "stun.l.google.com:19302";
+
+/// Standard STUN port, used when a server is given without one.
+pub const DEFAULT_PORT: u16 = 3478;
+
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_RESPONSE: u16 = 0x0101;
+const MAGIC_COOKIE: u32 = 0x2112_A442;
+const ATTR_MAPPED_ADDRESS: u16 = 0x0001;
+const ATTR_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+const HEADER_LEN: usize = 20;
+
+/// UDP is lossy, so the request is sent a few times before giving up.
+const ATTEMPTS: u32 = 3;
+const ATTEMPT_TIMEOUT: Duration = Duration::from_millis(1500);
+
+/// Asks a STUN server (`host:port`) for the public address of the given family.
+pub async fn query(server: &str, version: IpVersion) -> Result<IpAddr> {
+    let server_addr = lookup_host(server)
+        .await
+        .map_err(|e| {
+            Error::IpDetection(format!("Failed to resolve STUN server '{}': {}", server, e))
+        })?
+        .find(|addr| version.matches(addr.ip()))
+        .ok_or_else(|| {
+            Error::IpDetection(format!(
+                "STUN server '{}' has no {} address",
+                server,
+                version.label()
+            ))
+        })?;
+
+    let bind_addr = match version {
+        IpVersion::V4 => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        IpVersion::V6 => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    };
+    let socket = UdpSocket::bind(bind_addr).await?;
+    socket.connect(server_addr).await?;
+
+    let mut transaction_id = [0u8; 12];
+    transaction_id.fill_with(|| fastrand::u8(..));
+    let request = binding_request(&transaction_id);
+
+    let mut buf = [0u8; 1024];
+    for _ in 0..ATTEMPTS {
+        socket.send(&request).await?;
+
+        match timeout(ATTEMPT_TIMEOUT, socket.recv(&mut buf)).await {
+            Ok(Ok(len)) => return parse_binding_response(&buf[..len], &transaction_id),
+            Ok(Err(e)) => return Err(e.into()),
+            Err(_) => continue,
+        }
+    }
+
+    Err(Error::IpDetection(format!(
+        "STUN server '{}' did not respond",
+        server
+    )))
+}
+
+fn binding_request(transaction_id: &[u8; 12]) -> Vec<u8> {
+    let mut request = Vec::with_capacity(HEADER_LEN);
+    request.extend_from_slice(&BINDING_REQUEST.to_be_bytes());
+    request.extend_from_slice(&0u16.to_be_bytes());
+    request.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    request.extend_from_slice(transaction_id);
+    request
+}
+
+fn parse_binding_response(response: &[u8], transaction_id: &[u8; 12]) -> Result<IpAddr> {
+    if response.len() < HEADER_LEN {
+        return Err(Error::IpDetection("STUN response is too short".to_string()));
+    }
+
+    let message_type = u16::from_be_bytes([response[0], response[1]]);
+    if message_type != BINDING_RESPONSE {
+        return Err(Error::IpDetection(format!(
+            "Unexpected STUN message type {:#06x}",
+            message_type
+        )));
+    }
+    if response[8..HEADER_LEN] != transaction_id[..] {
+        return Err(Error::IpDetection(
+            "STUN response transaction ID doesn't match".to_string(),
+        ));
+    }
+
+    let length = u16::from_be_bytes([response[2], response[3]]) as usize;
+    let attributes = response
+        .get(HEADER_LEN..HEADER_LEN + length)
+        .ok_or_else(|| Error::IpDetection("STUN response is truncated".to_string()))?;
+
+    // Prefer XOR-MAPPED-ADDRESS; old servers only send MAPPED-ADDRESS
+    let mut mapped = None;
+    let mut offset = 0;
+    while offset + 4 <= attributes.len() {
+        let attr_type = u16::from_be_bytes([attributes[offset], attributes[offset + 1]]);
+        let attr_len =
+            u16::from_be_bytes([attributes[offset + 2], attributes[offset + 3]]) as usize;
+        let value = attributes
+            .get(offset + 4..offset + 4 + attr_len)
+            .ok_or_else(|| Error::IpDetection("STUN attribute is truncated".to_string()))?;
+
+        match attr_type {
+            ATTR_XOR_MAPPED_ADDRESS => return parse_address(value, Some(transaction_id)),
+            ATTR_MAPPED_ADDRESS => mapped = Some(parse_address(value, None)?),
+            _ => {}
+        }
+
+        // Attribute values are padded to a multiple of four bytes
+        offset += 4 + attr_len.div_ceil(4) * 4;
+    }
+
+    mapped.ok_or_else(|| Error::IpDetection("STUN response has no mapped address".to_string()))
+}
+
+/// Decodes a (XOR-)MAPPED-ADDRESS value. The transaction ID is given for the
+/// XOR variant, whose address is XORed with the magic cookie and transaction ID.
+fn parse_address(value: &[u8], xor_transaction_id: Option<&[u8; 12]>) -> Result<IpAddr> {
+    let family = *value
+        .get(1)
+        .ok_or_else(|| Error::IpDetection("STUN address attribute is too short".to_string()))?;
+
+    let mut key = [0u8; 16];
+    if let Some(transaction_id) = xor_transaction_id {
+        key[..4].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        key[4..].copy_from_slice(transaction_id);
+    }
+
+    match family {
+        0x01 => {
+            let bytes: [u8; 4] = value
+                .get(4..8)
+                .and_then(|b| b.try_into().ok())
+                .ok_or_else(|| Error::IpDetection("STUN IPv4 address is truncated".to_string()))?;
+            let octets: [u8; 4] = std::array::from_fn(|i| bytes[i] ^ key[i]);
+            Ok(IpAddr::V4(Ipv4Addr::from(octets)))
+        }
+        0x02 => {
+            let bytes: [u8; 16] = value
+                .get(4..20)
+                .and_then(|b| b.try_into().ok())
+                .ok_or_else(|| Error::IpDetection("STUN IPv6 address is truncated".to_string()))?;
+            let octets: [u8; 16] = std::array::from_fn(|i| bytes[i] ^ key[i]);
+            Ok(IpAddr::V6(Ipv6Addr::from(octets)))
+        }
+        other => Err(Error::IpDetection(format!(
+            "Unknown STUN address family {:#04x}",
+            other
+        ))),
+    }
+}
@@ -0,0 +1,127 @@
+//! systemd integration: readiness and watchdog notifications for
+//! `Type=notify` services, and an example unit file.
+//!
+//! Notifications follow the `sd_notify(3)` protocol: a datagram with
+//! `KEY=value` lines sent to the socket in `$NOTIFY_SOCKET`. Outside of
+//! systemd the variable is unset and every call does nothing.
+
+use crate::error::Result;
+use std::env;
+use std::path::Path;
+use std::time::Duration;
+
+/// Sends a state update such as `READY=1` to systemd. Returns `false` when
+/// not running under systemd.
+#[cfg(unix)]
+pub fn notify(state: &str) -> Result<bool> {
+    use std::os::unix::net::UnixDatagram;
+
+    let Some(socket_path) = env::var_os("NOTIFY_SOCKET") else {
+        return Ok(false);
+    };
+
+    let socket = UnixDatagram::unbound()?;
+    let socket_path = Path::new(&socket_path);
+
+    // A leading `@` names a socket in the abstract namespace
+    #[cfg(target_os = "linux")]
+    if let Some(name) = socket_path.to_str().and_then(|p| p.strip_prefix('@')) {
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::net::SocketAddr;
+
+        let addr = SocketAddr::from_abstract_name(name)?;
+        socket.send_to_addr(state.as_bytes(), &addr)?;
+        return Ok(true);
+    }
+
+    socket.send_to(state.as_bytes(), socket_path)?;
+    Ok(true)
+}
+
+#[cfg(not(unix))]
+pub fn notify(_state: &str) -> Result<bool> {
+    Ok(false)
+}
+
+/// How often systemd expects a `WATCHDOG=1` ping, if `WatchdogSec=` is set
+/// for this process.
+pub fn watchdog_interval() -> Option<Duration> {
+    // WATCHDOG_PID is set when the watchdog is meant for a specific process
+    if let Ok(pid) = env::var("WATCHDOG_PID")
+        && pid.parse() != Ok(std::process::id())
+    {
+        return None;
+    }
+
+    let usec: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
+
+/// A hardened service unit running the daemon from `binary`, with settings
+/// read from `env_file` and, optionally, a config file.
+pub fn unit_file(binary: &Path, env_file: &Path, config: Option<&Path>) -> String {
+    let mut exec_start = format!("{} run --daemon", binary.display());
+    if let Some(config) = config {
+        exec_start.push_str(&format!(" --config {}", config.display()));
+    }
+
+    format!(
+        "\
+[Unit]
+Description=Cloudflare dynamic DNS updater
+Documentation=https://github.com/jmfederico/cloudflare-ddns
+Wants=network-online.target
+After=network-online.target
+
+[Service]
+Type=notify
+ExecStart={exec_start}
+ExecReload=/bin/kill -HUP $MAINPID
+EnvironmentFile={env_file}
+# The cache lives in /var/cache/cloudflare-ddns, created by CacheDirectory=
+Environment=CACHE_PATH=%C/cloudflare-ddns/cache.json
+CacheDirectory=cloudflare-ddns
+Restart=on-failure
+RestartSec=30
+# Restart the daemon if it stops responding for two minutes
+WatchdogSec=120
+
+# Run as an unprivileged throwaway user with a read-only view of the system
+DynamicUser=yes
+NoNewPrivileges=yes
+CapabilityBoundingSet=
+# Uncomment to let HTTP_LISTEN bind a port below 1024
+#AmbientCapabilities=CAP_NET_BIND_SERVICE
+#CapabilityBoundingSet=CAP_NET_BIND_SERVICE
+ProtectSystem=strict
+ProtectHome=yes
+PrivateTmp=yes
+PrivateDevices=yes
+ProtectClock=yes
+ProtectHostname=yes
+ProtectKernelTunables=yes
+ProtectKernelModules=yes
+ProtectKernelLogs=yes
+ProtectControlGroups=yes
+ProtectProc=invisible
+ProcSubset=pid
+# AF_NETLINK is needed by the interface IP source to list addresses, and by
+# WATCH_NETWORK to hear about changes
+RestrictAddressFamilies=AF_UNIX AF_INET AF_INET6 AF_NETLINK
+RestrictNamespaces=yes
+RestrictRealtime=yes
+RestrictSUIDSGID=yes
+LockPersonality=yes
+MemoryDenyWriteExecute=yes
+SystemCallArchitectures=native
+SystemCallFilter=@system-service
+SystemCallFilter=~@privileged @resources
+UMask=0077
+
+[Install]
+WantedBy=multi-user.target
+",
+        exec_start = exec_start,
+        env_file = env_file.display(),
+    )
+}
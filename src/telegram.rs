@@ -0,0 +1,144 @@
+use crate::error::{Error, Result};
+use crate::notify::{Event, EventKind, Notifier};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+/// Message sent when a record changes, unless `message_template` is set.
+pub const DEFAULT_MESSAGE_TEMPLATE: &str =
+    "🔄 {record_name} ({record_type}) changed from {old_ip} to {new_ip}";
+
+/// Message sent when a record keeps failing, unless `failure_template` is set.
+pub const DEFAULT_FAILURE_TEMPLATE: &str =
+    "❌ {record_name} ({record_type}) failed to update {failures} times in a row: {error}";
+
+/// Message sent when a reported failure is over, unless `recovered_template` is set.
+pub const DEFAULT_RECOVERED_TEMPLATE: &str =
+    "✅ {record_name} ({record_type}) is updating again after {failures} failures";
+
+/// Settings for the Telegram notifier.
+#[derive(Debug, Clone)]
+pub struct TelegramConfig {
+    pub bot_token: String,
+    pub chat_id: String,
+    pub message_template: String,
+    pub failure_template: String,
+    pub recovered_template: String,
+    /// Consecutive failures of a record before a message is sent.
+    pub failure_threshold: u32,
+}
+
+#[derive(Debug, Serialize)]
+struct SendMessage<'a> {
+    chat_id: &'a str,
+    text: &'a str,
+}
+
+#[derive(Debug, Deserialize)]
+struct TelegramResponse {
+    ok: bool,
+    description: Option<String>,
+}
+
+/// Sends a message through a Telegram bot when a record changes, when it
+/// has failed several cycles in a row or recovered from that, and when the
+/// daemon stops.
+pub struct Telegram {
+    client: Client,
+    config: TelegramConfig,
+}
+
+impl Telegram {
+    pub fn new(client: Client, config: TelegramConfig) -> Self {
+        Self { client, config }
+    }
+
+    /// The bot's own threshold, which can only be stricter than the
+    /// dispatcher's.
+    fn failure_threshold(&self, event: &Event<'_>) -> u32 {
+        self.config.failure_threshold.max(event.failure_threshold)
+    }
+
+    async fn send(&self, text: &str) -> Result<()> {
+        let url = format!(
+            "https://api.telegram.org/bot{}/sendMessage",
+            self.config.bot_token
+        );
+        // The bot token is part of the URL, so keep it out of error messages
+        let response = self
+            .client
+            .post(&url)
+            .json(&SendMessage {
+                chat_id: &self.config.chat_id,
+                text,
+            })
+            .send()
+            .await
+            .map_err(reqwest::Error::without_url)?;
+        let response: TelegramResponse =
+            response.json().await.map_err(reqwest::Error::without_url)?;
+
+        if !response.ok {
+            return Err(Error::Notification(format!(
+                "Telegram API error: {}",
+                response.description.unwrap_or_default()
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Notifier for Telegram {
+    fn name(&self) -> &'static str {
+        "Telegram"
+    }
+
+    /// Failures are only reported once they have happened `failure_threshold`
+    /// times in a row, so a flaky cycle doesn't page anyone. Recoveries are
+    /// only reported for failures that were.
+    fn wants(&self, event: &Event<'_>) -> bool {
+        match event.kind {
+            EventKind::IpChanged { .. } => true,
+            EventKind::UpdateFailed { failures, .. } => failures == self.failure_threshold(event),
+            EventKind::Recovered { failures } => failures >= self.failure_threshold(event),
+            EventKind::ShuttingDown => true,
+        }
+    }
+
+    async fn notify(&self, event: &Event<'_>) -> Result<()> {
+        let mut values = vec![
+            ("record_name", event.record_name.to_string()),
+            ("record_type", event.record_type.to_string()),
+        ];
+        let template = match event.kind {
+            EventKind::IpChanged { old_ip, new_ip, .. } => {
+                values.push(("old_ip", old_ip.unwrap_or("none").to_string()));
+                values.push(("new_ip", new_ip.to_string()));
+                &self.config.message_template
+            }
+            EventKind::UpdateFailed { error, failures } => {
+                values.push(("error", error.to_string()));
+                values.push(("failures", failures.to_string()));
+                &self.config.failure_template
+            }
+            EventKind::Recovered { failures } => {
+                values.push(("failures", failures.to_string()));
+                &self.config.recovered_template
+            }
+            EventKind::ShuttingDown => return self.send(&event.title()).await,
+        };
+
+        self.send(&render(template, &values)).await
+    }
+}
+
+/// Replaces `{name}` placeholders in a message template.
+fn render(template: &str, values: &[(&str, String)]) -> String {
+    values
+        .iter()
+        .fold(template.to_string(), |text, (name, value)| {
+            text.replace(&format!("{{{}}}", name), value)
+        })
+}
@@ -0,0 +1,2831 @@
+//! The update loop: compares the detected public IP against each configured
+//! record and pushes changes to Cloudflare.
+
+use crate::cache::{CacheFile, DnsCache, Flap, load_cache, record_key, save_cache};
+use crate::config::{Config, Discovery, DuplicatePolicy, RecordConfig, Ttl, ZoneRef};
+use crate::configwatch::ConfigWatcher;
+use crate::error::{Context, Error, Result};
+use crate::failover::Failover;
+use crate::history::{History, HistoryEntry, Trigger};
+use crate::hooks::HookEvent;
+use crate::ip::{IpDetector, IpSource, IpVersion, get_public_ip, is_public};
+use crate::leader::{Leader, Role};
+use crate::netwatch::NetworkWatcher;
+use crate::notify::Dispatcher;
+use crate::preflight;
+use crate::provider::{
+    DnsProvider, DnsRecord, Provider, UpdateDnsRecord, UpdateOnlyProvider, update_only_from_config,
+};
+use crate::retry::retry;
+use crate::server::{
+    self, AdminApi, AdminCommand, AdminReply, DynDnsServer, Health, HealthFile, PushRequest,
+    Settings, TriggerEndpoint,
+};
+use crate::state::{self, RecordId, State};
+use crate::systemd;
+use chrono::{DateTime, Utc};
+use futures::future::join_all;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{Notify, mpsc};
+use tracing::{error, info, warn};
+
+/// How long the daemon waits for shutdown notifications to be delivered.
+const SHUTDOWN_NOTIFY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait after a network change before checking, so that an
+/// address arriving in several steps is complete.
+const NETWORK_SETTLE: Duration = Duration::from_secs(3);
+
+/// Target of the summary line every run ends with, which `--quiet` keeps
+/// for log-based dashboards.
+pub const SUMMARY_TARGET: &str = "cloudflare_ddns::summary";
+
+/// What happened to a single record during a run.
+#[derive(Debug, PartialEq)]
+pub enum RecordOutcome {
+    CacheHit,
+    UpToDate,
+    Updated {
+        old_ip: String,
+    },
+    Created,
+    /// A change was needed but skipped because of `--dry-run`/`check`.
+    DryRun,
+    /// The address changed, but the record was written too recently to be
+    /// written again, see [`RecordConfig::min_update_interval`], or it flaps
+    /// and the new address isn't confirmed yet, see [`Config::flap_window`].
+    Deferred,
+}
+
+/// What happened in an update cycle, for `--output json`.
+#[derive(Debug, Default, Serialize)]
+pub struct CycleReport {
+    pub dry_run: bool,
+    /// Number of records looked at.
+    pub checked: usize,
+    /// Number of records updated or created.
+    pub changed: usize,
+    pub failed: usize,
+    /// Number of changed records that didn't resolve to their new address
+    /// in time, with `VERIFY_PROPAGATION` enabled.
+    pub unverified: usize,
+    /// Number of marked records that aren't configured and were deleted,
+    /// with `PRUNE_RECORDS` enabled.
+    pub pruned: usize,
+    /// Number of records left as they were: cache hits, records that were
+    /// up to date, deferred changes and dry runs.
+    pub skipped: usize,
+    /// Number of records whose new address waits for their
+    /// `MIN_UPDATE_INTERVAL` to pass or, when they flap, to be confirmed.
+    pub deferred: usize,
+    pub records: Vec<RecordReport>,
+    /// Errors that aren't about a single record, like failed discovery.
+    pub errors: Vec<String>,
+    /// How long the cycle took, in milliseconds.
+    pub duration_ms: u64,
+    /// What paused updates, for a run that was skipped because of it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub paused: Option<String>,
+    /// Number of records the cycle set out to process, including those it
+    /// hasn't got to yet.
+    #[serde(skip)]
+    pub planned: usize,
+}
+
+/// The result for a single record in a [`CycleReport`].
+#[derive(Debug, Serialize)]
+pub struct RecordReport {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub record_type: String,
+    pub provider: Provider,
+    pub status: RecordStatus,
+    /// The address the record had before it was updated.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_ip: Option<String>,
+    /// The detected address the record is meant to have.
+    pub new_ip: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Whether the changed record resolved to the new address, when that
+    /// was checked.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verified: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verification_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordStatus {
+    CacheHit,
+    UpToDate,
+    Updated,
+    Created,
+    DryRun,
+    Deferred,
+    Failed,
+}
+
+impl RecordReport {
+    fn new(
+        record: &RecordConfig,
+        new_ip: Option<&str>,
+        outcome: std::result::Result<&RecordOutcome, &str>,
+    ) -> Self {
+        let (status, old_ip, error) = match outcome {
+            Ok(RecordOutcome::CacheHit) => (RecordStatus::CacheHit, None, None),
+            Ok(RecordOutcome::UpToDate) => (RecordStatus::UpToDate, None, None),
+            Ok(RecordOutcome::Updated { old_ip }) => {
+                (RecordStatus::Updated, Some(old_ip.clone()), None)
+            }
+            Ok(RecordOutcome::Created) => (RecordStatus::Created, None, None),
+            Ok(RecordOutcome::DryRun) => (RecordStatus::DryRun, None, None),
+            Ok(RecordOutcome::Deferred) => (RecordStatus::Deferred, None, None),
+            Err(e) => (RecordStatus::Failed, None, Some(e.to_string())),
+        };
+        Self {
+            name: record.name.clone(),
+            record_type: record.record_type.clone(),
+            provider: record.provider,
+            status,
+            old_ip,
+            new_ip: new_ip.map(str::to_string),
+            error,
+            verified: None,
+            verification_error: None,
+        }
+    }
+}
+
+impl CycleReport {
+    /// The report of a run skipped because `pause` paused updates.
+    pub fn paused(config: &Config, pause: String) -> Self {
+        Self {
+            dry_run: config.dry_run,
+            paused: Some(pause),
+            ..Self::default()
+        }
+    }
+
+    /// Marks a cycle that was stopped before it finished, counting the
+    /// records it didn't get to as failed.
+    pub fn stop(&mut self, error: String) {
+        self.count_records();
+        let unreached = self.planned.saturating_sub(self.records.len());
+        self.checked += unreached;
+        self.failed += unreached;
+        self.errors.push(error);
+    }
+
+    /// Fills in the totals from the records reported so far.
+    fn count_records(&mut self) {
+        self.checked = self.records.len();
+        self.changed = self
+            .records
+            .iter()
+            .filter(|r| matches!(r.status, RecordStatus::Updated | RecordStatus::Created))
+            .count();
+        self.failed = self
+            .records
+            .iter()
+            .filter(|r| r.status == RecordStatus::Failed)
+            .count();
+        self.unverified = self
+            .records
+            .iter()
+            .filter(|r| r.verified == Some(false))
+            .count();
+        self.deferred = self
+            .records
+            .iter()
+            .filter(|r| r.status == RecordStatus::Deferred)
+            .count();
+        self.skipped = self.checked - self.changed - self.failed;
+    }
+
+    /// Logs one line with the totals, as fields for JSON logs.
+    pub fn log_summary(&self) {
+        info!(
+            target: SUMMARY_TARGET,
+            checked = self.checked,
+            updated = self.changed,
+            skipped = self.skipped,
+            failed = self.failed,
+            duration_ms = self.duration_ms,
+            "📊 Run summary:"
+        );
+    }
+
+    /// The cycle as an error if any record failed or discovery didn't work.
+    pub fn result(&self) -> Result<()> {
+        if self.failed > 0 {
+            return Err(Error::RecordsFailed {
+                failed: self.failed,
+                checked: self.checked,
+            });
+        }
+        match self.errors.first() {
+            Some(e) => Err(Error::Provider(e.clone())),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A record update that has been decided on but not sent yet, so that updates
+/// in the same zone can go out together in one batch.
+#[derive(Debug)]
+struct PendingUpdate {
+    zone_id: String,
+    record_id: String,
+    old_ip: String,
+    /// The address the record is updated to, which is embedded in the
+    /// content of records with a template.
+    new_ip: String,
+    data: UpdateDnsRecord,
+}
+
+/// An update together with the result of sending it.
+type AppliedUpdate = (PendingUpdate, Result<(), String>);
+
+/// Result of looking at a single record: either it's finished, or it needs
+/// updates, one for each of its records on Cloudflare that is out of date.
+#[derive(Debug)]
+enum RecordStep {
+    Done(RecordOutcome),
+    Update(Vec<PendingUpdate>),
+}
+
+/// Whether the cache says the record already points at `current_ip`, so
+/// `service` doesn't need to be asked. Counts the hit when it does.
+fn cached_ip_is_current(
+    cache: &mut CacheFile,
+    config: &Config,
+    record: &RecordConfig,
+    current_ip: &str,
+    service: &str,
+) -> bool {
+    let Some(cached_data) = cache.find_mut(&record.name, &record.record_type) else {
+        return false;
+    };
+
+    if cached_data.is_expired(record.cache_expiry_hours) {
+        info!(
+            "⏰ Cache expired ({}h), checking {}",
+            record.cache_expiry_hours, service
+        );
+        return false;
+    }
+
+    if cached_data.ip_address != current_ip {
+        info!(
+            "🔄 Cache hit but IP changed: {} -> {}",
+            cached_data.ip_address, current_ip
+        );
+        return false;
+    }
+
+    // Catches records that were changed behind our back before the cache
+    // expires
+    if let Some(runs) = config.reconcile_every
+        && cached_data.cache_hits + 1 >= runs
+    {
+        info!(
+            "🔁 {} runs since the last check, checking {}",
+            cached_data.cache_hits + 1,
+            service
+        );
+        return false;
+    }
+
+    cached_data.cache_hits += 1;
+    info!(
+        "✅ Cache hit! IP unchanged ({}), skipping {} API call",
+        current_ip, service
+    );
+    info!(
+        "   Last checked: {}",
+        cached_data.last_checked.format("%Y-%m-%d %H:%M:%S UTC")
+    );
+    true
+}
+
+/// Checks the address that the record's source detected against
+/// [`Config::confirm_ip_source`] when the record is about to get a new
+/// address, so that a single misbehaving source can't point it elsewhere.
+/// Records with several sources or a fixed address aren't checked. Each
+/// family is confirmed once per cycle, in `confirmations`.
+async fn confirm_change(
+    cache: &CacheFile,
+    config: &Config,
+    record: &RecordConfig,
+    detected: &str,
+    current_ip: &str,
+    confirmations: &mut HashMap<IpVersion, std::result::Result<String, String>>,
+) -> Result<()> {
+    let Some(confirm) = &config.confirm_ip_source else {
+        return Ok(());
+    };
+    if !record.ip_sources.is_empty()
+        || matches!(record.ip_source, IpSource::Static(_))
+        || record.ip_source == *confirm
+        || cache
+            .find(&record.name, &record.record_type)
+            .is_some_and(|cached_data| cached_data.ip_address == current_ip)
+    {
+        return Ok(());
+    }
+
+    let version = IpVersion::for_record_type(&record.record_type);
+    if let Entry::Vacant(entry) = confirmations.entry(version) {
+        info!(
+            "🤝 Confirming the {} address with {}...",
+            version.label(),
+            confirm
+        );
+        let detector = IpDetector {
+            source: confirm.clone(),
+            ..config.ip.clone()
+        };
+        let label = format!("Confirming public {} address", version.label());
+        let confirmation = retry(&config.retry, &label, || get_public_ip(&detector, version))
+            .await
+            .map(|ip| ip.to_string())
+            .map_err(|e| e.to_string());
+        entry.insert(confirmation);
+    }
+    // With an IPv6 suffix only the prefixes have to agree
+    match &confirmations[&version] {
+        Ok(confirmed)
+            if record_address(record, confirmed.clone())
+                == record_address(record, detected.to_string()) =>
+        {
+            info!("🤝 {} confirms {}", confirm, detected);
+            Ok(())
+        }
+        Ok(confirmed) => Err(Error::IpDetection(format!(
+            "{} detected {} but {} detected {}, leaving the record alone",
+            record.ip_source, detected, confirm, confirmed
+        ))),
+        Err(e) => Err(Error::IpDetection(format!(
+            "couldn't confirm {} with {}: {}",
+            detected, confirm, e
+        ))),
+    }
+}
+
+/// Whether the change of a record to `current_ip` is held off because the
+/// record flaps: its address changed less than [`Config::flap_window`] ago,
+/// and `current_ip` is the one it had before. The change goes through once
+/// `current_ip` has been detected for [`Config::flap_confirmation`].
+fn flap_held(
+    cache: &mut CacheFile,
+    config: &Config,
+    record: &RecordConfig,
+    current_ip: &str,
+) -> bool {
+    let key = record_key(&record.name, &record.record_type);
+    let Some(cached_data) = cache.records.get(&key) else {
+        return false;
+    };
+    if cached_data.ip_address == current_ip || config.force || config.flap_confirmation == 0 {
+        cache.flaps.remove(&key);
+        return false;
+    }
+
+    let now = Utc::now();
+    let flapped_back = config.flap_window > 0
+        && cached_data.previous_ip.as_deref() == Some(current_ip)
+        && now - cached_data.last_updated < chrono::Duration::seconds(config.flap_window as i64);
+    let confirmation = chrono::Duration::seconds(config.flap_confirmation as i64);
+    match cache.flaps.get(&key) {
+        Some(flap) if flap.candidate == current_ip => {
+            let stable = now - flap.since;
+            if stable >= confirmation {
+                info!(
+                    "✅ {} has been detected for {}s, writing it",
+                    current_ip,
+                    stable.num_seconds()
+                );
+                cache.flaps.remove(&key);
+                return false;
+            }
+            info!(
+                "⏳ Waiting for {} to stay detected for another {}s before writing it",
+                current_ip,
+                // Rounded up, so that it never says 0s
+                ((confirmation - stable).num_milliseconds() + 999) / 1000
+            );
+            true
+        }
+        _ if flapped_back => {
+            warn!(
+                "⚠️  {} ({}) flaps from {} back to {}, holding it off until it stays detected for {}s",
+                record.name,
+                record.record_type,
+                cached_data.ip_address,
+                current_ip,
+                config.flap_confirmation
+            );
+            cache.flaps.insert(
+                key,
+                Flap {
+                    candidate: current_ip.to_string(),
+                    since: now,
+                },
+            );
+            true
+        }
+        // Whatever was held off isn't detected anymore
+        _ => {
+            cache.flaps.remove(&key);
+            false
+        }
+    }
+}
+
+/// When the record may be written again, if it was written less than its
+/// [`RecordConfig::min_update_interval`] ago and `current_ip` would change it.
+fn deferred_until(
+    cache: &CacheFile,
+    config: &Config,
+    record: &RecordConfig,
+    current_ip: &str,
+) -> Option<DateTime<Utc>> {
+    if record.min_update_interval == 0 || config.force {
+        return None;
+    }
+    let cached_data = cache.find(&record.name, &record.record_type)?;
+    if cached_data.ip_address == current_ip {
+        return None;
+    }
+    let until =
+        cached_data.last_written? + chrono::Duration::seconds(record.min_update_interval as i64);
+    (until > Utc::now()).then_some(until)
+}
+
+/// The address a record should get from the detected address, which differs
+/// for AAAA records with an IPv6 suffix.
+fn record_address(record: &RecordConfig, detected: String) -> String {
+    let Some(suffix) = record.ipv6_suffix else {
+        return detected;
+    };
+    match detected.parse() {
+        Ok(ip @ IpAddr::V6(_)) => {
+            let address = record.address_for(ip).to_string();
+            info!(
+                "🧩 Prefix /{} of {} with suffix {} is {}",
+                suffix.prefix_len, detected, suffix.suffix, address
+            );
+            address
+        }
+        _ => detected,
+    }
+}
+
+/// Fails a record that Cloudflare would refuse to set to `ip`: one that
+/// another Cloudflare product has locked, or one to be proxied that can't
+/// be. Warns when a configured TTL is ignored because the record is proxied.
+fn check_conflicts(
+    record: &RecordConfig,
+    existing: Option<&DnsRecord>,
+    ip: &str,
+    proxied: bool,
+) -> Result<()> {
+    if existing.is_some_and(|r| r.locked) {
+        return Err(Error::Provider(format!(
+            "The {} record '{}' is locked by another Cloudflare product, such as Email Routing, and can't be updated",
+            record.record_type, record.name
+        )));
+    }
+
+    if !proxied {
+        return Ok(());
+    }
+    // Cloudflare doesn't proxy private addresses, and says so for others
+    let private = ip.parse().is_ok_and(|ip| !is_public(ip));
+    let refused = existing.is_some_and(|r| !r.proxiable && r.content == ip);
+    if private || refused {
+        return Err(Error::Config(format!(
+            "'{}' is proxied, but Cloudflare can't proxy {}; set `proxied` to false for the record",
+            record.name, ip
+        )));
+    }
+    if let Ttl::Seconds(ttl) = record.ttl {
+        warn!(
+            "⚠️  '{}' is proxied, so Cloudflare ignores its TTL of {}s and uses auto",
+            record.name, ttl
+        );
+    }
+    Ok(())
+}
+
+/// The comment to write to a record that is set to `ip`: `RECORD_COMMENT`
+/// with its placeholders filled in, or else the record's current comment,
+/// which replacing the record would otherwise clear.
+fn record_comment(config: &Config, ip: &str, existing: Option<&DnsRecord>) -> Option<String> {
+    match &config.record_comment {
+        Some(template) => Some(fill_timestamp(&template.replace("{ip}", ip))),
+        None => existing.and_then(|r| r.comment.clone()),
+    }
+}
+
+/// Replaces `{timestamp}` with the current time.
+fn fill_timestamp(text: &str) -> String {
+    text.replace(
+        "{timestamp}",
+        &Utc::now().format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+    )
+}
+
+/// Whether a record's live content is the `wanted` content, in which any
+/// `{timestamp}` stands for whatever time was written. Cloudflare may
+/// return TXT content in quotes, and names in CNAME and other records are
+/// compared without case or a trailing dot.
+fn content_matches(record_type: &str, live: &str, wanted: &str) -> bool {
+    let live = match record_type {
+        "TXT" => live
+            .strip_prefix('"')
+            .and_then(|quoted| quoted.strip_suffix('"'))
+            .unwrap_or(live)
+            .to_string(),
+        "A" | "AAAA" => live.to_string(),
+        _ => live.trim_end_matches('.').to_ascii_lowercase(),
+    };
+    let wanted = match record_type {
+        "TXT" | "A" | "AAAA" => wanted.to_string(),
+        _ => wanted.trim_end_matches('.').to_ascii_lowercase(),
+    };
+
+    let parts: Vec<&str> = wanted.split("{timestamp}").collect();
+    let Some(mut rest) = live.strip_prefix(parts[0]) else {
+        return false;
+    };
+    let [middle @ .., last] = &parts[1..] else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// The tags to give a record: its current ones plus those of `RECORD_TAGS`.
+fn record_tags(config: &Config, existing: Option<&DnsRecord>) -> Vec<String> {
+    let mut tags = existing.map(|r| r.tags.clone()).unwrap_or_default();
+    for tag in &config.record_tags {
+        if !tags.contains(tag) {
+            tags.push(tag.clone());
+        }
+    }
+    tags
+}
+
+/// Sends the address of a record on an update-only provider. With no live
+/// record to compare against, it is sent whenever the cache doesn't vouch
+/// for it.
+async fn push_address(
+    service: &dyn UpdateOnlyProvider,
+    cache: &mut CacheFile,
+    config: &Config,
+    record: &RecordConfig,
+    current_ip: &str,
+) -> Result<RecordOutcome> {
+    if !config.dry_run
+        && !config.force
+        && cached_ip_is_current(cache, config, record, current_ip, service.name())
+    {
+        return Ok(RecordOutcome::CacheHit);
+    }
+
+    if config.dry_run {
+        info!(
+            "🧪 Dry run: would set '{}' to '{}' on {}",
+            record.name,
+            current_ip,
+            service.name()
+        );
+        return Ok(RecordOutcome::DryRun);
+    }
+
+    info!(
+        "🔄 Setting '{}' to '{}' on {}...",
+        record.name,
+        current_ip,
+        service.name()
+    );
+    let ip = current_ip.parse()?;
+    retry(&config.retry, "Updating DNS record", || {
+        service.set_address(&record.name, ip)
+    })
+    .await?;
+    info!("✅ Successfully updated DNS record!");
+
+    // Only a cached address says what the record pointed at before
+    let old_ip = match cache.find_mut(&record.name, &record.record_type) {
+        Some(cached_data) => {
+            let old_ip = cached_data.ip_address.clone();
+            cached_data.update_ip(current_ip.to_string());
+            Some(old_ip)
+        }
+        None => {
+            cache.insert(DnsCache::new(
+                record.name.clone(),
+                record.record_type.clone(),
+                current_ip.to_string(),
+            ));
+            None
+        }
+    };
+    Ok(match old_ip {
+        Some(old_ip) if old_ip != current_ip => RecordOutcome::Updated { old_ip },
+        _ => RecordOutcome::UpToDate,
+    })
+}
+
+/// Health checks the primary address of a record with failover and returns
+/// the address the record should point at: the backup once the primary has
+/// failed the configured number of checks in a row, and the primary again
+/// as soon as it passes one.
+async fn failover_address(
+    client: &reqwest::Client,
+    cache: &mut CacheFile,
+    record: &RecordConfig,
+    failover: &Failover,
+    primary: &str,
+) -> String {
+    let Ok(ip) = primary.parse() else {
+        return primary.to_string();
+    };
+    let key = record_key(&record.name, &record.record_type);
+
+    match failover.check.probe(client, ip).await {
+        Ok(()) => {
+            if cache
+                .health
+                .remove(&key)
+                .is_some_and(|failed| failed >= failover.failures)
+            {
+                info!(
+                    "💚 {} passes its health check again, switching back from the backup",
+                    primary
+                );
+            }
+            primary.to_string()
+        }
+        Err(e) => {
+            let failed = cache.health.entry(key).or_default();
+            *failed += 1;
+            if *failed > failover.failures {
+                warn!(
+                    "⚠️  {} still fails its health check, keeping the backup {}: {}",
+                    primary, failover.backup, e
+                );
+                return failover.backup.to_string();
+            }
+            warn!(
+                "⚠️  Health check {} of {} failed ({} of {}): {}",
+                failover.check, primary, failed, failover.failures, e
+            );
+            if *failed < failover.failures {
+                return primary.to_string();
+            }
+            warn!(
+                "⚠️  Switching to the backup address {} while {} is down",
+                failover.backup, primary
+            );
+            failover.backup.to_string()
+        }
+    }
+}
+
+/// Checks a record against the cache and Cloudflare. Records that are missing
+/// are created right away; records that need changing are returned as a
+/// [`PendingUpdate`] for [`run_cycle`] to apply.
+async fn process_record(
+    provider: &dyn DnsProvider,
+    cache: &mut CacheFile,
+    config: &Config,
+    record: &RecordConfig,
+    zone_id: &str,
+    current_ip: &str,
+) -> Result<RecordStep> {
+    let record_name = &record.name;
+    let record_type = &record.record_type;
+
+    // Dry runs always look at the live record, since validating it is the
+    // whole point, and forced updates don't trust the cache either
+    if !config.dry_run
+        && !config.force
+        && cached_ip_is_current(cache, config, record, current_ip, "Cloudflare")
+    {
+        return Ok(RecordStep::Done(RecordOutcome::CacheHit));
+    }
+
+    let content = record.content_for(current_ip);
+
+    info!("📋 Fetching DNS records for '{}'...", record_name);
+    let records = retry(&config.retry, "Fetching DNS records", || {
+        provider.get_records(zone_id, Some(record_name))
+    })
+    .await?;
+
+    // Find the records with the matching type (default to A record)
+    let matching: Vec<&DnsRecord> = records
+        .iter()
+        .filter(|r| &r.record_type == record_type && r.name.eq_ignore_ascii_case(record_name))
+        .collect();
+    let Some(target_record) = matching.first() else {
+        if !config.create_if_missing {
+            return Err(if records.is_empty() {
+                Error::Provider(format!("No DNS record found with name '{}'", record_name))
+            } else {
+                Error::Provider(format!(
+                    "No {} record found with name '{}'",
+                    record_type, record_name
+                ))
+            });
+        }
+
+        check_conflicts(record, None, &content, record.proxied == Some(true))?;
+        let content = fill_timestamp(&content);
+
+        if config.dry_run {
+            info!(
+                "🧪 Dry run: would create {} record '{}' with '{}'",
+                record_type, record_name, content
+            );
+            return Ok(RecordStep::Done(RecordOutcome::DryRun));
+        }
+
+        info!(
+            "➕ No {} record found for '{}', creating it with '{}'...",
+            record_type, record_name, content
+        );
+
+        let record_data = UpdateDnsRecord {
+            record_type: record_type.clone(),
+            name: record_name.clone(),
+            content,
+            ttl: record.ttl.resolve(None),
+            proxied: record.proxied,
+            comment: record_comment(config, current_ip, None),
+            tags: record_tags(config, None),
+        };
+
+        let created = provider.create_record(zone_id, record_data).await?;
+
+        info!("✅ Successfully created DNS record!");
+        info!("   Record: {}", created.name);
+        info!("   Type: {}", created.record_type);
+        info!("   IP: {}", created.content);
+        info!("   TTL: {}", created.ttl);
+        info!("   Proxied: {}", created.proxied);
+
+        match cache.find_mut(record_name, record_type) {
+            Some(cached_data) => cached_data.update_ip(current_ip.to_string()),
+            None => {
+                cache.insert(DnsCache::new(
+                    record_name.clone(),
+                    record_type.clone(),
+                    current_ip.to_string(),
+                ));
+            }
+        }
+
+        return Ok(RecordStep::Done(RecordOutcome::Created));
+    };
+
+    let targets = match config.duplicate_records {
+        _ if matching.len() == 1 => &matching[..],
+        DuplicatePolicy::UpdateFirst => {
+            info!(
+                "👥 Found {} {} records for '{}', only updating the first",
+                matching.len(),
+                record_type,
+                record_name
+            );
+            &matching[..1]
+        }
+        DuplicatePolicy::UpdateAll => {
+            info!(
+                "👥 Found {} {} records for '{}', updating all of them",
+                matching.len(),
+                record_type,
+                record_name
+            );
+            &matching[..]
+        }
+        DuplicatePolicy::Error => {
+            return Err(Error::Provider(format!(
+                "Found {} {} records with name '{}', set DUPLICATE_RECORDS to update-all or update-first to update them",
+                matching.len(),
+                record_type,
+                record_name
+            )));
+        }
+    };
+
+    // Update or create cache entry with current Cloudflare record
+    let cached_data = match cache.find_mut(record_name, record_type) {
+        Some(cached_data) => {
+            cached_data.update_checked();
+            cached_data
+        }
+        None => cache.insert(DnsCache::new(
+            record_name.clone(),
+            record_type.clone(),
+            target_record.content.clone(),
+        )),
+    };
+
+    let mut updates = Vec::new();
+    for target_record in targets {
+        info!(
+            "🔍 Found DNS record: {} -> {} (TTL: {}, proxied: {})",
+            target_record.name, target_record.content, target_record.ttl, target_record.proxied
+        );
+
+        // Keep the record's current proxy status unless one is configured
+        let proxied = record.proxied.unwrap_or(target_record.proxied);
+        check_conflicts(record, Some(target_record), &content, proxied)?;
+
+        // Cloudflare always reports proxied records as TTL 1, so only compare
+        // the TTL of records that aren't proxied
+        let ttl = record.ttl.resolve(Some(target_record.ttl));
+        let ttl_changed = !proxied && target_record.ttl != ttl;
+
+        let tags = record_tags(config, Some(target_record));
+        let tags_missing = tags.len() != target_record.tags.len();
+
+        // Check if update is needed
+        let content_changed = !content_matches(record_type, &target_record.content, &content);
+        let written = fill_timestamp(&content);
+        let up_to_date =
+            !content_changed && target_record.proxied == proxied && !ttl_changed && !tags_missing;
+        if up_to_date && !config.force {
+            continue;
+        }
+
+        if config.dry_run {
+            info!(
+                "🧪 Dry run: would update DNS record from '{}' to '{}' (proxied: {} -> {}, TTL: {} -> {})",
+                target_record.content,
+                written,
+                target_record.proxied,
+                proxied,
+                target_record.ttl,
+                ttl
+            );
+        } else if up_to_date {
+            info!("🔄 Forcing update of DNS record with '{}'...", written);
+        } else if content_changed {
+            info!(
+                "🔄 Updating DNS record from '{}' to '{}'...",
+                target_record.content, written
+            );
+        } else if target_record.proxied != proxied {
+            info!(
+                "🔄 Updating DNS record proxy status from {} to {}...",
+                target_record.proxied, proxied
+            );
+        } else if ttl_changed {
+            info!(
+                "🔄 Updating DNS record TTL from {} to {}...",
+                target_record.ttl, ttl
+            );
+        } else {
+            info!(
+                "🏷️  Adding tags {} to DNS record...",
+                tags[target_record.tags.len()..].join(", ")
+            );
+        }
+
+        updates.push(PendingUpdate {
+            zone_id: zone_id.to_string(),
+            record_id: target_record.id.clone(),
+            // The content of a template doesn't say which address it had
+            old_ip: match record.template {
+                Some(_) => cached_data.ip_address.clone(),
+                None => target_record.content.clone(),
+            },
+            new_ip: current_ip.to_string(),
+            data: UpdateDnsRecord {
+                record_type: record_type.clone(),
+                name: record_name.clone(),
+                content: written,
+                ttl,
+                proxied: Some(proxied),
+                comment: record_comment(config, current_ip, Some(target_record)),
+                tags,
+            },
+        });
+    }
+
+    if updates.is_empty() {
+        info!("✅ DNS record is already up to date!");
+
+        // Update cache with current IP if it was different
+        if cached_data.ip_address != current_ip {
+            cached_data.update_ip(current_ip.to_string());
+        }
+
+        return Ok(RecordStep::Done(RecordOutcome::UpToDate));
+    }
+
+    if config.dry_run {
+        return Ok(RecordStep::Done(RecordOutcome::DryRun));
+    }
+
+    Ok(RecordStep::Update(updates))
+}
+
+/// Makes the records of a name and type with several sources point at
+/// `current_ips`, a comma-separated list of addresses: one record for each
+/// address, none for any other. Missing records are created before the
+/// others are deleted, so the name keeps resolving while the set changes.
+async fn process_record_set(
+    provider: &dyn DnsProvider,
+    cache: &mut CacheFile,
+    config: &Config,
+    record: &RecordConfig,
+    zone_id: &str,
+    current_ips: &str,
+) -> Result<RecordOutcome> {
+    let record_name = &record.name;
+    let record_type = &record.record_type;
+
+    if !config.dry_run
+        && !config.force
+        && cached_ip_is_current(cache, config, record, current_ips, "Cloudflare")
+    {
+        return Ok(RecordOutcome::CacheHit);
+    }
+
+    info!("📋 Fetching DNS records for '{}'...", record_name);
+    let records = retry(&config.retry, "Fetching DNS records", || {
+        provider.get_records(zone_id, Some(record_name))
+    })
+    .await?;
+    let existing: Vec<&DnsRecord> = records
+        .iter()
+        .filter(|r| &r.record_type == record_type && r.name.eq_ignore_ascii_case(record_name))
+        .collect();
+    for existing_record in &existing {
+        info!(
+            "🔍 Found DNS record: {} -> {}",
+            existing_record.name, existing_record.content
+        );
+    }
+
+    let wanted: Vec<&str> = current_ips.split(',').collect();
+    let to_create: Vec<&str> = wanted
+        .iter()
+        .copied()
+        .filter(|ip| !existing.iter().any(|r| r.content == *ip))
+        .collect();
+    // Keep one record of every wanted address, and drop duplicates too
+    let mut kept: Vec<&str> = Vec::new();
+    let mut to_delete: Vec<&DnsRecord> = Vec::new();
+    for existing_record in &existing {
+        let content = existing_record.content.as_str();
+        if wanted.contains(&content) && !kept.contains(&content) {
+            kept.push(content);
+        } else {
+            to_delete.push(existing_record);
+        }
+    }
+
+    let mut old_ips: Vec<&str> = existing.iter().map(|r| r.content.as_str()).collect();
+    old_ips.sort();
+    old_ips.dedup();
+    let old_ip = old_ips.join(",");
+
+    if to_create.is_empty() && to_delete.is_empty() {
+        info!("✅ DNS records are already up to date!");
+    } else if config.dry_run {
+        for ip in &to_create {
+            info!(
+                "🧪 Dry run: would add {} record '{}' with '{}'",
+                record_type, record_name, ip
+            );
+        }
+        for stale in &to_delete {
+            info!(
+                "🧪 Dry run: would remove {} record '{}' with '{}'",
+                record_type, record_name, stale.content
+            );
+        }
+        return Ok(RecordOutcome::DryRun);
+    } else {
+        // New records copy the settings of the existing ones
+        let template = existing.first();
+        for ip in &to_create {
+            let proxied = record.proxied.or(template.map(|r| r.proxied));
+            check_conflicts(record, None, ip, proxied == Some(true))?;
+            info!(
+                "➕ Adding {} record '{}' with '{}'...",
+                record_type, record_name, ip
+            );
+            let record_data = UpdateDnsRecord {
+                record_type: record_type.clone(),
+                name: record_name.clone(),
+                content: ip.to_string(),
+                ttl: record.ttl.resolve(template.map(|r| r.ttl)),
+                proxied,
+                comment: record_comment(config, ip, template.copied()),
+                tags: record_tags(config, template.copied()),
+            };
+            provider
+                .create_record(zone_id, record_data)
+                .await
+                .with_context(|| format!("Failed to add '{}', no records were removed", ip))?;
+        }
+        for stale in &to_delete {
+            info!(
+                "➖ Removing {} record '{}' with '{}'...",
+                record_type, record_name, stale.content
+            );
+            retry(&config.retry, "Deleting DNS record", || {
+                provider.delete_record(zone_id, &stale.id)
+            })
+            .await?;
+        }
+        info!("✅ Successfully updated DNS records!");
+    }
+
+    match cache.find_mut(record_name, record_type) {
+        Some(cached_data) if cached_data.ip_address == current_ips => cached_data.update_checked(),
+        Some(cached_data) => cached_data.update_ip(current_ips.to_string()),
+        None => {
+            cache.insert(DnsCache::new(
+                record_name.clone(),
+                record_type.clone(),
+                current_ips.to_string(),
+            ));
+        }
+    }
+
+    Ok(if to_create.is_empty() && to_delete.is_empty() {
+        RecordOutcome::UpToDate
+    } else if existing.is_empty() {
+        RecordOutcome::Created
+    } else {
+        RecordOutcome::Updated { old_ip }
+    })
+}
+
+/// Adds the records whose address changed to the history database.
+/// `reports` and `cached_ips` are in the order of the configured records.
+fn log_history(
+    path: &Path,
+    config: &Config,
+    reports: &[RecordReport],
+    cached_ips: &[Option<String>],
+) -> Result<()> {
+    let changes: Vec<HistoryEntry> = reports
+        .iter()
+        .zip(cached_ips)
+        .filter_map(|(report, cached_ip)| {
+            let new_ip = report.new_ip.clone()?;
+            let trigger = match report.status {
+                RecordStatus::Created => Trigger::Created,
+                RecordStatus::Updated if report.old_ip.as_ref() == Some(&new_ip) => return None,
+                RecordStatus::Updated if config.force => Trigger::Forced,
+                RecordStatus::Updated if cached_ip.is_some() && *cached_ip != report.old_ip => {
+                    Trigger::Drift
+                }
+                RecordStatus::Updated => Trigger::IpChanged,
+                _ => return None,
+            };
+            Some(HistoryEntry {
+                time: Utc::now(),
+                record_name: report.name.clone(),
+                record_type: report.record_type.clone(),
+                old_ip: report.old_ip.clone(),
+                new_ip,
+                trigger,
+            })
+        })
+        .collect();
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    let history = History::open(path)?;
+    for change in &changes {
+        history.add(change)?;
+    }
+    Ok(())
+}
+
+/// Sends pending updates to Cloudflare, grouping those in the same zone into
+/// a single batch request. Returns each update together with its result.
+async fn apply_updates(
+    provider: &dyn DnsProvider,
+    config: &Config,
+    pending: Vec<(usize, PendingUpdate)>,
+) -> Vec<(usize, PendingUpdate, Result<(), String>)> {
+    let mut zone_ids: Vec<String> = Vec::new();
+    for (_, update) in &pending {
+        if !zone_ids.contains(&update.zone_id) {
+            zone_ids.push(update.zone_id.clone());
+        }
+    }
+
+    let mut results = Vec::new();
+    let mut pending = pending;
+    for zone_id in zone_ids {
+        let (updates, rest): (Vec<_>, Vec<_>) =
+            pending.into_iter().partition(|(_, u)| u.zone_id == zone_id);
+        pending = rest;
+
+        let result = if let [(_, update)] = updates.as_slice() {
+            retry(&config.retry, "Updating DNS record", || {
+                provider.update_record(&zone_id, &update.record_id, &update.data)
+            })
+            .await
+        } else {
+            info!(
+                "📦 Updating {} records in zone {} in one batch...",
+                updates.len(),
+                zone_id
+            );
+            let puts: Vec<(&str, &UpdateDnsRecord)> = updates
+                .iter()
+                .map(|(_, u)| (u.record_id.as_str(), &u.data))
+                .collect();
+            retry(&config.retry, "Batch updating DNS records", || {
+                provider.update_records(&zone_id, &puts)
+            })
+            .await
+        };
+
+        let result = result.map_err(|e| e.to_string());
+        results.extend(
+            updates
+                .into_iter()
+                .map(|(index, update)| (index, update, result.clone())),
+        );
+    }
+
+    results
+}
+
+/// Collects the results of the updates of each record, which may have
+/// several when it has duplicates on Cloudflare.
+fn group_by_record(
+    results: Vec<(usize, PendingUpdate, Result<(), String>)>,
+) -> Vec<(usize, Vec<AppliedUpdate>)> {
+    let mut grouped: Vec<(usize, Vec<_>)> = Vec::new();
+    for (index, update, result) in results {
+        match grouped.iter_mut().find(|(i, _)| *i == index) {
+            Some((_, updates)) => updates.push((update, result)),
+            None => grouped.push((index, vec![(update, result)])),
+        }
+    }
+    grouped
+}
+
+/// Returns the zone ID for a record, looking zone names up through the API
+/// the first time and remembering them in the cache afterwards.
+pub async fn resolve_zone_id(
+    provider: &dyn DnsProvider,
+    cache: &mut CacheFile,
+    config: &Config,
+    zone: &ZoneRef,
+) -> Result<String> {
+    let zone_name = match zone {
+        ZoneRef::Id(zone_id) => return Ok(zone_id.clone()),
+        ZoneRef::Name(zone_name) => zone_name,
+    };
+
+    if let Some(zone_id) = cache.zones.get(zone_name) {
+        return Ok(zone_id.clone());
+    }
+
+    info!("🔎 Looking up zone ID for '{}'...", zone_name);
+    let zone_id = retry(&config.retry, "Looking up zone", || {
+        provider.get_zone_id(zone_name)
+    })
+    .await?;
+    info!("🔎 Zone '{}' has ID {}", zone_name, zone_id);
+
+    cache.zones.insert(zone_name.clone(), zone_id.clone());
+    Ok(zone_id)
+}
+
+/// Runs a single update cycle: detects the public IP and processes every
+/// configured record against it. Failures are collected in the report
+/// rather than stopping the cycle; [`CycleReport::result`] turns them into
+/// an error.
+pub async fn run_cycle(
+    provider: &dyn DnsProvider,
+    dispatcher: &Dispatcher,
+    cache: &mut CacheFile,
+    config: &Config,
+) -> CycleReport {
+    let mut report = CycleReport::default();
+    fill_cycle(provider, dispatcher, cache, config, &mut report).await;
+    report
+}
+
+/// Runs a cycle like [`run_cycle`], recording each record in `report` as
+/// soon as it is done, so that a caller that stops the cycle early still
+/// knows what it got to (see [`CycleReport::stop`]).
+pub async fn fill_cycle(
+    provider: &dyn DnsProvider,
+    dispatcher: &Dispatcher,
+    cache: &mut CacheFile,
+    config: &Config,
+    report: &mut CycleReport,
+) {
+    let cycle_started = Instant::now();
+    report.dry_run = config.dry_run;
+
+    // Fall back to the configured records so a discovery error doesn't stop them
+    let records = match managed_records(provider, cache, config).await {
+        Ok(records) => records,
+        Err(e) => {
+            error!("❌ Record discovery failed: {}", e);
+            report
+                .errors
+                .push(format!("Record discovery failed: {}", e));
+            config.records.clone()
+        }
+    };
+    report.planned = records.len();
+
+    // Detect each address family once per IP source, and only if a record needs it
+    let mut current_ips: HashMap<(IpSource, IpVersion), Option<String>> = HashMap::new();
+
+    for (record, source) in records
+        .iter()
+        .flat_map(|record| record.sources().iter().map(move |source| (record, source)))
+    {
+        let version = IpVersion::for_record_type(&record.record_type);
+        let key = (source.clone(), version);
+        if current_ips.contains_key(&key) {
+            continue;
+        }
+
+        if *source == config.ip.source {
+            info!("🌐 Getting current public {} address...", version.label());
+        } else {
+            info!(
+                "🌐 Getting current public {} address from {}...",
+                version.label(),
+                source
+            );
+        }
+        let detector = IpDetector {
+            source: source.clone(),
+            ..config.ip.clone()
+        };
+        let label = format!("Detecting public {} address", version.label());
+        let current_ip =
+            match retry(&config.retry, &label, || get_public_ip(&detector, version)).await {
+                Ok(ip) => {
+                    info!("📍 Current {}: {}", version.label(), ip);
+                    Some(ip.to_string())
+                }
+                Err(e) => {
+                    error!(
+                        "❌ Failed to detect public {} address: {}",
+                        version.label(),
+                        e
+                    );
+                    None
+                }
+            };
+        current_ips.insert(key, current_ip);
+    }
+
+    let update_only = update_only_from_config(config);
+
+    // Where each of `report.records` is configured, as they finish out of order
+    let mut order = Vec::with_capacity(records.len());
+    // Changed records to look up afterwards. Proxied records resolve to
+    // Cloudflare's addresses, so there is nothing to compare them against.
+    let mut changed = Vec::new();
+    let known_zones = cache.zones.len();
+    let known_failures = cache.failures.clone();
+    let known_health = cache.health.clone();
+    let known_flaps = cache.flaps.clone();
+    // Only built when a record has failover health checks
+    let mut health_client = None;
+    // Addresses detected by CONFIRM_IP_SOURCE, once a record needs one
+    let mut confirmations = HashMap::new();
+
+    let mut pending = Vec::new();
+
+    // When each record started processing, to report how long its update took
+    let mut started = Vec::with_capacity(records.len());
+
+    // The addresses records were last set to, to tell drift from IP changes
+    let mut cached_ips = Vec::with_capacity(records.len());
+
+    for (index, record) in records.iter().enumerate() {
+        info!("📌 {} ({})", record.name, record.record_type);
+        started.push(Instant::now());
+        cached_ips.push(
+            cache
+                .find(&record.name, &record.record_type)
+                .map(|c| c.ip_address.clone()),
+        );
+
+        let version = IpVersion::for_record_type(&record.record_type);
+        // A record set points at every address that was detected, as a
+        // sorted list so that the cache can compare it
+        let mut addresses: Vec<String> = record
+            .sources()
+            .iter()
+            .filter_map(|source| {
+                current_ips
+                    .get(&(source.clone(), version))
+                    .cloned()
+                    .flatten()
+            })
+            .map(|detected| record_address(record, detected))
+            .collect();
+        addresses.sort();
+        addresses.dedup();
+        let mut current_ip = (!addresses.is_empty()).then(|| addresses.join(","));
+        if let (Some(failover), Some(primary)) = (&record.failover, &current_ip) {
+            let client = health_client.get_or_insert_with(|| config.http.client());
+            current_ip = Some(failover_address(client, cache, record, failover, primary).await);
+        }
+
+        let result = async {
+            let current_ip = current_ip.as_deref().ok_or_else(|| {
+                Error::IpDetection(format!("no public {} address detected", version.label()))
+            })?;
+
+            if let Some(detected) = current_ips
+                .get(&(record.ip_source.clone(), version))
+                .cloned()
+                .flatten()
+            {
+                confirm_change(
+                    cache,
+                    config,
+                    record,
+                    &detected,
+                    current_ip,
+                    &mut confirmations,
+                )
+                .await?;
+            }
+            if flap_held(cache, config, record, current_ip) {
+                return Ok(RecordStep::Done(RecordOutcome::Deferred));
+            }
+            if let Some(until) = deferred_until(cache, config, record, current_ip) {
+                info!(
+                    "⏳ Written less than {}s ago, holding off the change to {} until {}",
+                    record.min_update_interval,
+                    current_ip,
+                    until.format("%H:%M:%S UTC")
+                );
+                return Ok(RecordStep::Done(RecordOutcome::Deferred));
+            }
+
+            // Only records on Cloudflare have a zone
+            let Some(zone) = &record.zone else {
+                let service = update_only.get(&record.provider).ok_or_else(|| {
+                    Error::Config(format!("{} has no credentials configured", record.provider))
+                })?;
+                return push_address(service.as_ref(), cache, config, record, current_ip)
+                    .await
+                    .map(RecordStep::Done);
+            };
+
+            let zone_id = resolve_zone_id(provider, cache, config, zone)
+                .await
+                .map_err(|e| Error::Provider(format!("failed to resolve zone: {}", e)))?;
+
+            if !record.ip_sources.is_empty() {
+                return process_record_set(provider, cache, config, record, &zone_id, current_ip)
+                    .await
+                    .map(RecordStep::Done);
+            }
+            process_record(provider, cache, config, record, &zone_id, current_ip).await
+        }
+        .await;
+
+        match result {
+            Ok(RecordStep::Done(outcome)) => {
+                if matches!(
+                    outcome,
+                    RecordOutcome::Updated { .. } | RecordOutcome::Created
+                ) && let Some(cached_data) = cache.find_mut(&record.name, &record.record_type)
+                {
+                    cached_data.update_written();
+                }
+                // A deferred change is neither a success nor a failure yet
+                if let Some(new_ip) = current_ip.as_deref()
+                    && outcome != RecordOutcome::Deferred
+                {
+                    notify_outcome(
+                        dispatcher,
+                        cache,
+                        config,
+                        record,
+                        &outcome,
+                        new_ip,
+                        started[index].elapsed(),
+                    )
+                    .await;
+                }
+                if matches!(
+                    outcome,
+                    RecordOutcome::Updated { .. } | RecordOutcome::Created
+                ) && record.proxied != Some(true)
+                    // Lookups compare a single address, which neither a set
+                    // nor the content of a template is
+                    && record.ip_sources.is_empty()
+                    && record.template.is_none()
+                {
+                    changed.extend(current_ip.clone().map(|ip| (index, ip)));
+                }
+                report.records.push(RecordReport::new(
+                    record,
+                    current_ip.as_deref(),
+                    Ok(&outcome),
+                ));
+                order.push(index);
+            }
+            Ok(RecordStep::Update(updates)) => {
+                pending.extend(updates.into_iter().map(|update| (index, update)))
+            }
+            Err(e) => {
+                let e = e.to_string();
+                report_failure(dispatcher, cache, config, record, &e).await;
+                report
+                    .records
+                    .push(RecordReport::new(record, current_ip.as_deref(), Err(&e)));
+                order.push(index);
+            }
+        }
+    }
+
+    for (index, mut updates) in group_by_record(apply_updates(provider, config, pending).await) {
+        let record = &records[index];
+        // A record with duplicates failed if any of its updates did, and
+        // otherwise changed from the address of one that had another
+        let reported = updates
+            .iter()
+            .position(|(_, result)| result.is_err())
+            .or_else(|| {
+                updates
+                    .iter()
+                    .position(|(update, _)| update.old_ip != update.new_ip)
+            });
+        let (update, result) = updates.swap_remove(reported.unwrap_or(0));
+        match result {
+            Ok(()) => {
+                info!(
+                    "✅ Successfully updated DNS record {} ({})!",
+                    record.name, record.record_type
+                );
+                info!("   New IP: {}", update.new_ip);
+                if record.template.is_some() {
+                    info!("   Content: {}", update.data.content);
+                }
+                info!("   TTL: {}", update.data.ttl);
+                if let Some(proxied) = update.data.proxied {
+                    info!("   Proxied: {}", proxied);
+                }
+
+                if let Some(cached_data) = cache.find_mut(&record.name, &record.record_type) {
+                    cached_data.update_ip(update.new_ip.clone());
+                    cached_data.update_written();
+                }
+
+                let outcome = RecordOutcome::Updated {
+                    old_ip: update.old_ip,
+                };
+                notify_outcome(
+                    dispatcher,
+                    cache,
+                    config,
+                    record,
+                    &outcome,
+                    &update.new_ip,
+                    started[index].elapsed(),
+                )
+                .await;
+                if update.data.proxied != Some(true) && record.template.is_none() {
+                    changed.push((index, update.new_ip.clone()));
+                }
+                report.records.push(RecordReport::new(
+                    record,
+                    Some(&update.new_ip),
+                    Ok(&outcome),
+                ));
+                order.push(index);
+            }
+            Err(e) => {
+                report_failure(dispatcher, cache, config, record, &e).await;
+                report
+                    .records
+                    .push(RecordReport::new(record, Some(&update.new_ip), Err(&e)));
+                order.push(index);
+            }
+        }
+    }
+
+    match prune_records(provider, cache, config, &records).await {
+        Ok(pruned) => report.pruned = pruned,
+        Err(e) => {
+            error!("❌ Pruning records failed: {}", e);
+            report.errors.push(format!("Pruning records failed: {}", e));
+        }
+    }
+
+    if let Some(check) = &config.verify
+        && !changed.is_empty()
+    {
+        info!(
+            "🔎 Waiting up to {}s for {} changed record(s) to resolve...",
+            check.timeout.as_secs(),
+            changed.len()
+        );
+        let records = &records;
+        let lookups = changed.iter().map(|(index, ip)| async move {
+            let record = &records[*index];
+            let result = match ip.parse() {
+                Ok(ip) => check.wait_for(&record.name, ip).await,
+                Err(e) => Err(Error::Provider(format!("invalid address '{}': {}", ip, e))),
+            };
+            (*index, record, ip, result)
+        });
+        for (index, record, ip, result) in join_all(lookups).await {
+            let verification_error = match result {
+                Ok(()) => {
+                    info!(
+                        "✅ {} ({}) resolves to {}",
+                        record.name, record.record_type, ip
+                    );
+                    None
+                }
+                Err(e) => {
+                    warn!(
+                        "⚠️  {} ({}) was updated but doesn't resolve to {}: {}",
+                        record.name, record.record_type, ip, e
+                    );
+                    Some(e.to_string())
+                }
+            };
+            if let Some(position) = order.iter().position(|i| *i == index) {
+                let record_report = &mut report.records[position];
+                record_report.verified = Some(verification_error.is_none());
+                record_report.verification_error = verification_error;
+            }
+        }
+    }
+
+    // Report the records in the order they were configured
+    let mut results: Vec<_> = order
+        .into_iter()
+        .zip(std::mem::take(&mut report.records))
+        .collect();
+    results.sort_by_key(|(index, _)| *index);
+    report.records = results.into_iter().map(|(_, record)| record).collect();
+    report.count_records();
+
+    if let Some(path) = &config.history_path
+        && !config.dry_run
+        && let Err(e) = log_history(path, config, &report.records, &cached_ips)
+    {
+        warn!("⚠️  Failed to write update history: {}", e);
+    }
+
+    // Only rewrite the cache when at least one record (or zone) went to
+    // Cloudflare, when cache hits are being counted or when a record's
+    // failure count changed
+    let contacted_cloudflare = report
+        .records
+        .iter()
+        .any(|r| !matches!(r.status, RecordStatus::CacheHit | RecordStatus::Deferred))
+        || report.pruned > 0
+        || cache.zones.len() != known_zones;
+
+    // Dry runs leave the cache alone so the next real run isn't skipped
+    if (contacted_cloudflare
+        || config.reconcile_every.is_some()
+        || cache.failures != known_failures
+        || cache.health != known_health
+        || cache.flaps != known_flaps)
+        && !config.dry_run
+        && let Err(e) = save_cache(cache, &config.cache_path)
+    {
+        warn!("⚠️  Failed to save cache: {}", e);
+    }
+
+    if config.dry_run {
+        let changes = report
+            .records
+            .iter()
+            .filter(|r| r.status == RecordStatus::DryRun)
+            .count()
+            + report.pruned;
+        info!(
+            "🧪 Dry run complete: {} record(s) would be changed, nothing was updated",
+            changes
+        );
+    }
+
+    report.duration_ms = cycle_started.elapsed().as_millis() as u64;
+    report.log_summary();
+}
+
+/// Returns the configured records plus, when discovery is enabled, every A
+/// and AAAA record in the discovery zone whose comment or tags carry the marker.
+pub async fn managed_records(
+    provider: &dyn DnsProvider,
+    cache: &mut CacheFile,
+    config: &Config,
+) -> Result<Vec<RecordConfig>> {
+    let mut records = config.records.clone();
+
+    // Pruning deletes the marked records that aren't configured instead
+    let Some(discovery) = config.discovery.as_ref().filter(|d| !d.prune) else {
+        return Ok(records);
+    };
+
+    let zone_id = resolve_zone_id(provider, cache, config, &discovery.zone).await?;
+    let zone_records = retry(&config.retry, "Discovering records", || {
+        provider.get_records(&zone_id, None)
+    })
+    .await?;
+
+    let mut discovered = 0;
+    for zone_record in zone_records {
+        if is_marked(discovery, &zone_record) && !is_listed(&records, &zone_record) {
+            records.push(RecordConfig {
+                name: zone_record.name,
+                record_type: zone_record.record_type,
+                provider: Provider::Cloudflare,
+                zone: Some(discovery.zone.clone()),
+                proxied: None,
+                ttl: config.ttl,
+                ip_source: config.ip.source.clone(),
+                ip_sources: Vec::new(),
+                template: None,
+                failover: None,
+                cache_expiry_hours: config.cache_expiry_hours,
+                min_update_interval: config.min_update_interval,
+                ipv6_suffix: None,
+            });
+            discovered += 1;
+        }
+    }
+
+    info!(
+        "🔍 Discovered {} record(s) marked '{}'",
+        discovered, discovery.marker
+    );
+    Ok(records)
+}
+
+/// Whether a zone's record is an A or AAAA record carrying the marker in its
+/// comment or tags.
+fn is_marked(discovery: &Discovery, zone_record: &DnsRecord) -> bool {
+    let is_address = zone_record.record_type == "A" || zone_record.record_type == "AAAA";
+    let has_marker = zone_record
+        .comment
+        .as_deref()
+        .is_some_and(|comment| comment.contains(&discovery.marker))
+        || zone_record.tags.contains(&discovery.marker);
+    is_address && has_marker
+}
+
+/// Whether the name and type of a zone's record are among `records`.
+fn is_listed(records: &[RecordConfig], zone_record: &DnsRecord) -> bool {
+    records.iter().any(|r| {
+        r.name.eq_ignore_ascii_case(&zone_record.name) && r.record_type == zone_record.record_type
+    })
+}
+
+/// Deletes the marked records in the discovery zone that aren't among
+/// `records` when pruning is enabled, such as the AAAA records left behind
+/// after turning `DUAL_STACK` off. Returns how many were (or in a dry run,
+/// would be) deleted.
+async fn prune_records(
+    provider: &dyn DnsProvider,
+    cache: &mut CacheFile,
+    config: &Config,
+    records: &[RecordConfig],
+) -> Result<usize> {
+    let Some(discovery) = config.discovery.as_ref().filter(|d| d.prune) else {
+        return Ok(0);
+    };
+
+    let zone_id = resolve_zone_id(provider, cache, config, &discovery.zone).await?;
+    let zone_records = retry(&config.retry, "Listing records to prune", || {
+        provider.get_records(&zone_id, None)
+    })
+    .await?;
+
+    let mut pruned = 0;
+    for zone_record in zone_records
+        .iter()
+        .filter(|r| is_marked(discovery, r) && !is_listed(records, r))
+    {
+        if config.dry_run {
+            info!(
+                "🧪 Dry run: would delete unmanaged {} record '{}' ({})",
+                zone_record.record_type, zone_record.name, zone_record.content
+            );
+        } else {
+            info!(
+                "🗑️  Deleting unmanaged {} record '{}' ({})...",
+                zone_record.record_type, zone_record.name, zone_record.content
+            );
+            retry(&config.retry, "Deleting DNS record", || {
+                provider.delete_record(&zone_id, &zone_record.id)
+            })
+            .await?;
+            cache.remove(&zone_record.name, &zone_record.record_type);
+        }
+        pruned += 1;
+    }
+    Ok(pruned)
+}
+
+/// Ends the record's failure streak, sends the change notification if its
+/// address changed and runs the hooks.
+async fn notify_outcome(
+    dispatcher: &Dispatcher,
+    cache: &mut CacheFile,
+    config: &Config,
+    record: &RecordConfig,
+    outcome: &RecordOutcome,
+    new_ip: &str,
+    duration: Duration,
+) {
+    if !config.dry_run
+        && let Some(failures) = cache.clear_failures(&record.name, &record.record_type)
+    {
+        dispatcher
+            .recovered(&record.name, &record.record_type, failures)
+            .await;
+    }
+
+    let old_ip = match outcome {
+        RecordOutcome::Updated { old_ip } if old_ip != new_ip => Some(Some(old_ip.as_str())),
+        RecordOutcome::Created => Some(None),
+        _ => None,
+    };
+    if let Some(old_ip) = old_ip {
+        dispatcher
+            .ip_changed(&record.name, &record.record_type, old_ip, new_ip, duration)
+            .await;
+    }
+
+    if config.dry_run {
+        return;
+    }
+    if let Some(old_ip) = old_ip {
+        config
+            .hooks
+            .run(
+                &record.name,
+                &record.record_type,
+                HookEvent::Change { old_ip, new_ip },
+            )
+            .await;
+    }
+    config
+        .hooks
+        .run(
+            &record.name,
+            &record.record_type,
+            HookEvent::Success {
+                ip: new_ip,
+                changed: old_ip.is_some(),
+            },
+        )
+        .await;
+}
+
+/// Logs a record failure, sends the failure notification and runs the
+/// failure hook.
+async fn report_failure(
+    dispatcher: &Dispatcher,
+    cache: &mut CacheFile,
+    config: &Config,
+    record: &RecordConfig,
+    error: &str,
+) {
+    error!(
+        "❌ Failed to process {} ({}): {}",
+        record.name, record.record_type, error
+    );
+    if !config.dry_run {
+        let failures = cache.record_failure(&record.name, &record.record_type);
+        dispatcher
+            .update_failed(&record.name, &record.record_type, error, failures)
+            .await;
+        config
+            .hooks
+            .run(
+                &record.name,
+                &record.record_type,
+                HookEvent::Failure { error },
+            )
+            .await;
+    }
+}
+
+/// Resolves once SIGTERM or SIGINT (Ctrl+C) is received, or on Windows
+/// Ctrl+Break or the console window being closed.
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let mut sigterm =
+            signal(SignalKind::terminate()).expect("failed to install SIGTERM handler");
+        tokio::select! {
+            _ = sigterm.recv() => {}
+            _ = tokio::signal::ctrl_c() => {}
+        }
+    }
+
+    #[cfg(windows)]
+    {
+        use tokio::signal::windows::{ctrl_break, ctrl_close};
+
+        let mut ctrl_break = ctrl_break().expect("failed to install Ctrl+Break handler");
+        let mut ctrl_close = ctrl_close().expect("failed to install console close handler");
+        tokio::select! {
+            _ = ctrl_break.recv() => {}
+            _ = ctrl_close.recv() => {}
+            _ = tokio::signal::ctrl_c() => {}
+        }
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+/// Waits a random time of up to [`Config::start_jitter`] seconds.
+pub async fn start_jitter(config: &Config) {
+    if config.start_jitter == 0 {
+        return;
+    }
+    let delay = Duration::from_millis(fastrand::u64(0..=config.start_jitter * 1000));
+    info!(
+        "🎲 Waiting {:.1} seconds before the first check",
+        delay.as_secs_f64()
+    );
+    tokio::time::sleep(delay).await;
+}
+
+/// Keeps running update cycles every `sleep_interval` seconds (or longer with
+/// [`Config::max_sleep_interval`]) until `shutdown` resolves, usually
+/// [`shutdown_signal`]. A failed cycle is logged and retried on the next
+/// interval instead of stopping the daemon.
+///
+/// On Unix, SIGHUP calls `reload` for a new configuration and starts a
+/// cycle with it, as do edits of the config file with
+/// [`Config::watch_config`]. The cache, pushed addresses and failure counts
+/// carry over. The HTTP server, network and config file watching, the
+/// health settings, the leader lock and the cache path keep the settings the
+/// daemon started with.
+///
+/// With [`Config::leader_lock`], a cycle only runs while this daemon holds
+/// the lock, and a standby checks it again on the next interval.
+pub async fn run_daemon(
+    provider: &dyn DnsProvider,
+    dispatcher: &Dispatcher,
+    cache: &mut CacheFile,
+    config: &Config,
+    shutdown: impl Future<Output = ()>,
+    reload: impl Fn() -> Result<Config>,
+) -> Result<()> {
+    match config.max_sleep_interval {
+        Some(max) => info!(
+            "🔁 Running in daemon mode, checking every {} to {} seconds",
+            config.sleep_interval, max
+        ),
+        None => info!(
+            "🔁 Running in daemon mode, checking every {} seconds",
+            config.sleep_interval
+        ),
+    }
+
+    // Credentials that Cloudflare rejects won't work on the next cycle
+    // either, but a network problem at startup may well be over by then
+    match preflight::verify(provider, cache, config).await {
+        Ok(()) => {}
+        Err(e) if preflight::is_inconclusive(&e) => {
+            warn!(
+                "⚠️  Couldn't verify the credentials, continuing anyway: {}",
+                e.report()
+            );
+        }
+        Err(e) => return Err(e.context("Startup check failed")),
+    }
+
+    // The admin API changes records and intervals as the daemon runs
+    let mut config = Cow::Borrowed(config);
+    let state_path = state::state_path(&config);
+    let mut state = State::load(&state_path)?;
+
+    let health = Arc::new(
+        Health::new(config.health_max_age).with_file(HealthFile::path(&config.cache_path)),
+    );
+    let mut pushes = None;
+    let mut admin_calls = None;
+    let triggered = Arc::new(Notify::new());
+    if let Some(addr) = config.http_listen {
+        let dyndns = config.dyndns_server.clone().map(|login| {
+            let (requests, received) = mpsc::channel(16);
+            pushes = Some(received);
+            DynDnsServer { login, requests }
+        });
+        let trigger = config.trigger_token.clone().map(|token| TriggerEndpoint {
+            token,
+            cycle: triggered.clone(),
+        });
+        let admin = config.admin_token.clone().map(|token| {
+            let (requests, received) = mpsc::channel(16);
+            admin_calls = Some(received);
+            AdminApi { token, requests }
+        });
+        server::spawn(addr, health.clone(), dyndns, trigger, admin).await?;
+    }
+
+    // Under systemd with Type=notify, startup is complete once the health
+    // server is listening. The watchdog is fed from its own task so that a
+    // long sleep_interval doesn't trip it.
+    if notify_systemd("READY=1") {
+        info!("🐧 Notified systemd that the daemon is ready");
+    }
+    if let Some(watchdog) = systemd::watchdog_interval() {
+        info!("🐧 Pinging the systemd watchdog every {:?}", watchdog / 2);
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(watchdog / 2);
+            loop {
+                ticks.tick().await;
+                notify_systemd("WATCHDOG=1");
+            }
+        });
+    }
+
+    let mut watcher = if config.watch_network {
+        let watcher = NetworkWatcher::new(config.watch_interface.as_deref())?;
+        match &config.watch_interface {
+            Some(name) => info!("🔌 Checking right away when {} changes", name),
+            None => info!("🔌 Checking right away when the network changes"),
+        }
+        Some(watcher)
+    } else {
+        None
+    };
+
+    let mut config_watcher = match &config.config_file {
+        Some(path) if config.watch_config => {
+            let watcher = ConfigWatcher::new(path)?;
+            info!(
+                "👀 Reloading the configuration when {} changes",
+                path.display()
+            );
+            Some(watcher)
+        }
+        _ => None,
+    };
+
+    let mut leader = config.leader_lock.clone().map(|lock| {
+        info!("🗳️  Taking turns with other replicas through {}", lock);
+        Leader::new(lock, Duration::from_secs(config.leader_lease))
+    });
+
+    let mut interval = Duration::from_secs(config.sleep_interval);
+    tokio::pin!(shutdown);
+    let mut first_cycle = true;
+
+    // Addresses routers pushed through /nic/update, by record, which the
+    // regular cycles keep instead of detecting their own
+    let mut pushed = HashMap::new();
+
+    #[cfg(unix)]
+    let mut hangups = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+    // The provider and notifiers built from a reloaded configuration
+    let mut reloaded: Option<(Box<dyn DnsProvider>, Dispatcher)> = None;
+
+    'daemon: loop {
+        let (provider, dispatcher) = match &reloaded {
+            Some((provider, dispatcher)) => (&**provider, dispatcher),
+            None => (provider, dispatcher),
+        };
+        let cycle_config = with_pushed(&config, &pushed);
+        tokio::select! {
+            report = async {
+                if std::mem::take(&mut first_cycle) {
+                    start_jitter(&config).await;
+                }
+                if let Some(pause) = config.paused_by() {
+                    info!("⏸️  Updates are paused by {}, skipping this cycle", pause);
+                    let report = CycleReport::paused(&config, pause);
+                    report.log_summary();
+                    if config.json_output {
+                        print_json(&report);
+                    }
+                    health.record_cycle(true);
+                    return None;
+                }
+                match takes_turn(&mut leader, provider, cache, &config).await {
+                    Ok(true) => Some(run_cycle(provider, dispatcher, cache, &cycle_config).await),
+                    // A standby is healthy while it waits for its turn
+                    Ok(false) => {
+                        health.record_cycle(true);
+                        None
+                    }
+                    Err(e) => {
+                        warn!("⚠️  Couldn't check the leader lock, skipping this cycle: {}", e.report());
+                        health.record_cycle(false);
+                        None
+                    }
+                }
+            } => {
+                match report {
+                    Some(report) => {
+                        if config.json_output {
+                            print_json(&report);
+                        }
+                        interval = next_interval(&config, interval, &report);
+                        let result = report.result();
+                        health.record_cycle(result.is_ok());
+                        let now = chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC");
+                        match result {
+                            Ok(()) => {
+                                notify_systemd(&format!("STATUS=Last update succeeded at {}", now));
+                            }
+                            Err(e) => {
+                                error!("❌ Update cycle failed: {}", e);
+                                notify_systemd(&format!("STATUS=Last update failed at {}: {}", now, e));
+                            }
+                        }
+                    }
+                    None => interval = Duration::from_secs(config.sleep_interval),
+                }
+            }
+            _ = &mut shutdown => {
+                info!("🛑 Received shutdown signal, cancelling the update cycle in progress");
+                break;
+            }
+        }
+
+        info!("😴 Sleeping for {} seconds...", interval.as_secs());
+        let mut reload_requested = false;
+        let stop_watching = {
+            let network_change = async {
+                match &mut watcher {
+                    Some(watcher) => {
+                        watcher.changed().await?;
+                        info!(
+                            "🔌 Network changed, checking in {} seconds",
+                            NETWORK_SETTLE.as_secs()
+                        );
+                        tokio::time::sleep(NETWORK_SETTLE).await;
+                        watcher.discard_pending();
+                        Ok::<_, Error>(())
+                    }
+                    None => std::future::pending().await,
+                }
+            };
+            let mut network_change = std::pin::pin!(network_change);
+            let mut sleep = std::pin::pin!(tokio::time::sleep(interval));
+            // Pushed updates are handled while sleeping, without restarting
+            // the interval
+            loop {
+                let push = async {
+                    match &mut pushes {
+                        Some(pushes) => pushes.recv().await,
+                        None => std::future::pending().await,
+                    }
+                };
+                let admin_call = async {
+                    match &mut admin_calls {
+                        Some(calls) => calls.recv().await,
+                        None => std::future::pending().await,
+                    }
+                };
+                let config_change = async {
+                    match &mut config_watcher {
+                        Some(watcher) => watcher.changed().await,
+                        None => std::future::pending().await,
+                    }
+                };
+                let hangup = async {
+                    #[cfg(unix)]
+                    return hangups.recv().await;
+                    #[cfg(not(unix))]
+                    std::future::pending::<Option<()>>().await
+                };
+                tokio::select! {
+                    _ = &mut sleep => break false,
+                    _ = triggered.notified() => break false,
+                    Some(()) = hangup => {
+                        info!("🔃 Received SIGHUP, reloading the configuration");
+                        reload_requested = true;
+                        break false;
+                    }
+                    _ = config_change => {
+                        info!("🔃 The config file changed, reloading it");
+                        reload_requested = true;
+                        break false;
+                    }
+                    result = &mut network_change => {
+                        if let Err(e) = &result {
+                            warn!("⚠️  Stopped watching for network changes: {}", e);
+                        }
+                        break result.is_err();
+                    }
+                    Some(request) = push => {
+                        let paused = config.paused_by();
+                        if let Some(pause) = &paused {
+                            info!("⏸️  Updates are paused by {}, not applying pushed addresses", pause);
+                        }
+                        if paused.is_some() || leader.as_ref().is_some_and(|leader| !leader.is_leading()) {
+                            // The router tries again later, once updates resume or it
+                            // reaches the leader
+                            let answers = vec!["911".to_string(); request.hostnames.len()];
+                            let _ = request.reply.send(answers);
+                        } else {
+                            handle_push(provider, dispatcher, cache, &config, &mut pushed, request).await;
+                        }
+                    }
+                    Some(call) = admin_call => {
+                        let (reply, changed) =
+                            handle_admin(config.to_mut(), &mut state, &state_path, cache, call.command);
+                        // The API client may have given up waiting
+                        let _ = call.reply.send(reply);
+                        if changed {
+                            interval = Duration::from_secs(config.sleep_interval);
+                            break false;
+                        }
+                    }
+                    _ = &mut shutdown => {
+                        info!("🛑 Received shutdown signal");
+                        break 'daemon;
+                    }
+                }
+            }
+        };
+        if stop_watching {
+            watcher = None;
+        }
+
+        if reload_requested {
+            match reload().and_then(|new_config| {
+                let dispatcher = Dispatcher::from_config(&new_config)?;
+                Ok((new_config, dispatcher))
+            }) {
+                Ok((new_config, new_dispatcher)) => {
+                    let new_provider = crate::provider::from_config(&new_config);
+                    state = State::load(&state_path)?;
+                    interval = Duration::from_secs(new_config.sleep_interval);
+                    config = Cow::Owned(new_config);
+                    reloaded = Some((new_provider, new_dispatcher));
+                    info!(
+                        "✅ Configuration reloaded, {} records",
+                        config.records.len()
+                    );
+                }
+                Err(e) => error!(
+                    "❌ Failed to reload the configuration, keeping the current one: {}",
+                    e.report()
+                ),
+            }
+        }
+    }
+
+    let (provider, dispatcher) = match &reloaded {
+        Some((provider, dispatcher)) => (&**provider, dispatcher),
+        None => (provider, dispatcher),
+    };
+
+    notify_systemd("STOPPING=1");
+
+    // A cancelled cycle may have changed the cache without saving it. A
+    // standby's cache is out of date, the leader's is the one to keep
+    let leading = leader.as_ref().is_none_or(Leader::is_leading);
+    if !config.dry_run
+        && leading
+        && let Err(e) = save_cache(cache, &config.cache_path)
+    {
+        warn!("⚠️  Failed to save cache: {}", e);
+    }
+
+    // A standby can take over right away instead of when the lease ends
+    if let Some(leader) = &mut leader
+        && leading
+    {
+        match tokio::time::timeout(
+            SHUTDOWN_NOTIFY_TIMEOUT,
+            leader.release(provider, cache, &config),
+        )
+        .await
+        {
+            Ok(Ok(())) => info!("👑 Released the leader lock {}", leader.lock()),
+            Ok(Err(e)) => warn!("⚠️  Failed to release the leader lock: {}", e.report()),
+            Err(_) => warn!("⚠️  Releasing the leader lock timed out"),
+        }
+    }
+
+    // docker stop only waits 10 seconds before killing the container
+    if tokio::time::timeout(SHUTDOWN_NOTIFY_TIMEOUT, dispatcher.shutting_down())
+        .await
+        .is_err()
+    {
+        warn!(
+            "⚠️  Shutdown notifications timed out after {} seconds",
+            SHUTDOWN_NOTIFY_TIMEOUT.as_secs()
+        );
+    }
+
+    info!("👋 Daemon stopped");
+    Ok(())
+}
+
+/// Whether this replica runs the cycle: always without a leader lock, and
+/// with one while it holds the lock.
+async fn takes_turn(
+    leader: &mut Option<Leader>,
+    provider: &dyn DnsProvider,
+    cache: &mut CacheFile,
+    config: &Config,
+) -> Result<bool> {
+    let Some(leader) = leader else {
+        return Ok(true);
+    };
+    let was_leading = leader.is_leading();
+    match leader.check(provider, cache, config).await? {
+        Role::Leader => {
+            if !was_leading {
+                info!("👑 Took the leader lock {}", leader.lock());
+                // The previous leader may have changed a shared cache
+                *cache = load_cache(&config.cache_path);
+            }
+            Ok(true)
+        }
+        Role::Standby(holder) => {
+            match holder {
+                Some(holder) => info!("⏸️  {} holds the leader lock, standing by", holder),
+                None => info!(
+                    "⏸️  Another replica holds the leader lock {}, standing by",
+                    leader.lock()
+                ),
+            }
+            Ok(false)
+        }
+    }
+}
+
+/// Updates the records a router pushed addresses for through `/nic/update`,
+/// and answers with one DynDNS2 status per hostname.
+async fn handle_push(
+    provider: &dyn DnsProvider,
+    dispatcher: &Dispatcher,
+    cache: &mut CacheFile,
+    config: &Config,
+    pushed: &mut HashMap<(String, String), IpAddr>,
+    request: PushRequest,
+) {
+    let mut answers = Vec::with_capacity(request.hostnames.len());
+    for hostname in &request.hostnames {
+        // Records whose content doesn't come from a single address can't
+        // take a pushed one
+        let mut records = Vec::new();
+        let mut addresses = Vec::new();
+        for record in &config.records {
+            if !record.name.eq_ignore_ascii_case(hostname)
+                || record.template.is_some()
+                || !record.ip_sources.is_empty()
+            {
+                continue;
+            }
+            let version = IpVersion::for_record_type(&record.record_type);
+            let Some(address) = request.addresses.iter().find(|ip| version.matches(**ip)) else {
+                continue;
+            };
+            records.push(pushed_record(record, *address));
+            pushed.insert((record.name.clone(), record.record_type.clone()), *address);
+            if !addresses.contains(&address.to_string()) {
+                addresses.push(address.to_string());
+            }
+        }
+        if records.is_empty() {
+            warn!(
+                "⚠️  Got a DynDNS2 update for {}, which has no matching record",
+                hostname
+            );
+            answers.push("nohost".to_string());
+            continue;
+        }
+
+        let addresses = addresses.join(",");
+        info!("📮 Got a DynDNS2 update for {}: {}", hostname, addresses);
+        let push = Config {
+            records,
+            discovery: None,
+            ..config.clone()
+        };
+        let report = run_cycle(provider, dispatcher, cache, &push).await;
+        if config.json_output {
+            print_json(&report);
+        }
+        answers.push(if report.failed > 0 {
+            "911".to_string()
+        } else if report.changed > 0 {
+            format!("good {}", addresses)
+        } else {
+            format!("nochg {}", addresses)
+        });
+    }
+    // The router may have given up waiting
+    let _ = request.reply.send(answers);
+}
+
+/// Answers a call of the admin API, saving what it changes to the state
+/// file before applying it to `config`. Also returns whether the records
+/// or intervals changed.
+fn handle_admin(
+    config: &mut Config,
+    state: &mut State,
+    state_path: &Path,
+    cache: &mut CacheFile,
+    command: AdminCommand,
+) -> (AdminReply, bool) {
+    let mut next = state.clone();
+    match command {
+        AdminCommand::ListRecords => {
+            let records: Vec<serde_json::Value> = config
+                .records
+                .iter()
+                .map(|record| {
+                    let id = RecordId::of(record);
+                    serde_json::json!({
+                        "name": record.name,
+                        "type": record.record_type,
+                        "provider": record.provider.to_string(),
+                        "zone": record.zone.as_ref().map(ZoneRef::to_string),
+                        "proxied": record.proxied,
+                        "ttl": match record.ttl {
+                            Ttl::Preserve => serde_json::json!("preserve"),
+                            Ttl::Auto => serde_json::json!("auto"),
+                            Ttl::Seconds(seconds) => serde_json::json!(seconds),
+                        },
+                        "ip_source": record.ip_source.to_string(),
+                        "added": state.added.iter().any(|added| id.matches_added(added)),
+                    })
+                })
+                .collect();
+            (AdminReply::Ok(serde_json::json!(records)), false)
+        }
+        AdminCommand::Settings => {
+            let settings = Settings {
+                sleep_interval: Some(config.sleep_interval),
+                max_sleep_interval: config.max_sleep_interval,
+            };
+            (AdminReply::Ok(serde_json::json!(settings)), false)
+        }
+        AdminCommand::AddRecord(added) => {
+            let record = match added.to_record(config) {
+                Ok(record) => record,
+                Err(e) => return (AdminReply::Invalid(e.to_string()), false),
+            };
+            let id = RecordId::of(&record);
+            if config.records.iter().any(|r| RecordId::of(r) == id) {
+                let message = format!(
+                    "{} ({}) is already managed",
+                    record.name, record.record_type
+                );
+                return (AdminReply::Conflict(message), false);
+            }
+            next.removed.retain(|removed| *removed != id);
+            next.added.retain(|other| !id.matches_added(other));
+            next.added.push(added);
+            if let Err(e) = next.save(state_path) {
+                return (AdminReply::Failed(e.to_string()), false);
+            }
+            info!(
+                "🛠️  Added {} ({}) through the admin API",
+                record.name, record.record_type
+            );
+            let body = serde_json::json!({ "name": record.name, "type": record.record_type });
+            config.records.push(record);
+            *state = next;
+            (AdminReply::Created(body), true)
+        }
+        AdminCommand::RemoveRecord(id) => {
+            let Some(record) = config.records.iter().find(|r| RecordId::of(r) == id) else {
+                let message = format!("{} ({}) isn't managed", id.name, id.record_type);
+                return (AdminReply::NotFound(message), false);
+            };
+            let (name, record_type) = (record.name.clone(), record.record_type.clone());
+            // Listed as removed even if it was added through the API, in case
+            // it replaced a configured record
+            next.added.retain(|added| !id.matches_added(added));
+            if !next.removed.contains(&id) {
+                next.removed.push(id.clone());
+            }
+            if let Err(e) = next.save(state_path) {
+                return (AdminReply::Failed(e.to_string()), false);
+            }
+            info!(
+                "🛠️  Removed {} ({}) through the admin API",
+                name, record_type
+            );
+            config.records.retain(|r| RecordId::of(r) != id);
+            cache.remove(&name, &record_type);
+            *state = next;
+            (AdminReply::Removed, true)
+        }
+        AdminCommand::UpdateSettings(settings) => {
+            let sleep_interval = settings.sleep_interval.unwrap_or(config.sleep_interval);
+            let max_sleep_interval = match settings.max_sleep_interval {
+                Some(0) => None,
+                Some(max) => Some(max),
+                None => config.max_sleep_interval,
+            };
+            if sleep_interval == 0 {
+                let message = "sleep_interval must be at least 1 second".to_string();
+                return (AdminReply::Invalid(message), false);
+            }
+            if max_sleep_interval.is_some_and(|max| max < sleep_interval) {
+                let message = "max_sleep_interval can't be shorter than sleep_interval".to_string();
+                return (AdminReply::Invalid(message), false);
+            }
+            if settings.sleep_interval.is_some() {
+                next.sleep_interval = settings.sleep_interval;
+            }
+            if settings.max_sleep_interval.is_some() {
+                next.max_sleep_interval = settings.max_sleep_interval;
+            }
+            if let Err(e) = next.save(state_path) {
+                return (AdminReply::Failed(e.to_string()), false);
+            }
+            match max_sleep_interval {
+                Some(max) => info!(
+                    "🛠️  Now checking every {} to {} seconds, as set through the admin API",
+                    sleep_interval, max
+                ),
+                None => info!(
+                    "🛠️  Now checking every {} seconds, as set through the admin API",
+                    sleep_interval
+                ),
+            }
+            config.sleep_interval = sleep_interval;
+            config.max_sleep_interval = max_sleep_interval;
+            *state = next;
+            let settings = Settings {
+                sleep_interval: Some(sleep_interval),
+                max_sleep_interval,
+            };
+            (AdminReply::Ok(serde_json::json!(settings)), true)
+        }
+    }
+}
+
+/// `record` kept at an address a router pushed.
+fn pushed_record(record: &RecordConfig, address: IpAddr) -> RecordConfig {
+    RecordConfig {
+        ip_source: IpSource::Static(vec![address]),
+        ipv6_suffix: None,
+        failover: None,
+        ..record.clone()
+    }
+}
+
+/// The configuration with the records routers pushed addresses for kept at
+/// those addresses.
+fn with_pushed<'a>(
+    config: &'a Config,
+    pushed: &HashMap<(String, String), IpAddr>,
+) -> Cow<'a, Config> {
+    if pushed.is_empty() {
+        return Cow::Borrowed(config);
+    }
+    let records = config
+        .records
+        .iter()
+        .map(
+            |record| match pushed.get(&(record.name.clone(), record.record_type.clone())) {
+                Some(address) => pushed_record(record, *address),
+                None => record.clone(),
+            },
+        )
+        .collect();
+    Cow::Owned(Config {
+        records,
+        ..config.clone()
+    })
+}
+
+/// The wait before the next cycle. With `max_sleep_interval` set, a cycle
+/// that changed nothing and had no failures doubles the wait, up to the
+/// maximum, while any other goes back to `sleep_interval`.
+fn next_interval(config: &Config, current: Duration, report: &CycleReport) -> Duration {
+    let shortest = Duration::from_secs(config.sleep_interval);
+    match config.max_sleep_interval {
+        // A deferred change is due before long at the usual interval
+        Some(max) if report.changed == 0 && report.deferred == 0 && report.result().is_ok() => {
+            (current * 2).clamp(shortest, Duration::from_secs(max))
+        }
+        _ => shortest,
+    }
+}
+
+/// Prints a cycle report as a single line of JSON on stdout.
+pub fn print_json(report: &CycleReport) {
+    match serde_json::to_string(report) {
+        Ok(json) => println!("{}", json),
+        Err(e) => error!("❌ Failed to serialize the cycle report: {}", e),
+    }
+}
+
+/// Sends a state update to systemd, returning whether it was sent. Failing to
+/// reach systemd never stops the daemon.
+fn notify_systemd(state: &str) -> bool {
+    match systemd::notify(state) {
+        Ok(sent) => sent,
+        Err(e) => {
+            warn!("⚠️  Failed to notify systemd: {}", e);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    const BASE: &str = r#"
+api_token = "token"
+zone_id = "zone"
+"#;
+
+    fn config(settings: &str) -> Config {
+        Config::from_toml(&format!(
+            "{}{}\n[[records]]\nname = \"home.example.com\"\n",
+            BASE, settings
+        ))
+    }
+
+    /// A cache in which the record moved from `previous_ip` to `ip` just now.
+    fn changed_cache(previous_ip: &str, ip: &str) -> CacheFile {
+        let mut cache = CacheFile::default();
+        cache
+            .insert(DnsCache::new(
+                "home.example.com".to_string(),
+                "A".to_string(),
+                previous_ip.to_string(),
+            ))
+            .update_ip(ip.to_string());
+        cache
+    }
+
+    #[test]
+    fn holds_a_flap_back_until_it_stays_detected() {
+        let config = config("flap_window = 600\nflap_confirmation = 300");
+        let record = &config.records[0];
+        let mut cache = changed_cache("1.1.1.1", "8.8.8.8");
+
+        assert!(flap_held(&mut cache, &config, record, "1.1.1.1"));
+        assert!(flap_held(&mut cache, &config, record, "1.1.1.1"));
+
+        let key = record_key("home.example.com", "A");
+        cache.flaps.get_mut(&key).unwrap().since -= chrono::Duration::seconds(300);
+        assert!(!flap_held(&mut cache, &config, record, "1.1.1.1"));
+        assert!(cache.flaps.is_empty());
+    }
+
+    #[test]
+    fn lets_other_addresses_through_a_flap_hold() {
+        let config = config("flap_window = 600\nflap_confirmation = 300");
+        let record = &config.records[0];
+        let mut cache = changed_cache("1.1.1.1", "8.8.8.8");
+
+        assert!(flap_held(&mut cache, &config, record, "1.1.1.1"));
+        assert!(!flap_held(&mut cache, &config, record, "9.9.9.9"));
+        assert!(cache.flaps.is_empty());
+    }
+
+    #[test]
+    fn doesnt_hold_flaps_without_a_confirmation() {
+        let config = config("flap_window = 600\nflap_confirmation = 0");
+        let mut cache = changed_cache("1.1.1.1", "8.8.8.8");
+
+        assert!(!flap_held(
+            &mut cache,
+            &config,
+            &config.records[0],
+            "1.1.1.1"
+        ));
+        assert!(cache.flaps.is_empty());
+    }
+
+    #[test]
+    fn defers_changes_within_the_min_update_interval() {
+        let config = config("min_update_interval = 600");
+        let record = &config.records[0];
+        let mut cache = changed_cache("1.1.1.1", "8.8.8.8");
+        assert_eq!(deferred_until(&cache, &config, record, "9.9.9.9"), None);
+
+        let cached_data = cache.find_mut("home.example.com", "A").unwrap();
+        cached_data.update_written();
+        let written = cached_data.last_written.unwrap();
+        assert_eq!(
+            deferred_until(&cache, &config, record, "9.9.9.9"),
+            Some(written + chrono::Duration::seconds(600))
+        );
+        assert_eq!(deferred_until(&cache, &config, record, "8.8.8.8"), None);
+
+        let forced = Config {
+            force: true,
+            ..config.clone()
+        };
+        assert_eq!(deferred_until(&cache, &forced, record, "9.9.9.9"), None);
+    }
+
+    #[tokio::test]
+    async fn refuses_changes_the_confirming_source_disagrees_with() {
+        let config = config("confirm_ip_source = \"static:1.1.1.1\"");
+        let record = &config.records[0];
+        let cache = changed_cache("9.9.9.9", "8.8.8.8");
+
+        let mut confirmations = HashMap::new();
+        confirm_change(
+            &cache,
+            &config,
+            record,
+            "1.1.1.1",
+            "1.1.1.1",
+            &mut confirmations,
+        )
+        .await
+        .unwrap();
+        let error = confirm_change(
+            &cache,
+            &config,
+            record,
+            "8.8.4.4",
+            "8.8.4.4",
+            &mut confirmations,
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(error, Error::IpDetection(_)), "{:?}", error);
+        // Records keeping their address aren't checked
+        confirm_change(
+            &cache,
+            &config,
+            record,
+            "8.8.8.8",
+            "8.8.8.8",
+            &mut confirmations,
+        )
+        .await
+        .unwrap();
+    }
+
+    #[test]
+    fn backs_off_while_nothing_changes() {
+        let config = config("sleep_interval = 60\nmax_sleep_interval = 200");
+        let quiet = CycleReport::default();
+        let minute = Duration::from_secs(60);
+
+        assert_eq!(next_interval(&config, minute, &quiet), minute * 2);
+        assert_eq!(
+            next_interval(&config, minute * 3, &quiet),
+            Duration::from_secs(200)
+        );
+        for busy in [
+            CycleReport {
+                changed: 1,
+                ..CycleReport::default()
+            },
+            CycleReport {
+                deferred: 1,
+                ..CycleReport::default()
+            },
+            CycleReport {
+                checked: 1,
+                failed: 1,
+                ..CycleReport::default()
+            },
+        ] {
+            assert_eq!(next_interval(&config, minute * 3, &busy), minute);
+        }
+    }
+
+    /// A zone with the records of [`zone_records`] that notes which ones get
+    /// deleted.
+    #[derive(Default)]
+    struct Zone {
+        deleted: Mutex<Vec<String>>,
+    }
+
+    #[async_trait::async_trait]
+    impl DnsProvider for Zone {
+        async fn get_zone_id(&self, _zone_name: &str) -> Result<String> {
+            Ok("zone".to_string())
+        }
+
+        async fn get_records(
+            &self,
+            _zone_id: &str,
+            _record_name: Option<&str>,
+        ) -> Result<Vec<DnsRecord>> {
+            Ok(zone_records())
+        }
+
+        async fn update_record(
+            &self,
+            _zone_id: &str,
+            _record_id: &str,
+            _record: &UpdateDnsRecord,
+        ) -> Result<()> {
+            unreachable!("pruning doesn't update records")
+        }
+
+        async fn create_record(
+            &self,
+            _zone_id: &str,
+            _record: UpdateDnsRecord,
+        ) -> Result<DnsRecord> {
+            unreachable!("pruning doesn't create records")
+        }
+
+        async fn delete_record(&self, _zone_id: &str, record_id: &str) -> Result<()> {
+            self.deleted.lock().unwrap().push(record_id.to_string());
+            Ok(())
+        }
+    }
+
+    fn zone_records() -> Vec<DnsRecord> {
+        vec![
+            zone_record("configured", "home.example.com", "A", Some("ddns")),
+            zone_record("stale", "old.example.com", "AAAA", Some("ddns")),
+            zone_record("unmarked", "www.example.com", "A", None),
+            zone_record("text", "old.example.com", "TXT", Some("ddns")),
+        ]
+    }
+
+    fn zone_record(id: &str, name: &str, record_type: &str, comment: Option<&str>) -> DnsRecord {
+        DnsRecord {
+            id: id.to_string(),
+            name: name.to_string(),
+            content: "1.1.1.1".to_string(),
+            record_type: record_type.to_string(),
+            ttl: 300,
+            proxied: false,
+            comment: comment.map(str::to_string),
+            tags: Vec::new(),
+            proxiable: true,
+            locked: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn prunes_only_marked_address_records_that_arent_configured() {
+        let config = config("discovery_marker = \"ddns\"\nprune_records = true");
+        let zone = Zone::default();
+        let mut cache = changed_cache("1.1.1.1", "8.8.8.8");
+
+        let dry_run = Config {
+            dry_run: true,
+            ..config.clone()
+        };
+        let pruned = prune_records(&zone, &mut cache, &dry_run, &config.records)
+            .await
+            .unwrap();
+        assert_eq!(pruned, 1);
+        assert!(zone.deleted.lock().unwrap().is_empty());
+
+        let pruned = prune_records(&zone, &mut cache, &config, &config.records)
+            .await
+            .unwrap();
+        assert_eq!(pruned, 1);
+        assert_eq!(*zone.deleted.lock().unwrap(), ["stale"]);
+        assert!(cache.find("home.example.com", "A").is_some());
+    }
+}
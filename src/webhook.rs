@@ -0,0 +1,82 @@
+use crate::error::Result;
+use crate::notify::{Event, EventKind, Notifier};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use reqwest::Client;
+use serde::Serialize;
+
+/// JSON body posted to the webhook.
+#[derive(Debug, Serialize)]
+struct WebhookPayload<'a> {
+    /// `ip_changed` after a successful update or create, `update_failed`
+    /// when a record fails, `recovered` when it succeeds again, and
+    /// `shutting_down` when the daemon stops.
+    event: &'static str,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    record_name: &'a str,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    record_type: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    old_ip: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    new_ip: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+    /// Consecutive failures, for `update_failed` and `recovered`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    failures: Option<u32>,
+    timestamp: DateTime<Utc>,
+}
+
+/// Posts a JSON notification to a user-configured URL for every event.
+pub struct Webhook {
+    client: Client,
+    url: String,
+}
+
+impl Webhook {
+    pub fn new(client: Client, url: String) -> Self {
+        Self { client, url }
+    }
+}
+
+#[async_trait]
+impl Notifier for Webhook {
+    fn name(&self) -> &'static str {
+        "Webhook"
+    }
+
+    async fn notify(&self, event: &Event<'_>) -> Result<()> {
+        let mut payload = WebhookPayload {
+            event: event.kind.name(),
+            record_name: event.record_name,
+            record_type: event.record_type,
+            old_ip: None,
+            new_ip: None,
+            error: None,
+            failures: None,
+            timestamp: event.timestamp,
+        };
+        match event.kind {
+            EventKind::IpChanged { old_ip, new_ip, .. } => {
+                payload.old_ip = old_ip;
+                payload.new_ip = Some(new_ip);
+            }
+            EventKind::UpdateFailed { error, failures } => {
+                payload.error = Some(error);
+                payload.failures = Some(failures);
+            }
+            EventKind::Recovered { failures } => payload.failures = Some(failures),
+            EventKind::ShuttingDown => {}
+        }
+
+        self.client
+            .post(&self.url)
+            .json(&payload)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
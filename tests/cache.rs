@@ -0,0 +1,36 @@
+//! The cache of records and their streaks.
+
+use cloudflare_ddns::cache::{CacheFile, DnsCache, atomic_write, record_key};
+use std::fs;
+
+#[test]
+fn removing_a_record_forgets_its_streaks() {
+    let mut cache = CacheFile::default();
+    cache.insert(DnsCache::new(
+        "home.example.com".to_string(),
+        "A".to_string(),
+        "198.51.100.10".to_string(),
+    ));
+    let key = record_key("home.example.com", "A");
+    cache.failures.insert(key.clone(), 2);
+    cache.health.insert(key.clone(), 2);
+
+    cache.remove("home.example.com", "A");
+
+    assert!(cache.find("home.example.com", "A").is_none());
+    assert!(cache.failures.is_empty());
+    assert!(cache.health.is_empty());
+}
+
+#[test]
+fn writes_files_whole() {
+    let dir = std::env::temp_dir().join(format!("cloudflare-ddns-atomic-{}", std::process::id()));
+    let path = dir.join("health.json");
+
+    atomic_write(&path, b"first").unwrap();
+    atomic_write(&path, b"second").unwrap();
+
+    assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    fs::remove_dir_all(&dir).unwrap();
+}
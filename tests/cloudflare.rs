@@ -0,0 +1,363 @@
+//! The Cloudflare client against a mock of the API.
+
+use cloudflare_ddns::cloudflare::{ApiErrorKind, Auth, CloudflareClient};
+use cloudflare_ddns::error::Error;
+use cloudflare_ddns::provider::UpdateDnsRecord;
+use serde_json::{Value, json};
+use wiremock::matchers::{body_json, header, method, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const ZONE_ID: &str = "023e105f4ecef8ad9ca31a8372d0c353";
+
+fn client(server: &MockServer) -> CloudflareClient {
+    CloudflareClient::new("test-token".to_string()).with_base_url(&server.uri())
+}
+
+fn success(result: Value) -> Value {
+    json!({ "success": true, "errors": [], "messages": [], "result": result })
+}
+
+fn failure(code: u32, message: &str) -> Value {
+    json!({
+        "success": false,
+        "errors": [{ "code": code, "message": message }],
+        "messages": [],
+        "result": null,
+    })
+}
+
+fn record(id: &str, name: &str, content: &str) -> Value {
+    json!({
+        "id": id,
+        "name": name,
+        "type": "A",
+        "content": content,
+        "ttl": 1,
+        "proxied": false,
+    })
+}
+
+#[tokio::test]
+async fn lists_records_by_name() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path(format!("/zones/{}/dns_records", ZONE_ID)))
+        .and(query_param("name", "home.example.com"))
+        .and(header("Authorization", "Bearer test-token"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(success(json!([record(
+                "372e67954025e0ba6aaa6d586b9e0b59",
+                "home.example.com",
+                "198.51.100.4"
+            )]))),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let records = client(&server)
+        .get_dns_records(ZONE_ID, Some("home.example.com"))
+        .await
+        .unwrap();
+
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].id, "372e67954025e0ba6aaa6d586b9e0b59");
+    assert_eq!(records[0].content, "198.51.100.4");
+    assert_eq!(records[0].record_type, "A");
+}
+
+#[tokio::test]
+async fn follows_pagination() {
+    let server = MockServer::start().await;
+    for page in 1..=2 {
+        let mut body = success(json!([record(
+            &format!("id-{}", page),
+            &format!("host{}.example.com", page),
+            "198.51.100.4"
+        )]));
+        body["result_info"] = json!({ "page": page, "total_pages": 2 });
+        Mock::given(method("GET"))
+            .and(path(format!("/zones/{}/dns_records", ZONE_ID)))
+            .and(query_param("page", page.to_string()))
+            .respond_with(ResponseTemplate::new(200).set_body_json(body))
+            .expect(1)
+            .mount(&server)
+            .await;
+    }
+
+    let records = client(&server)
+        .with_per_page(1)
+        .get_dns_records(ZONE_ID, None)
+        .await
+        .unwrap();
+
+    let ids: Vec<&str> = records.iter().map(|record| record.id.as_str()).collect();
+    assert_eq!(ids, ["id-1", "id-2"]);
+}
+
+#[tokio::test]
+async fn updates_a_record() {
+    let server = MockServer::start().await;
+    Mock::given(method("PUT"))
+        .and(path(format!("/zones/{}/dns_records/record-id", ZONE_ID)))
+        .and(body_json(json!({
+            "type": "A",
+            "name": "home.example.com",
+            "content": "203.0.113.7",
+            "ttl": 300,
+            "proxied": true,
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(success(record(
+            "record-id",
+            "home.example.com",
+            "203.0.113.7",
+        ))))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let update = UpdateDnsRecord {
+        record_type: "A".to_string(),
+        name: "home.example.com".to_string(),
+        content: "203.0.113.7".to_string(),
+        ttl: 300,
+        proxied: Some(true),
+        comment: None,
+        tags: Vec::new(),
+    };
+    client(&server)
+        .update_dns_record(ZONE_ID, "record-id", &update)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn deletes_a_record() {
+    let server = MockServer::start().await;
+    Mock::given(method("DELETE"))
+        .and(path(format!("/zones/{}/dns_records/record-id", ZONE_ID)))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(success(json!({ "id": "record-id" }))),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    client(&server)
+        .delete_dns_record(ZONE_ID, "record-id")
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn looks_up_zone_ids() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/zones"))
+        .and(query_param("name", "example.com"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(success(json!([{ "id": ZONE_ID, "name": "example.com" }]))),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/zones"))
+        .and(query_param("name", "example.org"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(success(json!([]))))
+        .mount(&server)
+        .await;
+
+    let client = client(&server);
+    assert_eq!(client.get_zone_id("example.com").await.unwrap(), ZONE_ID);
+
+    let error = client.get_zone_id("example.org").await.unwrap_err();
+    assert!(matches!(error, Error::Provider(_)), "{:?}", error);
+    assert_eq!(error.to_string(), "No zone found with name 'example.org'");
+}
+
+#[tokio::test]
+async fn sends_global_api_keys_in_headers() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/user"))
+        .and(header("X-Auth-Key", "global-key"))
+        .and(header("X-Auth-Email", "me@example.com"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(success(json!({ "email": "me@example.com" }))),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let client = CloudflareClient::with_auth(Auth::GlobalKey {
+        key: "global-key".to_string(),
+        email: "me@example.com".to_string(),
+    })
+    .with_base_url(&format!("{}/", server.uri()));
+
+    assert_eq!(client.get_user_email().await.unwrap(), "me@example.com");
+}
+
+#[tokio::test]
+async fn reports_api_errors_with_their_code() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/user/tokens/verify"))
+        .respond_with(ResponseTemplate::new(400).set_body_json(failure(1000, "Invalid API Token")))
+        .mount(&server)
+        .await;
+
+    let error = client(&server).verify_token().await.unwrap_err();
+
+    let Error::CloudflareApi(api_error) = &error else {
+        panic!("expected an API error, got {:?}", error);
+    };
+    assert_eq!(api_error.code(), Some(1000));
+    assert_eq!(api_error.kind(), ApiErrorKind::InvalidCredentials);
+    assert!(
+        error
+            .to_string()
+            .starts_with("API token was rejected: Code 1000: Invalid API Token. "),
+        "{}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn recognizes_missing_records() {
+    let server = MockServer::start().await;
+    Mock::given(method("PUT"))
+        .respond_with(ResponseTemplate::new(404).set_body_json(failure(81044, "Record not found")))
+        .mount(&server)
+        .await;
+
+    let update = UpdateDnsRecord {
+        record_type: "A".to_string(),
+        name: "gone.example.com".to_string(),
+        content: "203.0.113.7".to_string(),
+        ttl: 1,
+        proxied: None,
+        comment: None,
+        tags: Vec::new(),
+    };
+    let error = client(&server)
+        .update_dns_record(ZONE_ID, "gone", &update)
+        .await
+        .unwrap_err();
+
+    match error {
+        Error::CloudflareApi(api_error) => {
+            assert_eq!(api_error.kind(), ApiErrorKind::RecordNotFound)
+        }
+        other => panic!("expected an API error, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn explains_ttls_the_plan_does_not_allow() {
+    let server = MockServer::start().await;
+    Mock::given(method("PUT"))
+        .respond_with(ResponseTemplate::new(400).set_body_json(failure(
+            9021,
+            "Invalid TTL. Must be between 60 and 86400 seconds, or 1 for Automatic.",
+        )))
+        .mount(&server)
+        .await;
+
+    let update = UpdateDnsRecord {
+        record_type: "A".to_string(),
+        name: "home.example.com".to_string(),
+        content: "203.0.113.7".to_string(),
+        ttl: 30,
+        proxied: None,
+        comment: None,
+        tags: Vec::new(),
+    };
+    let error = client(&server)
+        .update_dns_record(ZONE_ID, "record-id", &update)
+        .await
+        .unwrap_err();
+
+    let Error::CloudflareApi(api_error) = &error else {
+        panic!("expected an API error, got {:?}", error);
+    };
+    assert_eq!(api_error.kind(), ApiErrorKind::InvalidTtl);
+    assert!(
+        error.to_string().contains("use at least 60 seconds"),
+        "{}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn surfaces_server_errors_as_http_errors() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(502).set_body_string("Bad Gateway"))
+        .mount(&server)
+        .await;
+
+    let error = client(&server)
+        .get_dns_records(ZONE_ID, None)
+        .await
+        .unwrap_err();
+
+    match error.root() {
+        Error::Http(e) => assert_eq!(e.status().map(|status| status.as_u16()), Some(502)),
+        other => panic!("expected an HTTP error, got {:?}", other),
+    }
+    assert!(cloudflare_ddns::retry::is_transient(&error));
+}
+
+#[tokio::test]
+async fn gives_up_when_rate_limited() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "0"))
+        .expect(4)
+        .mount(&server)
+        .await;
+
+    let error = client(&server).list_zones().await.unwrap_err();
+
+    assert!(matches!(error, Error::RateLimited(_)), "{:?}", error);
+}
+
+#[tokio::test]
+async fn rejects_malformed_responses() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path(format!("/zones/{}/dns_records", ZONE_ID)))
+        .respond_with(ResponseTemplate::new(200).set_body_string("<html>Not the API</html>"))
+        .mount(&server)
+        .await;
+
+    let error = client(&server)
+        .get_dns_records(ZONE_ID, None)
+        .await
+        .unwrap_err();
+
+    match error {
+        Error::Http(e) => assert!(e.is_decode(), "{:?}", e),
+        other => panic!("expected a decoding error, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn rejects_successful_responses_without_a_result() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path(format!("/zones/{}/dns_records", ZONE_ID)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(success(Value::Null)))
+        .mount(&server)
+        .await;
+
+    let error = client(&server)
+        .get_dns_records(ZONE_ID, None)
+        .await
+        .unwrap_err();
+
+    assert!(matches!(error, Error::Provider(_)), "{:?}", error);
+    assert_eq!(error.to_string(), "No result in response");
+}
@@ -0,0 +1,18 @@
+//! Fixtures shared by the test binaries.
+
+use cloudflare_ddns::config::Config;
+use std::fs;
+
+/// Loads a configuration from `toml`, without the environment of the shell
+/// the tests run in. `name` keeps the files of concurrent tests apart.
+pub fn load(name: &str, toml: &str) -> Config {
+    let path = std::env::temp_dir().join(format!(
+        "cloudflare-ddns-{}-{}.toml",
+        name,
+        std::process::id()
+    ));
+    fs::write(&path, toml).unwrap();
+    let config = Config::load_isolated(&path);
+    fs::remove_file(&path).unwrap();
+    config.unwrap()
+}
@@ -0,0 +1,102 @@
+//! Loading and checking the configuration.
+
+mod common;
+
+use common::load;
+
+#[test]
+fn accepts_templated_records_of_other_types() {
+    let config = load(
+        "template",
+        r#"
+api_token = "token"
+zone_id = "zone"
+
+[[records]]
+name = "_ip.example.com"
+type = "TXT"
+template = "ip={ip}"
+"#,
+    );
+
+    assert_eq!(config.problems(), Vec::<String>::new());
+}
+
+#[test]
+fn flags_other_types_without_a_template() {
+    let config = load(
+        "no-template",
+        r#"
+api_token = "token"
+zone_id = "zone"
+
+[[records]]
+name = "_ip.example.com"
+type = "TXT"
+"#,
+    );
+
+    let problems = config.problems();
+    assert_eq!(problems.len(), 1, "{:?}", problems);
+    assert!(
+        problems[0].contains("only A and AAAA records"),
+        "{:?}",
+        problems
+    );
+}
+
+#[test]
+fn dual_stack_twins_of_fixed_ipv4_records_detect_their_address() {
+    let config = load(
+        "twin",
+        r#"
+api_token = "token"
+zone_id = "zone"
+dual_stack = true
+
+[[records]]
+name = "fixed.example.com"
+ip_source = "static:203.0.113.7"
+
+[[records]]
+name = "both.example.com"
+ip_source = "static:203.0.113.7,2001:db8::7"
+"#,
+    );
+    let twin = |name: &str| {
+        config
+            .records
+            .iter()
+            .find(|r| r.name == name && r.record_type == "AAAA")
+            .unwrap()
+    };
+
+    assert_eq!(twin("fixed.example.com").ip_source, config.ip.source);
+    assert_eq!(
+        twin("both.example.com").ip_source.to_string(),
+        "static:203.0.113.7,2001:db8::7"
+    );
+}
+
+#[test]
+fn flags_ttls_below_a_minute() {
+    let config = load(
+        "ttl",
+        r#"
+api_token = "token"
+zone_id = "zone"
+
+[[records]]
+name = "short.example.com"
+ttl = 30
+
+[[records]]
+name = "minute.example.com"
+ttl = 60
+"#,
+    );
+
+    let problems = config.problems();
+    assert_eq!(problems.len(), 1, "{:?}", problems);
+    assert!(problems[0].contains("TTL 30"), "{:?}", problems);
+}
@@ -0,0 +1,87 @@
+//! Failover health checks against local servers.
+
+use cloudflare_ddns::error::Error;
+use cloudflare_ddns::failover::HealthCheck;
+use reqwest::Client;
+use std::net::IpAddr;
+use tokio::net::TcpListener;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const LOCALHOST: IpAddr = IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
+
+#[test]
+fn parses_checks() {
+    assert_eq!(
+        "tcp:{ip}:443".parse::<HealthCheck>().unwrap(),
+        HealthCheck::Tcp {
+            host: "{ip}".to_string(),
+            port: 443
+        }
+    );
+    assert_eq!(
+        "tcp:[2001:db8::1]:22".parse::<HealthCheck>().unwrap(),
+        HealthCheck::Tcp {
+            host: "2001:db8::1".to_string(),
+            port: 22
+        }
+    );
+    assert_eq!(
+        "ping:{ip}".parse::<HealthCheck>().unwrap(),
+        HealthCheck::Ping("{ip}".to_string())
+    );
+    assert_eq!(
+        "https://{ip}/health".parse::<HealthCheck>().unwrap(),
+        HealthCheck::Http("https://{ip}/health".to_string())
+    );
+
+    for invalid in ["tcp:{ip}", "ping:", "udp:{ip}:53"] {
+        let error = invalid.parse::<HealthCheck>().unwrap_err();
+        assert!(matches!(error, Error::Config(_)), "{:?}", error);
+    }
+}
+
+#[tokio::test]
+async fn connects_over_tcp() {
+    let listener = TcpListener::bind((LOCALHOST, 0)).await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let check = HealthCheck::Tcp {
+        host: "{ip}".to_string(),
+        port,
+    };
+
+    check.probe(&Client::new(), LOCALHOST).await.unwrap();
+
+    drop(listener);
+    let error = check.probe(&Client::new(), LOCALHOST).await.unwrap_err();
+    assert!(matches!(error, Error::HealthCheck(_)), "{:?}", error);
+}
+
+#[tokio::test]
+async fn requires_successful_http_answers() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/health"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/down"))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&server)
+        .await;
+    let port = server.address().port();
+
+    let healthy = HealthCheck::Http(format!("http://{{ip}}:{}/health", port));
+    healthy.probe(&Client::new(), LOCALHOST).await.unwrap();
+
+    let down = HealthCheck::Http(format!("http://{{ip}}:{}/down", port));
+    let error = down.probe(&Client::new(), LOCALHOST).await.unwrap_err();
+    assert!(
+        error
+            .to_string()
+            .ends_with("answered with 503 Service Unavailable"),
+        "{}",
+        error
+    );
+}
@@ -0,0 +1,171 @@
+//! Address detection through HTTP services served by a mock.
+
+use cloudflare_ddns::error::Error;
+use cloudflare_ddns::ip::{IpDetector, IpService, IpVersion, get_public_ip};
+use serde_json::json;
+use std::net::IpAddr;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+async fn serve(server: &MockServer, route: &str, response: ResponseTemplate) {
+    Mock::given(method("GET"))
+        .and(path(route))
+        .respond_with(response)
+        .mount(server)
+        .await;
+}
+
+fn detector(services: Vec<IpService>) -> IpDetector {
+    IpDetector {
+        services,
+        ..IpDetector::default()
+    }
+}
+
+#[tokio::test]
+async fn reads_plain_text_answers() {
+    let server = MockServer::start().await;
+    serve(
+        &server,
+        "/ip",
+        ResponseTemplate::new(200).set_body_string("1.1.1.1\n"),
+    )
+    .await;
+
+    let detector = detector(vec![IpService::text(&format!("{}/ip", server.uri()))]);
+    let ip = get_public_ip(&detector, IpVersion::V4).await.unwrap();
+
+    assert_eq!(ip, "1.1.1.1".parse::<IpAddr>().unwrap());
+}
+
+#[tokio::test]
+async fn reads_nested_json_fields() {
+    let server = MockServer::start().await;
+    serve(
+        &server,
+        "/json",
+        ResponseTemplate::new(200).set_body_json(json!({ "data": { "ip": "8.8.8.8" } })),
+    )
+    .await;
+
+    let detector = detector(vec![IpService::json(
+        &format!("{}/json", server.uri()),
+        "data.ip",
+    )]);
+    let ip = get_public_ip(&detector, IpVersion::V4).await.unwrap();
+
+    assert_eq!(ip, "8.8.8.8".parse::<IpAddr>().unwrap());
+}
+
+#[tokio::test]
+async fn rejects_answers_that_are_no_address() {
+    let server = MockServer::start().await;
+    serve(
+        &server,
+        "/ip",
+        ResponseTemplate::new(200).set_body_string("<html>Rate limit exceeded</html>"),
+    )
+    .await;
+
+    let detector = detector(vec![IpService::text(&format!("{}/ip", server.uri()))]);
+    let error = get_public_ip(&detector, IpVersion::V4).await.unwrap_err();
+
+    assert!(matches!(error.root(), Error::IpDetection(_)), "{:?}", error);
+}
+
+#[tokio::test]
+async fn rejects_json_without_the_field() {
+    let server = MockServer::start().await;
+    serve(
+        &server,
+        "/json",
+        ResponseTemplate::new(200).set_body_json(json!({ "origin": "8.8.8.8" })),
+    )
+    .await;
+
+    let detector = detector(vec![IpService::json(
+        &format!("{}/json", server.uri()),
+        "ip",
+    )]);
+    let error = get_public_ip(&detector, IpVersion::V4).await.unwrap_err();
+
+    assert!(
+        error
+            .root()
+            .to_string()
+            .contains("Response has no string field 'ip'"),
+        "{}",
+        error.report()
+    );
+}
+
+#[tokio::test]
+async fn rejects_private_addresses() {
+    let server = MockServer::start().await;
+    serve(
+        &server,
+        "/ip",
+        ResponseTemplate::new(200).set_body_string("192.168.1.20"),
+    )
+    .await;
+
+    let mut detector = detector(vec![IpService::text(&format!("{}/ip", server.uri()))]);
+    let error = get_public_ip(&detector, IpVersion::V4).await.unwrap_err();
+    assert!(matches!(error, Error::IpDetection(_)), "{:?}", error);
+
+    detector.allow_private = true;
+    let ip = get_public_ip(&detector, IpVersion::V4).await.unwrap();
+    assert_eq!(ip, "192.168.1.20".parse::<IpAddr>().unwrap());
+}
+
+#[tokio::test]
+async fn skips_failing_services() {
+    let server = MockServer::start().await;
+    serve(&server, "/down", ResponseTemplate::new(503)).await;
+    serve(
+        &server,
+        "/ip",
+        ResponseTemplate::new(200).set_body_string("1.1.1.1"),
+    )
+    .await;
+
+    let detector = detector(vec![
+        IpService::text(&format!("{}/down", server.uri())),
+        IpService::text(&format!("{}/ip", server.uri())),
+    ]);
+    let ip = get_public_ip(&detector, IpVersion::V4).await.unwrap();
+
+    assert_eq!(ip, "1.1.1.1".parse::<IpAddr>().unwrap());
+}
+
+#[tokio::test]
+async fn requires_services_to_agree() {
+    let server = MockServer::start().await;
+    serve(
+        &server,
+        "/a",
+        ResponseTemplate::new(200).set_body_string("1.1.1.1"),
+    )
+    .await;
+    serve(
+        &server,
+        "/b",
+        ResponseTemplate::new(200).set_body_string("1.0.0.1"),
+    )
+    .await;
+
+    let mut detector = detector(vec![
+        IpService::text(&format!("{}/a", server.uri())),
+        IpService::text(&format!("{}/b", server.uri())),
+    ]);
+    detector.consensus = 2;
+    let error = get_public_ip(&detector, IpVersion::V4).await.unwrap_err();
+
+    assert!(
+        error
+            .to_string()
+            .starts_with("No 2 IP services agreed on the IPv4 address"),
+        "{}",
+        error
+    );
+}
@@ -0,0 +1,63 @@
+//! The cycle report printed with `--output json`.
+
+mod common;
+
+use cloudflare_ddns::provider::Provider;
+use cloudflare_ddns::updater::{CycleReport, RecordReport, RecordStatus};
+use common::load;
+
+#[test]
+fn marks_paused_runs() {
+    let config = load(
+        "paused",
+        r#"
+api_token = "token"
+zone_id = "zone"
+paused = true
+
+[[records]]
+name = "home.example.com"
+"#,
+    );
+
+    let report = CycleReport::paused(&config, config.paused_by().unwrap());
+    let json = serde_json::to_value(&report).unwrap();
+
+    assert!(json["paused"].is_string());
+    assert_eq!(json["checked"], 0);
+    assert_eq!(json["records"], serde_json::json!([]));
+}
+
+#[test]
+fn leaves_out_paused_for_runs_that_ran() {
+    let json = serde_json::to_value(CycleReport::default()).unwrap();
+
+    assert!(json.get("paused").is_none());
+}
+
+#[test]
+fn counts_the_records_a_stopped_run_didnt_get_to_as_failed() {
+    let mut report = CycleReport {
+        planned: 3,
+        records: vec![RecordReport {
+            name: "home.example.com".to_string(),
+            record_type: "A".to_string(),
+            provider: Provider::Cloudflare,
+            status: RecordStatus::Updated,
+            old_ip: Some("203.0.113.7".to_string()),
+            new_ip: Some("198.51.100.10".to_string()),
+            error: None,
+            verified: None,
+            verification_error: None,
+        }],
+        ..CycleReport::default()
+    };
+
+    report.stop("stopped".to_string());
+
+    assert_eq!(report.checked, 3);
+    assert_eq!(report.changed, 1);
+    assert_eq!(report.failed, 2);
+    assert_eq!(report.skipped, 0);
+    assert_eq!(report.errors, ["stopped"]);
+}
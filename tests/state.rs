@@ -0,0 +1,56 @@
+//! Changes made through the admin API, applied on top of the configuration.
+
+mod common;
+
+use cloudflare_ddns::state::State;
+use common::load;
+
+#[test]
+fn keeps_the_configured_intervals_when_the_saved_ones_no_longer_fit() {
+    let mut config = load(
+        "intervals",
+        r#"
+api_token = "token"
+zone_id = "zone"
+sleep_interval = 300
+max_sleep_interval = 600
+
+[[records]]
+name = "home.example.com"
+"#,
+    );
+    let state = State {
+        sleep_interval: Some(1000),
+        ..State::default()
+    };
+
+    state.apply(&mut config).unwrap();
+
+    assert_eq!(config.sleep_interval, 300);
+    assert_eq!(config.max_sleep_interval, Some(600));
+}
+
+#[test]
+fn applies_saved_intervals_that_fit() {
+    let mut config = load(
+        "fitting",
+        r#"
+api_token = "token"
+zone_id = "zone"
+sleep_interval = 300
+
+[[records]]
+name = "home.example.com"
+"#,
+    );
+    let state = State {
+        sleep_interval: Some(120),
+        max_sleep_interval: Some(900),
+        ..State::default()
+    };
+
+    state.apply(&mut config).unwrap();
+
+    assert_eq!(config.sleep_interval, 120);
+    assert_eq!(config.max_sleep_interval, Some(900));
+}
@@ -0,0 +1,45 @@
+//! On/off switches read from the environment, in their own test binary
+//! since they change it.
+
+use cloudflare_ddns::config::Config;
+use cloudflare_ddns::error::Error;
+use std::fs;
+
+#[test]
+fn reads_switches_strictly() {
+    let path =
+        std::env::temp_dir().join(format!("cloudflare-ddns-pause-{}.toml", std::process::id()));
+    fs::write(
+        &path,
+        "api_token = \"token\"\nzone_id = \"zone\"\n\n[[records]]\nname = \"home.example.com\"\n",
+    )
+    .unwrap();
+    let load_with = |name: &str, value: &str| {
+        // SAFETY: the only test of this binary, so nothing reads the
+        // environment meanwhile
+        unsafe { std::env::set_var(name, value) };
+        let config = Config::load(Some(&path), false);
+        unsafe { std::env::remove_var(name) };
+        config
+    };
+    let load = |value: &str| load_with("PAUSED", value);
+
+    for value in ["true", "1", "yes", "ON"] {
+        assert!(load(value).unwrap().paused, "{}", value);
+    }
+    for value in ["false", "0", "no", "off"] {
+        assert!(!load(value).unwrap().paused, "{}", value);
+    }
+    for value in ["paused", "2", "y"] {
+        let error = load(value).unwrap_err();
+        assert!(matches!(error, Error::Config(_)), "{}: {:?}", value, error);
+    }
+
+    // The other switches are read the same way
+    let config = load_with("DUAL_STACK", "yes").unwrap();
+    assert!(config.records.iter().any(|r| r.record_type == "AAAA"));
+    let error = load_with("PRUNE_RECORDS", "maybe").unwrap_err();
+    assert!(matches!(error, Error::Config(_)), "{:?}", error);
+
+    fs::remove_file(&path).unwrap();
+}